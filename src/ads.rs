@@ -0,0 +1,120 @@
+// Posts LRP ads on a schedule. The client drives this from its event loop;
+// it decides which channels can take an ad right now, this just keeps track of what's due.
+
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::data::{Channel, Character};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduledAd {
+    pub character: Character,
+    pub channel: Channel,
+    pub texts: Vec<String>, // Rotated through, one per post
+    pub interval: Duration,
+}
+
+#[derive(Debug)]
+struct Entry {
+    ad: ScheduledAd,
+    next_text: usize,
+    last_posted: Option<Instant>,
+}
+
+#[derive(Debug, Default)]
+pub struct AdScheduler {
+    entries: Mutex<Vec<Entry>>,
+}
+
+impl AdScheduler {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Post `texts` (in rotation) to the channel as `character` every `interval`.
+    /// Replaces any existing schedule for that character and channel.
+    /// The interval is stretched to the server's lfrp_flood if it's shorter.
+    pub fn schedule(
+        &self,
+        character: Character,
+        channel: Channel,
+        texts: Vec<String>,
+        interval: Duration,
+    ) {
+        let mut entries = self.entries.lock();
+        let ad = ScheduledAd {
+            character,
+            channel: channel.clone(),
+            texts,
+            interval,
+        };
+        match entries
+            .iter_mut()
+            .find(|e| e.ad.character == character && e.ad.channel == channel)
+        {
+            Some(entry) => {
+                // Keep the cooldown; the server still remembers the last post.
+                entry.ad = ad;
+                entry.next_text = 0;
+            }
+            None => entries.push(Entry {
+                ad,
+                next_text: 0,
+                last_posted: None,
+            }),
+        }
+    }
+
+    pub fn unschedule(&self, character: &Character, channel: &Channel) -> bool {
+        let mut entries = self.entries.lock();
+        let len = entries.len();
+        entries.retain(|e| !(e.ad.character == *character && e.ad.channel == *channel));
+        entries.len() != len
+    }
+
+    pub fn scheduled(&self) -> Vec<ScheduledAd> {
+        self.entries.lock().iter().map(|e| e.ad.clone()).collect()
+    }
+
+    /// Collect the ads that should be posted now, and mark them as posted.
+    /// `cooldown` yields the minimum gap between ads for a character and channel,
+    /// or None if an ad can't go there at all right now (not joined, chat-only, etc.).
+    pub(crate) fn take_due(
+        &self,
+        now: Instant,
+        cooldown: impl Fn(&Character, &Channel) -> Option<Duration>,
+    ) -> Vec<(Character, Channel, String)> {
+        let mut due = Vec::new();
+        for entry in self.entries.lock().iter_mut() {
+            if entry.ad.texts.is_empty() {
+                continue;
+            }
+            let Some(cooldown) = cooldown(&entry.ad.character, &entry.ad.channel) else {
+                continue;
+            };
+            let gap = entry.ad.interval.max(cooldown);
+            if entry
+                .last_posted
+                .is_some_and(|last| now.duration_since(last) < gap)
+            {
+                continue;
+            }
+            let text = entry.ad.texts[entry.next_text % entry.ad.texts.len()].clone();
+            entry.next_text = (entry.next_text + 1) % entry.ad.texts.len();
+            entry.last_posted = Some(now);
+            due.push((entry.ad.character, entry.ad.channel.clone(), text));
+        }
+        due
+    }
+
+    // The server refused an ad for being too soon. The ERR doesn't say which channel it was for,
+    // so every ad the character has already posted gets pushed back.
+    pub(crate) fn cooled_down(&self, character: &Character, now: Instant) {
+        for entry in self.entries.lock().iter_mut() {
+            if entry.ad.character == *character && entry.last_posted.is_some() {
+                entry.last_posted = Some(now);
+            }
+        }
+    }
+}
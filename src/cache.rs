@@ -1,19 +1,67 @@
 // Cache trait for the client.
-use std::{borrow::Cow, collections::HashSet};
+use std::{borrow::Cow, collections::HashSet, sync::Arc};
 
-use serde::Serialize;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 
 use crate::{
     data::{
-        Channel, ChannelData, ChannelMode, Character, CharacterData, FriendRelation, Gender,
-        Message, MessageChannel, Status,
+        Channel, ChannelData, ChannelMode, Character, CharacterData, FriendRelation,
+        FurryPreference, Gender, Language, Message, MessageChannel, Orientation,
+        PendingFriendRequest, Role, StaffReport, Status, TypingStatus,
     },
+    http_endpoints::{CharacterProfileResponse, MappingListResponse},
     util::timestamp::Timestamp,
 };
 
+mod memory;
+pub use memory::{MemoryCache, MemoryCacheError};
+mod layered;
+pub use layered::{LayeredCache, LayeredCacheError};
+mod json_file;
+pub use json_file::{JsonFileCache, JsonFileCacheError};
+mod observed;
+#[cfg(feature = "metrics")]
+pub use observed::MetricsObserver;
+pub use observed::{CacheObserver, ObservedCache};
+mod retention;
+pub use retention::{EvictionHook, Retention, RetentionClass};
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+mod columns;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "sqlite")]
+pub use sqlite::{SqliteCache, SqliteCacheError};
+#[cfg(feature = "postgres")]
+mod postgres;
+#[cfg(feature = "postgres")]
+pub use postgres::{PostgresCache, PostgresCacheError};
+
 pub trait Cache: std::marker::Sync + Sized + std::marker::Send {
-    type Error: std::error::Error;
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    // Brackets a burst of writes (like the LIS flood on connect) so a persistent backend can make
+    // them one transaction. Batches nest; only the outermost commit counts. No-ops by default.
+    fn begin_batch(&self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    fn commit_batch(&self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    // Applies retention limits to everything held, rather than just the history a message was
+    // added to, and yields whether anything went. The client runs it every so often if asked to
+    // (see ClientBuilder::with_cache_compaction). Nothing to do by default.
+    fn compact(&self) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+
+    // Called about once a second while the client's running (see Client::start), for backends
+    // with housekeeping of their own, like writing changes out on a timer. Nothing by default.
+    fn tick(&self) -> Result<(), Self::Error> {
+        Ok(())
+    }
 
+    // Messages and ads in a channel also move its last_activity along, if the channel is known.
     fn insert_message(&self, source: MessageChannel, message: Message)
         -> Result<bool, Self::Error>;
     fn insert_channel(
@@ -27,7 +75,20 @@ pub trait Cache: std::marker::Sync + Sized + std::marker::Send {
         channel: Cow<Channel>,
         character: Cow<Character>,
         ad: Cow<str>,
+        timestamp: Timestamp,
     ) -> Result<bool, Self::Error>;
+    fn insert_report(&self, report: Cow<StaffReport>) -> Result<bool, Self::Error>;
+    fn insert_profile(
+        &self,
+        character: Cow<Character>,
+        profile: CachedProfile,
+    ) -> Result<bool, Self::Error>;
+    fn set_memo(&self, character: Cow<Character>, memo: Cow<str>) -> Result<bool, Self::Error>;
+    fn remove_report(&self, callid: u32) -> Result<bool, Self::Error>;
+    // Forget what a character said and what's known about them: their messages anywhere, every
+    // PM history they're part of, their ads, any memo on them and their profile.
+    // For honoring deletion requests.
+    fn purge_character(&self, character: Cow<Character>) -> Result<bool, Self::Error>;
 
     fn add_channel_member(
         &self,
@@ -46,6 +107,9 @@ pub trait Cache: std::marker::Sync + Sized + std::marker::Send {
     fn add_global_op(&self, character: Cow<Character>) -> Result<bool, Self::Error>;
     fn remove_global_op(&self, character: Cow<Character>) -> Result<bool, Self::Error>;
 
+    fn add_ignored(&self, character: Cow<Character>) -> Result<bool, Self::Error>;
+    fn remove_ignored(&self, character: Cow<Character>) -> Result<bool, Self::Error>;
+
     fn add_channel_op(
         &self,
         channel: Cow<Channel>,
@@ -57,6 +121,24 @@ pub trait Cache: std::marker::Sync + Sized + std::marker::Send {
         character: Cow<Character>,
     ) -> Result<bool, Self::Error>;
 
+    fn add_channel_ban(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error>;
+    fn remove_channel_ban(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error>;
+    // A ban that lifts itself at `expires`.
+    fn add_channel_timeout(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+        expires: Timestamp,
+    ) -> Result<bool, Self::Error>;
+
     fn update_channel(
         &self,
         channel: Cow<Channel>,
@@ -67,18 +149,66 @@ pub trait Cache: std::marker::Sync + Sized + std::marker::Send {
         character: Cow<Character>,
         data: PartialUserData,
     ) -> Result<bool, Self::Error>;
+    // Like the above, but hand back what was there before. The defaults read it first, which isn't
+    // atomic; implementations that can do better should.
+    fn update_channel_diff(
+        &self,
+        channel: Cow<Channel>,
+        data: PartialChannelData,
+    ) -> Result<Updated<ChannelData>, Self::Error> {
+        let previous = self.get_channel(&channel)?;
+        Ok(match self.update_channel(channel, data)? {
+            true => Updated::Changed(previous),
+            false => Updated::Unchanged,
+        })
+    }
+    fn update_character_diff(
+        &self,
+        character: Cow<Character>,
+        data: PartialUserData,
+    ) -> Result<Updated<CharacterData>, Self::Error> {
+        let previous = self.get_character(&character)?;
+        Ok(match self.update_character(character, data)? {
+            true => Updated::Changed(previous),
+            false => Updated::Unchanged,
+        })
+    }
 
     fn set_friends(&self, friends: Cow<[FriendRelation]>) -> Result<bool, Self::Error>;
     fn set_bookmarks(&self, bookmarks: Cow<[Character]>) -> Result<bool, Self::Error>;
+    fn set_ignored(&self, ignored: Cow<[Character]>) -> Result<bool, Self::Error>;
+    fn set_incoming_friend_requests(
+        &self,
+        requests: Cow<[PendingFriendRequest]>,
+    ) -> Result<bool, Self::Error>;
+    fn set_outgoing_friend_requests(
+        &self,
+        requests: Cow<[PendingFriendRequest]>,
+    ) -> Result<bool, Self::Error>;
     fn set_channel_members(
         &self,
         channel: Cow<Channel>,
         members: Cow<[Character]>,
     ) -> Result<bool, Self::Error>;
-    fn set_global_channels(&self, channels: Cow<[(Channel, u32)]>) -> Result<bool, Self::Error>;
-    fn set_unofficial_channels(&self, channels: Cow<[(Channel, u32)]>)
-        -> Result<bool, Self::Error>;
+    fn set_global_channels(
+        &self,
+        channels: Cow<[(Channel, u32)]>,
+        fetched_at: Timestamp,
+    ) -> Result<bool, Self::Error>;
+    fn set_unofficial_channels(
+        &self,
+        channels: Cow<[(Channel, u32)]>,
+        fetched_at: Timestamp,
+    ) -> Result<bool, Self::Error>;
     fn set_global_ops(&self, ops: Cow<[Character]>) -> Result<bool, Self::Error>;
+    // The whole mapping list. It's the same for everyone and barely ever changes.
+    fn set_kink_mapping(&self, mapping: CachedKinkMapping) -> Result<bool, Self::Error>;
+    // From a CBL banlist. Anyone on it who's timed out keeps their expiry.
+    fn set_channel_bans(
+        &self,
+        channel: Cow<Channel>,
+        bans: Cow<[Character]>,
+    ) -> Result<bool, Self::Error>;
     fn set_channel_ops(
         &self,
         channel: Cow<Channel>,
@@ -86,17 +216,54 @@ pub trait Cache: std::marker::Sync + Sized + std::marker::Send {
     ) -> Result<bool, Self::Error>;
 
     fn get_channel(&self, channel: &Channel) -> Result<Option<ChannelData>, Self::Error>;
-    fn get_channels(&self) -> Result<Cow<[ChannelData]>, Self::Error>;
+    fn get_channels(&self) -> Result<Cow<'_, [ChannelData]>, Self::Error>;
     fn get_character(&self, character: &Character) -> Result<Option<CharacterData>, Self::Error>;
-    fn get_characters(&self) -> Result<Cow<[CharacterData]>, Self::Error>;
+    fn get_profile(&self, character: &Character) -> Result<Option<CachedProfile>, Self::Error>;
+    fn get_global_ops(&self) -> Result<Cow<'_, [Character]>, Self::Error>;
+    fn get_channel_ops(&self, channel: &Channel) -> Result<Cow<'_, [Character]>, Self::Error>;
+    fn get_channel_members(&self, channel: &Channel) -> Result<Cow<'_, [Character]>, Self::Error>;
+    // Bans and timeouts that are still in effect.
+    fn get_channel_bans(&self, channel: &Channel) -> Result<Cow<'_, [ChannelBan]>, Self::Error>;
+    // The latest ad from each character in the channel, if it was posted at or after `since`
+    // and hasn't outlived the cache's ad retention.
+    fn get_ads(
+        &self,
+        channel: &Channel,
+        since: Option<Timestamp>,
+    ) -> Result<Cow<'_, [CachedAd]>, Self::Error>;
+    fn get_global_channels(&self) -> Result<Option<ChannelListing>, Self::Error>;
+    fn get_unofficial_channels(&self) -> Result<Option<ChannelListing>, Self::Error>;
+    fn get_kink_mapping(&self) -> Result<Option<CachedKinkMapping>, Self::Error>;
+    fn get_memo(&self, character: &Character) -> Result<Option<String>, Self::Error>;
+    fn get_characters(&self) -> Result<Cow<'_, [CharacterData]>, Self::Error>;
     fn get_messages(
         &self,
         source: &MessageChannel,
         since: Option<Timestamp>,
         limit: Option<u32>,
-    ) -> Result<Cow<[Message]>, Self::Error>;
-    fn get_friend_relations(&self) -> Result<Cow<[FriendRelation]>, Self::Error>;
-    fn get_friends(&self) -> Result<Cow<[Character]>, Self::Error> {
+    ) -> Result<Cow<'_, [Message]>, Self::Error>;
+    // PM history is kept per (own character, other character), so one account's characters
+    // never see each other's conversations. These take the own character explicitly.
+    fn get_private_messages(
+        &self,
+        own_character: &Character,
+        other: &Character,
+        since: Option<Timestamp>,
+        limit: Option<u32>,
+    ) -> Result<Cow<'_, [Message]>, Self::Error> {
+        self.get_messages(
+            &MessageChannel::private(*own_character, *other),
+            since,
+            limit,
+        )
+    }
+    // Everyone `own_character` has PM history with.
+    fn get_pm_partners(
+        &self,
+        own_character: &Character,
+    ) -> Result<Cow<'_, [Character]>, Self::Error>;
+    fn get_friend_relations(&self) -> Result<Cow<'_, [FriendRelation]>, Self::Error>;
+    fn get_friends(&self) -> Result<Cow<'_, [Character]>, Self::Error> {
         self.get_friend_relations().map(|v| {
             v.iter()
                 .map(|c| c.other_character)
@@ -105,21 +272,164 @@ pub trait Cache: std::marker::Sync + Sized + std::marker::Send {
                 .collect()
         })
     }
-    fn get_bookmarks(&self) -> Result<Cow<[Character]>, Self::Error>;
+    fn get_bookmarks(&self) -> Result<Cow<'_, [Character]>, Self::Error>;
+    fn get_reports(&self) -> Result<Cow<'_, [StaffReport]>, Self::Error>;
+    fn get_ignored(&self) -> Result<Cow<'_, [Character]>, Self::Error>;
+    fn get_incoming_friend_requests(&self) -> Result<Cow<'_, [PendingFriendRequest]>, Self::Error>;
+    fn get_outgoing_friend_requests(&self) -> Result<Cow<'_, [PendingFriendRequest]>, Self::Error>;
+}
+
+/// The result of an update that keeps the old value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Updated<T> {
+    Unchanged,
+    Changed(Option<T>), // None if the cache didn't have it before (or doesn't keep anything)
+}
+
+impl<T> Updated<T> {
+    pub fn is_changed(&self) -> bool {
+        matches!(self, Updated::Changed(_))
+    }
 }
 
-#[derive(Serialize, Debug, Default)]
+#[derive(Serialize, Debug, Default, Clone)]
 pub struct PartialChannelData<'a> {
     pub mode: Option<ChannelMode>,
     pub title: Option<Cow<'a, str>>,
     pub description: Option<Cow<'a, str>>,
+    pub owner: Option<Character>,
+    pub member_count: Option<u32>, // From CHA/ORS, for channels we're not in
 }
 
-#[derive(Serialize, Debug, Default)]
+#[derive(Serialize, Debug, Default, Clone)]
 pub struct PartialUserData<'a> {
     pub gender: Option<Gender>,
     pub status: Option<Status>,
     pub status_message: Option<Cow<'a, str>>,
+    pub typing: Option<TypingStatus>,
+    // From a profile. Some(None) for a profile without it.
+    pub orientation: Option<Option<Orientation>>,
+    pub languages: Option<Vec<Language>>,
+    pub furry_preference: Option<Option<FurryPreference>>,
+    pub role: Option<Option<Role>>,
+    #[serde(serialize_with = "crate::util::timestamp::serialize_option")]
+    pub last_profile_update: Option<Timestamp>,
+}
+
+// Profiles are big and only change when someone edits them, so they're kept with when they were fetched.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedProfile {
+    pub profile: Arc<CharacterProfileResponse>,
+    #[serde(with = "crate::util::timestamp")]
+    pub fetched_at: DateTime<Utc>,
+}
+
+impl CachedProfile {
+    pub fn age(&self, now: DateTime<Utc>) -> std::time::Duration {
+        (now - self.fetched_at).to_std().unwrap_or_default()
+    }
+}
+
+// Same for the mapping list, which is a few megabytes that change maybe once a year.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedKinkMapping {
+    pub mapping: Arc<MappingListResponse>,
+    #[serde(with = "crate::util::timestamp")]
+    pub fetched_at: Timestamp,
+}
+
+impl CachedKinkMapping {
+    pub fn age(&self, now: Timestamp) -> std::time::Duration {
+        (now - self.fetched_at).to_std().unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ChannelBan {
+    pub character: Character,
+    #[serde(
+        serialize_with = "crate::util::timestamp::serialize_option",
+        deserialize_with = "crate::util::timestamp::deserialize_option"
+    )]
+    pub expires: Option<Timestamp>, // None for a ban, Some for a timeout
+}
+
+impl ChannelBan {
+    pub fn in_effect(&self, now: Timestamp) -> bool {
+        self.expires.is_none_or(|expires| expires > now)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CachedAd {
+    pub character: Character,
+    pub ad: String,
+    #[serde(with = "crate::util::timestamp")]
+    pub timestamp: Timestamp,
+}
+
+// CHA/ORS results, with when they arrived so that channel browsers can show how stale they are.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ChannelListing {
+    pub channels: Vec<(Channel, u32)>, // Name and member count
+    #[serde(with = "crate::util::timestamp")]
+    pub fetched_at: Timestamp,
+}
+
+// For the caches that keep real data: only overwrite fields that were given and differ.
+// Yields whether anything changed.
+fn update_field<T: PartialEq>(field: &mut T, value: Option<T>) -> bool {
+    match value {
+        Some(value) if *field != value => {
+            *field = value;
+            true
+        }
+        _ => false,
+    }
+}
+
+fn apply_channel_data(data: &mut ChannelData, update: PartialChannelData) -> bool {
+    // Not short-circuiting; every field has to be applied.
+    update_field(&mut data.channel_mode, update.mode)
+        | update_field(
+            &mut data.title,
+            update.title.map(|title| title.into_owned()),
+        )
+        | update_field(
+            &mut data.description,
+            update
+                .description
+                .map(|description| description.into_owned()),
+        )
+        | update_field(&mut data.owner, update.owner.map(Some))
+        | update_field(&mut data.member_count, update.member_count)
+}
+
+// The parts of ChannelData that aren't kept with the rest of it, filled in as it's read back.
+fn fill_channel_data(data: &mut ChannelData, ops: &[Character]) {
+    data.ops = ops.to_vec();
+    if !data.members.is_empty() {
+        data.member_count = data.members.len() as u32;
+    }
+    data.ads_allowed = data.channel_mode.allows_ads();
+}
+
+fn apply_user_data(data: &mut CharacterData, update: PartialUserData) -> bool {
+    update_field(&mut data.gender, update.gender)
+        | update_field(&mut data.status, update.status)
+        | update_field(
+            &mut data.status_message,
+            update.status_message.map(|message| message.into_owned()),
+        )
+        | update_field(&mut data.typing, update.typing)
+        | update_field(&mut data.orientation, update.orientation)
+        | update_field(&mut data.languages, update.languages)
+        | update_field(&mut data.furry_preference, update.furry_preference)
+        | update_field(&mut data.role, update.role)
+        | update_field(
+            &mut data.last_profile_update,
+            update.last_profile_update.map(Some),
+        )
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -168,7 +478,7 @@ impl Cache for NoCache {
         Ok(None)
     }
 
-    fn get_channels(&self) -> Result<Cow<[ChannelData]>, Self::Error> {
+    fn get_channels(&self) -> Result<Cow<'_, [ChannelData]>, Self::Error> {
         Ok(Vec::new().into())
     }
 
@@ -176,7 +486,7 @@ impl Cache for NoCache {
         Ok(None)
     }
 
-    fn get_characters(&self) -> Result<Cow<[CharacterData]>, Self::Error> {
+    fn get_characters(&self) -> Result<Cow<'_, [CharacterData]>, Self::Error> {
         Ok(Vec::new().into())
     }
 
@@ -201,7 +511,14 @@ impl Cache for NoCache {
         _source: &MessageChannel,
         _since: Option<Timestamp>,
         _limit: Option<u32>,
-    ) -> Result<Cow<[Message]>, Self::Error> {
+    ) -> Result<Cow<'_, [Message]>, Self::Error> {
+        Ok(Vec::new().into())
+    }
+
+    fn get_pm_partners(
+        &self,
+        _own_character: &Character,
+    ) -> Result<Cow<'_, [Character]>, Self::Error> {
         Ok(Vec::new().into())
     }
 
@@ -221,11 +538,11 @@ impl Cache for NoCache {
         Ok(true)
     }
 
-    fn get_friend_relations(&self) -> Result<Cow<[FriendRelation]>, Self::Error> {
+    fn get_friend_relations(&self) -> Result<Cow<'_, [FriendRelation]>, Self::Error> {
         Ok(Vec::new().into())
     }
 
-    fn get_bookmarks(&self) -> Result<Cow<[Character]>, Self::Error> {
+    fn get_bookmarks(&self) -> Result<Cow<'_, [Character]>, Self::Error> {
         Ok(Vec::new().into())
     }
 
@@ -237,17 +554,30 @@ impl Cache for NoCache {
         Ok(true)
     }
 
-    fn set_global_channels(&self, _channels: Cow<[(Channel, u32)]>) -> Result<bool, Self::Error> {
+    fn set_global_channels(
+        &self,
+        _channels: Cow<[(Channel, u32)]>,
+        _fetched_at: Timestamp,
+    ) -> Result<bool, Self::Error> {
         Ok(true)
     }
 
     fn set_unofficial_channels(
         &self,
         _channels: Cow<[(Channel, u32)]>,
+        _fetched_at: Timestamp,
     ) -> Result<bool, Self::Error> {
         Ok(true)
     }
 
+    fn get_global_channels(&self) -> Result<Option<ChannelListing>, Self::Error> {
+        Ok(None)
+    }
+
+    fn get_unofficial_channels(&self) -> Result<Option<ChannelListing>, Self::Error> {
+        Ok(None)
+    }
+
     fn add_global_op(&self, _character: Cow<Character>) -> Result<bool, Self::Error> {
         Ok(true)
     }
@@ -289,7 +619,146 @@ impl Cache for NoCache {
         _channel: Cow<Channel>,
         _character: Cow<Character>,
         _ad: Cow<str>,
+        _timestamp: Timestamp,
     ) -> Result<bool, Self::Error> {
         Ok(true)
     }
+    fn insert_report(&self, _report: Cow<StaffReport>) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    fn remove_report(&self, _callid: u32) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    fn purge_character(&self, _character: Cow<Character>) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    fn get_reports(&self) -> Result<Cow<'_, [StaffReport]>, Self::Error> {
+        Ok(Vec::new().into())
+    }
+
+    fn add_ignored(&self, _character: Cow<Character>) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    fn remove_ignored(&self, _character: Cow<Character>) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    fn set_ignored(&self, _ignored: Cow<[Character]>) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    fn get_ignored(&self) -> Result<Cow<'_, [Character]>, Self::Error> {
+        Ok(Vec::new().into())
+    }
+
+    fn set_incoming_friend_requests(
+        &self,
+        _requests: Cow<[PendingFriendRequest]>,
+    ) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    fn set_outgoing_friend_requests(
+        &self,
+        _requests: Cow<[PendingFriendRequest]>,
+    ) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    fn get_incoming_friend_requests(&self) -> Result<Cow<'_, [PendingFriendRequest]>, Self::Error> {
+        Ok(Vec::new().into())
+    }
+
+    fn get_outgoing_friend_requests(&self) -> Result<Cow<'_, [PendingFriendRequest]>, Self::Error> {
+        Ok(Vec::new().into())
+    }
+
+    fn insert_profile(
+        &self,
+        _character: Cow<Character>,
+        _profile: CachedProfile,
+    ) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    fn get_profile(&self, _character: &Character) -> Result<Option<CachedProfile>, Self::Error> {
+        Ok(None)
+    }
+
+    fn set_kink_mapping(&self, _mapping: CachedKinkMapping) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    fn get_kink_mapping(&self) -> Result<Option<CachedKinkMapping>, Self::Error> {
+        Ok(None)
+    }
+
+    fn set_memo(&self, _character: Cow<Character>, _memo: Cow<str>) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    fn get_memo(&self, _character: &Character) -> Result<Option<String>, Self::Error> {
+        Ok(None)
+    }
+
+    fn get_global_ops(&self) -> Result<Cow<'_, [Character]>, Self::Error> {
+        Ok(Vec::new().into())
+    }
+
+    fn get_channel_ops(&self, _channel: &Channel) -> Result<Cow<'_, [Character]>, Self::Error> {
+        Ok(Vec::new().into())
+    }
+
+    fn get_channel_members(&self, _channel: &Channel) -> Result<Cow<'_, [Character]>, Self::Error> {
+        Ok(Vec::new().into())
+    }
+
+    fn add_channel_ban(
+        &self,
+        _channel: Cow<Channel>,
+        _character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    fn remove_channel_ban(
+        &self,
+        _channel: Cow<Channel>,
+        _character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    fn add_channel_timeout(
+        &self,
+        _channel: Cow<Channel>,
+        _character: Cow<Character>,
+        _expires: Timestamp,
+    ) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    fn set_channel_bans(
+        &self,
+        _channel: Cow<Channel>,
+        _bans: Cow<[Character]>,
+    ) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    fn get_channel_bans(&self, _channel: &Channel) -> Result<Cow<'_, [ChannelBan]>, Self::Error> {
+        Ok(Vec::new().into())
+    }
+
+    fn get_ads(
+        &self,
+        _channel: &Channel,
+        _since: Option<Timestamp>,
+    ) -> Result<Cow<'_, [CachedAd]>, Self::Error> {
+        Ok(Vec::new().into())
+    }
 }
@@ -0,0 +1,26 @@
+// How the database caches turn values into columns and back.
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::{data::Character, util::StackString};
+
+// Plain enums (gender, status, mode) go in as their bare serde name rather than a JSON string.
+pub(super) fn enum_text<T: Serialize>(value: &T) -> serde_json::Result<String> {
+    Ok(match serde_json::to_value(value)? {
+        Value::String(text) => text,
+        other => other.to_string(),
+    })
+}
+
+pub(super) fn parse_enum<T: DeserializeOwned>(text: String) -> serde_json::Result<T> {
+    serde_json::from_value(Value::String(text))
+}
+
+pub(super) fn name(character: &Character) -> String {
+    character.0.to_string()
+}
+
+pub(super) fn character(name: String) -> Character {
+    Character(StackString::new(&name))
+}
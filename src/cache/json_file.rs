@@ -0,0 +1,558 @@
+// A MemoryCache that's saved to one JSON file, for small bots that want their history to survive
+// a restart without pulling in a database.
+//
+// The whole cache is rewritten on every flush: on the client's tick (see Cache::tick) once there
+// are changes and the last flush was at least the flush interval ago, on flush(), and on drop.
+// Nothing's written from inside the cache calls themselves.
+// Writes go to a temporary file that's renamed over the old one, so a crash mid-flush can't leave
+// a half-written cache behind.
+
+use std::{
+    borrow::Cow,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    mem,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use super::{
+    memory::{MemoryCache, MemoryCacheError},
+    retention::{EvictionHook, Retention, RetentionClass},
+    Cache, CachedAd, CachedKinkMapping, CachedProfile, ChannelBan, ChannelListing,
+    PartialChannelData, PartialUserData, Updated,
+};
+use crate::{
+    data::{
+        Channel, ChannelData, Character, CharacterData, FriendRelation, Message, MessageChannel,
+        PendingFriendRequest, StaffReport,
+    },
+    util::{diagnostic, timestamp::Timestamp},
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum JsonFileCacheError {
+    #[error("Failed to read or write the cache file")]
+    Io(#[from] io::Error),
+    #[error("Failed to (de)serialize the cache file")]
+    Json(#[from] serde_json::Error),
+}
+
+impl From<MemoryCacheError> for JsonFileCacheError {
+    fn from(err: MemoryCacheError) -> Self {
+        match err {}
+    }
+}
+
+type JsonFileResult<T> = Result<T, JsonFileCacheError>;
+
+const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub struct JsonFileCache {
+    inner: MemoryCache,
+    path: PathBuf,
+    flush_interval: Duration,
+    dirty: AtomicBool,
+    batch_depth: AtomicU32, // No flushing halfway through a batch
+    last_flush: Mutex<Instant>,
+}
+
+impl JsonFileCache {
+    /// Load the cache from `path`, or start empty if there's nothing there yet.
+    pub fn open<P: AsRef<Path>>(path: P) -> JsonFileResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        let inner = MemoryCache::new();
+        match File::open(&path) {
+            Ok(file) => inner.restore(serde_json::from_reader(BufReader::new(file))?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err.into()),
+        }
+        Ok(JsonFileCache {
+            inner,
+            path,
+            flush_interval: DEFAULT_FLUSH_INTERVAL,
+            dirty: AtomicBool::new(false),
+            batch_depth: AtomicU32::new(0),
+            last_flush: Mutex::new(Instant::now()),
+        })
+    }
+
+    /// How long changes can sit in memory before they're written out. 30 seconds by default.
+    /// Timed flushes come from Cache::tick, which a running Client calls every second; without
+    /// one, changes are only written by flush() and on drop.
+    pub fn with_flush_interval(mut self, interval: Duration) -> Self {
+        self.flush_interval = interval;
+        self
+    }
+
+    /// See MemoryCache::with_retention.
+    pub fn with_retention(mut self, retention: Retention) -> Self {
+        self.inner = mem::take(&mut self.inner).with_retention(retention);
+        self
+    }
+
+    /// See MemoryCache::with_source_retention.
+    pub fn with_source_retention(mut self, source: MessageChannel, retention: Retention) -> Self {
+        self.inner = mem::take(&mut self.inner).with_source_retention(source, retention);
+        self
+    }
+
+    /// See MemoryCache::with_class_retention.
+    pub fn with_class_retention(mut self, class: RetentionClass, retention: Retention) -> Self {
+        self.inner = mem::take(&mut self.inner).with_class_retention(class, retention);
+        self
+    }
+
+    /// See MemoryCache::with_ad_retention.
+    pub fn with_ad_retention(mut self, max_age: Duration) -> Self {
+        self.inner = mem::take(&mut self.inner).with_ad_retention(max_age);
+        self
+    }
+
+    /// See MemoryCache::with_character_limit.
+    pub fn with_character_limit(mut self, limit: usize) -> Self {
+        self.inner = mem::take(&mut self.inner).with_character_limit(limit);
+        self
+    }
+
+    /// See MemoryCache::with_eviction_hook.
+    pub fn with_eviction_hook(mut self, hook: impl EvictionHook + 'static) -> Self {
+        self.inner = mem::take(&mut self.inner).with_eviction_hook(hook);
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write out any changes now. Dropping the cache does this too, but can only log a failure.
+    pub fn flush(&self) -> JsonFileResult<()> {
+        let mut last_flush = self.last_flush.lock();
+        // Cleared first, so anything that changes while this runs is caught by the next flush.
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+        let result = self.write_file();
+        if result.is_err() {
+            self.dirty.store(true, Ordering::Release);
+        }
+        *last_flush = Instant::now();
+        result
+    }
+
+    fn write_file(&self) -> JsonFileResult<()> {
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        let mut file = BufWriter::new(File::create(&temp)?);
+        serde_json::to_writer(&mut file, &self.inner.state())?;
+        file.into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+        fs::rename(&temp, &self.path)?;
+        Ok(())
+    }
+
+    fn flush_due(&self) -> JsonFileResult<()> {
+        if !self.dirty.load(Ordering::Acquire) || self.batch_depth.load(Ordering::Acquire) > 0 {
+            return Ok(());
+        }
+        if self.last_flush.lock().elapsed() < self.flush_interval {
+            return Ok(());
+        }
+        self.flush()
+    }
+
+    fn write(&self, result: Result<bool, MemoryCacheError>) -> JsonFileResult<bool> {
+        let changed = result?;
+        if changed {
+            self.dirty.store(true, Ordering::Release);
+        }
+        Ok(changed)
+    }
+}
+
+impl Drop for JsonFileCache {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            diagnostic!(
+                warn,
+                "Failed to save cache to {}: {err:?}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+impl Cache for JsonFileCache {
+    type Error = JsonFileCacheError;
+
+    fn begin_batch(&self) -> Result<(), Self::Error> {
+        self.batch_depth.fetch_add(1, Ordering::AcqRel);
+        Ok(())
+    }
+
+    fn commit_batch(&self) -> Result<(), Self::Error> {
+        // Unbalanced commits do nothing
+        let _ = self
+            .batch_depth
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |depth| {
+                depth.checked_sub(1)
+            });
+        Ok(())
+    }
+
+    fn compact(&self) -> Result<bool, Self::Error> {
+        self.write(self.inner.compact())
+    }
+
+    fn tick(&self) -> Result<(), Self::Error> {
+        self.flush_due()
+    }
+
+    fn insert_message(
+        &self,
+        source: MessageChannel,
+        message: Message,
+    ) -> Result<bool, Self::Error> {
+        self.write(self.inner.insert_message(source, message))
+    }
+
+    fn insert_channel(
+        &self,
+        channel: Cow<Channel>,
+        data: PartialChannelData,
+        members: Cow<[Character]>,
+    ) -> Result<bool, Self::Error> {
+        self.write(self.inner.insert_channel(channel, data, members))
+    }
+
+    fn insert_ad(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+        ad: Cow<str>,
+        timestamp: Timestamp,
+    ) -> Result<bool, Self::Error> {
+        self.write(self.inner.insert_ad(channel, character, ad, timestamp))
+    }
+
+    fn insert_report(&self, report: Cow<StaffReport>) -> Result<bool, Self::Error> {
+        self.write(self.inner.insert_report(report))
+    }
+
+    fn insert_profile(
+        &self,
+        character: Cow<Character>,
+        profile: CachedProfile,
+    ) -> Result<bool, Self::Error> {
+        self.write(self.inner.insert_profile(character, profile))
+    }
+
+    fn set_memo(&self, character: Cow<Character>, memo: Cow<str>) -> Result<bool, Self::Error> {
+        self.write(self.inner.set_memo(character, memo))
+    }
+
+    fn remove_report(&self, callid: u32) -> Result<bool, Self::Error> {
+        self.write(self.inner.remove_report(callid))
+    }
+
+    fn purge_character(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.write(self.inner.purge_character(character))
+    }
+
+    fn add_channel_member(
+        &self,
+        channel: Cow<Channel>,
+        member: Character,
+    ) -> Result<bool, Self::Error> {
+        self.write(self.inner.add_channel_member(channel, member))
+    }
+
+    fn remove_channel_member(
+        &self,
+        channel: Cow<Channel>,
+        member: Character,
+    ) -> Result<bool, Self::Error> {
+        self.write(self.inner.remove_channel_member(channel, member))
+    }
+
+    fn add_bookmark(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.write(self.inner.add_bookmark(character))
+    }
+
+    fn remove_bookmark(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.write(self.inner.remove_bookmark(character))
+    }
+
+    fn add_global_op(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.write(self.inner.add_global_op(character))
+    }
+
+    fn remove_global_op(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.write(self.inner.remove_global_op(character))
+    }
+
+    fn add_ignored(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.write(self.inner.add_ignored(character))
+    }
+
+    fn remove_ignored(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.write(self.inner.remove_ignored(character))
+    }
+
+    fn add_channel_op(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        self.write(self.inner.add_channel_op(channel, character))
+    }
+
+    fn remove_channel_op(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        self.write(self.inner.remove_channel_op(channel, character))
+    }
+
+    fn add_channel_ban(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        self.write(self.inner.add_channel_ban(channel, character))
+    }
+
+    fn remove_channel_ban(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        self.write(self.inner.remove_channel_ban(channel, character))
+    }
+
+    fn add_channel_timeout(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+        expires: Timestamp,
+    ) -> Result<bool, Self::Error> {
+        self.write(self.inner.add_channel_timeout(channel, character, expires))
+    }
+
+    fn update_channel(
+        &self,
+        channel: Cow<Channel>,
+        data: PartialChannelData,
+    ) -> Result<bool, Self::Error> {
+        self.write(self.inner.update_channel(channel, data))
+    }
+
+    fn update_character(
+        &self,
+        character: Cow<Character>,
+        data: PartialUserData,
+    ) -> Result<bool, Self::Error> {
+        self.write(self.inner.update_character(character, data))
+    }
+
+    fn update_channel_diff(
+        &self,
+        channel: Cow<Channel>,
+        data: PartialChannelData,
+    ) -> Result<Updated<ChannelData>, Self::Error> {
+        let updated = self.inner.update_channel_diff(channel, data)?;
+        self.write(Ok(updated.is_changed()))?;
+        Ok(updated)
+    }
+
+    fn update_character_diff(
+        &self,
+        character: Cow<Character>,
+        data: PartialUserData,
+    ) -> Result<Updated<CharacterData>, Self::Error> {
+        let updated = self.inner.update_character_diff(character, data)?;
+        self.write(Ok(updated.is_changed()))?;
+        Ok(updated)
+    }
+
+    fn set_friends(&self, friends: Cow<[FriendRelation]>) -> Result<bool, Self::Error> {
+        self.write(self.inner.set_friends(friends))
+    }
+
+    fn set_bookmarks(&self, bookmarks: Cow<[Character]>) -> Result<bool, Self::Error> {
+        self.write(self.inner.set_bookmarks(bookmarks))
+    }
+
+    fn set_ignored(&self, ignored: Cow<[Character]>) -> Result<bool, Self::Error> {
+        self.write(self.inner.set_ignored(ignored))
+    }
+
+    fn set_incoming_friend_requests(
+        &self,
+        requests: Cow<[PendingFriendRequest]>,
+    ) -> Result<bool, Self::Error> {
+        self.write(self.inner.set_incoming_friend_requests(requests))
+    }
+
+    fn set_outgoing_friend_requests(
+        &self,
+        requests: Cow<[PendingFriendRequest]>,
+    ) -> Result<bool, Self::Error> {
+        self.write(self.inner.set_outgoing_friend_requests(requests))
+    }
+
+    fn set_channel_members(
+        &self,
+        channel: Cow<Channel>,
+        members: Cow<[Character]>,
+    ) -> Result<bool, Self::Error> {
+        self.write(self.inner.set_channel_members(channel, members))
+    }
+
+    fn set_global_channels(
+        &self,
+        channels: Cow<[(Channel, u32)]>,
+        fetched_at: Timestamp,
+    ) -> Result<bool, Self::Error> {
+        self.write(self.inner.set_global_channels(channels, fetched_at))
+    }
+
+    fn set_unofficial_channels(
+        &self,
+        channels: Cow<[(Channel, u32)]>,
+        fetched_at: Timestamp,
+    ) -> Result<bool, Self::Error> {
+        self.write(self.inner.set_unofficial_channels(channels, fetched_at))
+    }
+
+    fn set_global_ops(&self, ops: Cow<[Character]>) -> Result<bool, Self::Error> {
+        self.write(self.inner.set_global_ops(ops))
+    }
+
+    fn set_kink_mapping(&self, mapping: CachedKinkMapping) -> Result<bool, Self::Error> {
+        self.write(self.inner.set_kink_mapping(mapping))
+    }
+
+    fn set_channel_bans(
+        &self,
+        channel: Cow<Channel>,
+        bans: Cow<[Character]>,
+    ) -> Result<bool, Self::Error> {
+        self.write(self.inner.set_channel_bans(channel, bans))
+    }
+
+    fn set_channel_ops(
+        &self,
+        channel: Cow<Channel>,
+        ops: Cow<[Character]>,
+    ) -> Result<bool, Self::Error> {
+        self.write(self.inner.set_channel_ops(channel, ops))
+    }
+
+    fn get_channel(&self, channel: &Channel) -> Result<Option<ChannelData>, Self::Error> {
+        Ok(self.inner.get_channel(channel)?)
+    }
+
+    fn get_channels(&self) -> Result<Cow<'_, [ChannelData]>, Self::Error> {
+        Ok(self.inner.get_channels()?)
+    }
+
+    fn get_character(&self, character: &Character) -> Result<Option<CharacterData>, Self::Error> {
+        Ok(self.inner.get_character(character)?)
+    }
+
+    fn get_profile(&self, character: &Character) -> Result<Option<CachedProfile>, Self::Error> {
+        Ok(self.inner.get_profile(character)?)
+    }
+
+    fn get_global_ops(&self) -> Result<Cow<'_, [Character]>, Self::Error> {
+        Ok(self.inner.get_global_ops()?)
+    }
+
+    fn get_channel_ops(&self, channel: &Channel) -> Result<Cow<'_, [Character]>, Self::Error> {
+        Ok(self.inner.get_channel_ops(channel)?)
+    }
+
+    fn get_channel_members(&self, channel: &Channel) -> Result<Cow<'_, [Character]>, Self::Error> {
+        Ok(self.inner.get_channel_members(channel)?)
+    }
+
+    fn get_channel_bans(&self, channel: &Channel) -> Result<Cow<'_, [ChannelBan]>, Self::Error> {
+        Ok(self.inner.get_channel_bans(channel)?)
+    }
+
+    fn get_ads(
+        &self,
+        channel: &Channel,
+        since: Option<Timestamp>,
+    ) -> Result<Cow<'_, [CachedAd]>, Self::Error> {
+        Ok(self.inner.get_ads(channel, since)?)
+    }
+
+    fn get_global_channels(&self) -> Result<Option<ChannelListing>, Self::Error> {
+        Ok(self.inner.get_global_channels()?)
+    }
+
+    fn get_unofficial_channels(&self) -> Result<Option<ChannelListing>, Self::Error> {
+        Ok(self.inner.get_unofficial_channels()?)
+    }
+
+    fn get_kink_mapping(&self) -> Result<Option<CachedKinkMapping>, Self::Error> {
+        Ok(self.inner.get_kink_mapping()?)
+    }
+
+    fn get_memo(&self, character: &Character) -> Result<Option<String>, Self::Error> {
+        Ok(self.inner.get_memo(character)?)
+    }
+
+    fn get_characters(&self) -> Result<Cow<'_, [CharacterData]>, Self::Error> {
+        Ok(self.inner.get_characters()?)
+    }
+
+    fn get_messages(
+        &self,
+        source: &MessageChannel,
+        since: Option<Timestamp>,
+        limit: Option<u32>,
+    ) -> Result<Cow<'_, [Message]>, Self::Error> {
+        Ok(self.inner.get_messages(source, since, limit)?)
+    }
+
+    fn get_pm_partners(
+        &self,
+        own_character: &Character,
+    ) -> Result<Cow<'_, [Character]>, Self::Error> {
+        Ok(self.inner.get_pm_partners(own_character)?)
+    }
+
+    fn get_friend_relations(&self) -> Result<Cow<'_, [FriendRelation]>, Self::Error> {
+        Ok(self.inner.get_friend_relations()?)
+    }
+
+    fn get_bookmarks(&self) -> Result<Cow<'_, [Character]>, Self::Error> {
+        Ok(self.inner.get_bookmarks()?)
+    }
+
+    fn get_reports(&self) -> Result<Cow<'_, [StaffReport]>, Self::Error> {
+        Ok(self.inner.get_reports()?)
+    }
+
+    fn get_ignored(&self) -> Result<Cow<'_, [Character]>, Self::Error> {
+        Ok(self.inner.get_ignored()?)
+    }
+
+    fn get_incoming_friend_requests(&self) -> Result<Cow<'_, [PendingFriendRequest]>, Self::Error> {
+        Ok(self.inner.get_incoming_friend_requests()?)
+    }
+
+    fn get_outgoing_friend_requests(&self) -> Result<Cow<'_, [PendingFriendRequest]>, Self::Error> {
+        Ok(self.inner.get_outgoing_friend_requests()?)
+    }
+}
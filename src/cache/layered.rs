@@ -0,0 +1,604 @@
+// Two caches stacked: every write goes to both, reads come from the front.
+// The usual setup is a MemoryCache in front of a SqliteCache, for fast reads that survive a restart.
+//
+// Writes hit the back first, so the front never has something the back failed to store.
+// Change detection comes from the front, since that's what reads see.
+// A read the front has nothing for (None, or an empty list) falls through to the back,
+// which is how history from before a restart stays reachable while the front is still empty.
+
+use std::borrow::Cow;
+
+use super::{
+    Cache, CachedAd, CachedKinkMapping, CachedProfile, ChannelBan, ChannelListing,
+    PartialChannelData, PartialUserData, Updated,
+};
+use crate::{
+    data::{
+        Channel, ChannelData, Character, CharacterData, FriendRelation, Message, MessageChannel,
+        PendingFriendRequest, StaffReport,
+    },
+    util::timestamp::Timestamp,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum LayeredCacheError<F: std::error::Error + 'static, B: std::error::Error + 'static> {
+    #[error("Error from the front cache")]
+    Front(#[source] F),
+    #[error("Error from the back cache")]
+    Back(#[source] B),
+}
+
+type LayeredResult<T, F, B> =
+    Result<T, LayeredCacheError<<F as Cache>::Error, <B as Cache>::Error>>;
+
+#[derive(Debug)]
+pub struct LayeredCache<F: Cache, B: Cache> {
+    front: F,
+    back: B,
+}
+
+impl<F: Cache, B: Cache> LayeredCache<F, B> {
+    pub fn new(front: F, back: B) -> Self {
+        LayeredCache { front, back }
+    }
+
+    pub fn front(&self) -> &F {
+        &self.front
+    }
+
+    pub fn back(&self) -> &B {
+        &self.back
+    }
+
+    pub fn into_inner(self) -> (F, B) {
+        (self.front, self.back)
+    }
+
+    // The back's write has already run by now; the front's only happens if that worked.
+    fn write<T>(
+        &self,
+        back: Result<bool, B::Error>,
+        front: impl FnOnce(&F) -> Result<T, F::Error>,
+    ) -> LayeredResult<T, F, B> {
+        back.map_err(LayeredCacheError::Back)?;
+        front(&self.front).map_err(LayeredCacheError::Front)
+    }
+
+    fn read_option<T>(
+        &self,
+        read_front: impl FnOnce(&F) -> Result<Option<T>, F::Error>,
+        read_back: impl FnOnce(&B) -> Result<Option<T>, B::Error>,
+    ) -> LayeredResult<Option<T>, F, B> {
+        match read_front(&self.front).map_err(LayeredCacheError::Front)? {
+            Some(value) => Ok(Some(value)),
+            None => read_back(&self.back).map_err(LayeredCacheError::Back),
+        }
+    }
+
+    fn read_list<'a, T: Clone>(
+        &'a self,
+        read_front: impl FnOnce(&'a F) -> Result<Cow<'a, [T]>, F::Error>,
+        read_back: impl FnOnce(&'a B) -> Result<Cow<'a, [T]>, B::Error>,
+    ) -> LayeredResult<Cow<'a, [T]>, F, B> {
+        let values = read_front(&self.front).map_err(LayeredCacheError::Front)?;
+        if !values.is_empty() {
+            return Ok(values);
+        }
+        read_back(&self.back).map_err(LayeredCacheError::Back)
+    }
+}
+
+impl<F: Cache, B: Cache> Cache for LayeredCache<F, B> {
+    type Error = LayeredCacheError<F::Error, B::Error>;
+
+    fn begin_batch(&self) -> Result<(), Self::Error> {
+        self.back.begin_batch().map_err(LayeredCacheError::Back)?;
+        self.front.begin_batch().map_err(LayeredCacheError::Front)
+    }
+
+    fn commit_batch(&self) -> Result<(), Self::Error> {
+        self.back.commit_batch().map_err(LayeredCacheError::Back)?;
+        self.front.commit_batch().map_err(LayeredCacheError::Front)
+    }
+
+    fn compact(&self) -> Result<bool, Self::Error> {
+        self.write(self.back.compact(), |front| front.compact())
+    }
+
+    fn tick(&self) -> Result<(), Self::Error> {
+        self.back.tick().map_err(LayeredCacheError::Back)?;
+        self.front.tick().map_err(LayeredCacheError::Front)
+    }
+
+    fn insert_message(
+        &self,
+        source: MessageChannel,
+        message: Message,
+    ) -> Result<bool, Self::Error> {
+        self.write(
+            self.back.insert_message(source.clone(), message.clone()),
+            |front| front.insert_message(source, message),
+        )
+    }
+
+    fn insert_channel(
+        &self,
+        channel: Cow<Channel>,
+        data: PartialChannelData,
+        members: Cow<[Character]>,
+    ) -> Result<bool, Self::Error> {
+        self.write(
+            self.back
+                .insert_channel(channel.clone(), data.clone(), members.clone()),
+            |front| front.insert_channel(channel, data, members),
+        )
+    }
+
+    fn insert_ad(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+        ad: Cow<str>,
+        timestamp: Timestamp,
+    ) -> Result<bool, Self::Error> {
+        self.write(
+            self.back
+                .insert_ad(channel.clone(), character.clone(), ad.clone(), timestamp),
+            |front| front.insert_ad(channel, character, ad, timestamp),
+        )
+    }
+
+    fn insert_report(&self, report: Cow<StaffReport>) -> Result<bool, Self::Error> {
+        self.write(self.back.insert_report(report.clone()), |front| {
+            front.insert_report(report)
+        })
+    }
+
+    fn insert_profile(
+        &self,
+        character: Cow<Character>,
+        profile: CachedProfile,
+    ) -> Result<bool, Self::Error> {
+        self.write(
+            self.back.insert_profile(character.clone(), profile.clone()),
+            |front| front.insert_profile(character, profile),
+        )
+    }
+
+    fn set_memo(&self, character: Cow<Character>, memo: Cow<str>) -> Result<bool, Self::Error> {
+        self.write(
+            self.back.set_memo(character.clone(), memo.clone()),
+            |front| front.set_memo(character, memo),
+        )
+    }
+
+    fn remove_report(&self, callid: u32) -> Result<bool, Self::Error> {
+        self.write(self.back.remove_report(callid), |front| {
+            front.remove_report(callid)
+        })
+    }
+
+    fn purge_character(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.write(self.back.purge_character(character.clone()), |front| {
+            front.purge_character(character)
+        })
+    }
+
+    fn add_channel_member(
+        &self,
+        channel: Cow<Channel>,
+        member: Character,
+    ) -> Result<bool, Self::Error> {
+        self.write(
+            self.back.add_channel_member(channel.clone(), member),
+            |front| front.add_channel_member(channel, member),
+        )
+    }
+
+    fn remove_channel_member(
+        &self,
+        channel: Cow<Channel>,
+        member: Character,
+    ) -> Result<bool, Self::Error> {
+        self.write(
+            self.back.remove_channel_member(channel.clone(), member),
+            |front| front.remove_channel_member(channel, member),
+        )
+    }
+
+    fn add_bookmark(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.write(self.back.add_bookmark(character.clone()), |front| {
+            front.add_bookmark(character)
+        })
+    }
+
+    fn remove_bookmark(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.write(self.back.remove_bookmark(character.clone()), |front| {
+            front.remove_bookmark(character)
+        })
+    }
+
+    fn add_global_op(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.write(self.back.add_global_op(character.clone()), |front| {
+            front.add_global_op(character)
+        })
+    }
+
+    fn remove_global_op(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.write(self.back.remove_global_op(character.clone()), |front| {
+            front.remove_global_op(character)
+        })
+    }
+
+    fn add_ignored(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.write(self.back.add_ignored(character.clone()), |front| {
+            front.add_ignored(character)
+        })
+    }
+
+    fn remove_ignored(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.write(self.back.remove_ignored(character.clone()), |front| {
+            front.remove_ignored(character)
+        })
+    }
+
+    fn add_channel_op(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        self.write(
+            self.back.add_channel_op(channel.clone(), character.clone()),
+            |front| front.add_channel_op(channel, character),
+        )
+    }
+
+    fn remove_channel_op(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        self.write(
+            self.back
+                .remove_channel_op(channel.clone(), character.clone()),
+            |front| front.remove_channel_op(channel, character),
+        )
+    }
+
+    fn add_channel_ban(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        self.write(
+            self.back
+                .add_channel_ban(channel.clone(), character.clone()),
+            |front| front.add_channel_ban(channel, character),
+        )
+    }
+
+    fn remove_channel_ban(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        self.write(
+            self.back
+                .remove_channel_ban(channel.clone(), character.clone()),
+            |front| front.remove_channel_ban(channel, character),
+        )
+    }
+
+    fn add_channel_timeout(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+        expires: Timestamp,
+    ) -> Result<bool, Self::Error> {
+        self.write(
+            self.back
+                .add_channel_timeout(channel.clone(), character.clone(), expires),
+            |front| front.add_channel_timeout(channel, character, expires),
+        )
+    }
+
+    fn update_channel(
+        &self,
+        channel: Cow<Channel>,
+        data: PartialChannelData,
+    ) -> Result<bool, Self::Error> {
+        self.write(
+            self.back.update_channel(channel.clone(), data.clone()),
+            |front| front.update_channel(channel, data),
+        )
+    }
+
+    fn update_character(
+        &self,
+        character: Cow<Character>,
+        data: PartialUserData,
+    ) -> Result<bool, Self::Error> {
+        self.write(
+            self.back.update_character(character.clone(), data.clone()),
+            |front| front.update_character(character, data),
+        )
+    }
+
+    fn update_channel_diff(
+        &self,
+        channel: Cow<Channel>,
+        data: PartialChannelData,
+    ) -> Result<Updated<ChannelData>, Self::Error> {
+        self.write(
+            self.back.update_channel(channel.clone(), data.clone()),
+            |front| front.update_channel_diff(channel, data),
+        )
+    }
+
+    fn update_character_diff(
+        &self,
+        character: Cow<Character>,
+        data: PartialUserData,
+    ) -> Result<Updated<CharacterData>, Self::Error> {
+        self.write(
+            self.back.update_character(character.clone(), data.clone()),
+            |front| front.update_character_diff(character, data),
+        )
+    }
+
+    fn set_friends(&self, friends: Cow<[FriendRelation]>) -> Result<bool, Self::Error> {
+        self.write(self.back.set_friends(friends.clone()), |front| {
+            front.set_friends(friends)
+        })
+    }
+
+    fn set_bookmarks(&self, bookmarks: Cow<[Character]>) -> Result<bool, Self::Error> {
+        self.write(self.back.set_bookmarks(bookmarks.clone()), |front| {
+            front.set_bookmarks(bookmarks)
+        })
+    }
+
+    fn set_ignored(&self, ignored: Cow<[Character]>) -> Result<bool, Self::Error> {
+        self.write(self.back.set_ignored(ignored.clone()), |front| {
+            front.set_ignored(ignored)
+        })
+    }
+
+    fn set_incoming_friend_requests(
+        &self,
+        requests: Cow<[PendingFriendRequest]>,
+    ) -> Result<bool, Self::Error> {
+        self.write(
+            self.back.set_incoming_friend_requests(requests.clone()),
+            |front| front.set_incoming_friend_requests(requests),
+        )
+    }
+
+    fn set_outgoing_friend_requests(
+        &self,
+        requests: Cow<[PendingFriendRequest]>,
+    ) -> Result<bool, Self::Error> {
+        self.write(
+            self.back.set_outgoing_friend_requests(requests.clone()),
+            |front| front.set_outgoing_friend_requests(requests),
+        )
+    }
+
+    fn set_channel_members(
+        &self,
+        channel: Cow<Channel>,
+        members: Cow<[Character]>,
+    ) -> Result<bool, Self::Error> {
+        self.write(
+            self.back
+                .set_channel_members(channel.clone(), members.clone()),
+            |front| front.set_channel_members(channel, members),
+        )
+    }
+
+    fn set_global_channels(
+        &self,
+        channels: Cow<[(Channel, u32)]>,
+        fetched_at: Timestamp,
+    ) -> Result<bool, Self::Error> {
+        self.write(
+            self.back.set_global_channels(channels.clone(), fetched_at),
+            |front| front.set_global_channels(channels, fetched_at),
+        )
+    }
+
+    fn set_unofficial_channels(
+        &self,
+        channels: Cow<[(Channel, u32)]>,
+        fetched_at: Timestamp,
+    ) -> Result<bool, Self::Error> {
+        self.write(
+            self.back
+                .set_unofficial_channels(channels.clone(), fetched_at),
+            |front| front.set_unofficial_channels(channels, fetched_at),
+        )
+    }
+
+    fn set_global_ops(&self, ops: Cow<[Character]>) -> Result<bool, Self::Error> {
+        self.write(self.back.set_global_ops(ops.clone()), |front| {
+            front.set_global_ops(ops)
+        })
+    }
+
+    fn set_kink_mapping(&self, mapping: CachedKinkMapping) -> Result<bool, Self::Error> {
+        self.write(self.back.set_kink_mapping(mapping.clone()), |front| {
+            front.set_kink_mapping(mapping)
+        })
+    }
+
+    fn set_channel_bans(
+        &self,
+        channel: Cow<Channel>,
+        bans: Cow<[Character]>,
+    ) -> Result<bool, Self::Error> {
+        self.write(
+            self.back.set_channel_bans(channel.clone(), bans.clone()),
+            |front| front.set_channel_bans(channel, bans),
+        )
+    }
+
+    fn set_channel_ops(
+        &self,
+        channel: Cow<Channel>,
+        ops: Cow<[Character]>,
+    ) -> Result<bool, Self::Error> {
+        self.write(
+            self.back.set_channel_ops(channel.clone(), ops.clone()),
+            |front| front.set_channel_ops(channel, ops),
+        )
+    }
+
+    fn get_channel(&self, channel: &Channel) -> Result<Option<ChannelData>, Self::Error> {
+        self.read_option(
+            |front| front.get_channel(channel),
+            |back| back.get_channel(channel),
+        )
+    }
+
+    fn get_channels(&self) -> Result<Cow<'_, [ChannelData]>, Self::Error> {
+        self.read_list(F::get_channels, B::get_channels)
+    }
+
+    fn get_character(&self, character: &Character) -> Result<Option<CharacterData>, Self::Error> {
+        self.read_option(
+            |front| front.get_character(character),
+            |back| back.get_character(character),
+        )
+    }
+
+    fn get_profile(&self, character: &Character) -> Result<Option<CachedProfile>, Self::Error> {
+        self.read_option(
+            |front| front.get_profile(character),
+            |back| back.get_profile(character),
+        )
+    }
+
+    fn get_global_ops(&self) -> Result<Cow<'_, [Character]>, Self::Error> {
+        self.read_list(F::get_global_ops, B::get_global_ops)
+    }
+
+    fn get_channel_ops(&self, channel: &Channel) -> Result<Cow<'_, [Character]>, Self::Error> {
+        self.read_list(
+            |front| front.get_channel_ops(channel),
+            |back| back.get_channel_ops(channel),
+        )
+    }
+
+    fn get_channel_members(&self, channel: &Channel) -> Result<Cow<'_, [Character]>, Self::Error> {
+        self.read_list(
+            |front| front.get_channel_members(channel),
+            |back| back.get_channel_members(channel),
+        )
+    }
+
+    fn get_channel_bans(&self, channel: &Channel) -> Result<Cow<'_, [ChannelBan]>, Self::Error> {
+        self.read_list(
+            |front| front.get_channel_bans(channel),
+            |back| back.get_channel_bans(channel),
+        )
+    }
+
+    fn get_ads(
+        &self,
+        channel: &Channel,
+        since: Option<Timestamp>,
+    ) -> Result<Cow<'_, [CachedAd]>, Self::Error> {
+        self.read_list(
+            |front| front.get_ads(channel, since),
+            |back| back.get_ads(channel, since),
+        )
+    }
+
+    fn get_global_channels(&self) -> Result<Option<ChannelListing>, Self::Error> {
+        self.read_option(F::get_global_channels, B::get_global_channels)
+    }
+
+    fn get_unofficial_channels(&self) -> Result<Option<ChannelListing>, Self::Error> {
+        self.read_option(F::get_unofficial_channels, B::get_unofficial_channels)
+    }
+
+    fn get_kink_mapping(&self) -> Result<Option<CachedKinkMapping>, Self::Error> {
+        self.read_option(F::get_kink_mapping, B::get_kink_mapping)
+    }
+
+    fn get_memo(&self, character: &Character) -> Result<Option<String>, Self::Error> {
+        self.read_option(
+            |front| front.get_memo(character),
+            |back| back.get_memo(character),
+        )
+    }
+
+    fn get_characters(&self) -> Result<Cow<'_, [CharacterData]>, Self::Error> {
+        self.read_list(F::get_characters, B::get_characters)
+    }
+
+    fn get_messages(
+        &self,
+        source: &MessageChannel,
+        since: Option<Timestamp>,
+        limit: Option<u32>,
+    ) -> Result<Cow<'_, [Message]>, Self::Error> {
+        self.read_list(
+            |front| front.get_messages(source, since, limit),
+            |back| back.get_messages(source, since, limit),
+        )
+    }
+
+    fn get_private_messages(
+        &self,
+        own_character: &Character,
+        other: &Character,
+        since: Option<Timestamp>,
+        limit: Option<u32>,
+    ) -> Result<Cow<'_, [Message]>, Self::Error> {
+        self.read_list(
+            |front| front.get_private_messages(own_character, other, since, limit),
+            |back| back.get_private_messages(own_character, other, since, limit),
+        )
+    }
+
+    fn get_pm_partners(
+        &self,
+        own_character: &Character,
+    ) -> Result<Cow<'_, [Character]>, Self::Error> {
+        self.read_list(
+            |front| front.get_pm_partners(own_character),
+            |back| back.get_pm_partners(own_character),
+        )
+    }
+
+    fn get_friend_relations(&self) -> Result<Cow<'_, [FriendRelation]>, Self::Error> {
+        self.read_list(F::get_friend_relations, B::get_friend_relations)
+    }
+
+    fn get_bookmarks(&self) -> Result<Cow<'_, [Character]>, Self::Error> {
+        self.read_list(F::get_bookmarks, B::get_bookmarks)
+    }
+
+    fn get_reports(&self) -> Result<Cow<'_, [StaffReport]>, Self::Error> {
+        self.read_list(F::get_reports, B::get_reports)
+    }
+
+    fn get_ignored(&self) -> Result<Cow<'_, [Character]>, Self::Error> {
+        self.read_list(F::get_ignored, B::get_ignored)
+    }
+
+    fn get_incoming_friend_requests(&self) -> Result<Cow<'_, [PendingFriendRequest]>, Self::Error> {
+        self.read_list(
+            F::get_incoming_friend_requests,
+            B::get_incoming_friend_requests,
+        )
+    }
+
+    fn get_outgoing_friend_requests(&self) -> Result<Cow<'_, [PendingFriendRequest]>, Self::Error> {
+        self.read_list(
+            F::get_outgoing_friend_requests,
+            B::get_outgoing_friend_requests,
+        )
+    }
+}
@@ -0,0 +1,1007 @@
+// Plain in-memory cache. Everything is gone when the process exits, but the getters actually work,
+// and updates only report a change when something really changed.
+
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use chrono::Utc;
+use dashmap::{mapref::entry::Entry, DashMap};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    apply_channel_data, apply_user_data, fill_channel_data,
+    retention::{EvictionHook, Retention, RetentionClass, RetentionPolicy},
+    Cache, CachedAd, CachedKinkMapping, CachedProfile, ChannelBan, ChannelListing,
+    PartialChannelData, PartialUserData, Updated,
+};
+use crate::{
+    data::{
+        Channel, ChannelData, Character, CharacterData, FriendRelation, Message, MessageChannel,
+        PendingFriendRequest, StaffReport,
+    },
+    util::timestamp::Timestamp,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum MemoryCacheError {} // Nothing in here can fail
+
+// Everything a MemoryCache holds, in a form that serializes. For JsonFileCache.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct MemoryState {
+    channels: Vec<ChannelData>,
+    channel_ops: HashMap<Channel, Vec<Character>>,
+    characters: Vec<CharacterData>,
+    bans: HashMap<Channel, Vec<ChannelBan>>,
+    messages: Vec<(MessageChannel, Vec<Message>)>, // Not a map, JSON keys have to be strings
+    ads: HashMap<Channel, Vec<CachedAd>>,
+    reports: Vec<StaffReport>,
+    profiles: HashMap<Character, CachedProfile>,
+    memos: HashMap<Character, String>,
+    global_ops: HashSet<Character>,
+    bookmarks: HashSet<Character>,
+    ignored: HashSet<Character>,
+    friends: HashSet<FriendRelation>,
+    incoming_requests: HashSet<PendingFriendRequest>,
+    outgoing_requests: HashSet<PendingFriendRequest>,
+    global_channels: Option<ChannelListing>,
+    unofficial_channels: Option<ChannelListing>,
+    // Was kink_mapping, before it came with a fetch time. An old one is left behind and refetched.
+    mapping: Option<CachedKinkMapping>,
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryCache {
+    channels: DashMap<Channel, ChannelData>,
+    channel_ops: DashMap<Channel, Vec<Character>>, // In COL order; the first one is the owner
+    characters: DashMap<Character, CharacterData>,
+    bans: DashMap<Channel, HashMap<Character, Option<Timestamp>>>, // Expiry, for timeouts
+    messages: DashMap<MessageChannel, Vec<Message>>,
+    ads: DashMap<Channel, HashMap<Character, CachedAd>>, // The latest ad from each character
+    reports: RwLock<HashMap<u32, StaffReport>>,
+    profiles: DashMap<Character, CachedProfile>,
+    memos: DashMap<Character, String>,
+    global_ops: RwLock<HashSet<Character>>,
+    bookmarks: RwLock<HashSet<Character>>,
+    ignored: RwLock<HashSet<Character>>,
+    friends: RwLock<HashSet<FriendRelation>>,
+    incoming_requests: RwLock<HashSet<PendingFriendRequest>>,
+    outgoing_requests: RwLock<HashSet<PendingFriendRequest>>,
+    global_channels: RwLock<Option<ChannelListing>>,
+    unofficial_channels: RwLock<Option<ChannelListing>>,
+    kink_mapping: RwLock<Option<CachedKinkMapping>>,
+    retention: RetentionPolicy,
+    character_limit: Option<usize>,
+    character_clock: AtomicU64,
+    character_used: DashMap<Character, u64>, // Clock reading at last use; only kept with a limit
+}
+
+impl MemoryCache {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Limit every message history. Keeps everything by default.
+    pub fn with_retention(mut self, retention: Retention) -> Self {
+        self.retention.default = retention;
+        self
+    }
+
+    /// Limit one message history, overriding `with_retention` for it.
+    pub fn with_source_retention(mut self, source: MessageChannel, retention: Retention) -> Self {
+        self.retention.sources.insert(source, retention);
+        self
+    }
+
+    /// Limit every history of one class, overriding `with_retention` for them.
+    pub fn with_class_retention(mut self, class: RetentionClass, retention: Retention) -> Self {
+        self.retention.classes.insert(class, retention);
+        self
+    }
+
+    /// Drop ads once they're older than `max_age`. Kept until they're replaced by default.
+    pub fn with_ad_retention(mut self, max_age: Duration) -> Self {
+        self.retention.ads = Some(max_age);
+        self
+    }
+
+    /// Cap how many characters are tracked, forgetting the least recently used ones first.
+    /// Friends, bookmarks and anyone in a channel we know about are never forgotten,
+    /// so the cap can be exceeded if there are more of them than that.
+    pub fn with_character_limit(mut self, limit: usize) -> Self {
+        self.character_limit = Some(limit);
+        self
+    }
+
+    /// Hear about messages as they're dropped for being over the limits.
+    pub fn with_eviction_hook(mut self, hook: impl EvictionHook + 'static) -> Self {
+        self.retention.hook = Some(Arc::new(hook));
+        self
+    }
+}
+
+impl MemoryCache {
+    // Copies everything out. Not atomic across the maps, which is fine for a cache.
+    pub(crate) fn state(&self) -> MemoryState {
+        fn collect<K: Clone + Eq + Hash, V: Clone>(map: &DashMap<K, V>) -> HashMap<K, V> {
+            map.iter()
+                .map(|entry| (entry.key().clone(), entry.value().clone()))
+                .collect()
+        }
+        MemoryState {
+            channels: self.channels.iter().map(|entry| entry.clone()).collect(),
+            channel_ops: collect(&self.channel_ops),
+            characters: self.characters.iter().map(|entry| entry.clone()).collect(),
+            bans: self
+                .bans
+                .iter()
+                .map(|entry| {
+                    let bans = entry
+                        .iter()
+                        .map(|(character, expires)| ChannelBan {
+                            character: *character,
+                            expires: *expires,
+                        })
+                        .collect();
+                    (entry.key().clone(), bans)
+                })
+                .collect(),
+            messages: self
+                .messages
+                .iter()
+                .map(|entry| (entry.key().clone(), entry.value().clone()))
+                .collect(),
+            ads: self
+                .ads
+                .iter()
+                .map(|entry| (entry.key().clone(), entry.values().cloned().collect()))
+                .collect(),
+            reports: self.reports.read().values().cloned().collect(),
+            profiles: collect(&self.profiles),
+            memos: self
+                .memos
+                .iter()
+                .map(|entry| (*entry.key(), entry.value().clone()))
+                .collect(),
+            global_ops: self.global_ops.read().clone(),
+            bookmarks: self.bookmarks.read().clone(),
+            ignored: self.ignored.read().clone(),
+            friends: self.friends.read().clone(),
+            incoming_requests: self.incoming_requests.read().clone(),
+            outgoing_requests: self.outgoing_requests.read().clone(),
+            global_channels: self.global_channels.read().clone(),
+            unofficial_channels: self.unofficial_channels.read().clone(),
+            mapping: self.kink_mapping.read().clone(),
+        }
+    }
+
+    // Replaces everything with what's in `state`. Retention and the character limit catch up
+    // as new things come in.
+    pub(crate) fn restore(&self, state: MemoryState) {
+        fn fill<K: Eq + Hash, V>(map: &DashMap<K, V>, items: impl IntoIterator<Item = (K, V)>) {
+            map.clear();
+            for (key, value) in items {
+                map.insert(key, value);
+            }
+        }
+        fill(
+            &self.channels,
+            state
+                .channels
+                .into_iter()
+                .map(|data| (data.channel.clone(), data)),
+        );
+        fill(&self.channel_ops, state.channel_ops);
+        fill(
+            &self.characters,
+            state
+                .characters
+                .into_iter()
+                .map(|data| (data.character, data)),
+        );
+        fill(
+            &self.bans,
+            state.bans.into_iter().map(|(channel, bans)| {
+                let bans = bans.into_iter().map(|ban| (ban.character, ban.expires));
+                (channel, bans.collect())
+            }),
+        );
+        fill(&self.messages, state.messages);
+        fill(
+            &self.ads,
+            state.ads.into_iter().map(|(channel, ads)| {
+                let ads = ads.into_iter().map(|ad| (ad.character, ad));
+                (channel, ads.collect())
+            }),
+        );
+        *self.reports.write() = state
+            .reports
+            .into_iter()
+            .map(|report| (report.callid, report))
+            .collect();
+        fill(&self.profiles, state.profiles);
+        fill(&self.memos, state.memos);
+        *self.global_ops.write() = state.global_ops;
+        *self.bookmarks.write() = state.bookmarks;
+        *self.ignored.write() = state.ignored;
+        *self.friends.write() = state.friends;
+        *self.incoming_requests.write() = state.incoming_requests;
+        *self.outgoing_requests.write() = state.outgoing_requests;
+        *self.global_channels.write() = state.global_channels;
+        *self.unofficial_channels.write() = state.unofficial_channels;
+        *self.kink_mapping.write() = state.mapping;
+    }
+}
+
+// Replace a whole set, and say whether it's any different from before.
+fn replace_set<T: Eq + Hash + Copy>(set: &RwLock<HashSet<T>>, items: &[T]) -> bool {
+    let items = items.iter().copied().collect::<HashSet<_>>();
+    let mut set = set.write();
+    if *set == items {
+        false
+    } else {
+        *set = items;
+        true
+    }
+}
+
+impl MemoryCache {
+    fn read_channel(&self, data: &ChannelData) -> ChannelData {
+        let mut data = data.clone();
+        let ops = self.channel_ops.get(&data.channel);
+        fill_channel_data(&mut data, ops.as_deref().map_or(&[], |ops| &ops[..]));
+        data
+    }
+
+    fn touch_channel(&self, channel: &Channel, at: Timestamp) {
+        if let Some(mut data) = self.channels.get_mut(channel) {
+            data.last_activity = data.last_activity.max(Some(at));
+        }
+    }
+
+    fn touch_character(&self, character: Character) {
+        if self.character_limit.is_some() {
+            let now = self.character_clock.fetch_add(1, Ordering::Relaxed);
+            self.character_used.insert(character, now);
+        }
+    }
+
+    // Called after adding someone. Trims a tenth below the limit at a time,
+    // so this doesn't run (and sort everyone) for every new arrival.
+    fn evict_characters(&self) {
+        let Some(limit) = self.character_limit else {
+            return;
+        };
+        if self.characters.len() <= limit {
+            return;
+        }
+        let mut keep = self.bookmarks.read().clone();
+        keep.extend(
+            self.friends
+                .read()
+                .iter()
+                .flat_map(|relation| [relation.own_character, relation.other_character]),
+        );
+        for channel in self.channels.iter() {
+            keep.extend(channel.members.iter().copied());
+        }
+        let mut candidates = self
+            .characters
+            .iter()
+            .map(|entry| *entry.key())
+            .filter(|character| !keep.contains(character))
+            .map(|character| {
+                let used = self.character_used.get(&character).map_or(0, |used| *used);
+                (used, character)
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_unstable_by_key(|(used, _)| *used);
+        let target = limit - limit / 10;
+        let excess = self.characters.len().saturating_sub(target);
+        for (_, character) in candidates.into_iter().take(excess) {
+            self.characters.remove(&character);
+            self.character_used.remove(&character);
+        }
+    }
+}
+
+fn new_channel(channel: Channel) -> ChannelData {
+    ChannelData {
+        channel,
+        ..Default::default()
+    }
+}
+
+impl Cache for MemoryCache {
+    type Error = MemoryCacheError;
+
+    fn compact(&self) -> Result<bool, Self::Error> {
+        let now = Utc::now();
+        let mut dropped = false;
+        let sources = self
+            .messages
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect::<Vec<_>>();
+        for source in sources {
+            let evicted = match self.messages.get_mut(&source) {
+                Some(mut messages) => self.retention.trim(&source, &mut messages, now),
+                None => continue,
+            };
+            dropped |= !evicted.is_empty();
+            self.retention.notify(source, evicted);
+        }
+        if let Some(cutoff) = self.retention.ad_cutoff(now) {
+            for mut ads in self.ads.iter_mut() {
+                let before = ads.len();
+                ads.retain(|_, ad| ad.timestamp >= cutoff);
+                dropped |= ads.len() != before;
+            }
+        }
+        Ok(dropped)
+    }
+
+    fn insert_message(
+        &self,
+        source: MessageChannel,
+        message: Message,
+    ) -> Result<bool, Self::Error> {
+        if let MessageChannel::Channel(channel) = &source {
+            self.touch_channel(channel, message.timestamp);
+        }
+        let evicted = {
+            let mut messages = self.messages.entry(source.clone()).or_default();
+            messages.push(message);
+            self.retention.trim(&source, &mut messages, Utc::now())
+        };
+        // Not while holding the entry, in case the hook reads the cache back.
+        self.retention.notify(source, evicted);
+        Ok(true)
+    }
+
+    fn insert_channel(
+        &self,
+        channel: Cow<Channel>,
+        data: PartialChannelData,
+        members: Cow<[Character]>,
+    ) -> Result<bool, Self::Error> {
+        let channel = channel.into_owned();
+        let mut entry = self
+            .channels
+            .entry(channel.clone())
+            .or_insert_with(|| new_channel(channel));
+        let changed = apply_channel_data(&mut entry, data);
+        if entry.members[..] != members[..] {
+            entry.members = members.into_owned();
+            return Ok(true);
+        }
+        Ok(changed)
+    }
+
+    fn insert_ad(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+        ad: Cow<str>,
+        timestamp: Timestamp,
+    ) -> Result<bool, Self::Error> {
+        self.touch_channel(&channel, timestamp);
+        // Every ad is a new post, even if it says the same thing as last time.
+        let character = character.into_owned();
+        let mut ads = self.ads.entry(channel.into_owned()).or_default();
+        ads.insert(
+            character,
+            CachedAd {
+                character,
+                ad: ad.into_owned(),
+                timestamp,
+            },
+        );
+        if let Some(cutoff) = self.retention.ad_cutoff(Utc::now()) {
+            ads.retain(|_, ad| ad.timestamp >= cutoff);
+        }
+        Ok(true)
+    }
+
+    fn insert_report(&self, report: Cow<StaffReport>) -> Result<bool, Self::Error> {
+        let report = report.into_owned();
+        let previous = self.reports.write().insert(report.callid, report.clone());
+        Ok(previous.as_ref() != Some(&report))
+    }
+
+    fn insert_profile(
+        &self,
+        character: Cow<Character>,
+        profile: CachedProfile,
+    ) -> Result<bool, Self::Error> {
+        // Profiles don't compare; a fresh fetch always counts as an update.
+        self.profiles.insert(character.into_owned(), profile);
+        Ok(true)
+    }
+
+    fn set_memo(&self, character: Cow<Character>, memo: Cow<str>) -> Result<bool, Self::Error> {
+        let previous = self
+            .memos
+            .insert(character.into_owned(), memo.clone().into_owned());
+        Ok(previous.as_deref() != Some(&*memo))
+    }
+
+    fn remove_report(&self, callid: u32) -> Result<bool, Self::Error> {
+        Ok(self.reports.write().remove(&callid).is_some())
+    }
+
+    fn purge_character(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        let character = *character;
+        let mut purged = false;
+        self.messages.retain(|source, messages| {
+            if let MessageChannel::PrivateMessage(own, other) = source {
+                if *own == character || *other == character {
+                    purged |= !messages.is_empty();
+                    return false;
+                }
+            }
+            let before = messages.len();
+            messages.retain(|message| message.character != character);
+            purged |= messages.len() != before;
+            true
+        });
+        for mut ads in self.ads.iter_mut() {
+            purged |= ads.remove(&character).is_some();
+        }
+        purged |= self.memos.remove(&character).is_some();
+        purged |= self.profiles.remove(&character).is_some();
+        Ok(purged)
+    }
+
+    fn add_channel_member(
+        &self,
+        channel: Cow<Channel>,
+        member: Character,
+    ) -> Result<bool, Self::Error> {
+        let channel = channel.into_owned();
+        let mut entry = self
+            .channels
+            .entry(channel.clone())
+            .or_insert_with(|| new_channel(channel));
+        if entry.members.contains(&member) {
+            Ok(false)
+        } else {
+            entry.members.push(member);
+            Ok(true)
+        }
+    }
+
+    fn remove_channel_member(
+        &self,
+        channel: Cow<Channel>,
+        member: Character,
+    ) -> Result<bool, Self::Error> {
+        let Some(mut entry) = self.channels.get_mut(&channel) else {
+            return Ok(false);
+        };
+        let len = entry.members.len();
+        entry.members.retain(|c| *c != member);
+        Ok(entry.members.len() != len)
+    }
+
+    fn add_bookmark(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        Ok(self.bookmarks.write().insert(character.into_owned()))
+    }
+
+    fn remove_bookmark(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        Ok(self.bookmarks.write().remove(&character))
+    }
+
+    fn add_global_op(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        Ok(self.global_ops.write().insert(character.into_owned()))
+    }
+
+    fn remove_global_op(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        Ok(self.global_ops.write().remove(&character))
+    }
+
+    fn add_ignored(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        Ok(self.ignored.write().insert(character.into_owned()))
+    }
+
+    fn remove_ignored(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        Ok(self.ignored.write().remove(&character))
+    }
+
+    fn add_channel_op(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        let mut ops = self.channel_ops.entry(channel.into_owned()).or_default();
+        if ops.contains(&character) {
+            Ok(false)
+        } else {
+            ops.push(character.into_owned());
+            Ok(true)
+        }
+    }
+
+    fn remove_channel_op(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        let Some(mut ops) = self.channel_ops.get_mut(&channel) else {
+            return Ok(false);
+        };
+        let len = ops.len();
+        ops.retain(|c| *c != *character);
+        Ok(ops.len() != len)
+    }
+
+    fn add_channel_ban(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        let previous = self
+            .bans
+            .entry(channel.into_owned())
+            .or_default()
+            .insert(character.into_owned(), None);
+        Ok(previous != Some(None))
+    }
+
+    fn remove_channel_ban(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        Ok(self
+            .bans
+            .get_mut(&channel)
+            .is_some_and(|mut bans| bans.remove(&character).is_some()))
+    }
+
+    fn add_channel_timeout(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+        expires: Timestamp,
+    ) -> Result<bool, Self::Error> {
+        let previous = self
+            .bans
+            .entry(channel.into_owned())
+            .or_default()
+            .insert(character.into_owned(), Some(expires));
+        Ok(previous != Some(Some(expires)))
+    }
+
+    fn update_channel(
+        &self,
+        channel: Cow<Channel>,
+        data: PartialChannelData,
+    ) -> Result<bool, Self::Error> {
+        let channel = channel.into_owned();
+        let mut entry = self
+            .channels
+            .entry(channel.clone())
+            .or_insert_with(|| new_channel(channel));
+        Ok(apply_channel_data(&mut entry, data))
+    }
+
+    fn update_character(
+        &self,
+        character: Cow<Character>,
+        data: PartialUserData,
+    ) -> Result<bool, Self::Error> {
+        let character = character.into_owned();
+        let mut added = false;
+        let changed = {
+            let mut entry = self.characters.entry(character).or_insert_with(|| {
+                added = true; // Hearing about someone new is a change in itself
+                CharacterData {
+                    character,
+                    ..Default::default()
+                }
+            });
+            apply_user_data(&mut entry, data) || added
+        };
+        self.touch_character(character);
+        if added {
+            self.evict_characters();
+        }
+        Ok(changed)
+    }
+
+    fn update_channel_diff(
+        &self,
+        channel: Cow<Channel>,
+        data: PartialChannelData,
+    ) -> Result<Updated<ChannelData>, Self::Error> {
+        let channel = channel.into_owned();
+        let (mut entry, previous) = match self.channels.entry(channel.clone()) {
+            Entry::Occupied(entry) => {
+                let previous = self.read_channel(entry.get());
+                (entry.into_ref(), Some(previous))
+            }
+            Entry::Vacant(entry) => (entry.insert(new_channel(channel)), None),
+        };
+        Ok(match apply_channel_data(&mut entry, data) {
+            true => Updated::Changed(previous),
+            false => Updated::Unchanged,
+        })
+    }
+
+    fn update_character_diff(
+        &self,
+        character: Cow<Character>,
+        data: PartialUserData,
+    ) -> Result<Updated<CharacterData>, Self::Error> {
+        let character = character.into_owned();
+        let (changed, previous) = {
+            let (mut entry, previous) = match self.characters.entry(character) {
+                Entry::Occupied(entry) => {
+                    let previous = entry.get().clone();
+                    (entry.into_ref(), Some(previous))
+                }
+                Entry::Vacant(entry) => {
+                    let data = CharacterData {
+                        character,
+                        ..Default::default()
+                    };
+                    (entry.insert(data), None)
+                }
+            };
+            (apply_user_data(&mut entry, data), previous)
+        };
+        self.touch_character(character);
+        if previous.is_none() {
+            self.evict_characters();
+        }
+        // Same as update_character, someone new is a change whatever the data says.
+        Ok(match changed || previous.is_none() {
+            true => Updated::Changed(previous),
+            false => Updated::Unchanged,
+        })
+    }
+
+    fn set_friends(&self, friends: Cow<[FriendRelation]>) -> Result<bool, Self::Error> {
+        Ok(replace_set(&self.friends, &friends))
+    }
+
+    fn set_bookmarks(&self, bookmarks: Cow<[Character]>) -> Result<bool, Self::Error> {
+        Ok(replace_set(&self.bookmarks, &bookmarks))
+    }
+
+    fn set_ignored(&self, ignored: Cow<[Character]>) -> Result<bool, Self::Error> {
+        Ok(replace_set(&self.ignored, &ignored))
+    }
+
+    fn set_incoming_friend_requests(
+        &self,
+        requests: Cow<[PendingFriendRequest]>,
+    ) -> Result<bool, Self::Error> {
+        Ok(replace_set(&self.incoming_requests, &requests))
+    }
+
+    fn set_outgoing_friend_requests(
+        &self,
+        requests: Cow<[PendingFriendRequest]>,
+    ) -> Result<bool, Self::Error> {
+        Ok(replace_set(&self.outgoing_requests, &requests))
+    }
+
+    fn set_channel_members(
+        &self,
+        channel: Cow<Channel>,
+        members: Cow<[Character]>,
+    ) -> Result<bool, Self::Error> {
+        let channel = channel.into_owned();
+        let mut entry = self
+            .channels
+            .entry(channel.clone())
+            .or_insert_with(|| new_channel(channel));
+        if entry.members[..] == members[..] {
+            Ok(false)
+        } else {
+            entry.members = members.into_owned();
+            Ok(true)
+        }
+    }
+
+    fn set_global_channels(
+        &self,
+        channels: Cow<[(Channel, u32)]>,
+        fetched_at: Timestamp,
+    ) -> Result<bool, Self::Error> {
+        let mut listing = self.global_channels.write();
+        // Always take the new fetch time, but only call it a change if the list is different.
+        let changed = listing
+            .as_ref()
+            .is_none_or(|old| old.channels[..] != channels[..]);
+        *listing = Some(ChannelListing {
+            channels: channels.into_owned(),
+            fetched_at,
+        });
+        Ok(changed)
+    }
+
+    fn set_unofficial_channels(
+        &self,
+        channels: Cow<[(Channel, u32)]>,
+        fetched_at: Timestamp,
+    ) -> Result<bool, Self::Error> {
+        let mut listing = self.unofficial_channels.write();
+        let changed = listing
+            .as_ref()
+            .is_none_or(|old| old.channels[..] != channels[..]);
+        *listing = Some(ChannelListing {
+            channels: channels.into_owned(),
+            fetched_at,
+        });
+        Ok(changed)
+    }
+
+    fn set_global_ops(&self, ops: Cow<[Character]>) -> Result<bool, Self::Error> {
+        Ok(replace_set(&self.global_ops, &ops))
+    }
+
+    fn set_channel_bans(
+        &self,
+        channel: Cow<Channel>,
+        bans: Cow<[Character]>,
+    ) -> Result<bool, Self::Error> {
+        let now = Utc::now();
+        let mut entry = self.bans.entry(channel.into_owned()).or_default();
+        let new = bans
+            .iter()
+            .map(|character| {
+                let expires = entry
+                    .get(character)
+                    .copied()
+                    .flatten()
+                    .filter(|expires| *expires > now);
+                (*character, expires)
+            })
+            .collect::<HashMap<_, _>>();
+        if *entry == new {
+            Ok(false)
+        } else {
+            *entry = new;
+            Ok(true)
+        }
+    }
+
+    fn set_channel_ops(
+        &self,
+        channel: Cow<Channel>,
+        ops: Cow<[Character]>,
+    ) -> Result<bool, Self::Error> {
+        let previous = self.channel_ops.insert(channel.into_owned(), ops.to_vec());
+        Ok(previous.as_deref() != Some(&ops[..]))
+    }
+
+    fn get_channel(&self, channel: &Channel) -> Result<Option<ChannelData>, Self::Error> {
+        Ok(self
+            .channels
+            .get(channel)
+            .map(|data| self.read_channel(&data)))
+    }
+
+    fn get_channels(&self) -> Result<Cow<'_, [ChannelData]>, Self::Error> {
+        Ok(self
+            .channels
+            .iter()
+            .map(|data| self.read_channel(&data))
+            .collect::<Vec<_>>()
+            .into())
+    }
+
+    fn get_character(&self, character: &Character) -> Result<Option<CharacterData>, Self::Error> {
+        let data = self.characters.get(character).map(|data| data.clone());
+        if data.is_some() {
+            self.touch_character(*character);
+        }
+        Ok(data)
+    }
+
+    fn get_profile(&self, character: &Character) -> Result<Option<CachedProfile>, Self::Error> {
+        Ok(self.profiles.get(character).map(|profile| profile.clone()))
+    }
+
+    fn set_kink_mapping(&self, mapping: CachedKinkMapping) -> Result<bool, Self::Error> {
+        // Same as profiles, not worth comparing.
+        *self.kink_mapping.write() = Some(mapping);
+        Ok(true)
+    }
+
+    fn get_kink_mapping(&self) -> Result<Option<CachedKinkMapping>, Self::Error> {
+        Ok(self.kink_mapping.read().clone())
+    }
+
+    fn get_global_ops(&self) -> Result<Cow<'_, [Character]>, Self::Error> {
+        Ok(self
+            .global_ops
+            .read()
+            .iter()
+            .copied()
+            .collect::<Vec<_>>()
+            .into())
+    }
+
+    fn get_channel_ops(&self, channel: &Channel) -> Result<Cow<'_, [Character]>, Self::Error> {
+        Ok(self
+            .channel_ops
+            .get(channel)
+            .map(|ops| ops.clone())
+            .unwrap_or_default()
+            .into())
+    }
+
+    fn get_channel_members(&self, channel: &Channel) -> Result<Cow<'_, [Character]>, Self::Error> {
+        Ok(self
+            .channels
+            .get(channel)
+            .map(|data| data.members.clone())
+            .unwrap_or_default()
+            .into())
+    }
+
+    fn get_channel_bans(&self, channel: &Channel) -> Result<Cow<'_, [ChannelBan]>, Self::Error> {
+        let now = Utc::now();
+        let mut bans = self
+            .bans
+            .get(channel)
+            .map(|bans| {
+                bans.iter()
+                    .map(|(character, expires)| ChannelBan {
+                        character: *character,
+                        expires: *expires,
+                    })
+                    .filter(|ban| ban.in_effect(now))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        bans.sort_by_key(|ban| ban.character);
+        Ok(bans.into())
+    }
+
+    fn get_ads(
+        &self,
+        channel: &Channel,
+        since: Option<Timestamp>,
+    ) -> Result<Cow<'_, [CachedAd]>, Self::Error> {
+        let Some(ads) = self.ads.get(channel) else {
+            return Ok(Vec::new().into());
+        };
+        let since = self.retention.ads_since(since, Utc::now());
+        let mut ads = ads
+            .values()
+            .filter(|ad| since.is_none_or(|since| ad.timestamp >= since))
+            .cloned()
+            .collect::<Vec<_>>();
+        ads.sort_by_key(|ad| ad.timestamp);
+        Ok(ads.into())
+    }
+
+    fn get_global_channels(&self) -> Result<Option<ChannelListing>, Self::Error> {
+        Ok(self.global_channels.read().clone())
+    }
+
+    fn get_unofficial_channels(&self) -> Result<Option<ChannelListing>, Self::Error> {
+        Ok(self.unofficial_channels.read().clone())
+    }
+
+    fn get_memo(&self, character: &Character) -> Result<Option<String>, Self::Error> {
+        Ok(self.memos.get(character).map(|memo| memo.clone()))
+    }
+
+    fn get_characters(&self) -> Result<Cow<'_, [CharacterData]>, Self::Error> {
+        Ok(self
+            .characters
+            .iter()
+            .map(|data| data.clone())
+            .collect::<Vec<_>>()
+            .into())
+    }
+
+    // Yields the newest `limit` messages at or after `since`, oldest first.
+    fn get_messages(
+        &self,
+        source: &MessageChannel,
+        since: Option<Timestamp>,
+        limit: Option<u32>,
+    ) -> Result<Cow<'_, [Message]>, Self::Error> {
+        let Some(messages) = self.messages.get(source) else {
+            return Ok(Vec::new().into());
+        };
+        let start = match since {
+            // Messages go in as they arrive, so they're already in order.
+            Some(since) => messages.partition_point(|message| message.timestamp < since),
+            None => 0,
+        };
+        let mut messages = &messages[start..];
+        if let Some(limit) = limit {
+            messages = &messages[messages.len().saturating_sub(limit as usize)..];
+        }
+        Ok(messages.to_vec().into())
+    }
+
+    fn get_pm_partners(
+        &self,
+        own_character: &Character,
+    ) -> Result<Cow<'_, [Character]>, Self::Error> {
+        Ok(self
+            .messages
+            .iter()
+            .filter(|entry| !entry.is_empty())
+            .filter_map(|entry| match entry.key() {
+                MessageChannel::PrivateMessage(own, other) if own == own_character => Some(*other),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .into())
+    }
+
+    fn get_friend_relations(&self) -> Result<Cow<'_, [FriendRelation]>, Self::Error> {
+        Ok(self
+            .friends
+            .read()
+            .iter()
+            .copied()
+            .collect::<Vec<_>>()
+            .into())
+    }
+
+    fn get_bookmarks(&self) -> Result<Cow<'_, [Character]>, Self::Error> {
+        Ok(self
+            .bookmarks
+            .read()
+            .iter()
+            .copied()
+            .collect::<Vec<_>>()
+            .into())
+    }
+
+    fn get_reports(&self) -> Result<Cow<'_, [StaffReport]>, Self::Error> {
+        let mut reports = self.reports.read().values().cloned().collect::<Vec<_>>();
+        reports.sort_by_key(|report| report.callid);
+        Ok(reports.into())
+    }
+
+    fn get_ignored(&self) -> Result<Cow<'_, [Character]>, Self::Error> {
+        Ok(self
+            .ignored
+            .read()
+            .iter()
+            .copied()
+            .collect::<Vec<_>>()
+            .into())
+    }
+
+    fn get_incoming_friend_requests(&self) -> Result<Cow<'_, [PendingFriendRequest]>, Self::Error> {
+        Ok(self
+            .incoming_requests
+            .read()
+            .iter()
+            .copied()
+            .collect::<Vec<_>>()
+            .into())
+    }
+
+    fn get_outgoing_friend_requests(&self) -> Result<Cow<'_, [PendingFriendRequest]>, Self::Error> {
+        Ok(self
+            .outgoing_requests
+            .read()
+            .iter()
+            .copied()
+            .collect::<Vec<_>>()
+            .into())
+    }
+}
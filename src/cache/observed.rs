@@ -0,0 +1,518 @@
+// Wraps any Cache to time each call, so a persistent backend that's slowing down shows up before
+// it starts holding up dispatch. Every operation is reported by name (the trait method's), with how
+// long it took and whether it failed.
+
+use std::{
+    borrow::Cow,
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use super::{
+    Cache, CachedAd, CachedKinkMapping, CachedProfile, ChannelBan, ChannelListing,
+    PartialChannelData, PartialUserData, Updated,
+};
+use crate::{
+    data::{
+        Channel, ChannelData, Character, CharacterData, FriendRelation, Message, MessageChannel,
+        PendingFriendRequest, StaffReport,
+    },
+    util::timestamp::Timestamp,
+};
+
+/// Hears about every operation an [`ObservedCache`] passes on.
+/// Runs inside the cache call, so it shouldn't block for long.
+pub trait CacheObserver: Send + Sync {
+    fn operation(&self, name: &'static str, elapsed: Duration, failed: bool);
+}
+
+impl<F: Fn(&'static str, Duration, bool) + Send + Sync> CacheObserver for F {
+    fn operation(&self, name: &'static str, elapsed: Duration, failed: bool) {
+        self(name, elapsed, failed)
+    }
+}
+
+/// Reports operations through the metrics facade, as `fchat_cache_operations_total` and
+/// `fchat_cache_operation_seconds`, both labelled by operation.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MetricsObserver;
+
+#[cfg(feature = "metrics")]
+impl CacheObserver for MetricsObserver {
+    fn operation(&self, name: &'static str, elapsed: Duration, failed: bool) {
+        crate::telemetry::cache_operation(name, elapsed, failed);
+    }
+}
+
+pub struct ObservedCache<C> {
+    inner: C,
+    observer: Arc<dyn CacheObserver>,
+}
+
+impl<C: fmt::Debug> fmt::Debug for ObservedCache<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ObservedCache")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<C: Cache> ObservedCache<C> {
+    pub fn new(inner: C, observer: impl CacheObserver + 'static) -> Self {
+        ObservedCache {
+            inner,
+            observer: Arc::new(observer),
+        }
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    fn observe<'a, T>(
+        &'a self,
+        name: &'static str,
+        op: impl FnOnce(&'a C) -> Result<T, C::Error>,
+    ) -> Result<T, C::Error> {
+        let start = Instant::now();
+        let result = op(&self.inner);
+        self.observer.operation(name, start.elapsed(), result.is_err());
+        result
+    }
+}
+
+impl<C: Cache> Cache for ObservedCache<C> {
+    type Error = C::Error;
+
+    fn begin_batch(&self) -> Result<(), Self::Error> {
+        self.observe("begin_batch", |inner| inner.begin_batch())
+    }
+    fn commit_batch(&self) -> Result<(), Self::Error> {
+        self.observe("commit_batch", |inner| inner.commit_batch())
+    }
+    fn compact(&self) -> Result<bool, Self::Error> {
+        self.observe("compact", |inner| inner.compact())
+    }
+
+    fn tick(&self) -> Result<(), Self::Error> {
+        self.observe("tick", |inner| inner.tick())
+    }
+
+    fn insert_message(
+        &self,
+        source: MessageChannel,
+        message: Message,
+    ) -> Result<bool, Self::Error> {
+        self.observe("insert_message", |inner| {
+            inner.insert_message(source, message)
+        })
+    }
+
+    fn insert_channel(
+        &self,
+        channel: Cow<Channel>,
+        data: PartialChannelData,
+        members: Cow<[Character]>,
+    ) -> Result<bool, Self::Error> {
+        self.observe("insert_channel", |inner| {
+            inner.insert_channel(channel, data, members)
+        })
+    }
+
+    fn insert_ad(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+        ad: Cow<str>,
+        timestamp: Timestamp,
+    ) -> Result<bool, Self::Error> {
+        self.observe("insert_ad", |inner| {
+            inner.insert_ad(channel, character, ad, timestamp)
+        })
+    }
+
+    fn insert_report(&self, report: Cow<StaffReport>) -> Result<bool, Self::Error> {
+        self.observe("insert_report", |inner| inner.insert_report(report))
+    }
+
+    fn insert_profile(
+        &self,
+        character: Cow<Character>,
+        profile: CachedProfile,
+    ) -> Result<bool, Self::Error> {
+        self.observe("insert_profile", |inner| {
+            inner.insert_profile(character, profile)
+        })
+    }
+
+    fn set_memo(&self, character: Cow<Character>, memo: Cow<str>) -> Result<bool, Self::Error> {
+        self.observe("set_memo", |inner| inner.set_memo(character, memo))
+    }
+
+    fn remove_report(&self, callid: u32) -> Result<bool, Self::Error> {
+        self.observe("remove_report", |inner| inner.remove_report(callid))
+    }
+
+    fn purge_character(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.observe("purge_character", |inner| inner.purge_character(character))
+    }
+
+    fn add_channel_member(
+        &self,
+        channel: Cow<Channel>,
+        member: Character,
+    ) -> Result<bool, Self::Error> {
+        self.observe("add_channel_member", |inner| {
+            inner.add_channel_member(channel, member)
+        })
+    }
+
+    fn remove_channel_member(
+        &self,
+        channel: Cow<Channel>,
+        member: Character,
+    ) -> Result<bool, Self::Error> {
+        self.observe("remove_channel_member", |inner| {
+            inner.remove_channel_member(channel, member)
+        })
+    }
+
+    fn add_bookmark(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.observe("add_bookmark", |inner| inner.add_bookmark(character))
+    }
+
+    fn remove_bookmark(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.observe("remove_bookmark", |inner| inner.remove_bookmark(character))
+    }
+
+    fn add_global_op(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.observe("add_global_op", |inner| inner.add_global_op(character))
+    }
+
+    fn remove_global_op(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.observe("remove_global_op", |inner| {
+            inner.remove_global_op(character)
+        })
+    }
+
+    fn add_ignored(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.observe("add_ignored", |inner| inner.add_ignored(character))
+    }
+
+    fn remove_ignored(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.observe("remove_ignored", |inner| inner.remove_ignored(character))
+    }
+
+    fn add_channel_op(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        self.observe("add_channel_op", |inner| {
+            inner.add_channel_op(channel, character)
+        })
+    }
+
+    fn remove_channel_op(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        self.observe("remove_channel_op", |inner| {
+            inner.remove_channel_op(channel, character)
+        })
+    }
+
+    fn add_channel_ban(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        self.observe("add_channel_ban", |inner| {
+            inner.add_channel_ban(channel, character)
+        })
+    }
+
+    fn remove_channel_ban(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        self.observe("remove_channel_ban", |inner| {
+            inner.remove_channel_ban(channel, character)
+        })
+    }
+
+    fn add_channel_timeout(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+        expires: Timestamp,
+    ) -> Result<bool, Self::Error> {
+        self.observe("add_channel_timeout", |inner| {
+            inner.add_channel_timeout(channel, character, expires)
+        })
+    }
+
+    fn update_channel(
+        &self,
+        channel: Cow<Channel>,
+        data: PartialChannelData,
+    ) -> Result<bool, Self::Error> {
+        self.observe("update_channel", |inner| {
+            inner.update_channel(channel, data)
+        })
+    }
+
+    fn update_character(
+        &self,
+        character: Cow<Character>,
+        data: PartialUserData,
+    ) -> Result<bool, Self::Error> {
+        self.observe("update_character", |inner| {
+            inner.update_character(character, data)
+        })
+    }
+
+    fn set_friends(&self, friends: Cow<[FriendRelation]>) -> Result<bool, Self::Error> {
+        self.observe("set_friends", |inner| inner.set_friends(friends))
+    }
+
+    fn set_bookmarks(&self, bookmarks: Cow<[Character]>) -> Result<bool, Self::Error> {
+        self.observe("set_bookmarks", |inner| inner.set_bookmarks(bookmarks))
+    }
+
+    fn set_ignored(&self, ignored: Cow<[Character]>) -> Result<bool, Self::Error> {
+        self.observe("set_ignored", |inner| inner.set_ignored(ignored))
+    }
+
+    fn set_incoming_friend_requests(
+        &self,
+        requests: Cow<[PendingFriendRequest]>,
+    ) -> Result<bool, Self::Error> {
+        self.observe("set_incoming_friend_requests", |inner| {
+            inner.set_incoming_friend_requests(requests)
+        })
+    }
+
+    fn set_outgoing_friend_requests(
+        &self,
+        requests: Cow<[PendingFriendRequest]>,
+    ) -> Result<bool, Self::Error> {
+        self.observe("set_outgoing_friend_requests", |inner| {
+            inner.set_outgoing_friend_requests(requests)
+        })
+    }
+
+    fn set_channel_members(
+        &self,
+        channel: Cow<Channel>,
+        members: Cow<[Character]>,
+    ) -> Result<bool, Self::Error> {
+        self.observe("set_channel_members", |inner| {
+            inner.set_channel_members(channel, members)
+        })
+    }
+
+    fn set_global_channels(
+        &self,
+        channels: Cow<[(Channel, u32)]>,
+        fetched_at: Timestamp,
+    ) -> Result<bool, Self::Error> {
+        self.observe("set_global_channels", |inner| {
+            inner.set_global_channels(channels, fetched_at)
+        })
+    }
+
+    fn set_unofficial_channels(
+        &self,
+        channels: Cow<[(Channel, u32)]>,
+        fetched_at: Timestamp,
+    ) -> Result<bool, Self::Error> {
+        self.observe("set_unofficial_channels", |inner| {
+            inner.set_unofficial_channels(channels, fetched_at)
+        })
+    }
+
+    fn set_global_ops(&self, ops: Cow<[Character]>) -> Result<bool, Self::Error> {
+        self.observe("set_global_ops", |inner| inner.set_global_ops(ops))
+    }
+
+    fn set_kink_mapping(&self, mapping: CachedKinkMapping) -> Result<bool, Self::Error> {
+        self.observe("set_kink_mapping", |inner| inner.set_kink_mapping(mapping))
+    }
+
+    fn set_channel_bans(
+        &self,
+        channel: Cow<Channel>,
+        bans: Cow<[Character]>,
+    ) -> Result<bool, Self::Error> {
+        self.observe("set_channel_bans", |inner| {
+            inner.set_channel_bans(channel, bans)
+        })
+    }
+
+    fn set_channel_ops(
+        &self,
+        channel: Cow<Channel>,
+        ops: Cow<[Character]>,
+    ) -> Result<bool, Self::Error> {
+        self.observe("set_channel_ops", |inner| {
+            inner.set_channel_ops(channel, ops)
+        })
+    }
+
+    fn get_channel(&self, channel: &Channel) -> Result<Option<ChannelData>, Self::Error> {
+        self.observe("get_channel", |inner| inner.get_channel(channel))
+    }
+
+    fn get_channels(&self) -> Result<Cow<'_, [ChannelData]>, Self::Error> {
+        self.observe("get_channels", |inner| inner.get_channels())
+    }
+
+    fn get_character(&self, character: &Character) -> Result<Option<CharacterData>, Self::Error> {
+        self.observe("get_character", |inner| inner.get_character(character))
+    }
+
+    fn get_profile(&self, character: &Character) -> Result<Option<CachedProfile>, Self::Error> {
+        self.observe("get_profile", |inner| inner.get_profile(character))
+    }
+
+    fn get_global_ops(&self) -> Result<Cow<'_, [Character]>, Self::Error> {
+        self.observe("get_global_ops", |inner| inner.get_global_ops())
+    }
+
+    fn get_channel_ops(&self, channel: &Channel) -> Result<Cow<'_, [Character]>, Self::Error> {
+        self.observe("get_channel_ops", |inner| inner.get_channel_ops(channel))
+    }
+
+    fn get_channel_members(&self, channel: &Channel) -> Result<Cow<'_, [Character]>, Self::Error> {
+        self.observe("get_channel_members", |inner| {
+            inner.get_channel_members(channel)
+        })
+    }
+
+    fn get_channel_bans(&self, channel: &Channel) -> Result<Cow<'_, [ChannelBan]>, Self::Error> {
+        self.observe("get_channel_bans", |inner| inner.get_channel_bans(channel))
+    }
+
+    fn get_ads(
+        &self,
+        channel: &Channel,
+        since: Option<Timestamp>,
+    ) -> Result<Cow<'_, [CachedAd]>, Self::Error> {
+        self.observe("get_ads", |inner| inner.get_ads(channel, since))
+    }
+
+    fn get_global_channels(&self) -> Result<Option<ChannelListing>, Self::Error> {
+        self.observe("get_global_channels", |inner| inner.get_global_channels())
+    }
+
+    fn get_unofficial_channels(&self) -> Result<Option<ChannelListing>, Self::Error> {
+        self.observe("get_unofficial_channels", |inner| {
+            inner.get_unofficial_channels()
+        })
+    }
+
+    fn get_kink_mapping(&self) -> Result<Option<CachedKinkMapping>, Self::Error> {
+        self.observe("get_kink_mapping", |inner| inner.get_kink_mapping())
+    }
+
+    fn get_memo(&self, character: &Character) -> Result<Option<String>, Self::Error> {
+        self.observe("get_memo", |inner| inner.get_memo(character))
+    }
+
+    fn get_characters(&self) -> Result<Cow<'_, [CharacterData]>, Self::Error> {
+        self.observe("get_characters", |inner| inner.get_characters())
+    }
+
+    fn get_messages(
+        &self,
+        source: &MessageChannel,
+        since: Option<Timestamp>,
+        limit: Option<u32>,
+    ) -> Result<Cow<'_, [Message]>, Self::Error> {
+        self.observe("get_messages", |inner| {
+            inner.get_messages(source, since, limit)
+        })
+    }
+
+    fn get_pm_partners(
+        &self,
+        own_character: &Character,
+    ) -> Result<Cow<'_, [Character]>, Self::Error> {
+        self.observe("get_pm_partners", |inner| {
+            inner.get_pm_partners(own_character)
+        })
+    }
+
+    fn get_friend_relations(&self) -> Result<Cow<'_, [FriendRelation]>, Self::Error> {
+        self.observe("get_friend_relations", |inner| inner.get_friend_relations())
+    }
+
+    fn get_bookmarks(&self) -> Result<Cow<'_, [Character]>, Self::Error> {
+        self.observe("get_bookmarks", |inner| inner.get_bookmarks())
+    }
+
+    fn get_reports(&self) -> Result<Cow<'_, [StaffReport]>, Self::Error> {
+        self.observe("get_reports", |inner| inner.get_reports())
+    }
+
+    fn get_ignored(&self) -> Result<Cow<'_, [Character]>, Self::Error> {
+        self.observe("get_ignored", |inner| inner.get_ignored())
+    }
+
+    fn get_incoming_friend_requests(&self) -> Result<Cow<'_, [PendingFriendRequest]>, Self::Error> {
+        self.observe("get_incoming_friend_requests", |inner| {
+            inner.get_incoming_friend_requests()
+        })
+    }
+
+    fn get_outgoing_friend_requests(&self) -> Result<Cow<'_, [PendingFriendRequest]>, Self::Error> {
+        self.observe("get_outgoing_friend_requests", |inner| {
+            inner.get_outgoing_friend_requests()
+        })
+    }
+
+    fn update_channel_diff(
+        &self,
+        channel: Cow<Channel>,
+        data: PartialChannelData,
+    ) -> Result<Updated<ChannelData>, Self::Error> {
+        self.observe("update_channel_diff", |inner| {
+            inner.update_channel_diff(channel, data)
+        })
+    }
+
+    fn update_character_diff(
+        &self,
+        character: Cow<Character>,
+        data: PartialUserData,
+    ) -> Result<Updated<CharacterData>, Self::Error> {
+        self.observe("update_character_diff", |inner| {
+            inner.update_character_diff(character, data)
+        })
+    }
+
+    fn get_private_messages(
+        &self,
+        own_character: &Character,
+        other: &Character,
+        since: Option<Timestamp>,
+        limit: Option<u32>,
+    ) -> Result<Cow<'_, [Message]>, Self::Error> {
+        self.observe("get_private_messages", |inner| {
+            inner.get_private_messages(own_character, other, since, limit)
+        })
+    }
+
+    fn get_friends(&self) -> Result<Cow<'_, [Character]>, Self::Error> {
+        self.observe("get_friends", |inner| inner.get_friends())
+    }
+}
@@ -0,0 +1,1683 @@
+// Cache that keeps everything in PostgreSQL, for running several bots against one shared store.
+//
+// The schema is the same shape as SqliteCache's (see MIGRATIONS below), with timestamps as
+// TIMESTAMPTZ and structured values (messages, profiles, reports) as JSON text. Point it at a
+// database, or a schema via search_path, of its own; the tables aren't prefixed.
+//
+// Any number of processes can share it. Migrations are serialized with an advisory lock, plain
+// writes are upserts, and anything that reads before it writes (to tell whether something changed)
+// takes an advisory lock on what it's changing for the length of its transaction. Every call is
+// its own transaction, so begin_batch/commit_batch are left as the default no-ops.
+//
+// The Cache trait is synchronous, so each call blocks on the pool. Inside a tokio runtime that has
+// to be the multi-threaded one; outside, the runtime the cache was created in is used.
+
+use std::{borrow::Cow, collections::HashSet, future::Future, sync::Arc, time::Duration};
+
+use chrono::{DurationRound, Utc};
+use sqlx::{
+    postgres::{PgPoolOptions, PgRow},
+    PgConnection, PgPool, Row,
+};
+use tokio::runtime::Handle;
+
+use super::{
+    apply_channel_data, apply_user_data, fill_channel_data,
+    columns::{character, enum_text, name, parse_enum},
+    retention::{EvictionHook, Retention, RetentionClass, RetentionPolicy},
+    Cache, CachedAd, CachedKinkMapping, CachedProfile, ChannelBan, ChannelListing,
+    PartialChannelData, PartialUserData,
+};
+use crate::{
+    data::{
+        Channel, ChannelData, Character, CharacterData, FriendRelation, Message, MessageChannel,
+        PendingFriendRequest, StaffReport,
+    },
+    util::timestamp::Timestamp,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum PostgresCacheError {
+    #[error("Error from PostgreSQL")]
+    Postgres(#[from] sqlx::Error),
+    #[error("Failed to (de)serialize a cached value")]
+    Json(#[from] serde_json::Error),
+    #[error("Database schema version {0} is newer than this version of the cache knows about")]
+    UnknownSchema(i64),
+}
+
+type PostgresResult<T> = Result<T, PostgresCacheError>;
+
+// Each entry moves the schema up one version (tracked in cache_schema). Never edit one that's
+// shipped; add another.
+const MIGRATIONS: &[&str] = &[r#"
+CREATE TABLE channels (
+    channel TEXT PRIMARY KEY,
+    mode TEXT NOT NULL,
+    title TEXT NOT NULL,
+    description TEXT NOT NULL,
+    owner TEXT
+);
+CREATE TABLE channel_members (
+    channel TEXT NOT NULL,
+    character TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    PRIMARY KEY (channel, character)
+);
+CREATE TABLE channel_ops (
+    channel TEXT NOT NULL,
+    character TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    PRIMARY KEY (channel, character)
+);
+CREATE TABLE channel_bans (
+    channel TEXT NOT NULL,
+    character TEXT NOT NULL,
+    expires TIMESTAMPTZ,
+    PRIMARY KEY (channel, character)
+);
+CREATE TABLE characters (
+    character TEXT PRIMARY KEY,
+    gender TEXT NOT NULL,
+    status TEXT NOT NULL,
+    status_message TEXT NOT NULL
+);
+CREATE TABLE messages (
+    id BIGSERIAL PRIMARY KEY,
+    source TEXT NOT NULL,
+    timestamp TIMESTAMPTZ NOT NULL,
+    character TEXT NOT NULL,
+    content TEXT NOT NULL
+);
+CREATE INDEX messages_by_source ON messages (source, timestamp);
+CREATE TABLE ads (
+    channel TEXT NOT NULL,
+    character TEXT NOT NULL,
+    ad TEXT NOT NULL,
+    timestamp TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (channel, character)
+);
+CREATE TABLE reports (
+    callid BIGINT PRIMARY KEY,
+    report TEXT NOT NULL
+);
+CREATE TABLE profiles (
+    character TEXT PRIMARY KEY,
+    profile TEXT NOT NULL,
+    fetched_at TIMESTAMPTZ NOT NULL
+);
+CREATE TABLE memos (
+    character TEXT PRIMARY KEY,
+    memo TEXT NOT NULL
+);
+CREATE TABLE relations (
+    kind TEXT NOT NULL,
+    character TEXT NOT NULL,
+    PRIMARY KEY (kind, character)
+);
+CREATE TABLE friends (
+    own_character TEXT NOT NULL,
+    other_character TEXT NOT NULL,
+    PRIMARY KEY (own_character, other_character)
+);
+CREATE TABLE friend_requests (
+    id BIGINT PRIMARY KEY,
+    source TEXT NOT NULL,
+    dest TEXT NOT NULL,
+    incoming BOOLEAN NOT NULL
+);
+CREATE TABLE channel_listings (
+    kind TEXT PRIMARY KEY,
+    channels TEXT NOT NULL,
+    fetched_at TIMESTAMPTZ NOT NULL
+);
+CREATE TABLE kink_mapping (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    mapping TEXT NOT NULL
+);
+"#,
+    r#"
+ALTER TABLE characters ADD COLUMN typing TEXT NOT NULL DEFAULT 'clear';
+"#,
+    r#"
+ALTER TABLE kink_mapping ADD COLUMN fetched_at TIMESTAMPTZ NOT NULL DEFAULT 'epoch';
+"#,
+    r#"
+ALTER TABLE channels ADD COLUMN member_count BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE channels ADD COLUMN last_activity TIMESTAMPTZ;
+"#,
+    r#"
+ALTER TABLE characters ADD COLUMN orientation TEXT;
+ALTER TABLE characters ADD COLUMN languages TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE characters ADD COLUMN furry_preference TEXT;
+ALTER TABLE characters ADD COLUMN role TEXT;
+ALTER TABLE characters ADD COLUMN last_profile_update TIMESTAMPTZ;
+"#,
+    r#"
+ALTER TABLE friends ADD COLUMN last_online TIMESTAMPTZ;
+"#];
+
+// Held while migrating, so two clients starting at once don't both create the tables.
+const MIGRATION_LOCK: i64 = 0x6663_6861_7473; // "fchats"
+
+// Kinds for the relations table.
+const BOOKMARK: &str = "bookmark";
+const IGNORED: &str = "ignored";
+const GLOBAL_OP: &str = "global_op";
+
+// And for channel_listings.
+const GLOBAL_CHANNELS: &str = "global";
+const UNOFFICIAL_CHANNELS: &str = "unofficial";
+
+#[derive(Debug)]
+pub struct PostgresCache {
+    pool: PgPool,
+    runtime: Handle,
+    retention: RetentionPolicy,
+}
+
+impl PostgresCache {
+    pub async fn connect(url: &str) -> PostgresResult<Self> {
+        Self::from_pool(PgPoolOptions::new().connect(url).await?).await
+    }
+
+    /// Use an already configured pool. The schema is created or brought up to date.
+    pub async fn from_pool(pool: PgPool) -> PostgresResult<Self> {
+        migrate(&pool).await?;
+        Ok(PostgresCache {
+            pool,
+            runtime: Handle::current(),
+            retention: RetentionPolicy::default(),
+        })
+    }
+
+    /// Limit every message history. Keeps everything by default.
+    /// Enforced as messages come in, and across the whole database by `compact()`, which
+    /// ClientBuilder::with_cache_compaction runs on an interval.
+    pub fn with_retention(mut self, retention: Retention) -> Self {
+        self.retention.default = retention;
+        self
+    }
+
+    /// Limit one message history, overriding `with_retention` for it.
+    pub fn with_source_retention(mut self, source: MessageChannel, retention: Retention) -> Self {
+        self.retention.sources.insert(source, retention);
+        self
+    }
+
+    /// Limit every history of one class, overriding `with_retention` for them.
+    pub fn with_class_retention(mut self, class: RetentionClass, retention: Retention) -> Self {
+        self.retention.classes.insert(class, retention);
+        self
+    }
+
+    /// Drop ads once they're older than `max_age`. Kept until they're replaced by default.
+    pub fn with_ad_retention(mut self, max_age: Duration) -> Self {
+        self.retention.ads = Some(max_age);
+        self
+    }
+
+    /// Hear about messages as they're deleted for being over the limits.
+    /// Only the process that deleted them hears about it.
+    pub fn with_eviction_hook(mut self, hook: impl EvictionHook + 'static) -> Self {
+        self.retention.hook = Some(Arc::new(hook));
+        self
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    fn block<T>(&self, query: impl Future<Output = PostgresResult<T>>) -> PostgresResult<T> {
+        match Handle::try_current() {
+            Ok(_) => tokio::task::block_in_place(|| self.runtime.block_on(query)),
+            Err(_) => self.runtime.block_on(query),
+        }
+    }
+}
+
+async fn migrate(pool: &PgPool) -> PostgresResult<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(MIGRATION_LOCK)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("CREATE TABLE IF NOT EXISTS cache_schema (version BIGINT NOT NULL)")
+        .execute(&mut *tx)
+        .await?;
+    let version: i64 = sqlx::query_scalar("SELECT version FROM cache_schema")
+        .fetch_optional(&mut *tx)
+        .await?
+        .unwrap_or(0);
+    if version > MIGRATIONS.len() as i64 {
+        return Err(PostgresCacheError::UnknownSchema(version));
+    }
+    for migration in MIGRATIONS.iter().skip(version as usize) {
+        sqlx::raw_sql(migration).execute(&mut *tx).await?;
+    }
+    sqlx::query("DELETE FROM cache_schema")
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("INSERT INTO cache_schema (version) VALUES ($1)")
+        .bind(MIGRATIONS.len() as i64)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+// Serializes read-compare-write on one thing across every process, until the transaction ends.
+async fn lock(conn: &mut PgConnection, key: &str) -> PostgresResult<()> {
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+        .bind(key)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+// Postgres keeps microseconds; anything finer would never compare equal after a round trip.
+fn micros(timestamp: Timestamp) -> Timestamp {
+    timestamp
+        .duration_trunc(chrono::Duration::microseconds(1))
+        .unwrap_or(timestamp)
+}
+
+async fn read_channel(
+    conn: &mut PgConnection,
+    channel: &Channel,
+) -> PostgresResult<Option<ChannelData>> {
+    let Some((mode, title, description, owner, member_count, last_activity)) = sqlx::query_as::<
+        _,
+        (
+            String,
+            String,
+            String,
+            Option<String>,
+            i64,
+            Option<Timestamp>,
+        ),
+    >(
+        "SELECT mode, title, description, owner, member_count, last_activity
+             FROM channels WHERE channel = $1",
+        )
+        .bind(channel.0.to_string())
+        .fetch_optional(&mut *conn)
+        .await?
+    else {
+        return Ok(None);
+    };
+    let mut data = ChannelData {
+        channel: channel.clone(),
+        channel_mode: parse_enum(mode)?,
+        members: read_ordered(conn, "channel_members", channel).await?,
+        description,
+        title,
+        owner: owner.map(character),
+        member_count: member_count as u32,
+        last_activity,
+        ..Default::default()
+    };
+    fill_channel_data(
+        &mut data,
+        &read_ordered(conn, "channel_ops", channel).await?,
+    );
+    Ok(Some(data))
+}
+
+async fn write_channel(conn: &mut PgConnection, data: &ChannelData) -> PostgresResult<()> {
+    // last_activity is left to touch_channel, which doesn't take the channel's lock.
+    sqlx::query(
+        "INSERT INTO channels (channel, mode, title, description, owner, member_count)
+         VALUES ($1, $2, $3, $4, $5, $6)
+         ON CONFLICT (channel) DO UPDATE SET mode = EXCLUDED.mode, title = EXCLUDED.title,
+             description = EXCLUDED.description, owner = EXCLUDED.owner,
+             member_count = EXCLUDED.member_count",
+    )
+    .bind(data.channel.0.to_string())
+    .bind(enum_text(&data.channel_mode)?)
+    .bind(&data.title)
+    .bind(&data.description)
+    .bind(data.owner.as_ref().map(name))
+    .bind(data.member_count as i64)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+async fn touch_channel(
+    conn: &mut PgConnection,
+    channel: &Channel,
+    at: Timestamp,
+) -> PostgresResult<()> {
+    sqlx::query(
+        "UPDATE channels SET last_activity = GREATEST(last_activity, $2) WHERE channel = $1",
+    )
+    .bind(channel.0.to_string())
+    .bind(at)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+// Make sure a channel has a row, so members can be attached to it.
+async fn ensure_channel(conn: &mut PgConnection, channel: &Channel) -> PostgresResult<()> {
+    sqlx::query(
+        "INSERT INTO channels (channel, mode, title, description, owner)
+         VALUES ($1, $2, '', '', NULL)
+         ON CONFLICT (channel) DO NOTHING",
+    )
+    .bind(channel.0.to_string())
+    .bind(enum_text(&ChannelData::default().channel_mode)?)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+// channel_members and channel_ops both keep characters in the order they were given.
+async fn read_ordered(
+    conn: &mut PgConnection,
+    table: &str,
+    channel: &Channel,
+) -> PostgresResult<Vec<Character>> {
+    let rows = sqlx::query_scalar::<_, String>(&format!(
+        "SELECT character FROM {table} WHERE channel = $1 ORDER BY position"
+    ))
+    .bind(channel.0.to_string())
+    .fetch_all(conn)
+    .await?;
+    Ok(rows.into_iter().map(character).collect())
+}
+
+// Expects to be in a transaction.
+async fn replace_ordered(
+    conn: &mut PgConnection,
+    table: &str,
+    channel: &Channel,
+    characters: &[Character],
+) -> PostgresResult<bool> {
+    lock(conn, &format!("{table}:{}", channel.0)).await?;
+    if read_ordered(conn, table, channel).await? == characters {
+        return Ok(false);
+    }
+    sqlx::query(&format!("DELETE FROM {table} WHERE channel = $1"))
+        .bind(channel.0.to_string())
+        .execute(&mut *conn)
+        .await?;
+    // Duplicates keep their first position, like SqliteCache.
+    let mut seen = HashSet::new();
+    let (names, positions): (Vec<_>, Vec<_>) = characters
+        .iter()
+        .filter(|member| seen.insert(**member))
+        .enumerate()
+        .map(|(position, member)| (name(member), position as i32))
+        .unzip();
+    sqlx::query(&format!(
+        "INSERT INTO {table} (channel, character, position)
+         SELECT $1, * FROM UNNEST($2::TEXT[], $3::INTEGER[])"
+    ))
+    .bind(channel.0.to_string())
+    .bind(names)
+    .bind(positions)
+    .execute(conn)
+    .await?;
+    Ok(true)
+}
+
+// Expects to be in a transaction. The lock keeps two processes from picking the same position,
+// and keeps both this and remove_ordered from landing in the middle of a replace_ordered.
+async fn add_ordered(
+    conn: &mut PgConnection,
+    table: &str,
+    channel: &Channel,
+    member: &Character,
+) -> PostgresResult<bool> {
+    lock(conn, &format!("{table}:{}", channel.0)).await?;
+    let inserted = sqlx::query(&format!(
+        "INSERT INTO {table} (channel, character, position)
+         SELECT $1, $2, COALESCE(MAX(position) + 1, 0) FROM {table} WHERE channel = $1
+         ON CONFLICT (channel, character) DO NOTHING"
+    ))
+    .bind(channel.0.to_string())
+    .bind(name(member))
+    .execute(conn)
+    .await?;
+    Ok(inserted.rows_affected() > 0)
+}
+
+// Expects to be in a transaction.
+async fn remove_ordered(
+    conn: &mut PgConnection,
+    table: &str,
+    channel: &Channel,
+    member: &Character,
+) -> PostgresResult<bool> {
+    lock(conn, &format!("{table}:{}", channel.0)).await?;
+    let removed = sqlx::query(&format!(
+        "DELETE FROM {table} WHERE channel = $1 AND character = $2"
+    ))
+    .bind(channel.0.to_string())
+    .bind(name(member))
+    .execute(conn)
+    .await?;
+    Ok(removed.rows_affected() > 0)
+}
+
+fn to_message((at, who, content): (Timestamp, String, String)) -> PostgresResult<Message> {
+    Ok(Message {
+        timestamp: at,
+        character: character(who),
+        content: serde_json::from_str(&content)?,
+    })
+}
+
+// Deletes whatever's past the retention limits for one source and returns it, oldest first.
+// One statement, so two processes trimming at once can't both claim the same messages.
+async fn evict_messages(
+    conn: &mut PgConnection,
+    source: &str,
+    retention: Retention,
+) -> PostgresResult<Vec<Message>> {
+    if retention.is_forever() {
+        return Ok(Vec::new());
+    }
+    let mut rows = sqlx::query_as::<_, (i64, Timestamp, String, String)>(
+        "DELETE FROM messages
+         WHERE source = $1 AND (timestamp < $2 OR id NOT IN
+             (SELECT id FROM messages WHERE source = $1 ORDER BY id DESC LIMIT $3))
+         RETURNING id, timestamp, character, content",
+    )
+    .bind(source)
+    .bind(retention.cutoff(Utc::now()))
+    .bind(retention.max_messages.map(|max| max as i64)) // NULL is no limit
+    .fetch_all(conn)
+    .await?;
+    rows.sort_by_key(|(id, ..)| *id);
+    rows.into_iter()
+        .map(|(_, at, who, content)| to_message((at, who, content)))
+        .collect()
+}
+
+// Sources are stored as JSON, so narrow it down to PMs in SQL and pick them apart here.
+async fn read_pm_sources(
+    conn: &mut PgConnection,
+) -> PostgresResult<Vec<(String, Character, Character)>> {
+    let sources = sqlx::query_scalar::<_, String>(
+        r#"SELECT DISTINCT source FROM messages WHERE source LIKE '{"type":"pm"%'"#,
+    )
+    .fetch_all(conn)
+    .await?;
+    let mut pms = Vec::new();
+    for source in sources {
+        if let MessageChannel::PrivateMessage(own, other) = serde_json::from_str(&source)? {
+            pms.push((source, own, other));
+        }
+    }
+    Ok(pms)
+}
+
+const CHARACTER_COLUMNS: &str = "character, gender, status, status_message, typing, \
+    orientation, languages, furry_preference, role, last_profile_update";
+
+// A characters row as it comes out, before the enums are parsed.
+struct CharacterRow {
+    character: String,
+    gender: String,
+    status: String,
+    status_message: String,
+    typing: String,
+    orientation: Option<String>,
+    languages: String, // JSON list
+    furry_preference: Option<String>,
+    role: Option<String>,
+    last_profile_update: Option<Timestamp>,
+}
+
+// For rows selected as CHARACTER_COLUMNS.
+impl sqlx::FromRow<'_, PgRow> for CharacterRow {
+    fn from_row(row: &PgRow) -> sqlx::Result<Self> {
+        Ok(CharacterRow {
+            character: row.try_get(0)?,
+            gender: row.try_get(1)?,
+            status: row.try_get(2)?,
+            status_message: row.try_get(3)?,
+            typing: row.try_get(4)?,
+            orientation: row.try_get(5)?,
+            languages: row.try_get(6)?,
+            furry_preference: row.try_get(7)?,
+            role: row.try_get(8)?,
+            last_profile_update: row.try_get(9)?,
+        })
+    }
+}
+
+impl CharacterRow {
+    fn parse(self) -> PostgresResult<CharacterData> {
+        Ok(CharacterData {
+            character: character(self.character),
+            gender: parse_enum(self.gender)?,
+            status: parse_enum(self.status)?,
+            status_message: self.status_message,
+            typing: parse_enum(self.typing)?,
+            orientation: self.orientation.map(parse_enum).transpose()?,
+            languages: serde_json::from_str(&self.languages)?,
+            furry_preference: self.furry_preference.map(parse_enum).transpose()?,
+            role: self.role.map(parse_enum).transpose()?,
+            last_profile_update: self.last_profile_update,
+        })
+    }
+}
+
+async fn read_character(
+    conn: &mut PgConnection,
+    who: &Character,
+) -> PostgresResult<Option<CharacterData>> {
+    sqlx::query_as::<_, CharacterRow>(&format!(
+        "SELECT {CHARACTER_COLUMNS} FROM characters WHERE character = $1"
+    ))
+    .bind(name(who))
+    .fetch_optional(conn)
+    .await?
+    .map(CharacterRow::parse)
+    .transpose()
+}
+
+async fn read_relations(conn: &mut PgConnection, kind: &str) -> PostgresResult<Vec<Character>> {
+    let rows = sqlx::query_scalar::<_, String>(
+        "SELECT character FROM relations WHERE kind = $1 ORDER BY character",
+    )
+    .bind(kind)
+    .fetch_all(conn)
+    .await?;
+    Ok(rows.into_iter().map(character).collect())
+}
+
+// Expects to be in a transaction.
+async fn replace_relations(
+    conn: &mut PgConnection,
+    kind: &str,
+    characters: &[Character],
+) -> PostgresResult<bool> {
+    lock(conn, &format!("relations:{kind}")).await?;
+    let old = read_relations(conn, kind)
+        .await?
+        .into_iter()
+        .collect::<HashSet<_>>();
+    let new = characters.iter().copied().collect::<HashSet<_>>();
+    if old == new {
+        return Ok(false);
+    }
+    sqlx::query("DELETE FROM relations WHERE kind = $1")
+        .bind(kind)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("INSERT INTO relations (kind, character) SELECT $1, * FROM UNNEST($2::TEXT[])")
+        .bind(kind)
+        .bind(new.iter().map(name).collect::<Vec<_>>())
+        .execute(conn)
+        .await?;
+    Ok(true)
+}
+
+async fn add_relation(
+    conn: &mut PgConnection,
+    kind: &str,
+    who: &Character,
+) -> PostgresResult<bool> {
+    let inserted = sqlx::query(
+        "INSERT INTO relations (kind, character) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+    )
+    .bind(kind)
+    .bind(name(who))
+    .execute(conn)
+    .await?;
+    Ok(inserted.rows_affected() > 0)
+}
+
+async fn remove_relation(
+    conn: &mut PgConnection,
+    kind: &str,
+    who: &Character,
+) -> PostgresResult<bool> {
+    let removed = sqlx::query("DELETE FROM relations WHERE kind = $1 AND character = $2")
+        .bind(kind)
+        .bind(name(who))
+        .execute(conn)
+        .await?;
+    Ok(removed.rows_affected() > 0)
+}
+
+async fn read_friend_requests(
+    conn: &mut PgConnection,
+    incoming: bool,
+) -> PostgresResult<Vec<PendingFriendRequest>> {
+    let rows = sqlx::query_as::<_, (i64, String, String)>(
+        "SELECT id, source, dest FROM friend_requests WHERE incoming = $1 ORDER BY id",
+    )
+    .bind(incoming)
+    .fetch_all(conn)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(id, source, dest)| PendingFriendRequest {
+            id: id as u64,
+            source: character(source),
+            dest: character(dest),
+        })
+        .collect())
+}
+
+// Expects to be in a transaction.
+async fn replace_friend_requests(
+    conn: &mut PgConnection,
+    incoming: bool,
+    requests: &[PendingFriendRequest],
+) -> PostgresResult<bool> {
+    lock(conn, "friend_requests").await?;
+    let old = read_friend_requests(conn, incoming)
+        .await?
+        .into_iter()
+        .collect::<HashSet<_>>();
+    let new = requests.iter().copied().collect::<HashSet<_>>();
+    if old == new {
+        return Ok(false);
+    }
+    sqlx::query("DELETE FROM friend_requests WHERE incoming = $1")
+        .bind(incoming)
+        .execute(&mut *conn)
+        .await?;
+    for request in new {
+        sqlx::query(
+            "INSERT INTO friend_requests (id, source, dest, incoming) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (id) DO UPDATE SET source = EXCLUDED.source, dest = EXCLUDED.dest,
+                 incoming = EXCLUDED.incoming",
+        )
+        .bind(request.id as i64)
+        .bind(name(&request.source))
+        .bind(name(&request.dest))
+        .bind(incoming)
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(true)
+}
+
+async fn read_friends(conn: &mut PgConnection) -> PostgresResult<Vec<FriendRelation>> {
+    let rows = sqlx::query_as::<_, (String, String, Option<Timestamp>)>(
+        "SELECT own_character, other_character, last_online FROM friends
+         ORDER BY own_character, other_character",
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(own, other, last_online)| FriendRelation {
+            own_character: character(own),
+            other_character: character(other),
+            last_online,
+        })
+        .collect())
+}
+
+// Every ban on record for the channel, expired timeouts included, by name.
+async fn read_bans(conn: &mut PgConnection, channel: &Channel) -> PostgresResult<Vec<ChannelBan>> {
+    let rows = sqlx::query_as::<_, (String, Option<Timestamp>)>(
+        "SELECT character, expires FROM channel_bans WHERE channel = $1 ORDER BY character",
+    )
+    .bind(channel.0.to_string())
+    .fetch_all(conn)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(who, expires)| ChannelBan {
+            character: character(who),
+            expires,
+        })
+        .collect())
+}
+
+// Expects to be in a transaction.
+async fn write_ban(
+    conn: &mut PgConnection,
+    channel: &Channel,
+    character: &Character,
+    expires: Option<Timestamp>,
+) -> PostgresResult<bool> {
+    // FOR UPDATE can't lock a row that isn't there yet, so two new bans would both be changes.
+    lock(conn, &format!("channel_bans:{}", channel.0)).await?;
+    let expires = expires.map(micros);
+    let old = sqlx::query_scalar::<_, Option<Timestamp>>(
+        "SELECT expires FROM channel_bans WHERE channel = $1 AND character = $2",
+    )
+    .bind(channel.0.to_string())
+    .bind(name(character))
+    .fetch_optional(&mut *conn)
+    .await?;
+    if old == Some(expires) {
+        return Ok(false);
+    }
+    sqlx::query(
+        "INSERT INTO channel_bans (channel, character, expires) VALUES ($1, $2, $3)
+         ON CONFLICT (channel, character) DO UPDATE SET expires = EXCLUDED.expires",
+    )
+    .bind(channel.0.to_string())
+    .bind(name(character))
+    .bind(expires)
+    .execute(conn)
+    .await?;
+    Ok(true)
+}
+
+async fn read_listing(
+    conn: &mut PgConnection,
+    kind: &str,
+) -> PostgresResult<Option<ChannelListing>> {
+    let row = sqlx::query_as::<_, (String, Timestamp)>(
+        "SELECT channels, fetched_at FROM channel_listings WHERE kind = $1",
+    )
+    .bind(kind)
+    .fetch_optional(conn)
+    .await?;
+    row.map(|(channels, fetched_at)| {
+        Ok(ChannelListing {
+            channels: serde_json::from_str(&channels)?,
+            fetched_at,
+        })
+    })
+    .transpose()
+}
+
+// Expects to be in a transaction.
+async fn write_listing(
+    conn: &mut PgConnection,
+    kind: &str,
+    channels: &[(Channel, u32)],
+    fetched_at: Timestamp,
+) -> PostgresResult<bool> {
+    lock(conn, &format!("channel_listings:{kind}")).await?;
+    // Always take the new fetch time, but only call it a change if the list is different.
+    let changed = read_listing(conn, kind)
+        .await?
+        .is_none_or(|old| old.channels[..] != *channels);
+    sqlx::query(
+        "INSERT INTO channel_listings (kind, channels, fetched_at) VALUES ($1, $2, $3)
+         ON CONFLICT (kind) DO UPDATE SET channels = EXCLUDED.channels,
+             fetched_at = EXCLUDED.fetched_at",
+    )
+    .bind(kind)
+    .bind(serde_json::to_string(channels)?)
+    .bind(fetched_at)
+    .execute(conn)
+    .await?;
+    Ok(changed)
+}
+
+impl Cache for PostgresCache {
+    type Error = PostgresCacheError;
+
+    // Each history is trimmed in its own transaction, so a big compaction doesn't hold locks
+    // across the whole table while other clients are writing.
+    fn compact(&self) -> Result<bool, Self::Error> {
+        let cutoff = self.retention.ad_cutoff(Utc::now());
+        let (evicted, ads) = self.block(async {
+            let sources = sqlx::query_scalar::<_, String>("SELECT DISTINCT source FROM messages")
+                .fetch_all(&self.pool)
+                .await?;
+            let mut evicted = Vec::new();
+            for key in sources {
+                let source: MessageChannel = serde_json::from_str(&key)?;
+                let retention = self.retention.for_source(&source);
+                let messages =
+                    evict_messages(&mut *self.pool.acquire().await?, &key, retention).await?;
+                if !messages.is_empty() {
+                    evicted.push((source, messages));
+                }
+            }
+            let ads = match cutoff {
+                Some(cutoff) => sqlx::query("DELETE FROM ads WHERE timestamp < $1")
+                    .bind(cutoff)
+                    .execute(&self.pool)
+                    .await?
+                    .rows_affected(),
+                None => 0,
+            };
+            Ok((evicted, ads))
+        })?;
+        let dropped = ads > 0 || !evicted.is_empty();
+        for (source, messages) in evicted {
+            self.retention.notify(source, messages);
+        }
+        Ok(dropped)
+    }
+
+    fn insert_message(
+        &self,
+        source: MessageChannel,
+        message: Message,
+    ) -> Result<bool, Self::Error> {
+        let key = serde_json::to_string(&source)?;
+        let retention = self.retention.for_source(&source);
+        let evicted = self.block(async {
+            let mut tx = self.pool.begin().await?;
+            sqlx::query(
+                "INSERT INTO messages (source, timestamp, character, content) VALUES ($1, $2, $3, $4)",
+            )
+            .bind(&key)
+            .bind(message.timestamp)
+            .bind(name(&message.character))
+            .bind(serde_json::to_string(&message.content)?)
+            .execute(&mut *tx)
+            .await?;
+            if let MessageChannel::Channel(channel) = &source {
+                touch_channel(&mut tx, channel, message.timestamp).await?;
+            }
+            let evicted = evict_messages(&mut tx, &key, retention).await?;
+            tx.commit().await?;
+            Ok(evicted)
+        })?;
+        self.retention.notify(source, evicted);
+        Ok(true)
+    }
+
+    fn insert_channel(
+        &self,
+        channel: Cow<Channel>,
+        data: PartialChannelData,
+        members: Cow<[Character]>,
+    ) -> Result<bool, Self::Error> {
+        self.block(async {
+            let mut tx = self.pool.begin().await?;
+            lock(&mut tx, &format!("channels:{}", channel.0)).await?;
+            let existing = read_channel(&mut tx, &channel).await?;
+            let is_new = existing.is_none();
+            let mut current = existing.unwrap_or_else(|| ChannelData {
+                channel: (*channel).clone(),
+                ..Default::default()
+            });
+            let mut changed = apply_channel_data(&mut current, data);
+            if changed || is_new {
+                write_channel(&mut tx, &current).await?;
+            }
+            changed |= replace_ordered(&mut tx, "channel_members", &channel, &members).await?;
+            tx.commit().await?;
+            Ok(changed)
+        })
+    }
+
+    fn insert_ad(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+        ad: Cow<str>,
+        timestamp: Timestamp,
+    ) -> Result<bool, Self::Error> {
+        // Every ad is a new post, even if it says the same thing as last time.
+        let cutoff = self.retention.ad_cutoff(Utc::now());
+        self.block(async {
+            sqlx::query(
+                "INSERT INTO ads (channel, character, ad, timestamp) VALUES ($1, $2, $3, $4)
+                 ON CONFLICT (channel, character) DO UPDATE SET ad = EXCLUDED.ad,
+                     timestamp = EXCLUDED.timestamp",
+            )
+            .bind(channel.0.to_string())
+            .bind(name(&character))
+            .bind(&*ad)
+            .bind(timestamp)
+            .execute(&self.pool)
+            .await?;
+            touch_channel(&mut *self.pool.acquire().await?, &channel, timestamp).await?;
+            if let Some(cutoff) = cutoff {
+                sqlx::query("DELETE FROM ads WHERE channel = $1 AND timestamp < $2")
+                    .bind(channel.0.to_string())
+                    .bind(cutoff)
+                    .execute(&self.pool)
+                    .await?;
+            }
+            Ok(true)
+        })
+    }
+
+    fn insert_report(&self, report: Cow<StaffReport>) -> Result<bool, Self::Error> {
+        let text = serde_json::to_string(&report)?;
+        // Only write (and so only report a change) if it's different from what's there.
+        self.block(async {
+            let written = sqlx::query(
+                "INSERT INTO reports (callid, report) VALUES ($1, $2)
+                 ON CONFLICT (callid) DO UPDATE SET report = EXCLUDED.report
+                 WHERE reports.report IS DISTINCT FROM EXCLUDED.report",
+            )
+            .bind(i64::from(report.callid))
+            .bind(&text)
+            .execute(&self.pool)
+            .await?;
+            Ok(written.rows_affected() > 0)
+        })
+    }
+
+    fn insert_profile(
+        &self,
+        character: Cow<Character>,
+        profile: CachedProfile,
+    ) -> Result<bool, Self::Error> {
+        // Profiles don't compare; a fresh fetch always counts as an update.
+        let text = serde_json::to_string(&*profile.profile)?;
+        self.block(async {
+            sqlx::query(
+                "INSERT INTO profiles (character, profile, fetched_at) VALUES ($1, $2, $3)
+                 ON CONFLICT (character) DO UPDATE SET profile = EXCLUDED.profile,
+                     fetched_at = EXCLUDED.fetched_at",
+            )
+            .bind(name(&character))
+            .bind(&text)
+            .bind(profile.fetched_at)
+            .execute(&self.pool)
+            .await?;
+            Ok(true)
+        })
+    }
+
+    fn set_memo(&self, character: Cow<Character>, memo: Cow<str>) -> Result<bool, Self::Error> {
+        self.block(async {
+            let written = sqlx::query(
+                "INSERT INTO memos (character, memo) VALUES ($1, $2)
+                 ON CONFLICT (character) DO UPDATE SET memo = EXCLUDED.memo
+                 WHERE memos.memo IS DISTINCT FROM EXCLUDED.memo",
+            )
+            .bind(name(&character))
+            .bind(&*memo)
+            .execute(&self.pool)
+            .await?;
+            Ok(written.rows_affected() > 0)
+        })
+    }
+
+    fn remove_report(&self, callid: u32) -> Result<bool, Self::Error> {
+        self.block(async {
+            let removed = sqlx::query("DELETE FROM reports WHERE callid = $1")
+                .bind(i64::from(callid))
+                .execute(&self.pool)
+                .await?;
+            Ok(removed.rows_affected() > 0)
+        })
+    }
+
+    fn purge_character(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.block(async {
+            let mut tx = self.pool.begin().await?;
+            let mut purged = 0;
+            let sources = read_pm_sources(&mut tx)
+                .await?
+                .into_iter()
+                .filter(|(_, own, other)| *own == *character || *other == *character)
+                .map(|(source, ..)| source)
+                .collect::<Vec<_>>();
+            purged += sqlx::query("DELETE FROM messages WHERE source = ANY($1)")
+                .bind(sources)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+            for table in ["messages", "ads", "memos", "profiles"] {
+                purged += sqlx::query(&format!("DELETE FROM {table} WHERE character = $1"))
+                    .bind(name(&character))
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+            }
+            tx.commit().await?;
+            Ok(purged > 0)
+        })
+    }
+
+    fn add_channel_member(
+        &self,
+        channel: Cow<Channel>,
+        member: Character,
+    ) -> Result<bool, Self::Error> {
+        self.block(async {
+            let mut tx = self.pool.begin().await?;
+            ensure_channel(&mut tx, &channel).await?;
+            let added = add_ordered(&mut tx, "channel_members", &channel, &member).await?;
+            tx.commit().await?;
+            Ok(added)
+        })
+    }
+
+    fn remove_channel_member(
+        &self,
+        channel: Cow<Channel>,
+        member: Character,
+    ) -> Result<bool, Self::Error> {
+        self.block(async {
+            let mut tx = self.pool.begin().await?;
+            let changed = remove_ordered(&mut tx, "channel_members", &channel, &member).await?;
+            tx.commit().await?;
+            Ok(changed)
+        })
+    }
+
+    fn add_bookmark(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.block(async {
+            add_relation(&mut *self.pool.acquire().await?, BOOKMARK, &character).await
+        })
+    }
+
+    fn remove_bookmark(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.block(async {
+            remove_relation(&mut *self.pool.acquire().await?, BOOKMARK, &character).await
+        })
+    }
+
+    fn add_global_op(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.block(async {
+            add_relation(&mut *self.pool.acquire().await?, GLOBAL_OP, &character).await
+        })
+    }
+
+    fn remove_global_op(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.block(async {
+            remove_relation(&mut *self.pool.acquire().await?, GLOBAL_OP, &character).await
+        })
+    }
+
+    fn add_ignored(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.block(async {
+            add_relation(&mut *self.pool.acquire().await?, IGNORED, &character).await
+        })
+    }
+
+    fn remove_ignored(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        self.block(async {
+            remove_relation(&mut *self.pool.acquire().await?, IGNORED, &character).await
+        })
+    }
+
+    fn add_channel_op(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        self.block(async {
+            let mut tx = self.pool.begin().await?;
+            let changed = add_ordered(&mut tx, "channel_ops", &channel, &character).await?;
+            tx.commit().await?;
+            Ok(changed)
+        })
+    }
+
+    fn remove_channel_op(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        self.block(async {
+            let mut tx = self.pool.begin().await?;
+            let changed = remove_ordered(&mut tx, "channel_ops", &channel, &character).await?;
+            tx.commit().await?;
+            Ok(changed)
+        })
+    }
+
+    fn add_channel_ban(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        self.block(async {
+            let mut tx = self.pool.begin().await?;
+            let changed = write_ban(&mut tx, &channel, &character, None).await?;
+            tx.commit().await?;
+            Ok(changed)
+        })
+    }
+
+    fn remove_channel_ban(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        self.block(async {
+            let removed =
+                sqlx::query("DELETE FROM channel_bans WHERE channel = $1 AND character = $2")
+                    .bind(channel.0.to_string())
+                    .bind(name(&character))
+                    .execute(&self.pool)
+                    .await?;
+            Ok(removed.rows_affected() > 0)
+        })
+    }
+
+    fn add_channel_timeout(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+        expires: Timestamp,
+    ) -> Result<bool, Self::Error> {
+        self.block(async {
+            let mut tx = self.pool.begin().await?;
+            let changed = write_ban(&mut tx, &channel, &character, Some(expires)).await?;
+            tx.commit().await?;
+            Ok(changed)
+        })
+    }
+
+    fn update_channel(
+        &self,
+        channel: Cow<Channel>,
+        data: PartialChannelData,
+    ) -> Result<bool, Self::Error> {
+        self.block(async {
+            let mut tx = self.pool.begin().await?;
+            lock(&mut tx, &format!("channels:{}", channel.0)).await?;
+            let existing = read_channel(&mut tx, &channel).await?;
+            let is_new = existing.is_none();
+            let mut current = existing.unwrap_or_else(|| ChannelData {
+                channel: (*channel).clone(),
+                ..Default::default()
+            });
+            let changed = apply_channel_data(&mut current, data);
+            if changed || is_new {
+                write_channel(&mut tx, &current).await?;
+            }
+            tx.commit().await?;
+            Ok(changed)
+        })
+    }
+
+    fn update_character(
+        &self,
+        character: Cow<Character>,
+        data: PartialUserData,
+    ) -> Result<bool, Self::Error> {
+        self.block(async {
+            let mut tx = self.pool.begin().await?;
+            lock(&mut tx, &format!("characters:{}", character.0)).await?;
+            let existing = read_character(&mut tx, &character).await?;
+            // Hearing about someone new is a change in itself.
+            let is_new = existing.is_none();
+            let mut current = existing.unwrap_or_else(|| CharacterData {
+                character: *character,
+                ..Default::default()
+            });
+            let changed = apply_user_data(&mut current, data) || is_new;
+            if changed {
+                sqlx::query(&format!(
+                    "INSERT INTO characters ({CHARACTER_COLUMNS})
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+                     ON CONFLICT (character) DO UPDATE SET gender = EXCLUDED.gender,
+                         status = EXCLUDED.status, status_message = EXCLUDED.status_message,
+                         typing = EXCLUDED.typing, orientation = EXCLUDED.orientation,
+                         languages = EXCLUDED.languages,
+                         furry_preference = EXCLUDED.furry_preference, role = EXCLUDED.role,
+                         last_profile_update = EXCLUDED.last_profile_update"
+                ))
+                .bind(name(&current.character))
+                .bind(enum_text(&current.gender)?)
+                .bind(enum_text(&current.status)?)
+                .bind(&current.status_message)
+                .bind(enum_text(&current.typing)?)
+                .bind(current.orientation.as_ref().map(enum_text).transpose()?)
+                .bind(serde_json::to_string(&current.languages)?)
+                .bind(
+                    current
+                        .furry_preference
+                        .as_ref()
+                        .map(enum_text)
+                        .transpose()?,
+                )
+                .bind(current.role.as_ref().map(enum_text).transpose()?)
+                .bind(current.last_profile_update)
+                .execute(&mut *tx)
+                .await?;
+            }
+            tx.commit().await?;
+            Ok(changed)
+        })
+    }
+
+    fn set_friends(&self, friends: Cow<[FriendRelation]>) -> Result<bool, Self::Error> {
+        self.block(async {
+            let mut tx = self.pool.begin().await?;
+            lock(&mut tx, "friends").await?;
+            let old = read_friends(&mut tx)
+                .await?
+                .into_iter()
+                .collect::<HashSet<_>>();
+            let new = friends.iter().copied().collect::<HashSet<_>>();
+            if old == new {
+                return Ok(false);
+            }
+            sqlx::query("DELETE FROM friends").execute(&mut *tx).await?;
+            let mut own = Vec::with_capacity(new.len());
+            let mut other = Vec::with_capacity(new.len());
+            let mut last_online = Vec::with_capacity(new.len());
+            for relation in &new {
+                own.push(name(&relation.own_character));
+                other.push(name(&relation.other_character));
+                last_online.push(relation.last_online.map(micros));
+            }
+            sqlx::query(
+                "INSERT INTO friends (own_character, other_character, last_online)
+                 SELECT * FROM UNNEST($1::TEXT[], $2::TEXT[], $3::TIMESTAMPTZ[])",
+            )
+            .bind(own)
+            .bind(other)
+            .bind(last_online)
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+            Ok(true)
+        })
+    }
+
+    fn set_bookmarks(&self, bookmarks: Cow<[Character]>) -> Result<bool, Self::Error> {
+        self.block(async {
+            let mut tx = self.pool.begin().await?;
+            let changed = replace_relations(&mut tx, BOOKMARK, &bookmarks).await?;
+            tx.commit().await?;
+            Ok(changed)
+        })
+    }
+
+    fn set_ignored(&self, ignored: Cow<[Character]>) -> Result<bool, Self::Error> {
+        self.block(async {
+            let mut tx = self.pool.begin().await?;
+            let changed = replace_relations(&mut tx, IGNORED, &ignored).await?;
+            tx.commit().await?;
+            Ok(changed)
+        })
+    }
+
+    fn set_incoming_friend_requests(
+        &self,
+        requests: Cow<[PendingFriendRequest]>,
+    ) -> Result<bool, Self::Error> {
+        self.block(async {
+            let mut tx = self.pool.begin().await?;
+            let changed = replace_friend_requests(&mut tx, true, &requests).await?;
+            tx.commit().await?;
+            Ok(changed)
+        })
+    }
+
+    fn set_outgoing_friend_requests(
+        &self,
+        requests: Cow<[PendingFriendRequest]>,
+    ) -> Result<bool, Self::Error> {
+        self.block(async {
+            let mut tx = self.pool.begin().await?;
+            let changed = replace_friend_requests(&mut tx, false, &requests).await?;
+            tx.commit().await?;
+            Ok(changed)
+        })
+    }
+
+    fn set_channel_members(
+        &self,
+        channel: Cow<Channel>,
+        members: Cow<[Character]>,
+    ) -> Result<bool, Self::Error> {
+        self.block(async {
+            let mut tx = self.pool.begin().await?;
+            ensure_channel(&mut tx, &channel).await?;
+            let changed = replace_ordered(&mut tx, "channel_members", &channel, &members).await?;
+            tx.commit().await?;
+            Ok(changed)
+        })
+    }
+
+    fn set_global_channels(
+        &self,
+        channels: Cow<[(Channel, u32)]>,
+        fetched_at: Timestamp,
+    ) -> Result<bool, Self::Error> {
+        self.block(async {
+            let mut tx = self.pool.begin().await?;
+            let changed = write_listing(&mut tx, GLOBAL_CHANNELS, &channels, fetched_at).await?;
+            tx.commit().await?;
+            Ok(changed)
+        })
+    }
+
+    fn set_unofficial_channels(
+        &self,
+        channels: Cow<[(Channel, u32)]>,
+        fetched_at: Timestamp,
+    ) -> Result<bool, Self::Error> {
+        self.block(async {
+            let mut tx = self.pool.begin().await?;
+            let changed =
+                write_listing(&mut tx, UNOFFICIAL_CHANNELS, &channels, fetched_at).await?;
+            tx.commit().await?;
+            Ok(changed)
+        })
+    }
+
+    fn set_global_ops(&self, ops: Cow<[Character]>) -> Result<bool, Self::Error> {
+        self.block(async {
+            let mut tx = self.pool.begin().await?;
+            let changed = replace_relations(&mut tx, GLOBAL_OP, &ops).await?;
+            tx.commit().await?;
+            Ok(changed)
+        })
+    }
+
+    fn set_channel_bans(
+        &self,
+        channel: Cow<Channel>,
+        bans: Cow<[Character]>,
+    ) -> Result<bool, Self::Error> {
+        self.block(async {
+            let mut tx = self.pool.begin().await?;
+            lock(&mut tx, &format!("channel_bans:{}", channel.0)).await?;
+            let now = Utc::now();
+            let old = read_bans(&mut tx, &channel).await?;
+            let mut new = bans
+                .iter()
+                .map(|character| ChannelBan {
+                    character: *character,
+                    expires: old
+                        .iter()
+                        .find(|ban| ban.character == *character)
+                        .and_then(|ban| ban.expires)
+                        .filter(|expires| *expires > now),
+                })
+                .collect::<Vec<_>>();
+            new.sort_by_key(|ban| ban.character);
+            new.dedup();
+            if old == new {
+                return Ok(false);
+            }
+            sqlx::query("DELETE FROM channel_bans WHERE channel = $1")
+                .bind(channel.0.to_string())
+                .execute(&mut *tx)
+                .await?;
+            for ban in new {
+                write_ban(&mut tx, &channel, &ban.character, ban.expires).await?;
+            }
+            tx.commit().await?;
+            Ok(true)
+        })
+    }
+
+    fn set_channel_ops(
+        &self,
+        channel: Cow<Channel>,
+        ops: Cow<[Character]>,
+    ) -> Result<bool, Self::Error> {
+        self.block(async {
+            let mut tx = self.pool.begin().await?;
+            let changed = replace_ordered(&mut tx, "channel_ops", &channel, &ops).await?;
+            tx.commit().await?;
+            Ok(changed)
+        })
+    }
+
+    fn get_channel(&self, channel: &Channel) -> Result<Option<ChannelData>, Self::Error> {
+        self.block(async { read_channel(&mut *self.pool.acquire().await?, channel).await })
+    }
+
+    fn get_channels(&self) -> Result<Cow<'_, [ChannelData]>, Self::Error> {
+        self.block(async {
+            let mut conn = self.pool.acquire().await?;
+            let names =
+                sqlx::query_scalar::<_, String>("SELECT channel FROM channels ORDER BY channel")
+                    .fetch_all(&mut *conn)
+                    .await?;
+            let mut channels = Vec::with_capacity(names.len());
+            for channel in names {
+                if let Some(data) =
+                    read_channel(&mut conn, &Channel(channel.as_str().into())).await?
+                {
+                    channels.push(data);
+                }
+            }
+            Ok(channels.into())
+        })
+    }
+
+    fn get_character(&self, character: &Character) -> Result<Option<CharacterData>, Self::Error> {
+        self.block(async { read_character(&mut *self.pool.acquire().await?, character).await })
+    }
+
+    fn get_profile(&self, character: &Character) -> Result<Option<CachedProfile>, Self::Error> {
+        let row = self.block(async {
+            Ok(sqlx::query_as::<_, (String, Timestamp)>(
+                "SELECT profile, fetched_at FROM profiles WHERE character = $1",
+            )
+            .bind(name(character))
+            .fetch_optional(&self.pool)
+            .await?)
+        })?;
+        row.map(|(profile, fetched_at)| {
+            Ok(CachedProfile {
+                profile: Arc::new(serde_json::from_str(&profile)?),
+                fetched_at,
+            })
+        })
+        .transpose()
+    }
+
+    fn set_kink_mapping(&self, mapping: CachedKinkMapping) -> Result<bool, Self::Error> {
+        let text = serde_json::to_string(&*mapping.mapping)?;
+        // There's only ever the one row.
+        self.block(async {
+            sqlx::query(
+                "INSERT INTO kink_mapping (id, mapping, fetched_at) VALUES (0, $1, $2)
+                 ON CONFLICT (id) DO UPDATE
+                 SET mapping = EXCLUDED.mapping, fetched_at = EXCLUDED.fetched_at",
+            )
+            .bind(&text)
+            .bind(mapping.fetched_at)
+            .execute(&self.pool)
+            .await?;
+            Ok(true)
+        })
+    }
+
+    fn get_kink_mapping(&self) -> Result<Option<CachedKinkMapping>, Self::Error> {
+        let row = self.block(async {
+            Ok(sqlx::query_as::<_, (String, Timestamp)>(
+                "SELECT mapping, fetched_at FROM kink_mapping WHERE id = 0",
+            )
+            .fetch_optional(&self.pool)
+            .await?)
+        })?;
+        row.map(|(mapping, fetched_at)| {
+            Ok(CachedKinkMapping {
+                mapping: Arc::new(serde_json::from_str(&mapping)?),
+                fetched_at,
+            })
+        })
+        .transpose()
+    }
+
+    fn get_global_ops(&self) -> Result<Cow<'_, [Character]>, Self::Error> {
+        self.block(async {
+            Ok(read_relations(&mut *self.pool.acquire().await?, GLOBAL_OP)
+                .await?
+                .into())
+        })
+    }
+
+    fn get_channel_ops(&self, channel: &Channel) -> Result<Cow<'_, [Character]>, Self::Error> {
+        self.block(async {
+            let mut conn = self.pool.acquire().await?;
+            Ok(read_ordered(&mut conn, "channel_ops", channel)
+                .await?
+                .into())
+        })
+    }
+
+    fn get_channel_members(&self, channel: &Channel) -> Result<Cow<'_, [Character]>, Self::Error> {
+        self.block(async {
+            let mut conn = self.pool.acquire().await?;
+            Ok(read_ordered(&mut conn, "channel_members", channel)
+                .await?
+                .into())
+        })
+    }
+
+    fn get_channel_bans(&self, channel: &Channel) -> Result<Cow<'_, [ChannelBan]>, Self::Error> {
+        let now = Utc::now();
+        let mut bans =
+            self.block(async { read_bans(&mut *self.pool.acquire().await?, channel).await })?;
+        bans.retain(|ban| ban.in_effect(now));
+        Ok(bans.into())
+    }
+
+    fn get_ads(
+        &self,
+        channel: &Channel,
+        since: Option<Timestamp>,
+    ) -> Result<Cow<'_, [CachedAd]>, Self::Error> {
+        let since = self.retention.ads_since(since, Utc::now());
+        let rows = self.block(async {
+            Ok(sqlx::query_as::<_, (String, String, Timestamp)>(
+                "SELECT character, ad, timestamp FROM ads
+                 WHERE channel = $1 AND ($2::TIMESTAMPTZ IS NULL OR timestamp >= $2)
+                 ORDER BY timestamp",
+            )
+            .bind(channel.0.to_string())
+            .bind(since)
+            .fetch_all(&self.pool)
+            .await?)
+        })?;
+        Ok(rows
+            .into_iter()
+            .map(|(who, ad, timestamp)| CachedAd {
+                character: character(who),
+                ad,
+                timestamp,
+            })
+            .collect::<Vec<_>>()
+            .into())
+    }
+
+    fn get_global_channels(&self) -> Result<Option<ChannelListing>, Self::Error> {
+        self.block(async { read_listing(&mut *self.pool.acquire().await?, GLOBAL_CHANNELS).await })
+    }
+
+    fn get_unofficial_channels(&self) -> Result<Option<ChannelListing>, Self::Error> {
+        self.block(async {
+            read_listing(&mut *self.pool.acquire().await?, UNOFFICIAL_CHANNELS).await
+        })
+    }
+
+    fn get_memo(&self, character: &Character) -> Result<Option<String>, Self::Error> {
+        self.block(async {
+            Ok(
+                sqlx::query_scalar::<_, String>("SELECT memo FROM memos WHERE character = $1")
+                    .bind(name(character))
+                    .fetch_optional(&self.pool)
+                    .await?,
+            )
+        })
+    }
+
+    fn get_characters(&self) -> Result<Cow<'_, [CharacterData]>, Self::Error> {
+        let rows: Vec<CharacterRow> = self.block(async {
+            Ok(sqlx::query_as(&format!(
+                "SELECT {CHARACTER_COLUMNS} FROM characters ORDER BY character"
+            ))
+            .fetch_all(&self.pool)
+            .await?)
+        })?;
+        rows.into_iter()
+            .map(CharacterRow::parse)
+            .collect::<PostgresResult<Vec<_>>>()
+            .map(Cow::Owned)
+    }
+
+    // Yields the newest `limit` messages at or after `since`, oldest first.
+    fn get_messages(
+        &self,
+        source: &MessageChannel,
+        since: Option<Timestamp>,
+        limit: Option<u32>,
+    ) -> Result<Cow<'_, [Message]>, Self::Error> {
+        let key = serde_json::to_string(source)?;
+        let rows = self.block(async {
+            Ok(sqlx::query_as::<_, (Timestamp, String, String)>(
+                "SELECT timestamp, character, content FROM messages
+                 WHERE source = $1 AND ($2::TIMESTAMPTZ IS NULL OR timestamp >= $2)
+                 ORDER BY id DESC LIMIT $3",
+            )
+            .bind(&key)
+            .bind(since)
+            .bind(limit.map(i64::from)) // NULL is no limit
+            .fetch_all(&self.pool)
+            .await?)
+        })?;
+        let mut messages = rows
+            .into_iter()
+            .map(to_message)
+            .collect::<PostgresResult<Vec<_>>>()?;
+        messages.reverse();
+        Ok(messages.into())
+    }
+
+    fn get_pm_partners(
+        &self,
+        own_character: &Character,
+    ) -> Result<Cow<'_, [Character]>, Self::Error> {
+        let sources =
+            self.block(async { read_pm_sources(&mut *self.pool.acquire().await?).await })?;
+        Ok(sources
+            .into_iter()
+            .filter(|(_, own, _)| own == own_character)
+            .map(|(_, _, other)| other)
+            .collect::<Vec<_>>()
+            .into())
+    }
+
+    fn get_friend_relations(&self) -> Result<Cow<'_, [FriendRelation]>, Self::Error> {
+        self.block(async { Ok(read_friends(&mut *self.pool.acquire().await?).await?.into()) })
+    }
+
+    fn get_bookmarks(&self) -> Result<Cow<'_, [Character]>, Self::Error> {
+        self.block(async {
+            Ok(read_relations(&mut *self.pool.acquire().await?, BOOKMARK)
+                .await?
+                .into())
+        })
+    }
+
+    fn get_reports(&self) -> Result<Cow<'_, [StaffReport]>, Self::Error> {
+        let rows = self.block(async {
+            Ok(
+                sqlx::query_scalar::<_, String>("SELECT report FROM reports ORDER BY callid")
+                    .fetch_all(&self.pool)
+                    .await?,
+            )
+        })?;
+        rows.iter()
+            .map(|report| Ok(serde_json::from_str(report)?))
+            .collect::<PostgresResult<Vec<_>>>()
+            .map(Cow::Owned)
+    }
+
+    fn get_ignored(&self) -> Result<Cow<'_, [Character]>, Self::Error> {
+        self.block(async {
+            Ok(read_relations(&mut *self.pool.acquire().await?, IGNORED)
+                .await?
+                .into())
+        })
+    }
+
+    fn get_incoming_friend_requests(&self) -> Result<Cow<'_, [PendingFriendRequest]>, Self::Error> {
+        self.block(async {
+            Ok(read_friend_requests(&mut *self.pool.acquire().await?, true)
+                .await?
+                .into())
+        })
+    }
+
+    fn get_outgoing_friend_requests(&self) -> Result<Cow<'_, [PendingFriendRequest]>, Self::Error> {
+        self.block(async {
+            Ok(
+                read_friend_requests(&mut *self.pool.acquire().await?, false)
+                    .await?
+                    .into(),
+            )
+        })
+    }
+}
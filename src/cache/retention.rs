@@ -0,0 +1,169 @@
+// How much message history (and how many ads) the provided caches hold on to. Left alone, a
+// long-running client keeps every message it ever saw.
+//
+// Limits are checked as messages come in, for that history only. Cache::compact goes over
+// everything, which is what catches histories that have gone quiet and ads that have aged out.
+
+use std::{collections::HashMap, fmt, sync::Arc, time::Duration};
+
+use crate::{
+    data::{Message, MessageChannel},
+    util::timestamp::Timestamp,
+};
+
+/// Limits on one message history. Either, both or neither can be set; the default keeps everything.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Retention {
+    pub max_messages: Option<usize>,
+    pub max_age: Option<Duration>,
+}
+
+impl Retention {
+    pub const FOREVER: Retention = Retention {
+        max_messages: None,
+        max_age: None,
+    };
+
+    pub fn max_messages(max_messages: usize) -> Self {
+        Retention {
+            max_messages: Some(max_messages),
+            ..Default::default()
+        }
+    }
+
+    pub fn max_age(max_age: Duration) -> Self {
+        Retention {
+            max_age: Some(max_age),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_max_messages(self, max_messages: usize) -> Self {
+        Retention {
+            max_messages: Some(max_messages),
+            ..self
+        }
+    }
+
+    pub fn with_max_age(self, max_age: Duration) -> Self {
+        Retention {
+            max_age: Some(max_age),
+            ..self
+        }
+    }
+
+    pub fn is_forever(&self) -> bool {
+        *self == Retention::FOREVER
+    }
+
+    // Anything older than this goes.
+    pub(crate) fn cutoff(&self, now: Timestamp) -> Option<Timestamp> {
+        let max_age = chrono::Duration::from_std(self.max_age?).ok()?;
+        now.checked_sub_signed(max_age)
+    }
+}
+
+/// Kinds of message history that can be given their own retention,
+/// e.g. keeping PMs forever while channels only keep a week.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RetentionClass {
+    Channel,
+    PrivateMessage,
+}
+
+impl RetentionClass {
+    pub fn of(source: &MessageChannel) -> Self {
+        match source {
+            MessageChannel::Channel(_) => RetentionClass::Channel,
+            MessageChannel::PrivateMessage(..) => RetentionClass::PrivateMessage,
+        }
+    }
+}
+
+/// Called with whatever a cache drops to stay within its retention, e.g. to archive it somewhere.
+/// Runs inside the cache call, so it shouldn't block for long.
+pub trait EvictionHook: Send + Sync {
+    fn evicted(&self, source: MessageChannel, messages: Vec<Message>);
+}
+
+impl<F: Fn(MessageChannel, Vec<Message>) + Send + Sync> EvictionHook for F {
+    fn evicted(&self, source: MessageChannel, messages: Vec<Message>) {
+        self(source, messages)
+    }
+}
+
+// What a cache keeps per message history, and who hears about what it drops.
+#[derive(Default, Clone)]
+pub(crate) struct RetentionPolicy {
+    pub(crate) default: Retention,
+    pub(crate) classes: HashMap<RetentionClass, Retention>,
+    pub(crate) sources: HashMap<MessageChannel, Retention>,
+    pub(crate) ads: Option<Duration>, // Max age
+    pub(crate) hook: Option<Arc<dyn EvictionHook>>,
+}
+
+impl fmt::Debug for RetentionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetentionPolicy")
+            .field("default", &self.default)
+            .field("classes", &self.classes)
+            .field("sources", &self.sources)
+            .field("ads", &self.ads)
+            .field("hook", &self.hook.is_some())
+            .finish()
+    }
+}
+
+impl RetentionPolicy {
+    // The most specific limit wins: the source's own, then its class's, then the default.
+    pub(crate) fn for_source(&self, source: &MessageChannel) -> Retention {
+        self.sources
+            .get(source)
+            .or_else(|| self.classes.get(&RetentionClass::of(source)))
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    // Ads posted before this go.
+    pub(crate) fn ad_cutoff(&self, now: Timestamp) -> Option<Timestamp> {
+        Retention::max_age(self.ads?).cutoff(now)
+    }
+
+    // Where get_ads starts from: expired ads are left out even if compaction hasn't got to them.
+    pub(crate) fn ads_since(&self, since: Option<Timestamp>, now: Timestamp) -> Option<Timestamp> {
+        since.max(self.ad_cutoff(now))
+    }
+
+    // Trims a history kept in insertion order, and hands back what it took out.
+    pub(crate) fn trim(
+        &self,
+        source: &MessageChannel,
+        messages: &mut Vec<Message>,
+        now: Timestamp,
+    ) -> Vec<Message> {
+        let retention = self.for_source(source);
+        let mut evicted = Vec::new();
+        if let Some(cutoff) = retention.cutoff(now) {
+            let (old, kept) = messages
+                .drain(..)
+                .partition(|message| message.timestamp < cutoff);
+            evicted = old;
+            *messages = kept;
+        }
+        if let Some(max) = retention.max_messages {
+            if messages.len() > max {
+                let excess = messages.len() - max;
+                evicted.extend(messages.drain(..excess));
+            }
+        }
+        evicted
+    }
+
+    pub(crate) fn notify(&self, source: MessageChannel, evicted: Vec<Message>) {
+        if let Some(hook) = &self.hook {
+            if !evicted.is_empty() {
+                hook.evicted(source, evicted);
+            }
+        }
+    }
+}
@@ -0,0 +1,1446 @@
+// Cache that keeps everything in SQLite, so logs and profiles survive a restart.
+// One connection behind a mutex; the client only ever touches the cache from its event loop
+// and the odd API call, so there's nothing to gain from a pool.
+
+use std::{borrow::Cow, collections::HashSet, path::Path, sync::Arc, time::Duration};
+
+use chrono::{TimeZone, Utc};
+use parking_lot::Mutex;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::{
+    apply_channel_data, apply_user_data, fill_channel_data,
+    columns::{character, enum_text, name, parse_enum},
+    retention::{EvictionHook, Retention, RetentionClass, RetentionPolicy},
+    Cache, CachedAd, CachedKinkMapping, CachedProfile, ChannelBan, ChannelListing,
+    PartialChannelData, PartialUserData,
+};
+use crate::{
+    data::{
+        Channel, ChannelData, Character, CharacterData, FriendRelation, Message, MessageChannel,
+        PendingFriendRequest, StaffReport,
+    },
+    util::timestamp::Timestamp,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum SqliteCacheError {
+    #[error("Error from SQLite")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("Failed to (de)serialize a cached value")]
+    Json(#[from] serde_json::Error),
+    #[error("Database schema version {0} is newer than this version of the cache knows about")]
+    UnknownSchema(i64),
+}
+
+type SqliteResult<T> = Result<T, SqliteCacheError>;
+
+// Each entry moves the schema up one version (PRAGMA user_version). Never edit one that's shipped;
+// add another.
+const MIGRATIONS: &[&str] = &[r#"
+CREATE TABLE channels (
+    channel TEXT PRIMARY KEY,
+    mode TEXT NOT NULL,
+    title TEXT NOT NULL,
+    description TEXT NOT NULL,
+    owner TEXT
+);
+CREATE TABLE channel_members (
+    channel TEXT NOT NULL,
+    character TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    PRIMARY KEY (channel, character)
+);
+CREATE TABLE channel_ops (
+    channel TEXT NOT NULL,
+    character TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    PRIMARY KEY (channel, character)
+);
+CREATE TABLE characters (
+    character TEXT PRIMARY KEY,
+    gender TEXT NOT NULL,
+    status TEXT NOT NULL,
+    status_message TEXT NOT NULL
+);
+CREATE TABLE messages (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    source TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    character TEXT NOT NULL,
+    content TEXT NOT NULL
+);
+CREATE INDEX messages_by_source ON messages (source, timestamp);
+CREATE TABLE ads (
+    channel TEXT NOT NULL,
+    character TEXT NOT NULL,
+    ad TEXT NOT NULL,
+    PRIMARY KEY (channel, character)
+);
+CREATE TABLE reports (
+    callid INTEGER PRIMARY KEY,
+    report TEXT NOT NULL
+);
+CREATE TABLE profiles (
+    character TEXT PRIMARY KEY,
+    profile TEXT NOT NULL,
+    fetched_at INTEGER NOT NULL
+);
+CREATE TABLE memos (
+    character TEXT PRIMARY KEY,
+    memo TEXT NOT NULL
+);
+CREATE TABLE relations (
+    kind TEXT NOT NULL,
+    character TEXT NOT NULL,
+    PRIMARY KEY (kind, character)
+);
+CREATE TABLE friends (
+    own_character TEXT NOT NULL,
+    other_character TEXT NOT NULL,
+    PRIMARY KEY (own_character, other_character)
+);
+CREATE TABLE friend_requests (
+    id INTEGER PRIMARY KEY,
+    source TEXT NOT NULL,
+    dest TEXT NOT NULL,
+    incoming INTEGER NOT NULL
+);
+CREATE TABLE channel_listings (
+    kind TEXT PRIMARY KEY,
+    channels TEXT NOT NULL,
+    fetched_at INTEGER NOT NULL
+);
+"#,
+    r#"
+ALTER TABLE ads ADD COLUMN timestamp INTEGER NOT NULL DEFAULT 0;
+"#,
+    r#"
+CREATE TABLE channel_bans (
+    channel TEXT NOT NULL,
+    character TEXT NOT NULL,
+    expires INTEGER,
+    PRIMARY KEY (channel, character)
+);
+"#,
+    r#"
+CREATE TABLE kink_mapping (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    mapping TEXT NOT NULL
+);
+"#,
+    r#"
+ALTER TABLE characters ADD COLUMN typing TEXT NOT NULL DEFAULT 'clear';
+"#,
+    r#"
+ALTER TABLE kink_mapping ADD COLUMN fetched_at INTEGER NOT NULL DEFAULT 0;
+"#,
+    r#"
+ALTER TABLE channels ADD COLUMN member_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE channels ADD COLUMN last_activity INTEGER;
+"#,
+    r#"
+ALTER TABLE characters ADD COLUMN orientation TEXT;
+ALTER TABLE characters ADD COLUMN languages TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE characters ADD COLUMN furry_preference TEXT;
+ALTER TABLE characters ADD COLUMN role TEXT;
+ALTER TABLE characters ADD COLUMN last_profile_update INTEGER;
+"#,
+    r#"
+ALTER TABLE friends ADD COLUMN last_online INTEGER;
+"#];
+
+// Kinds for the relations table.
+const BOOKMARK: &str = "bookmark";
+const IGNORED: &str = "ignored";
+const GLOBAL_OP: &str = "global_op";
+
+// And for channel_listings.
+const GLOBAL_CHANNELS: &str = "global";
+const UNOFFICIAL_CHANNELS: &str = "unofficial";
+
+#[derive(Debug)]
+pub struct SqliteCache {
+    conn: Mutex<Connection>,
+    batch_depth: Mutex<u32>, // Nested begin_batch calls; only the outermost opens a transaction
+    retention: RetentionPolicy,
+}
+
+impl SqliteCache {
+    pub fn open<P: AsRef<Path>>(path: P) -> SqliteResult<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> SqliteResult<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    /// Use an already open connection. The schema is created or brought up to date.
+    pub fn from_connection(mut conn: Connection) -> SqliteResult<Self> {
+        migrate(&mut conn)?;
+        Ok(SqliteCache {
+            conn: Mutex::new(conn),
+            batch_depth: Mutex::new(0),
+            retention: RetentionPolicy::default(),
+        })
+    }
+
+    /// Limit every message history. Keeps everything by default.
+    /// Enforced as messages come in, and across the whole database by `compact()`, which
+    /// ClientBuilder::with_cache_compaction runs on an interval.
+    pub fn with_retention(mut self, retention: Retention) -> Self {
+        self.retention.default = retention;
+        self
+    }
+
+    /// Limit one message history, overriding `with_retention` for it.
+    pub fn with_source_retention(mut self, source: MessageChannel, retention: Retention) -> Self {
+        self.retention.sources.insert(source, retention);
+        self
+    }
+
+    /// Limit every history of one class, overriding `with_retention` for them.
+    pub fn with_class_retention(mut self, class: RetentionClass, retention: Retention) -> Self {
+        self.retention.classes.insert(class, retention);
+        self
+    }
+
+    /// Drop ads once they're older than `max_age`. Kept until they're replaced by default.
+    pub fn with_ad_retention(mut self, max_age: Duration) -> Self {
+        self.retention.ads = Some(max_age);
+        self
+    }
+
+    /// Hear about messages as they're deleted for being over the limits.
+    pub fn with_eviction_hook(mut self, hook: impl EvictionHook + 'static) -> Self {
+        self.retention.hook = Some(Arc::new(hook));
+        self
+    }
+}
+
+fn migrate(conn: &mut Connection) -> SqliteResult<()> {
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > MIGRATIONS.len() as i64 {
+        return Err(SqliteCacheError::UnknownSchema(version));
+    }
+    let tx = conn.transaction()?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", i as i64 + 1)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+fn millis(timestamp: &Timestamp) -> i64 {
+    timestamp.timestamp_millis()
+}
+
+fn timestamp(millis: i64) -> Timestamp {
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .unwrap_or_default()
+}
+
+fn read_channel(conn: &Connection, channel: &Channel) -> SqliteResult<Option<ChannelData>> {
+    let Some((mode, title, description, owner, member_count, last_activity)) = conn
+        .query_row(
+            "SELECT mode, title, description, owner, member_count, last_activity
+             FROM channels WHERE channel = ?1",
+            params![channel.0.to_string()],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, u32>(4)?,
+                    row.get::<_, Option<i64>>(5)?,
+                ))
+            },
+        )
+        .optional()?
+    else {
+        return Ok(None);
+    };
+    let mut data = ChannelData {
+        channel: channel.clone(),
+        channel_mode: parse_enum(mode)?,
+        members: read_ordered(conn, "channel_members", channel)?,
+        description,
+        title,
+        owner: owner.map(character),
+        member_count,
+        last_activity: last_activity.map(timestamp),
+        ..Default::default()
+    };
+    fill_channel_data(&mut data, &read_ordered(conn, "channel_ops", channel)?);
+    Ok(Some(data))
+}
+
+fn write_channel(conn: &Connection, data: &ChannelData) -> SqliteResult<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO channels
+         (channel, mode, title, description, owner, member_count, last_activity)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            data.channel.0.to_string(),
+            enum_text(&data.channel_mode)?,
+            data.title,
+            data.description,
+            data.owner.as_ref().map(name),
+            data.member_count,
+            data.last_activity.as_ref().map(millis),
+        ],
+    )?;
+    Ok(())
+}
+
+fn touch_channel(conn: &Connection, channel: &Channel, at: &Timestamp) -> SqliteResult<()> {
+    conn.execute(
+        "UPDATE channels SET last_activity = MAX(COALESCE(last_activity, 0), ?2) WHERE channel = ?1",
+        params![channel.0.to_string(), millis(at)],
+    )?;
+    Ok(())
+}
+
+// Make sure a channel has a row, so members can be attached to it.
+fn ensure_channel(conn: &Connection, channel: &Channel) -> SqliteResult<()> {
+    if read_channel(conn, channel)?.is_none() {
+        write_channel(
+            conn,
+            &ChannelData {
+                channel: channel.clone(),
+                ..Default::default()
+            },
+        )?;
+    }
+    Ok(())
+}
+
+// channel_members and channel_ops both keep characters in the order they were given.
+fn read_ordered(conn: &Connection, table: &str, channel: &Channel) -> SqliteResult<Vec<Character>> {
+    let mut statement = conn.prepare_cached(&format!(
+        "SELECT character FROM {table} WHERE channel = ?1 ORDER BY position"
+    ))?;
+    let rows = statement
+        .query_map(params![channel.0.to_string()], |row| {
+            row.get::<_, String>(0)
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(rows.into_iter().map(character).collect())
+}
+
+fn replace_ordered(
+    conn: &Connection,
+    table: &str,
+    channel: &Channel,
+    characters: &[Character],
+) -> SqliteResult<bool> {
+    if read_ordered(conn, table, channel)? == characters {
+        return Ok(false);
+    }
+    conn.execute(
+        &format!("DELETE FROM {table} WHERE channel = ?1"),
+        params![channel.0.to_string()],
+    )?;
+    let mut insert = conn.prepare_cached(&format!(
+        "INSERT OR IGNORE INTO {table} (channel, character, position) VALUES (?1, ?2, ?3)"
+    ))?;
+    for (position, member) in characters.iter().enumerate() {
+        insert.execute(params![
+            channel.0.to_string(),
+            name(member),
+            position as i64
+        ])?;
+    }
+    Ok(true)
+}
+
+fn add_ordered(
+    conn: &Connection,
+    table: &str,
+    channel: &Channel,
+    member: &Character,
+) -> SqliteResult<bool> {
+    let inserted = conn.execute(
+        &format!(
+            "INSERT OR IGNORE INTO {table} (channel, character, position)
+             SELECT ?1, ?2, COALESCE(MAX(position) + 1, 0) FROM {table} WHERE channel = ?1"
+        ),
+        params![channel.0.to_string(), name(member)],
+    )?;
+    Ok(inserted > 0)
+}
+
+fn remove_ordered(
+    conn: &Connection,
+    table: &str,
+    channel: &Channel,
+    member: &Character,
+) -> SqliteResult<bool> {
+    let removed = conn.execute(
+        &format!("DELETE FROM {table} WHERE channel = ?1 AND character = ?2"),
+        params![channel.0.to_string(), name(member)],
+    )?;
+    Ok(removed > 0)
+}
+
+fn message_row(row: &rusqlite::Row) -> rusqlite::Result<(i64, String, String)> {
+    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+}
+
+fn to_message((at, who, content): (i64, String, String)) -> SqliteResult<Message> {
+    Ok(Message {
+        timestamp: timestamp(at),
+        character: character(who),
+        content: serde_json::from_str(&content)?,
+    })
+}
+
+// Deletes whatever's past the retention limits for one source, oldest first, and returns it.
+fn evict_messages(
+    conn: &Connection,
+    source: &str,
+    retention: Retention,
+) -> SqliteResult<Vec<Message>> {
+    if retention.is_forever() {
+        return Ok(Vec::new());
+    }
+    let cutoff = retention.cutoff(Utc::now());
+    let mut select = conn.prepare_cached(
+        "SELECT id, timestamp, character, content FROM messages
+         WHERE source = ?1 AND (timestamp < ?2 OR id NOT IN
+             (SELECT id FROM messages WHERE source = ?1 ORDER BY id DESC LIMIT ?3))
+         ORDER BY id",
+    )?;
+    let rows = select
+        .query_map(
+            params![
+                source,
+                cutoff.as_ref().map_or(i64::MIN, millis),
+                retention.max_messages.map_or(-1, |max| max as i64),
+            ],
+            |row| Ok((row.get::<_, i64>(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )?
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut delete = conn.prepare_cached("DELETE FROM messages WHERE id = ?1")?;
+    rows.into_iter()
+        .map(|(id, at, who, content)| {
+            delete.execute(params![id])?;
+            to_message((at, who, content))
+        })
+        .collect()
+}
+
+// Sources are stored as JSON, so narrow it down to PMs in SQL and pick them apart here.
+fn read_pm_sources(conn: &Connection) -> SqliteResult<Vec<(String, Character, Character)>> {
+    let mut statement = conn.prepare_cached(
+        r#"SELECT DISTINCT source FROM messages WHERE source LIKE '{"type":"pm"%'"#,
+    )?;
+    let sources = statement
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut pms = Vec::new();
+    for source in sources {
+        if let MessageChannel::PrivateMessage(own, other) = serde_json::from_str(&source)? {
+            pms.push((source, own, other));
+        }
+    }
+    Ok(pms)
+}
+
+const CHARACTER_COLUMNS: &str = "character, gender, status, status_message, typing, \
+    orientation, languages, furry_preference, role, last_profile_update";
+
+// A characters row as it comes out, before the enums are parsed.
+struct CharacterRow {
+    character: String,
+    gender: String,
+    status: String,
+    status_message: String,
+    typing: String,
+    orientation: Option<String>,
+    languages: String, // JSON list
+    furry_preference: Option<String>,
+    role: Option<String>,
+    last_profile_update: Option<i64>,
+}
+
+impl CharacterRow {
+    // For rows selected as CHARACTER_COLUMNS.
+    fn get(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(CharacterRow {
+            character: row.get(0)?,
+            gender: row.get(1)?,
+            status: row.get(2)?,
+            status_message: row.get(3)?,
+            typing: row.get(4)?,
+            orientation: row.get(5)?,
+            languages: row.get(6)?,
+            furry_preference: row.get(7)?,
+            role: row.get(8)?,
+            last_profile_update: row.get(9)?,
+        })
+    }
+
+    fn parse(self) -> SqliteResult<CharacterData> {
+        Ok(CharacterData {
+            character: character(self.character),
+            gender: parse_enum(self.gender)?,
+            status: parse_enum(self.status)?,
+            status_message: self.status_message,
+            typing: parse_enum(self.typing)?,
+            orientation: self.orientation.map(parse_enum).transpose()?,
+            languages: serde_json::from_str(&self.languages)?,
+            furry_preference: self.furry_preference.map(parse_enum).transpose()?,
+            role: self.role.map(parse_enum).transpose()?,
+            last_profile_update: self.last_profile_update.map(timestamp),
+        })
+    }
+}
+
+fn read_character(conn: &Connection, who: &Character) -> SqliteResult<Option<CharacterData>> {
+    conn.query_row(
+        &format!("SELECT {CHARACTER_COLUMNS} FROM characters WHERE character = ?1"),
+        params![name(who)],
+        CharacterRow::get,
+    )
+    .optional()?
+    .map(CharacterRow::parse)
+    .transpose()
+}
+
+fn write_character(conn: &Connection, data: &CharacterData) -> SqliteResult<()> {
+    conn.execute(
+        &format!(
+            "INSERT OR REPLACE INTO characters ({CHARACTER_COLUMNS})
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)"
+        ),
+        params![
+            name(&data.character),
+            enum_text(&data.gender)?,
+            enum_text(&data.status)?,
+            data.status_message,
+            enum_text(&data.typing)?,
+            data.orientation.as_ref().map(enum_text).transpose()?,
+            serde_json::to_string(&data.languages)?,
+            data.furry_preference.as_ref().map(enum_text).transpose()?,
+            data.role.as_ref().map(enum_text).transpose()?,
+            data.last_profile_update.as_ref().map(millis),
+        ],
+    )?;
+    Ok(())
+}
+
+fn read_relations(conn: &Connection, kind: &str) -> SqliteResult<Vec<Character>> {
+    let mut statement =
+        conn.prepare_cached("SELECT character FROM relations WHERE kind = ?1 ORDER BY character")?;
+    let rows = statement
+        .query_map(params![kind], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(rows.into_iter().map(character).collect())
+}
+
+fn replace_relations(
+    conn: &Connection,
+    kind: &str,
+    characters: &[Character],
+) -> SqliteResult<bool> {
+    let old = read_relations(conn, kind)?
+        .into_iter()
+        .collect::<HashSet<_>>();
+    let new = characters.iter().copied().collect::<HashSet<_>>();
+    if old == new {
+        return Ok(false);
+    }
+    conn.execute("DELETE FROM relations WHERE kind = ?1", params![kind])?;
+    let mut insert =
+        conn.prepare_cached("INSERT INTO relations (kind, character) VALUES (?1, ?2)")?;
+    for who in new {
+        insert.execute(params![kind, name(&who)])?;
+    }
+    Ok(true)
+}
+
+fn add_relation(conn: &Connection, kind: &str, who: &Character) -> SqliteResult<bool> {
+    let inserted = conn.execute(
+        "INSERT OR IGNORE INTO relations (kind, character) VALUES (?1, ?2)",
+        params![kind, name(who)],
+    )?;
+    Ok(inserted > 0)
+}
+
+fn remove_relation(conn: &Connection, kind: &str, who: &Character) -> SqliteResult<bool> {
+    let removed = conn.execute(
+        "DELETE FROM relations WHERE kind = ?1 AND character = ?2",
+        params![kind, name(who)],
+    )?;
+    Ok(removed > 0)
+}
+
+fn read_friend_requests(
+    conn: &Connection,
+    incoming: bool,
+) -> SqliteResult<Vec<PendingFriendRequest>> {
+    let mut statement = conn.prepare_cached(
+        "SELECT id, source, dest FROM friend_requests WHERE incoming = ?1 ORDER BY id",
+    )?;
+    let rows = statement
+        .query_map(params![incoming], |row| {
+            Ok(PendingFriendRequest {
+                id: row.get::<_, i64>(0)? as u64,
+                source: character(row.get(1)?),
+                dest: character(row.get(2)?),
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(rows)
+}
+
+fn replace_friend_requests(
+    conn: &Connection,
+    incoming: bool,
+    requests: &[PendingFriendRequest],
+) -> SqliteResult<bool> {
+    let old = read_friend_requests(conn, incoming)?
+        .into_iter()
+        .collect::<HashSet<_>>();
+    let new = requests.iter().copied().collect::<HashSet<_>>();
+    if old == new {
+        return Ok(false);
+    }
+    conn.execute(
+        "DELETE FROM friend_requests WHERE incoming = ?1",
+        params![incoming],
+    )?;
+    let mut insert = conn.prepare_cached(
+        "INSERT OR REPLACE INTO friend_requests (id, source, dest, incoming) VALUES (?1, ?2, ?3, ?4)",
+    )?;
+    for request in new {
+        insert.execute(params![
+            request.id as i64,
+            name(&request.source),
+            name(&request.dest),
+            incoming
+        ])?;
+    }
+    Ok(true)
+}
+
+fn read_friends(conn: &Connection) -> SqliteResult<Vec<FriendRelation>> {
+    let mut statement = conn.prepare_cached(
+        "SELECT own_character, other_character, last_online FROM friends
+         ORDER BY own_character, other_character",
+    )?;
+    let rows = statement
+        .query_map([], |row| {
+            Ok(FriendRelation {
+                own_character: character(row.get(0)?),
+                other_character: character(row.get(1)?),
+                last_online: row.get::<_, Option<i64>>(2)?.map(timestamp),
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(rows)
+}
+
+// Every ban on record for the channel, expired timeouts included, by name.
+fn read_bans(conn: &Connection, channel: &Channel) -> SqliteResult<Vec<ChannelBan>> {
+    let mut statement = conn.prepare_cached(
+        "SELECT character, expires FROM channel_bans WHERE channel = ?1 ORDER BY character",
+    )?;
+    let bans = statement
+        .query_map(params![channel.0.to_string()], |row| {
+            Ok(ChannelBan {
+                character: character(row.get(0)?),
+                expires: row.get::<_, Option<i64>>(1)?.map(timestamp),
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(bans)
+}
+
+fn write_ban(
+    conn: &Connection,
+    channel: &Channel,
+    character: &Character,
+    expires: Option<Timestamp>,
+) -> SqliteResult<bool> {
+    let old: Option<Option<i64>> = conn
+        .query_row(
+            "SELECT expires FROM channel_bans WHERE channel = ?1 AND character = ?2",
+            params![channel.0.to_string(), name(character)],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let expires = expires.as_ref().map(millis);
+    if old == Some(expires) {
+        return Ok(false);
+    }
+    conn.execute(
+        "INSERT OR REPLACE INTO channel_bans (channel, character, expires) VALUES (?1, ?2, ?3)",
+        params![channel.0.to_string(), name(character), expires],
+    )?;
+    Ok(true)
+}
+
+fn read_listing(conn: &Connection, kind: &str) -> SqliteResult<Option<ChannelListing>> {
+    let row = conn
+        .query_row(
+            "SELECT channels, fetched_at FROM channel_listings WHERE kind = ?1",
+            params![kind],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)),
+        )
+        .optional()?;
+    row.map(|(channels, fetched_at)| {
+        Ok(ChannelListing {
+            channels: serde_json::from_str(&channels)?,
+            fetched_at: timestamp(fetched_at),
+        })
+    })
+    .transpose()
+}
+
+fn write_listing(
+    conn: &Connection,
+    kind: &str,
+    channels: &[(Channel, u32)],
+    fetched_at: &Timestamp,
+) -> SqliteResult<bool> {
+    // Always take the new fetch time, but only call it a change if the list is different.
+    let changed = read_listing(conn, kind)?.is_none_or(|old| old.channels[..] != *channels);
+    conn.execute(
+        "INSERT OR REPLACE INTO channel_listings (kind, channels, fetched_at) VALUES (?1, ?2, ?3)",
+        params![kind, serde_json::to_string(channels)?, millis(fetched_at)],
+    )?;
+    Ok(changed)
+}
+
+impl Cache for SqliteCache {
+    type Error = SqliteCacheError;
+
+    // Anyone else writing in the meantime ends up in the same transaction, which is fine for a cache.
+    fn begin_batch(&self) -> Result<(), Self::Error> {
+        let conn = self.conn.lock();
+        let mut depth = self.batch_depth.lock();
+        if *depth == 0 {
+            conn.execute_batch("BEGIN")?;
+        }
+        *depth += 1;
+        Ok(())
+    }
+
+    fn commit_batch(&self) -> Result<(), Self::Error> {
+        let conn = self.conn.lock();
+        let mut depth = self.batch_depth.lock();
+        if *depth == 1 {
+            conn.execute_batch("COMMIT")?;
+        }
+        *depth = depth.saturating_sub(1); // Unbalanced commits do nothing
+        Ok(())
+    }
+
+    fn compact(&self) -> Result<bool, Self::Error> {
+        let now = Utc::now();
+        let mut dropped = false;
+        let mut evicted = Vec::new();
+        {
+            let mut conn = self.conn.lock();
+            let tx = conn.savepoint()?;
+            let sources = tx
+                .prepare_cached("SELECT DISTINCT source FROM messages")?
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            for key in sources {
+                let source: MessageChannel = serde_json::from_str(&key)?;
+                let messages = evict_messages(&tx, &key, self.retention.for_source(&source))?;
+                if !messages.is_empty() {
+                    evicted.push((source, messages));
+                }
+            }
+            if let Some(cutoff) = self.retention.ad_cutoff(now) {
+                dropped |= tx.execute(
+                    "DELETE FROM ads WHERE timestamp < ?1",
+                    params![millis(&cutoff)],
+                )? > 0;
+            }
+            tx.commit()?;
+        }
+        dropped |= !evicted.is_empty();
+        for (source, messages) in evicted {
+            self.retention.notify(source, messages);
+        }
+        Ok(dropped)
+    }
+
+    fn insert_message(
+        &self,
+        source: MessageChannel,
+        message: Message,
+    ) -> Result<bool, Self::Error> {
+        let key = serde_json::to_string(&source)?;
+        let evicted = {
+            let mut conn = self.conn.lock();
+            let tx = conn.savepoint()?;
+            tx.execute(
+                "INSERT INTO messages (source, timestamp, character, content) VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    key,
+                    millis(&message.timestamp),
+                    name(&message.character),
+                    serde_json::to_string(&message.content)?,
+                ],
+            )?;
+            if let MessageChannel::Channel(channel) = &source {
+                touch_channel(&tx, channel, &message.timestamp)?;
+            }
+            let evicted = evict_messages(&tx, &key, self.retention.for_source(&source))?;
+            tx.commit()?;
+            evicted
+        };
+        self.retention.notify(source, evicted);
+        Ok(true)
+    }
+
+    fn insert_channel(
+        &self,
+        channel: Cow<Channel>,
+        data: PartialChannelData,
+        members: Cow<[Character]>,
+    ) -> Result<bool, Self::Error> {
+        let mut conn = self.conn.lock();
+        let tx = conn.savepoint()?;
+        let existing = read_channel(&tx, &channel)?;
+        let is_new = existing.is_none();
+        let mut current = existing.unwrap_or_else(|| ChannelData {
+            channel: (*channel).clone(),
+            ..Default::default()
+        });
+        let mut changed = apply_channel_data(&mut current, data);
+        if changed || is_new {
+            write_channel(&tx, &current)?;
+        }
+        changed |= replace_ordered(&tx, "channel_members", &channel, &members)?;
+        tx.commit()?;
+        Ok(changed)
+    }
+
+    fn insert_ad(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+        ad: Cow<str>,
+        timestamp: Timestamp,
+    ) -> Result<bool, Self::Error> {
+        // Every ad is a new post, even if it says the same thing as last time.
+        let conn = self.conn.lock();
+        conn.execute(
+            "INSERT OR REPLACE INTO ads (channel, character, ad, timestamp) VALUES (?1, ?2, ?3, ?4)",
+            params![channel.0.to_string(), name(&character), ad, millis(&timestamp)],
+        )?;
+        touch_channel(&conn, &channel, &timestamp)?;
+        if let Some(cutoff) = self.retention.ad_cutoff(Utc::now()) {
+            conn.execute(
+                "DELETE FROM ads WHERE channel = ?1 AND timestamp < ?2",
+                params![channel.0.to_string(), millis(&cutoff)],
+            )?;
+        }
+        Ok(true)
+    }
+
+    fn insert_report(&self, report: Cow<StaffReport>) -> Result<bool, Self::Error> {
+        let conn = self.conn.lock();
+        let text = serde_json::to_string(&report)?;
+        let old: Option<String> = conn
+            .query_row(
+                "SELECT report FROM reports WHERE callid = ?1",
+                params![report.callid],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if old.as_deref() == Some(&text) {
+            return Ok(false);
+        }
+        conn.execute(
+            "INSERT OR REPLACE INTO reports (callid, report) VALUES (?1, ?2)",
+            params![report.callid, text],
+        )?;
+        Ok(true)
+    }
+
+    fn insert_profile(
+        &self,
+        character: Cow<Character>,
+        profile: CachedProfile,
+    ) -> Result<bool, Self::Error> {
+        // Profiles don't compare; a fresh fetch always counts as an update.
+        self.conn.lock().execute(
+            "INSERT OR REPLACE INTO profiles (character, profile, fetched_at) VALUES (?1, ?2, ?3)",
+            params![
+                name(&character),
+                serde_json::to_string(&*profile.profile)?,
+                millis(&profile.fetched_at),
+            ],
+        )?;
+        Ok(true)
+    }
+
+    fn set_memo(&self, character: Cow<Character>, memo: Cow<str>) -> Result<bool, Self::Error> {
+        let conn = self.conn.lock();
+        let old: Option<String> = conn
+            .query_row(
+                "SELECT memo FROM memos WHERE character = ?1",
+                params![name(&character)],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if old.as_deref() == Some(&*memo) {
+            return Ok(false);
+        }
+        conn.execute(
+            "INSERT OR REPLACE INTO memos (character, memo) VALUES (?1, ?2)",
+            params![name(&character), memo],
+        )?;
+        Ok(true)
+    }
+
+    fn remove_report(&self, callid: u32) -> Result<bool, Self::Error> {
+        let removed = self
+            .conn
+            .lock()
+            .execute("DELETE FROM reports WHERE callid = ?1", params![callid])?;
+        Ok(removed > 0)
+    }
+
+    fn purge_character(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        let who = name(&character);
+        let mut conn = self.conn.lock();
+        let tx = conn.savepoint()?;
+        let mut purged = 0;
+        for (source, own, other) in read_pm_sources(&tx)? {
+            if own == *character || other == *character {
+                purged += tx.execute("DELETE FROM messages WHERE source = ?1", params![source])?;
+            }
+        }
+        for table in ["messages", "ads", "memos", "profiles"] {
+            purged += tx.execute(
+                &format!("DELETE FROM {table} WHERE character = ?1"),
+                params![who],
+            )?;
+        }
+        tx.commit()?;
+        Ok(purged > 0)
+    }
+
+    fn add_channel_member(
+        &self,
+        channel: Cow<Channel>,
+        member: Character,
+    ) -> Result<bool, Self::Error> {
+        let conn = self.conn.lock();
+        ensure_channel(&conn, &channel)?;
+        add_ordered(&conn, "channel_members", &channel, &member)
+    }
+
+    fn remove_channel_member(
+        &self,
+        channel: Cow<Channel>,
+        member: Character,
+    ) -> Result<bool, Self::Error> {
+        remove_ordered(&self.conn.lock(), "channel_members", &channel, &member)
+    }
+
+    fn add_bookmark(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        add_relation(&self.conn.lock(), BOOKMARK, &character)
+    }
+
+    fn remove_bookmark(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        remove_relation(&self.conn.lock(), BOOKMARK, &character)
+    }
+
+    fn add_global_op(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        add_relation(&self.conn.lock(), GLOBAL_OP, &character)
+    }
+
+    fn remove_global_op(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        remove_relation(&self.conn.lock(), GLOBAL_OP, &character)
+    }
+
+    fn add_ignored(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        add_relation(&self.conn.lock(), IGNORED, &character)
+    }
+
+    fn remove_ignored(&self, character: Cow<Character>) -> Result<bool, Self::Error> {
+        remove_relation(&self.conn.lock(), IGNORED, &character)
+    }
+
+    fn add_channel_op(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        add_ordered(&self.conn.lock(), "channel_ops", &channel, &character)
+    }
+
+    fn remove_channel_op(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        remove_ordered(&self.conn.lock(), "channel_ops", &channel, &character)
+    }
+
+    fn add_channel_ban(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        write_ban(&self.conn.lock(), &channel, &character, None)
+    }
+
+    fn remove_channel_ban(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+    ) -> Result<bool, Self::Error> {
+        let removed = self.conn.lock().execute(
+            "DELETE FROM channel_bans WHERE channel = ?1 AND character = ?2",
+            params![channel.0.to_string(), name(&character)],
+        )?;
+        Ok(removed > 0)
+    }
+
+    fn add_channel_timeout(
+        &self,
+        channel: Cow<Channel>,
+        character: Cow<Character>,
+        expires: Timestamp,
+    ) -> Result<bool, Self::Error> {
+        write_ban(&self.conn.lock(), &channel, &character, Some(expires))
+    }
+
+    fn update_channel(
+        &self,
+        channel: Cow<Channel>,
+        data: PartialChannelData,
+    ) -> Result<bool, Self::Error> {
+        let conn = self.conn.lock();
+        let existing = read_channel(&conn, &channel)?;
+        let is_new = existing.is_none();
+        let mut current = existing.unwrap_or_else(|| ChannelData {
+            channel: (*channel).clone(),
+            ..Default::default()
+        });
+        let changed = apply_channel_data(&mut current, data);
+        if changed || is_new {
+            write_channel(&conn, &current)?;
+        }
+        Ok(changed)
+    }
+
+    fn update_character(
+        &self,
+        character: Cow<Character>,
+        data: PartialUserData,
+    ) -> Result<bool, Self::Error> {
+        let conn = self.conn.lock();
+        let existing = read_character(&conn, &character)?;
+        // Hearing about someone new is a change in itself.
+        let is_new = existing.is_none();
+        let mut current = existing.unwrap_or_else(|| CharacterData {
+            character: *character,
+            ..Default::default()
+        });
+        let changed = apply_user_data(&mut current, data) || is_new;
+        if changed {
+            write_character(&conn, &current)?;
+        }
+        Ok(changed)
+    }
+
+    fn set_friends(&self, friends: Cow<[FriendRelation]>) -> Result<bool, Self::Error> {
+        let mut conn = self.conn.lock();
+        let tx = conn.savepoint()?;
+        let old = read_friends(&tx)?.into_iter().collect::<HashSet<_>>();
+        let new = friends.iter().copied().collect::<HashSet<_>>();
+        if old == new {
+            return Ok(false);
+        }
+        tx.execute("DELETE FROM friends", [])?;
+        {
+            let mut insert = tx.prepare_cached(
+                "INSERT INTO friends (own_character, other_character, last_online)
+                 VALUES (?1, ?2, ?3)",
+            )?;
+            for relation in new {
+                insert.execute(params![
+                    name(&relation.own_character),
+                    name(&relation.other_character),
+                    relation.last_online.as_ref().map(millis),
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(true)
+    }
+
+    fn set_bookmarks(&self, bookmarks: Cow<[Character]>) -> Result<bool, Self::Error> {
+        let mut conn = self.conn.lock();
+        let tx = conn.savepoint()?;
+        let changed = replace_relations(&tx, BOOKMARK, &bookmarks)?;
+        tx.commit()?;
+        Ok(changed)
+    }
+
+    fn set_ignored(&self, ignored: Cow<[Character]>) -> Result<bool, Self::Error> {
+        let mut conn = self.conn.lock();
+        let tx = conn.savepoint()?;
+        let changed = replace_relations(&tx, IGNORED, &ignored)?;
+        tx.commit()?;
+        Ok(changed)
+    }
+
+    fn set_incoming_friend_requests(
+        &self,
+        requests: Cow<[PendingFriendRequest]>,
+    ) -> Result<bool, Self::Error> {
+        let mut conn = self.conn.lock();
+        let tx = conn.savepoint()?;
+        let changed = replace_friend_requests(&tx, true, &requests)?;
+        tx.commit()?;
+        Ok(changed)
+    }
+
+    fn set_outgoing_friend_requests(
+        &self,
+        requests: Cow<[PendingFriendRequest]>,
+    ) -> Result<bool, Self::Error> {
+        let mut conn = self.conn.lock();
+        let tx = conn.savepoint()?;
+        let changed = replace_friend_requests(&tx, false, &requests)?;
+        tx.commit()?;
+        Ok(changed)
+    }
+
+    fn set_channel_members(
+        &self,
+        channel: Cow<Channel>,
+        members: Cow<[Character]>,
+    ) -> Result<bool, Self::Error> {
+        let mut conn = self.conn.lock();
+        let tx = conn.savepoint()?;
+        ensure_channel(&tx, &channel)?;
+        let changed = replace_ordered(&tx, "channel_members", &channel, &members)?;
+        tx.commit()?;
+        Ok(changed)
+    }
+
+    fn set_global_channels(
+        &self,
+        channels: Cow<[(Channel, u32)]>,
+        fetched_at: Timestamp,
+    ) -> Result<bool, Self::Error> {
+        write_listing(&self.conn.lock(), GLOBAL_CHANNELS, &channels, &fetched_at)
+    }
+
+    fn set_unofficial_channels(
+        &self,
+        channels: Cow<[(Channel, u32)]>,
+        fetched_at: Timestamp,
+    ) -> Result<bool, Self::Error> {
+        write_listing(
+            &self.conn.lock(),
+            UNOFFICIAL_CHANNELS,
+            &channels,
+            &fetched_at,
+        )
+    }
+
+    fn set_global_ops(&self, ops: Cow<[Character]>) -> Result<bool, Self::Error> {
+        let mut conn = self.conn.lock();
+        let tx = conn.savepoint()?;
+        let changed = replace_relations(&tx, GLOBAL_OP, &ops)?;
+        tx.commit()?;
+        Ok(changed)
+    }
+
+    fn set_channel_bans(
+        &self,
+        channel: Cow<Channel>,
+        bans: Cow<[Character]>,
+    ) -> Result<bool, Self::Error> {
+        let mut conn = self.conn.lock();
+        let tx = conn.savepoint()?;
+        let now = Utc::now();
+        let old = read_bans(&tx, &channel)?;
+        let mut new = bans
+            .iter()
+            .map(|character| ChannelBan {
+                character: *character,
+                expires: old
+                    .iter()
+                    .find(|ban| ban.character == *character)
+                    .and_then(|ban| ban.expires)
+                    .filter(|expires| *expires > now),
+            })
+            .collect::<Vec<_>>();
+        new.sort_by_key(|ban| ban.character);
+        new.dedup();
+        if old == new {
+            return Ok(false);
+        }
+        tx.execute(
+            "DELETE FROM channel_bans WHERE channel = ?1",
+            params![channel.0.to_string()],
+        )?;
+        for ban in new {
+            write_ban(&tx, &channel, &ban.character, ban.expires)?;
+        }
+        tx.commit()?;
+        Ok(true)
+    }
+
+    fn set_channel_ops(
+        &self,
+        channel: Cow<Channel>,
+        ops: Cow<[Character]>,
+    ) -> Result<bool, Self::Error> {
+        let mut conn = self.conn.lock();
+        let tx = conn.savepoint()?;
+        let changed = replace_ordered(&tx, "channel_ops", &channel, &ops)?;
+        tx.commit()?;
+        Ok(changed)
+    }
+
+    fn get_channel(&self, channel: &Channel) -> Result<Option<ChannelData>, Self::Error> {
+        read_channel(&self.conn.lock(), channel)
+    }
+
+    fn get_channels(&self) -> Result<Cow<'_, [ChannelData]>, Self::Error> {
+        let conn = self.conn.lock();
+        let names = conn
+            .prepare_cached("SELECT channel FROM channels ORDER BY channel")?
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut channels = Vec::with_capacity(names.len());
+        for channel in names {
+            if let Some(data) = read_channel(&conn, &Channel(channel.as_str().into()))? {
+                channels.push(data);
+            }
+        }
+        Ok(channels.into())
+    }
+
+    fn get_character(&self, character: &Character) -> Result<Option<CharacterData>, Self::Error> {
+        read_character(&self.conn.lock(), character)
+    }
+
+    fn get_profile(&self, character: &Character) -> Result<Option<CachedProfile>, Self::Error> {
+        let row = self
+            .conn
+            .lock()
+            .query_row(
+                "SELECT profile, fetched_at FROM profiles WHERE character = ?1",
+                params![name(character)],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)),
+            )
+            .optional()?;
+        row.map(|(profile, fetched_at)| {
+            Ok(CachedProfile {
+                profile: Arc::new(serde_json::from_str(&profile)?),
+                fetched_at: timestamp(fetched_at),
+            })
+        })
+        .transpose()
+    }
+
+    fn set_kink_mapping(&self, mapping: CachedKinkMapping) -> Result<bool, Self::Error> {
+        // There's only ever the one row.
+        self.conn.lock().execute(
+            "INSERT OR REPLACE INTO kink_mapping (id, mapping, fetched_at) VALUES (0, ?1, ?2)",
+            params![
+                serde_json::to_string(&*mapping.mapping)?,
+                mapping.fetched_at.timestamp_millis()
+            ],
+        )?;
+        Ok(true)
+    }
+
+    fn get_kink_mapping(&self) -> Result<Option<CachedKinkMapping>, Self::Error> {
+        let row = self
+            .conn
+            .lock()
+            .query_row(
+                "SELECT mapping, fetched_at FROM kink_mapping WHERE id = 0",
+                [],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)),
+            )
+            .optional()?;
+        row.map(|(mapping, fetched_at)| {
+            Ok(CachedKinkMapping {
+                mapping: Arc::new(serde_json::from_str(&mapping)?),
+                fetched_at: timestamp(fetched_at),
+            })
+        })
+        .transpose()
+    }
+
+    fn get_global_ops(&self) -> Result<Cow<'_, [Character]>, Self::Error> {
+        Ok(read_relations(&self.conn.lock(), GLOBAL_OP)?.into())
+    }
+
+    fn get_channel_ops(&self, channel: &Channel) -> Result<Cow<'_, [Character]>, Self::Error> {
+        Ok(read_ordered(&self.conn.lock(), "channel_ops", channel)?.into())
+    }
+
+    fn get_channel_members(&self, channel: &Channel) -> Result<Cow<'_, [Character]>, Self::Error> {
+        Ok(read_ordered(&self.conn.lock(), "channel_members", channel)?.into())
+    }
+
+    fn get_channel_bans(&self, channel: &Channel) -> Result<Cow<'_, [ChannelBan]>, Self::Error> {
+        let now = Utc::now();
+        let mut bans = read_bans(&self.conn.lock(), channel)?;
+        bans.retain(|ban| ban.in_effect(now));
+        Ok(bans.into())
+    }
+
+    fn get_ads(
+        &self,
+        channel: &Channel,
+        since: Option<Timestamp>,
+    ) -> Result<Cow<'_, [CachedAd]>, Self::Error> {
+        let since = self.retention.ads_since(since, Utc::now());
+        let conn = self.conn.lock();
+        let mut statement = conn.prepare_cached(
+            "SELECT character, ad, timestamp FROM ads
+             WHERE channel = ?1 AND timestamp >= ?2
+             ORDER BY timestamp",
+        )?;
+        let ads = statement
+            .query_map(
+                params![
+                    channel.0.to_string(),
+                    since.as_ref().map_or(i64::MIN, millis)
+                ],
+                |row| {
+                    Ok(CachedAd {
+                        character: character(row.get(0)?),
+                        ad: row.get(1)?,
+                        timestamp: timestamp(row.get(2)?),
+                    })
+                },
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ads.into())
+    }
+
+    fn get_global_channels(&self) -> Result<Option<ChannelListing>, Self::Error> {
+        read_listing(&self.conn.lock(), GLOBAL_CHANNELS)
+    }
+
+    fn get_unofficial_channels(&self) -> Result<Option<ChannelListing>, Self::Error> {
+        read_listing(&self.conn.lock(), UNOFFICIAL_CHANNELS)
+    }
+
+    fn get_memo(&self, character: &Character) -> Result<Option<String>, Self::Error> {
+        Ok(self
+            .conn
+            .lock()
+            .query_row(
+                "SELECT memo FROM memos WHERE character = ?1",
+                params![name(character)],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn get_characters(&self) -> Result<Cow<'_, [CharacterData]>, Self::Error> {
+        let conn = self.conn.lock();
+        let mut statement = conn.prepare_cached(&format!(
+            "SELECT {CHARACTER_COLUMNS} FROM characters ORDER BY character"
+        ))?;
+        let rows = statement
+            .query_map([], CharacterRow::get)?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows.into_iter()
+            .map(CharacterRow::parse)
+            .collect::<SqliteResult<Vec<_>>>()
+            .map(Cow::Owned)
+    }
+
+    // Yields the newest `limit` messages at or after `since`, oldest first.
+    fn get_messages(
+        &self,
+        source: &MessageChannel,
+        since: Option<Timestamp>,
+        limit: Option<u32>,
+    ) -> Result<Cow<'_, [Message]>, Self::Error> {
+        let conn = self.conn.lock();
+        let mut statement = conn.prepare_cached(
+            "SELECT timestamp, character, content FROM messages
+             WHERE source = ?1 AND timestamp >= ?2
+             ORDER BY id DESC LIMIT ?3",
+        )?;
+        let rows = statement
+            .query_map(
+                params![
+                    serde_json::to_string(source)?,
+                    since.as_ref().map_or(i64::MIN, millis),
+                    limit.map_or(-1, i64::from), // -1 is no limit, as far as SQLite cares
+                ],
+                message_row,
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut messages = rows
+            .into_iter()
+            .map(to_message)
+            .collect::<SqliteResult<Vec<_>>>()?;
+        messages.reverse();
+        Ok(messages.into())
+    }
+
+    fn get_pm_partners(
+        &self,
+        own_character: &Character,
+    ) -> Result<Cow<'_, [Character]>, Self::Error> {
+        Ok(read_pm_sources(&self.conn.lock())?
+            .into_iter()
+            .filter(|(_, own, _)| own == own_character)
+            .map(|(_, _, other)| other)
+            .collect::<Vec<_>>()
+            .into())
+    }
+
+    fn get_friend_relations(&self) -> Result<Cow<'_, [FriendRelation]>, Self::Error> {
+        Ok(read_friends(&self.conn.lock())?.into())
+    }
+
+    fn get_bookmarks(&self) -> Result<Cow<'_, [Character]>, Self::Error> {
+        Ok(read_relations(&self.conn.lock(), BOOKMARK)?.into())
+    }
+
+    fn get_reports(&self) -> Result<Cow<'_, [StaffReport]>, Self::Error> {
+        let conn = self.conn.lock();
+        let mut statement = conn.prepare_cached("SELECT report FROM reports ORDER BY callid")?;
+        let rows = statement
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows.iter()
+            .map(|report| Ok(serde_json::from_str(report)?))
+            .collect::<SqliteResult<Vec<_>>>()
+            .map(Cow::Owned)
+    }
+
+    fn get_ignored(&self) -> Result<Cow<'_, [Character]>, Self::Error> {
+        Ok(read_relations(&self.conn.lock(), IGNORED)?.into())
+    }
+
+    fn get_incoming_friend_requests(&self) -> Result<Cow<'_, [PendingFriendRequest]>, Self::Error> {
+        Ok(read_friend_requests(&self.conn.lock(), true)?.into())
+    }
+
+    fn get_outgoing_friend_requests(&self) -> Result<Cow<'_, [PendingFriendRequest]>, Self::Error> {
+        Ok(read_friend_requests(&self.conn.lock(), false)?.into())
+    }
+}
@@ -14,91 +14,371 @@
 pub use async_trait::async_trait;
 use chrono::Utc;
 
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use std::{
+    any::Any,
     borrow::Cow,
-    sync::Arc,
+    collections::{HashMap, HashSet},
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 use thiserror::Error;
 
-use reqwest::Client as ReqwestClient;
-use tokio::sync::mpsc::{channel, Receiver, Sender};
+use dashmap::DashMap;
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    mpsc::{channel, unbounded_channel, Receiver, Sender, UnboundedReceiver, UnboundedSender},
+    oneshot,
+};
 
+pub use crate::credentials::{CredentialProvider, PlainPassword, TicketRefresher};
 use crate::{
-    cache::{Cache, NoCache, PartialChannelData, PartialUserData},
+    ads::AdScheduler,
+    cache::{
+        Cache, CachedKinkMapping, CachedProfile, ChannelListing, NoCache, PartialChannelData,
+        PartialUserData, Updated,
+    },
     data::{
-        Channel, Character, FriendRelation, Message, MessageChannel, MessageContent, Status,
-        TypingStatus,
+        Channel, ChannelMode, Character, Message, MessageChannel, MessageContent,
+        PendingFriendRequest, Status, TypingStatus,
+    },
+    highlight::{HighlightMatch, Highlighter},
+    http_endpoints::{
+        self, get_api_ticket, ApiError, AuthenticationError, CharacterGuestbookResponse,
+        CharacterProfileResponse, Endpoints, FriendLists, FriendRequestPartial, FullCharacter,
+        Image, Kink, OwnCharacter, ReportTarget,
     },
-    http_endpoints::{self, get_api_ticket},
+    kinks::{InfotagValue, KinkRegistry, ResolvedKink},
     protocol::*,
-    session::{Event, Session, SessionError},
+    ratelimit::{HttpLimiter, LimitedAction, RateLimiter},
+    retry::{retry, RetryPolicy},
+    search::{SearchQuery, SearchResult, SearchResults},
+    selector::{Explicit, SessionPurpose, SessionSelector},
+    replay::FrameRecorder,
+    session::{in_session_span, Event, Session, SessionError, DEFAULT_CHAT_URL},
+    single_flight::SingleFlight,
+    status::{AutoIdle, StatusManager},
+    tickets::TicketProvider,
+    telemetry,
+    transport::{HttpError, HttpTransport, Transport, WebsocketTransport},
+    util::{diagnostic, split_message, timestamp::Timestamp, StackString, StringInteger},
 };
 
+// How many profiles get_profiles has in flight at once. The profile rate limit still applies.
+const PROFILE_CONCURRENCY: usize = 4;
+
 #[derive(Debug)]
 pub struct Client<T: EventListener, C: Cache> {
     client_name: String,
     client_version: String,
 
-    username: String,
-    password: String,
-    token: RwLock<Token>,
-    http_client: ReqwestClient,
+    tickets: TicketProvider,
+    transport: Arc<dyn Transport>,
+    chat_url: String,
+    recorder: Option<Arc<FrameRecorder>>,
     // pub default_character: Character,
     pub own_characters: Vec<Character>,
 
     pub cache: C,
 
-    sessions: RwLock<Vec<Arc<Session>>>,
+    // Numbered as they're added, so the order they connected in isn't lost.
+    sessions: DashMap<Character, (u64, Arc<Session>)>,
+    sessions_added: AtomicU64,
     send_channel: Sender<Event>,
+    outgoing_channel: UnboundedSender<Event>,
+    outgoing: Mutex<Option<UnboundedReceiver<Event>>>, // Taken by start()
 
     event_listener: T,
+    event_filter: EventFilter,
+    status: StatusManager,
+    ads: AdScheduler,
+    // Searches waiting on FKS (or an ERR) from a given session.
+    pending_searches: DashMap<Character, oneshot::Sender<Result<SearchResults, ProtocolError>>>,
+    kinks: RwLock<Option<(Arc<KinkRegistry>, Timestamp)>>, // And when the site gave it out
+    kinks_loading: tokio::sync::Mutex<()>, // So only one caller goes and gets the mapping list
+    mapping_ttl: Duration,
+    profile_ttl: Duration,
+    profile_stale: Duration,
+    profile_revalidate: Mutex<HashSet<Character>>, // Stale profiles that were handed out and need fetching again
+    requests: SingleFlight<Request, Arc<dyn Any + Send + Sync>>, // Whatever type the request yields
+    highlighter: Option<Highlighter>,
+    split_messages: bool,
+    outbox: Option<Mutex<Outbox>>,
+    limiter: RateLimiter,
+    retry: RetryPolicy,
+    channel_list_refresh: Option<ChannelListRefresh>,
+    channel_lists_requested: Mutex<Option<Instant>>,
+    cache_compaction: Option<Duration>,
+    cache_compacted: Mutex<Instant>,
+    initial_sync: InitialSync,
+    character_batch: Option<Mutex<CharacterBatch>>,
+    resuming: DashMap<Character, Vec<Channel>>, // From a snapshot; taken once the session is ready
+    auto_rejoin: AutoRejoin,
+    pending_rejoins: Mutex<Vec<(Character, Channel, Instant)>>, // Due at the instant
+    selector: Box<dyn SessionSelector>,
+    dedup: MessageDedup,
+}
+
+// What an HTTP request was for, so the same one isn't in flight twice: the endpoint, and what it
+// was asked. Only for requests that are safe to share the answer to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Request {
+    Profile(Character),
+    Memo(Character),
+    Images(Character),
+    Friends(Character),
+    Guestbook(Character, u64),
+    FriendLists(FriendLists),
+    CharacterList,
+}
+
+// Only call into the listener if it's subscribed to that class of event.
+// Cache updates happen either way; this just skips the callback.
+macro_rules! emit {
+    ($self:ident, $class:ident, $method:ident($($arg:expr),* $(,)?)) => {
+        if $self.event_filter.contains(EventFilter::$class) {
+            $self.event_listener.$method($($arg),*).await
+        }
+    };
+}
+
+/// Classes of listener callbacks that a client can subscribe to.
+/// Session lifecycle, errors and raw commands are always delivered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EventFilter(u8);
+
+impl EventFilter {
+    pub const NONE: EventFilter = EventFilter(0);
+    /// Messages, ads, rolls, broadcasts and system messages
+    pub const MESSAGES: EventFilter = EventFilter(1 << 0);
+    /// Character status updates, typing, and friends/bookmarks logging in and out
+    pub const PRESENCE: EventFilter = EventFilter(1 << 1);
+    /// Channel data, channel lists, invites and joined channels
+    pub const CHANNELS: EventFilter = EventFilter(1 << 2);
+    /// Kicks, bans, timeouts, staff alerts and op changes
+    pub const MODERATION: EventFilter = EventFilter(1 << 3);
+    /// Friends, bookmarks, friend requests and the ignore list
+    pub const RELATIONS: EventFilter = EventFilter(1 << 4);
+    pub const ALL: EventFilter = EventFilter(0b11111);
+
+    pub fn contains(self, other: EventFilter) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl Default for EventFilter {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+impl std::ops::BitOr for EventFilter {
+    type Output = EventFilter;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        EventFilter(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitOrAssign for EventFilter {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0
+    }
 }
 
 #[derive(Error, Debug)]
 pub enum ClientError {
     #[error("Error from HTTP Request")]
-    RequestError(#[from] reqwest::Error),
+    RequestError(#[from] HttpError),
     #[error("Error from Websocket (Tungstenite)")]
     WebsocketError(#[from] tokio_tungstenite::tungstenite::Error),
     #[error("Default character doesn't exist or is invalid")]
     NoDefaultCharacter,
     #[error("Error from Session implementation")]
     SessionError(#[from] crate::session::SessionError),
+    #[error("No connected session is available to send this command")]
+    NoSession,
+    #[error("Error from Cache implementation")]
+    Cache(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error("Failed to refresh the API ticket")]
+    TicketRefresh(#[source] crate::credentials::CredentialError),
+    #[error("Credential provider supplied neither a ticket nor a password")]
+    NoCredentials,
+    #[error("No session is connected for that character")]
+    NoSuchSession,
+    #[error("Timed out waiting for the server to respond")]
+    Timeout,
+    #[error("Invalid search: {0}")]
+    InvalidSearch(&'static str),
+    #[error("A search is already running on that session")]
+    SearchInProgress,
+    #[error("Server rejected the command: {0:?}")]
+    Protocol(ProtocolError),
+    #[error("F-List API error: {0}")]
+    Api(#[from] ApiError),
+    #[error("Failed to log in")]
+    Authentication(#[from] AuthenticationError),
 }
 type ClientResult<T> = Result<T, ClientError>;
 
-#[derive(Debug, Clone)]
-struct Token {
-    last_updated: Instant,
-    pub ticket: String,
+impl ClientError {
+    // Cache errors are an associated type, so they can't have a blanket From impl.
+    fn cache<E: std::error::Error + Send + Sync + 'static>(err: E) -> Self {
+        telemetry::cache_error();
+        ClientError::Cache(Box::new(err))
+    }
 }
 
-impl Token {
-    fn new(ticket: String) -> Token {
-        Token {
-            ticket,
-            last_updated: Instant::now(),
-        }
+// Commands waiting for a session to come back. Only user-intent commands go in here;
+// status doesn't need to, because the desired status is re-applied on reconnect anyway.
+#[derive(Debug, Default)]
+struct Outbox {
+    reconnecting: HashSet<Character>,
+    queued: HashMap<Character, Vec<ClientCommand>>,
+}
+
+/// Which sessions the channel lists get requested through.
+/// The lists are the same for everyone, but some consumers want each session to see them arrive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefreshScope {
+    AnySession,
+    EverySession,
+}
+
+/// Things to ask for when a session comes up, so that the cache starts out populated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InitialSync {
+    pub global_channels: bool, // CHA
+    pub private_rooms: bool,   // ORS
+    pub ignore_list: bool,     // IGN list
+}
+
+impl InitialSync {
+    pub const NONE: InitialSync = InitialSync {
+        global_channels: false,
+        private_rooms: false,
+        ignore_list: false,
+    };
+    pub const ALL: InitialSync = InitialSync {
+        global_channels: true,
+        private_rooms: true,
+        ignore_list: true,
+    };
+}
+
+/// Everything needed to pick up where a client left off: who was connected, where, and as what.
+/// Serializable, so it can be written out before a restart and handed to ClientBuilder::resume after.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientSnapshot {
+    pub sessions: Vec<SessionSnapshot>,
+    pub status: Option<(Status, String)>, // The status the user set, not whatever auto-idle picked
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionSnapshot {
+    pub character: Character,
+    pub channels: Vec<Channel>,
+}
+
+// When to go back into a channel our own character was kicked from.
+#[derive(Debug, Clone, Default)]
+struct AutoRejoin {
+    default: Option<Duration>,
+    channels: HashMap<Channel, Option<Duration>>, // Overrides, None to stay out
+}
+
+impl AutoRejoin {
+    fn delay(&self, channel: &Channel) -> Option<Duration> {
+        self.channels.get(channel).copied().unwrap_or(self.default)
     }
-    fn zero() -> Token {
-        Token {
-            ticket: "NONE".to_owned(),
-            last_updated: Instant::now(), // Good luck.
-        }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ChannelListRefresh {
+    interval: Duration,
+    scope: RefreshScope,
+}
+
+// Channel traffic arrives once per session in the channel. Each session's count of a given line is
+// kept for a little while; a copy is only new if it takes some session past the highest count so far,
+// so the second session's copy is dropped but someone genuinely repeating themselves isn't.
+#[derive(Debug, Default)]
+pub(crate) struct MessageDedup {
+    seen: Mutex<HashMap<(Channel, Character, u64), SeenMessage>>,
+}
+
+#[derive(Debug)]
+struct SeenMessage {
+    at: Instant,
+    counts: HashMap<Character, u32>, // Per receiving session
+}
+
+impl MessageDedup {
+    const WINDOW: Duration = Duration::from_secs(10);
+
+    pub(crate) fn first_sighting(
+        &self,
+        session: Character,
+        channel: Channel,
+        character: Character,
+        text: &str,
+    ) -> bool {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        text.hash(&mut hasher);
+        let key = (channel, character, hasher.finish());
+
+        let now = Instant::now();
+        let mut seen = self.seen.lock();
+        seen.retain(|_, message| now.duration_since(message.at) < Self::WINDOW);
+        let message = seen.entry(key).or_insert_with(|| SeenMessage {
+            at: now,
+            counts: HashMap::new(),
+        });
+        message.at = now;
+        let most = message.counts.values().copied().max().unwrap_or(0);
+        let count = message.counts.entry(session).or_default();
+        *count += 1;
+        *count > most
     }
-    fn expired(&self) -> bool {
-        self.last_updated.elapsed() > Duration::from_secs(25 * 60)
+}
+
+// Character updates held back while sessions are syncing.
+#[derive(Debug, Default)]
+struct CharacterBatch {
+    syncing: HashSet<Character>, // Sessions that haven't seen their own NLN yet
+    started: Option<Instant>,
+    pending: HashSet<Character>,
+}
+
+impl CharacterBatch {
+    // If the end of the flood never shows up, give up waiting after this long.
+    const MAX_WAIT: Duration = Duration::from_secs(15);
+
+    fn begin(&mut self, session: Character) {
+        self.syncing.insert(session);
+        self.started.get_or_insert_with(Instant::now);
     }
-    fn update(&mut self, new: String) {
-        self.ticket = new;
-        self.last_updated = Instant::now();
+
+    // Yields the batch once nothing is syncing any more.
+    fn end(&mut self, session: &Character) -> Option<Vec<Character>> {
+        self.syncing.remove(session);
+        if self.syncing.is_empty() {
+            self.take()
+        } else {
+            None
+        }
     }
-}
-impl Default for Token {
-    fn default() -> Self {
-        Self::zero()
+
+    fn take(&mut self) -> Option<Vec<Character>> {
+        self.syncing.clear();
+        self.started = None;
+        (!self.pending.is_empty()).then(|| self.pending.drain().collect())
     }
 }
 
@@ -108,6 +388,27 @@ pub struct ClientBuilder<E: EventListener, C: Cache> {
     client_name: String,
     events: E,
     cache: C,
+    event_filter: EventFilter,
+    auto_idle: Option<AutoIdle>,
+    profile_ttl: Duration,
+    profile_stale: Duration,
+    mapping_ttl: Duration,
+    highlighter: Option<Highlighter>,
+    split_messages: bool,
+    outbox: bool,
+    limiter: RateLimiter,
+    retry: RetryPolicy,
+    endpoints: Endpoints,
+    transport: Arc<dyn Transport>,
+    chat_url: String,
+    recorder: Option<Arc<FrameRecorder>>,
+    channel_list_refresh: Option<ChannelListRefresh>,
+    cache_compaction: Option<Duration>,
+    initial_sync: InitialSync,
+    batch_character_updates: bool,
+    resume: Option<ClientSnapshot>,
+    auto_rejoin: AutoRejoin,
+    selector: Box<dyn SessionSelector>,
 }
 
 impl<E: EventListener> ClientBuilder<E, NoCache> {
@@ -117,6 +418,27 @@ impl<E: EventListener> ClientBuilder<E, NoCache> {
             client_name: "f-chat-rs".to_string(),
             events,
             cache: NoCache,
+            event_filter: EventFilter::ALL,
+            auto_idle: None,
+            profile_ttl: Duration::from_secs(30 * 60),
+            profile_stale: Duration::ZERO,
+            mapping_ttl: Duration::from_secs(7 * 24 * 60 * 60),
+            highlighter: None,
+            split_messages: false,
+            outbox: false,
+            limiter: RateLimiter::new(),
+            retry: RetryPolicy::default(),
+            endpoints: Endpoints::default(),
+            transport: Arc::new(WebsocketTransport),
+            chat_url: DEFAULT_CHAT_URL.to_owned(),
+            recorder: None,
+            channel_list_refresh: None,
+            cache_compaction: None,
+            initial_sync: InitialSync::NONE,
+            batch_character_updates: false,
+            resume: None,
+            auto_rejoin: AutoRejoin::default(),
+            selector: Box::new(Explicit),
         }
     }
 }
@@ -128,6 +450,221 @@ impl<E: EventListener + 'static, C: Cache + 'static> ClientBuilder<E, C> {
             client_name: self.client_name,
             events: self.events,
             cache,
+            event_filter: self.event_filter,
+            auto_idle: self.auto_idle,
+            profile_ttl: self.profile_ttl,
+            profile_stale: self.profile_stale,
+            mapping_ttl: self.mapping_ttl,
+            highlighter: self.highlighter,
+            split_messages: self.split_messages,
+            outbox: self.outbox,
+            limiter: self.limiter,
+            retry: self.retry,
+            endpoints: self.endpoints,
+            transport: self.transport,
+            chat_url: self.chat_url,
+            recorder: self.recorder,
+            channel_list_refresh: self.channel_list_refresh,
+            cache_compaction: self.cache_compaction,
+            initial_sync: self.initial_sync,
+            batch_character_updates: self.batch_character_updates,
+            resume: self.resume,
+            auto_rejoin: self.auto_rejoin,
+            selector: self.selector,
+        }
+    }
+
+    /// How to pick a session for send_message, join_channel and leave_channel when they're
+    /// called without a character. The default (Explicit) refuses to guess.
+    pub fn with_session_selector<S: SessionSelector + 'static>(self, selector: S) -> Self {
+        ClientBuilder {
+            selector: Box::new(selector),
+            ..self
+        }
+    }
+
+    /// Rejoin any channel our own character gets kicked from, after `delay`.
+    pub fn with_auto_rejoin(mut self, delay: Duration) -> Self {
+        self.auto_rejoin.default = Some(delay);
+        self
+    }
+
+    /// Override the rejoin delay for one channel. None means never rejoin it after a kick.
+    pub fn with_channel_rejoin(mut self, channel: Channel, delay: Option<Duration>) -> Self {
+        self.auto_rejoin.channels.insert(channel, delay);
+        self
+    }
+
+    /// Restore a snapshot taken with Client::snapshot. The status is set straight away,
+    /// and init connects every character in it, rejoining their channels once each is ready.
+    pub fn resume(self, snapshot: ClientSnapshot) -> Self {
+        ClientBuilder {
+            resume: Some(snapshot),
+            ..self
+        }
+    }
+
+    /// Collect character updates while sessions are receiving the initial LIS flood,
+    /// and deliver them in one EventListener::updated_characters call instead of one each.
+    pub fn with_batched_character_updates(self, batch_character_updates: bool) -> Self {
+        ClientBuilder {
+            batch_character_updates,
+            ..self
+        }
+    }
+
+    /// What to request from the server as soon as each session is ready.
+    pub fn with_initial_sync(self, initial_sync: InitialSync) -> Self {
+        ClientBuilder {
+            initial_sync,
+            ..self
+        }
+    }
+
+    /// Request the official (CHA) and unofficial (ORS) channel lists every `interval`.
+    pub fn with_channel_list_refresh(self, interval: Duration, scope: RefreshScope) -> Self {
+        ClientBuilder {
+            channel_list_refresh: Some(ChannelListRefresh { interval, scope }),
+            ..self
+        }
+    }
+
+    /// Run Cache::compact every `interval`, so retention limits apply to histories that have gone
+    /// quiet and to old ads, not just to whatever's being added to.
+    pub fn with_cache_compaction(self, interval: Duration) -> Self {
+        ClientBuilder {
+            cache_compaction: Some(interval),
+            ..self
+        }
+    }
+
+    /// How to retry HTTP requests that fail with a timeout, a connection error or a 5xx.
+    /// Reports are never retried. Use RetryPolicy::NONE to turn this off.
+    pub fn with_retry(self, retry: RetryPolicy) -> Self {
+        ClientBuilder { retry, ..self }
+    }
+
+    /// Send HTTP requests somewhere other than https://www.f-list.net, e.g. a mirror, a proxy
+    /// or a mock server. Only the JSON endpoints; the chat server is unaffected.
+    pub fn with_base_url<S: Into<String>>(self, base_url: S) -> Self {
+        ClientBuilder {
+            endpoints: self.endpoints.with_base_url(base_url),
+            ..self
+        }
+    }
+
+    /// Connect sessions somewhere other than the live chat server, e.g. the mock-server feature's
+    /// MockServer. Reconnects go to the same place.
+    pub fn with_chat_url<S: Into<String>>(self, chat_url: S) -> Self {
+        ClientBuilder {
+            chat_url: chat_url.into(),
+            ..self
+        }
+    }
+
+    /// Connect sessions over something other than tokio-tungstenite. Reconnects use it too.
+    pub fn with_transport<T: Transport + 'static>(self, transport: T) -> Self {
+        ClientBuilder {
+            transport: Arc::new(transport),
+            ..self
+        }
+    }
+
+    /// Make requests to the JSON endpoints through something other than reqwest.
+    /// Combines with with_base_url and with_http_limiter.
+    pub fn with_http_transport<T: HttpTransport + 'static>(self, transport: T) -> Self {
+        ClientBuilder {
+            endpoints: self.endpoints.with_transport(transport),
+            ..self
+        }
+    }
+
+    /// Write every frame each session sends and receives to `recorder`, to play back later
+    /// with replay::Replay.
+    pub fn with_frame_recorder(self, recorder: Arc<FrameRecorder>) -> Self {
+        ClientBuilder {
+            recorder: Some(recorder),
+            ..self
+        }
+    }
+
+    /// Hold every HTTP request the client makes to `limiter`'s caps. Unlimited by default.
+    pub fn with_http_limiter(self, limiter: HttpLimiter) -> Self {
+        ClientBuilder {
+            endpoints: self.endpoints.with_limiter(limiter),
+            ..self
+        }
+    }
+
+    /// Override how long to leave between account-wide rate limited actions (searches, profiles, reports).
+    pub fn with_rate_limit(self, action: LimitedAction, cooldown: Duration) -> Self {
+        ClientBuilder {
+            limiter: self.limiter.with_cooldown(action, cooldown),
+            ..self
+        }
+    }
+
+    /// Hold on to messages sent while a session is reconnecting, and send them once it's back.
+    pub fn with_outbox(self, outbox: bool) -> Self {
+        ClientBuilder { outbox, ..self }
+    }
+
+    /// Split over-long messages sent through Client::send_message on word boundaries,
+    /// rather than letting the server reject them.
+    pub fn with_message_splitting(self, split_messages: bool) -> Self {
+        ClientBuilder {
+            split_messages,
+            ..self
+        }
+    }
+
+    /// Check incoming channel messages against the highlighter, and call EventListener::mentioned on a match.
+    pub fn with_highlighter(self, highlighter: Highlighter) -> Self {
+        ClientBuilder {
+            highlighter: Some(highlighter),
+            ..self
+        }
+    }
+
+    /// How long a cached profile is good for before get_profile fetches it again. Defaults to 30 minutes.
+    pub fn with_profile_ttl(self, profile_ttl: Duration) -> Self {
+        ClientBuilder {
+            profile_ttl,
+            ..self
+        }
+    }
+
+    /// Past its TTL, keep handing out a cached profile for up to `stale` longer while it's fetched again
+    /// in the background. Defaults to zero, so an expired profile is always fetched before returning.
+    pub fn with_profile_stale_while_revalidate(self, stale: Duration) -> Self {
+        ClientBuilder {
+            profile_stale: stale,
+            ..self
+        }
+    }
+
+    /// How long the kink mapping list is kept, in memory or in the cache, before it's fetched again.
+    /// Defaults to a week; Client::refresh_kinks gets a new one sooner.
+    pub fn with_mapping_ttl(self, mapping_ttl: Duration) -> Self {
+        ClientBuilder {
+            mapping_ttl,
+            ..self
+        }
+    }
+
+    /// Switch every session to `status` after `after` without sending anything, and back once something is sent.
+    pub fn with_auto_idle(self, after: Duration, status: Status) -> Self {
+        ClientBuilder {
+            auto_idle: Some(AutoIdle { after, status }),
+            ..self
+        }
+    }
+
+    /// Only deliver the given classes of events to the listener, e.g. `EventFilter::MESSAGES | EventFilter::CHANNELS`
+    pub fn with_event_filter(self, event_filter: EventFilter) -> Self {
+        ClientBuilder {
+            event_filter,
+            ..self
         }
     }
 
@@ -144,10 +681,36 @@ impl<E: EventListener + 'static, C: Cache + 'static> ClientBuilder<E, C> {
         username: String,
         password: String,
     ) -> ClientResult<(Client<E, C>, Receiver<Event>)> {
-        let http = ReqwestClient::new();
-        let (send, rcv) = channel(8);
-        let ticket_init = get_api_ticket(&http, &username, &password, true).await?;
-        let token = Token::new(ticket_init.ticket);
+        self.init_with_credentials(username, PlainPassword(password))
+            .await
+    }
+
+    /// Initialize with credentials supplied on demand by `credentials`, which is consulted again on every refresh.
+    pub async fn init_with_credentials<P: CredentialProvider + 'static>(
+        self,
+        username: String,
+        credentials: P,
+    ) -> ClientResult<(Client<E, C>, Receiver<Event>)> {
+        if let Some(ticket) = credentials
+            .get_ticket(&username)
+            .await
+            .map_err(ClientError::TicketRefresh)?
+        {
+            return self
+                .init_from_ticket(username, ticket, Box::new(credentials))
+                .await;
+        }
+        let password = credentials
+            .get_password(&username)
+            .await
+            .map_err(ClientError::TicketRefresh)?
+            .ok_or(ClientError::NoCredentials)?;
+        let ticket_init = retry(&self.retry, || {
+            get_api_ticket(&self.endpoints, &username, &password, true)
+        })
+        .await??;
+        drop(password);
+        let ticket = ticket_init.ticket;
 
         let mut extra = ticket_init
             .extra
@@ -172,150 +735,1475 @@ impl<E: EventListener + 'static, C: Cache + 'static> ClientBuilder<E, C> {
                     .collect::<Vec<_>>()
                     .into(),
             )
-            .unwrap();
+            .map_err(ClientError::cache)?;
+        let received = Utc::now();
         self.cache
             .set_friends(
                 extra
                     .friends
                     .drain(..)
-                    .map(|v| FriendRelation {
-                        own_character: v.dest,
-                        other_character: v.source,
-                    })
+                    .map(|v| v.relation(received))
                     .collect::<Vec<_>>()
                     .into(),
             )
-            .unwrap();
+            .map_err(ClientError::cache)?;
+
+        let (client, rcv) = self.build(username, Box::new(credentials), ticket, own_characters);
+        client.resume_sessions().await;
+        Ok((client, rcv))
+    }
+
+    /// Initialize from a ticket obtained elsewhere, so the password never has to be handed over.
+    /// `refresh` is called for a new ticket whenever the current one expires.
+    ///
+    /// Tickets don't carry the character list, so `own_characters` starts out empty.
+    /// Friends and bookmarks are fetched through the HTTP endpoint instead.
+    pub async fn init_with_ticket(
+        self,
+        username: String,
+        ticket: String,
+        refresh: TicketRefresher,
+    ) -> ClientResult<(Client<E, C>, Receiver<Event>)> {
+        self.init_from_ticket(username, ticket, Box::new(refresh))
+            .await
+    }
+
+    async fn init_from_ticket(
+        self,
+        username: String,
+        ticket: String,
+        credentials: Box<dyn CredentialProvider>,
+    ) -> ClientResult<(Client<E, C>, Receiver<Event>)> {
+        let (client, rcv) = self.build(username, credentials, ticket, Vec::new());
+        client.sync_friends_bookmarks().await?;
+        client.resume_sessions().await;
+        Ok((client, rcv))
+    }
 
+    pub(crate) fn build(
+        self,
+        username: String,
+        credentials: Box<dyn CredentialProvider>,
+        ticket: String,
+        own_characters: Vec<Character>,
+    ) -> (Client<E, C>, Receiver<Event>) {
+        let (send, rcv) = channel(8);
+        let (outgoing_channel, outgoing) = unbounded_channel();
+        let status = StatusManager::new(self.auto_idle);
+        let mut resume = self.resume.unwrap_or_default();
+        if let Some((desired, message)) = resume.status.take() {
+            status.set_desired(desired, message);
+        }
         let client = Client {
             client_name: self.client_name,
             client_version: self.client_version,
-            username,
-            password,
-            token: RwLock::new(token),
-            http_client: http,
+            tickets: TicketProvider::new(username, credentials, ticket, self.endpoints, self.retry),
+            transport: self.transport,
+            chat_url: self.chat_url,
+            recorder: self.recorder,
             // default_character,
             own_characters,
             cache: self.cache,
             sessions: Default::default(),
+            sessions_added: AtomicU64::new(0),
             send_channel: send,
+            outgoing_channel,
+            outgoing: Mutex::new(Some(outgoing)),
             event_listener: self.events,
+            event_filter: self.event_filter,
+            status,
+            ads: AdScheduler::new(),
+            pending_searches: DashMap::new(),
+            kinks: RwLock::new(None),
+            kinks_loading: tokio::sync::Mutex::new(()),
+            mapping_ttl: self.mapping_ttl,
+            profile_ttl: self.profile_ttl,
+            profile_stale: self.profile_stale,
+            profile_revalidate: Mutex::new(HashSet::new()),
+            requests: SingleFlight::default(),
+            highlighter: self.highlighter,
+            split_messages: self.split_messages,
+            outbox: self.outbox.then(Default::default),
+            limiter: self.limiter,
+            retry: self.retry,
+            channel_list_refresh: self.channel_list_refresh,
+            channel_lists_requested: Mutex::new(None),
+            cache_compaction: self.cache_compaction,
+            cache_compacted: Mutex::new(Instant::now()),
+            initial_sync: self.initial_sync,
+            character_batch: self.batch_character_updates.then(Default::default),
+            auto_rejoin: self.auto_rejoin,
+            selector: self.selector,
+            dedup: MessageDedup::default(),
+            pending_rejoins: Mutex::new(Vec::new()),
+            resuming: resume
+                .sessions
+                .into_iter()
+                .map(|session| (session.character, session.channels))
+                .collect(),
         };
-
-        Ok((client, rcv))
+        (client, rcv)
     }
 }
 
 impl<T: EventListener, C: Cache> Client<T, C> {
     pub async fn start(&self, mut rcv: Receiver<Event>) {
-        while let Some(event) = rcv.recv().await {
-            self.dispatch(event).await;
+        // Background chores (auto-idle, scheduled ads) piggyback on the event loop.
+        let mut ticker = tokio::time::interval(Duration::from_secs(1));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // If start() is somehow running twice, only one of them gets to see outgoing commands.
+        let mut outgoing = self.outgoing.lock().take();
+        loop {
+            tokio::select! {
+                event = rcv.recv() => match event {
+                    Some(event) => self.dispatch(event).await,
+                    None => break,
+                },
+                Some(event) = async { outgoing.as_mut()?.recv().await } => self.dispatch(event).await,
+                _ = ticker.tick() => {
+                    self.poll_idle().await;
+                    self.poll_ads().await;
+                    self.poll_channel_lists().await;
+                    self.poll_character_batch().await;
+                    self.poll_rejoins().await;
+                    self.poll_profiles().await;
+                    self.poll_compaction().await;
+                    self.poll_cache().await;
+                }
+            }
         }
     }
 
-    pub async fn refresh(&self) -> Result<(), ClientError> {
-        let ticket =
-            get_api_ticket(&self.http_client, &self.username, &self.password, false).await?;
-        self.token.write().update(ticket.ticket);
-        Ok(())
+    async fn poll_channel_lists(&self) {
+        let Some(refresh) = self.channel_list_refresh else {
+            return;
+        };
+        let sessions: Vec<_> = match refresh.scope {
+            RefreshScope::AnySession => self.any_session().into_iter().collect(),
+            RefreshScope::EverySession => self.get_sessions(),
+        };
+        if sessions.is_empty() {
+            return; // Try again once something's connected
+        }
+        {
+            let mut requested = self.channel_lists_requested.lock();
+            if requested.is_some_and(|at| at.elapsed() < refresh.interval) {
+                return;
+            }
+            *requested = Some(Instant::now());
+        }
+        for session in sessions {
+            for command in [ClientCommand::GlobalChannels, ClientCommand::Channels] {
+                if let Err(err) = session.send_unattended(command).await {
+                    self.event_listener.client_error(err.into()).await
+                }
+            }
+        }
     }
 
-    pub async fn refresh_fast(&self) -> Result<(), ClientError> {
-        // Optimistically refresh if the token is more than 20 minutes old
-        // Supposedly it lasts 30 minutes but I don't trust these devs and their crap API
-        if self.token.read().expired() {
-            self.refresh().await?;
+    /// The official channel list, and when it was fetched, if the cache has it.
+    pub fn global_channels(&self) -> ClientResult<Option<ChannelListing>> {
+        self.cache.get_global_channels().map_err(ClientError::cache)
+    }
+
+    pub fn unofficial_channels(&self) -> ClientResult<Option<ChannelListing>> {
+        self.cache
+            .get_unofficial_channels()
+            .map_err(ClientError::cache)
+    }
+
+    async fn poll_ads(&self) {
+        let due = self.ads.take_due(Instant::now(), |character, channel| {
+            let session = self.get_session(character)?;
+            if !session.channels.contains(channel) {
+                return None;
+            }
+            // If the cache can't say, assume the channel takes ads; the server will tell us otherwise.
+            if let Ok(Some(data)) = self.cache.get_channel(channel) {
+                if data.channel_mode == ChannelMode::ChatOnly {
+                    return None;
+                }
+            }
+            Some(Duration::from_secs_f32(session.variables.ad_cooldown))
+        });
+        for (character, channel, text) in due {
+            let Some(session) = self.get_session(&character) else {
+                continue;
+            };
+            if let Err(err) = session
+                .send_unattended(ClientCommand::Ad {
+                    channel,
+                    message: text,
+                })
+                .await
+            {
+                self.event_listener.client_error(err.into()).await
+            }
+        }
+    }
+
+    /// Ads that get posted automatically while the client is running. See [AdScheduler::schedule].
+    pub fn ads(&self) -> &AdScheduler {
+        &self.ads
+    }
+
+    async fn poll_idle(&self) {
+        let sessions = self.get_sessions();
+        let Some(last_activity) = sessions.iter().map(|s| s.last_activity()).max() else {
+            return;
+        };
+        if let Some((status, statusmsg)) = self.status.poll(last_activity) {
+            if let Err(err) = self.broadcast_status(&sessions, status, statusmsg).await {
+                self.event_listener.client_error(err).await
+            }
+        }
+    }
+
+    async fn broadcast_status(
+        &self,
+        sessions: &[Arc<Session>],
+        status: Status,
+        statusmsg: String,
+    ) -> ClientResult<()> {
+        for session in sessions {
+            self.send_user_command(
+                &session.character,
+                ClientCommand::Status {
+                    status,
+                    statusmsg: statusmsg.clone(),
+                },
+            )
+            .await?;
         }
         Ok(())
     }
 
+    /// Set the status on every session. It's remembered, and re-applied to sessions as they (re)connect.
+    pub async fn set_status(&self, status: Status, statusmsg: String) -> ClientResult<()> {
+        self.status.set_desired(status, statusmsg.clone());
+        self.broadcast_status(&self.get_sessions(), status, statusmsg)
+            .await
+    }
+
+    /// The status last set with set_status, if any. This doesn't change while auto-idle is in effect.
+    pub fn desired_status(&self) -> Option<(Status, String)> {
+        self.status.desired()
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.status.is_idle()
+    }
+
+    /// Get a new API ticket now.
+    pub async fn refresh(&self) -> Result<(), ClientError> {
+        self.tickets.refresh().await
+    }
+
+    /// Get a new API ticket if the current one is getting old.
+    pub async fn refresh_fast(&self) -> Result<(), ClientError> {
+        self.tickets.refresh_fast().await
+    }
+
+    /// The account's tickets, for making requests to endpoints the client doesn't wrap.
+    pub fn tickets(&self) -> &TicketProvider {
+        &self.tickets
+    }
+
     pub async fn connect(&self, character: Character) -> ClientResult<()> {
         self.refresh().await?;
-        let token = self.token.read().ticket.clone();
         let session = Session::connect(
-            self.username.clone(),
-            token,
+            self.transport.clone(),
+            self.chat_url.clone(),
+            self.tickets.account().to_owned(),
+            self.tickets.current().to_string(),
             self.client_name.clone(),
             self.client_version.clone(),
             character,
             self.send_channel.clone(),
+            self.outgoing_channel.clone(),
+            self.recorder.clone(),
         )
         .await?;
 
         // Add the new session to the list, to hold on to it.
-        self.sessions.write().push(session);
+        self.add_session(session);
         Ok(())
     }
 
     pub async fn sync_friends_bookmarks(&self) -> ClientResult<bool> {
-        // Events are mostly emitted through the event-handler.
-        self.refresh_fast().await?;
-        let ticket = self.token.read().ticket.clone();
-        let mut list = http_endpoints::get_friends_list(&self.http_client, &ticket, &self.username)
-            .await?
-            .inner;
-        let update_bookmarks = self.cache.set_bookmarks(list.bookmarks.into()).unwrap();
-        let update_friends = self
-            .cache
-            .set_friends(Cow::from(
-                list.friends
-                    .drain(..)
-                    .map(|v| FriendRelation {
-                        own_character: v.dest,
-                        other_character: v.source,
-                    })
-                    .collect::<Vec<_>>(),
-            ))
-            .unwrap();
-        if update_bookmarks {
-            self.event_listener.updated_bookmarks().await
-        }
-        if update_friends {
-            self.event_listener.updated_friends().await
+        self.coalesce(Request::FriendLists(FriendLists::RELATIONS), || async {
+            // Events are mostly emitted through the event-handler.
+            let mut list = self
+                .tickets
+                .request(|endpoints, ticket, account| async move {
+                    http_endpoints::get_friends_list(
+                        endpoints,
+                        &ticket,
+                        account,
+                        FriendLists::RELATIONS,
+                    )
+                    .await
+                })
+                .await?;
+            let received = Utc::now();
+            let update_bookmarks = self
+                .cache
+                .set_bookmarks(list.bookmarks.into())
+                .map_err(ClientError::cache)?;
+            let update_friends = self
+                .cache
+                .set_friends(Cow::from(
+                    list.friends
+                        .drain(..)
+                        .map(|v| v.relation(received))
+                        .collect::<Vec<_>>(),
+                ))
+                .map_err(ClientError::cache)?;
+            if update_bookmarks {
+                emit!(self, RELATIONS, updated_bookmarks())
+            }
+            if update_friends {
+                emit!(self, RELATIONS, updated_friends())
+            }
+            Ok(update_friends || update_bookmarks)
+        })
+        .await
+    }
+
+    pub async fn sync_friend_requests(&self) -> ClientResult<Vec<PendingFriendRequest>> {
+        self.coalesce(Request::FriendLists(FriendLists::REQUESTS), || async {
+            // Yields the incoming requests, because those are the ones that need a response.
+            let mut list = self
+                .tickets
+                .request(|endpoints, ticket, account| async move {
+                    http_endpoints::get_friends_list(
+                        endpoints,
+                        &ticket,
+                        account,
+                        FriendLists::REQUESTS,
+                    )
+                    .await
+                })
+                .await?;
+            let incoming = list
+                .pending_incoming
+                .drain(..)
+                .map(PendingFriendRequest::from)
+                .collect::<Vec<_>>();
+            let outgoing = list
+                .pending_outgoing
+                .drain(..)
+                .map(PendingFriendRequest::from)
+                .collect::<Vec<_>>();
+            let update_incoming = self
+                .cache
+                .set_incoming_friend_requests(Cow::Borrowed(&incoming))
+                .map_err(ClientError::cache)?;
+            let update_outgoing = self
+                .cache
+                .set_outgoing_friend_requests(Cow::Owned(outgoing))
+                .map_err(ClientError::cache)?;
+            if update_incoming || update_outgoing {
+                emit!(self, RELATIONS, updated_friend_requests())
+            }
+            Ok(incoming)
+        })
+        .await
+    }
+
+    /// Every character on the account, with their IDs, fresh from the site.
+    /// `own_characters` only has the names from when the client was initialized, if that.
+    pub async fn get_character_list(&self) -> ClientResult<Vec<OwnCharacter>> {
+        self.coalesce(Request::CharacterList, || async {
+            let list = self
+                .tickets
+                .request(|endpoints, ticket, account| async move {
+                    http_endpoints::get_character_list(endpoints, &ticket, account).await
+                })
+                .await?;
+            Ok(list.characters)
+        })
+        .await
+    }
+
+    // How long to wait on the server to confirm joins and leaves.
+    const CHANNEL_TIMEOUT: Duration = Duration::from_secs(10);
+
+    // The named character's session, or whichever the selector picks if there's no name.
+    fn choose_session(
+        &self,
+        character: Option<&Character>,
+        purpose: SessionPurpose,
+    ) -> ClientResult<Arc<Session>> {
+        match character {
+            Some(character) => self.get_session(character),
+            None => self.selector.select(&self.get_sessions(), &purpose),
         }
-        Ok(update_friends || update_bookmarks)
+        .ok_or(ClientError::NoSuchSession)
     }
 
-    pub fn get_session(&self, session: &Character) -> Option<Arc<Session>> {
+    /// Join the channel as `character` (or as the session selector sees fit),
+    /// and wait for the server to confirm it.
+    pub async fn join_channel(
+        &self,
+        character: Option<&Character>,
+        channel: Channel,
+    ) -> ClientResult<()> {
+        let session = self.choose_session(character, SessionPurpose::Join(channel.clone()))?;
+        if session.channels.contains(&channel) {
+            return Ok(());
+        }
+        session.join_channel(channel.clone()).await?;
+        if session
+            .wait_for_channel(&channel, true, Self::CHANNEL_TIMEOUT)
+            .await
+        {
+            Ok(())
+        } else {
+            Err(ClientError::Timeout)
+        }
+    }
+
+    /// Leave the channel as `character` (or as the session selector sees fit),
+    /// and wait for the server to confirm it.
+    pub async fn leave_channel(
+        &self,
+        character: Option<&Character>,
+        channel: Channel,
+    ) -> ClientResult<()> {
+        let session = self.choose_session(character, SessionPurpose::Leave(channel.clone()))?;
+        if !session.channels.contains(&channel) {
+            return Ok(());
+        }
+        session.leave_channel(channel.clone()).await?;
+        if session
+            .wait_for_channel(&channel, false, Self::CHANNEL_TIMEOUT)
+            .await
+        {
+            Ok(())
+        } else {
+            Err(ClientError::Timeout)
+        }
+    }
+
+    /// Create a private room (CCR). The server joins `character` to it once it exists.
+    pub async fn create_channel(&self, character: &Character, name: String) -> ClientResult<()> {
+        let session = self
+            .get_session(character)
+            .ok_or(ClientError::NoSuchSession)?;
+        Ok(session.send(ClientCommand::CreateChannel { name }).await?)
+    }
+
+    /// Create an official channel (CRC). Requires global op.
+    pub async fn create_official_channel(
+        &self,
+        character: &Character,
+        name: String,
+    ) -> ClientResult<()> {
+        let session = self
+            .get_session(character)
+            .ok_or(ClientError::NoSuchSession)?;
+        Ok(session
+            .send(ClientCommand::GlobalCreateChannel { name })
+            .await?)
+    }
+
+    /// Invite `target` to a channel that `character` is in (CIU).
+    pub async fn invite(
+        &self,
+        character: &Character,
+        channel: Channel,
+        target: Character,
+    ) -> ClientResult<()> {
+        let session = self
+            .get_session(character)
+            .ok_or(ClientError::NoSuchSession)?;
+        Ok(session
+            .send(ClientCommand::ChannelInviteUser {
+                channel,
+                character: target,
+            })
+            .await?)
+    }
+
+    /// Run a character search (FKS) as `character`, and wait for the results.
+    /// Results are joined with whatever the cache knows about each character.
+    /// This waits on the event loop, so don't call it from inside a listener callback.
+    pub async fn search(
+        &self,
+        character: &Character,
+        query: SearchQuery,
+    ) -> ClientResult<SearchResults> {
+        query.validate().map_err(ClientError::InvalidSearch)?;
+        let session = self
+            .get_session(character)
+            .ok_or(ClientError::NoSuchSession)?;
+        let (send, rcv) = oneshot::channel();
+        match self.pending_searches.entry(*character) {
+            dashmap::mapref::entry::Entry::Occupied(_) => {
+                return Err(ClientError::SearchInProgress);
+            }
+            dashmap::mapref::entry::Entry::Vacant(entry) => {
+                entry.insert(send);
+            }
+        }
+        self.limiter.acquire(LimitedAction::Search).await;
+        if let Err(err) = session.send(query.into_command()).await {
+            self.pending_searches.remove(character);
+            return Err(err.into());
+        }
+        let mut results = match tokio::time::timeout(Self::CHANNEL_TIMEOUT, rcv).await {
+            Ok(Ok(Ok(results))) => results,
+            Ok(Ok(Err(ProtocolError::NoResults))) => SearchResults::default(),
+            Ok(Ok(Err(err))) => return Err(ClientError::Protocol(err)),
+            // Dropped (the session went away) or timed out
+            Ok(Err(_)) | Err(_) => {
+                self.pending_searches.remove(character);
+                return Err(ClientError::Timeout);
+            }
+        };
+        for result in results.characters.iter_mut() {
+            result.data = self
+                .cache
+                .get_character(&result.character)
+                .map_err(ClientError::cache)?;
+        }
+        Ok(results)
+    }
+
+    // Anyone making the same request meanwhile gets this one's answer, instead of sending their own.
+    async fn coalesce<V, F, Fut>(&self, request: Request, fetch: F) -> ClientResult<V>
+    where
+        V: Clone + Send + Sync + 'static,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = ClientResult<V>>,
+    {
+        let response = self
+            .requests
+            .run(request, || async {
+                let response: Arc<dyn Any + Send + Sync> = Arc::new(fetch().await?);
+                ClientResult::Ok(response)
+            })
+            .await?;
+        Ok(response
+            .downcast_ref::<V>()
+            .expect("Each kind of request yields the one type")
+            .clone())
+    }
+
+    /// Get a character's profile, from the cache if it's fresh enough, otherwise from the site.
+    /// With a stale-while-revalidate window set, a recently expired profile is returned as is and refetched later.
+    pub async fn get_profile(
+        &self,
+        character: Character,
+    ) -> ClientResult<Arc<CharacterProfileResponse>> {
+        if let Some(cached) = self
+            .cache
+            .get_profile(&character)
+            .map_err(ClientError::cache)?
+        {
+            let age = cached.age(Utc::now());
+            if age < self.profile_ttl {
+                return Ok(cached.profile);
+            }
+            if age < self.profile_ttl + self.profile_stale {
+                self.profile_revalidate.lock().insert(character);
+                return Ok(cached.profile);
+            }
+        }
+        self.fetch_profile(character).await
+    }
+
+    /// Get several profiles at once, a few at a time, as get_profile would.
+    /// Each character gets its own result, so one that fails doesn't lose the rest.
+    pub async fn get_profiles(
+        &self,
+        characters: impl IntoIterator<Item = Character>,
+    ) -> HashMap<Character, ClientResult<Arc<CharacterProfileResponse>>> {
+        // Any refresh that's due happens once up front, instead of racing in every request.
+        // If it fails, each request gets to try again (and fail) by itself.
+        let _ = self.tickets.refresh_fast().await;
+        let characters = characters.into_iter().collect::<HashSet<_>>();
+        stream::iter(characters)
+            .map(|character| async move { (character, self.get_profile(character).await) })
+            .buffer_unordered(PROFILE_CONCURRENCY)
+            .collect()
+            .await
+    }
+
+    // Anyone else asking for the same profile meanwhile gets this one's answer.
+    async fn fetch_profile(
+        &self,
+        character: Character,
+    ) -> ClientResult<Arc<CharacterProfileResponse>> {
+        self.coalesce(Request::Profile(character), || async move {
+            self.limiter.acquire(LimitedAction::Profile).await;
+            let profile = self
+                .tickets
+                .request(|endpoints, ticket, account| async move {
+                    http_endpoints::get_character_profile_data(
+                        endpoints, &ticket, account, character,
+                    )
+                    .await
+                })
+                .await?;
+            let profile = Arc::new(profile);
+            if self
+                .cache
+                .insert_profile(
+                    Cow::Owned(character),
+                    CachedProfile {
+                        profile: profile.clone(),
+                        fetched_at: Utc::now(),
+                    },
+                )
+                .map_err(ClientError::cache)?
+            {
+                emit!(self, PRESENCE, updated_character(character))
+            }
+            self.profile_details(character, &profile).await?;
+            Ok(profile)
+        })
+        .await
+    }
+
+    // Copy what CharacterData keeps from a profile. Only for characters the cache already has;
+    // a profile alone doesn't say whether they're online.
+    async fn profile_details(
+        &self,
+        character: Character,
+        profile: &CharacterProfileResponse,
+    ) -> ClientResult<()> {
+        if self
+            .cache
+            .get_character(&character)
+            .map_err(ClientError::cache)?
+            .is_none()
+        {
+            return Ok(());
+        }
+        // Without the mapping list, the infotags can't be read; the rest can wait for next time.
+        let Ok(kinks) = self.kinks().await else {
+            return Ok(());
+        };
+        if self
+            .cache
+            .update_character(
+                Cow::Owned(character),
+                profile_update(&kinks, &profile.infotags, profile.updated_at),
+            )
+            .map_err(ClientError::cache)?
+        {
+            emit!(self, PRESENCE, updated_character(character))
+        }
+        Ok(())
+    }
+
+    /// Bookmark a character. The cache is updated straight away, and the RTB that follows is a no-op.
+    pub async fn add_bookmark(&self, character: Character) -> ClientResult<()> {
+        let added = self
+            .cache
+            .add_bookmark(Cow::Owned(character))
+            .map_err(ClientError::cache)?;
+        if added {
+            emit!(self, RELATIONS, updated_bookmarks())
+        }
+        if let Err(err) = self.bookmark_request(character, true).await {
+            // Put it back the way it was.
+            if added
+                && self
+                    .cache
+                    .remove_bookmark(Cow::Owned(character))
+                    .map_err(ClientError::cache)?
+            {
+                emit!(self, RELATIONS, updated_bookmarks())
+            }
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    pub async fn remove_bookmark(&self, character: Character) -> ClientResult<()> {
+        let removed = self
+            .cache
+            .remove_bookmark(Cow::Owned(character))
+            .map_err(ClientError::cache)?;
+        if removed {
+            emit!(self, RELATIONS, updated_bookmarks())
+        }
+        if let Err(err) = self.bookmark_request(character, false).await {
+            if removed
+                && self
+                    .cache
+                    .add_bookmark(Cow::Owned(character))
+                    .map_err(ClientError::cache)?
+            {
+                emit!(self, RELATIONS, updated_bookmarks())
+            }
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    async fn bookmark_request(&self, character: Character, add: bool) -> ClientResult<()> {
+        // Adding or removing twice is harmless, so these are safe to repeat.
+        self.tickets
+            .request(|endpoints, ticket, account| async move {
+                if add {
+                    http_endpoints::add_bookmark(endpoints, &ticket, account, character).await
+                } else {
+                    http_endpoints::remove_bookmark(endpoints, &ticket, account, character).await
+                }
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Get the account's memo on a character, from the cache if it has one.
+    pub async fn get_memo(&self, character: Character) -> ClientResult<String> {
+        if let Some(memo) = self
+            .cache
+            .get_memo(&character)
+            .map_err(ClientError::cache)?
+        {
+            return Ok(memo);
+        }
+        let memo = self
+            .coalesce(Request::Memo(character), || async move {
+                let memo = self
+                    .tickets
+                    .request(|endpoints, ticket, account| async move {
+                        http_endpoints::get_character_memo(endpoints, &ticket, account, character)
+                            .await
+                    })
+                    .await?;
+                ClientResult::Ok(memo.note)
+            })
+            .await?;
+        self.cache
+            .set_memo(Cow::Owned(character), Cow::Borrowed(&memo))
+            .map_err(ClientError::cache)?;
+        Ok(memo)
+    }
+
+    /// Save a memo on a character. Yields the memo as the site saved it.
+    pub async fn set_memo(&self, character: Character, memo: &str) -> ClientResult<String> {
+        let memo = self
+            .tickets
+            .request(|endpoints, ticket, account| async move {
+                http_endpoints::set_character_memo(endpoints, &ticket, account, character, memo)
+                    .await
+            })
+            .await?
+            .note;
+        self.cache
+            .set_memo(Cow::Owned(character), Cow::Borrowed(&memo))
+            .map_err(ClientError::cache)?;
+        Ok(memo)
+    }
+
+    pub async fn get_character_images(&self, character: Character) -> ClientResult<Vec<Image>> {
+        self.coalesce(Request::Images(character), || async move {
+            let response = self
+                .tickets
+                .request(|endpoints, ticket, account| async move {
+                    http_endpoints::get_character_images(endpoints, &ticket, account, character)
+                        .await
+                })
+                .await?;
+            Ok(response.images)
+        })
+        .await
+    }
+
+    pub async fn get_character_friends(
+        &self,
+        character: Character,
+    ) -> ClientResult<Vec<FullCharacter>> {
+        self.coalesce(Request::Friends(character), || async move {
+            let response = self
+                .tickets
+                .request(|endpoints, ticket, account| async move {
+                    http_endpoints::get_character_friends(endpoints, &ticket, account, character)
+                        .await
+                })
+                .await?;
+            Ok(response.friends)
+        })
+        .await
+    }
+
+    /// One page of a character's guestbook, starting from 1.
+    pub async fn get_guestbook(
+        &self,
+        character: Character,
+        page: u64,
+    ) -> ClientResult<CharacterGuestbookResponse> {
+        self.coalesce(Request::Guestbook(character, page), || async move {
+            self.tickets
+                .request(|endpoints, ticket, account| async move {
+                    http_endpoints::get_character_guestbook(
+                        endpoints, &ticket, account, character, page,
+                    )
+                    .await
+                })
+                .await
+        })
+        .await
+    }
+
+    /// Sign `target`'s guestbook as `own`. Not retried, so as not to post twice.
+    pub async fn post_guestbook(
+        &self,
+        own: Character,
+        target: Character,
+        message: &str,
+        private: bool,
+    ) -> ClientResult<()> {
+        self.tickets
+            .request_once(|endpoints, ticket, account| async move {
+                http_endpoints::post_guestbook(
+                    endpoints, &ticket, account, own, target, message, private,
+                )
+                .await
+            })
+            .await?;
+        Ok(())
+    }
+
+    // Replying, approving and deleting all end up the same however many times they're sent.
+
+    pub async fn reply_guestbook(&self, post: u64, reply: &str) -> ClientResult<()> {
+        self.tickets
+            .request(|endpoints, ticket, account| async move {
+                http_endpoints::reply_guestbook(endpoints, &ticket, account, post, reply).await
+            })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn approve_guestbook(&self, post: u64, approve: bool) -> ClientResult<()> {
+        self.tickets
+            .request(|endpoints, ticket, account| async move {
+                http_endpoints::approve_guestbook(endpoints, &ticket, account, post, approve).await
+            })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_guestbook(&self, post: u64) -> ClientResult<()> {
+        self.tickets
+            .request(|endpoints, ticket, account| async move {
+                http_endpoints::delete_guestbook(endpoints, &ticket, account, post).await
+            })
+            .await?;
+        Ok(())
+    }
+
+    // The friend list in the cache catches up through the RTBs the server sends for these,
+    // or the next sync_friends_bookmarks/sync_friend_requests.
+
+    pub async fn remove_friend(&self, own: Character, other: Character) -> ClientResult<()> {
+        self.tickets
+            .request(|endpoints, ticket, account| async move {
+                http_endpoints::remove_friend(endpoints, &ticket, account, own, other).await
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Ask `target` to be friends with `own`. Not retried, so as not to send it twice.
+    pub async fn send_friend_request(
+        &self,
+        own: Character,
+        target: Character,
+    ) -> ClientResult<FriendRequestPartial> {
+        let response = self
+            .tickets
+            .request_once(|endpoints, ticket, account| async move {
+                http_endpoints::send_friend_request(endpoints, &ticket, account, own, target).await
+            })
+            .await?;
+        Ok(response.request)
+    }
+
+    // Answering a request twice is an error on the site's end, so none of these are retried.
+
+    pub async fn accept_friend_request(&self, request: u64) -> ClientResult<()> {
+        self.tickets
+            .request_once(|endpoints, ticket, account| async move {
+                http_endpoints::accept_friend_request(endpoints, &ticket, account, request).await
+            })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn deny_friend_request(&self, request: u64) -> ClientResult<()> {
+        self.tickets
+            .request_once(|endpoints, ticket, account| async move {
+                http_endpoints::deny_friend_request(endpoints, &ticket, account, request).await
+            })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn cancel_friend_request(&self, request: u64) -> ClientResult<()> {
+        self.tickets
+            .request_once(|endpoints, ticket, account| async move {
+                http_endpoints::cancel_friend_request(endpoints, &ticket, account, request).await
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Report a character or channel to the moderators, as `reporter`.
+    /// The last `recent_messages` messages from the cache are uploaded as the log first,
+    /// and SFC is only sent once the upload has gone through. Yields the log ID.
+    pub async fn report<R: Into<ReportTarget>>(
+        &self,
+        reporter: Character,
+        target: R,
+        reason: &str,
+        recent_messages: u32,
+    ) -> ClientResult<u64> {
+        let target = target.into();
+        let session = self
+            .get_session(&reporter)
+            .ok_or(ClientError::NoSuchSession)?;
+        let (source, tab, reported) = match &target {
+            ReportTarget::Channel { channel } => (
+                MessageChannel::Channel(channel.clone()),
+                channel.0.to_string(),
+                String::new(),
+            ),
+            ReportTarget::Character { character } => (
+                MessageChannel::private(reporter, *character),
+                character.0.to_string(),
+                character.0.to_string(),
+            ),
+        };
+        let log = self
+            .cache
+            .get_messages(&source, None, Some(recent_messages))
+            .map_err(ClientError::cache)?
+            .iter()
+            .map(format_log_line)
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        self.limiter.acquire(LimitedAction::Report).await;
+        // Not retried; it's not safe to upload the log twice.
+        let logid = self
+            .tickets
+            .request_once(|endpoints, ticket, account| {
+                let (log, target) = (&log, target.clone());
+                async move {
+                    http_endpoints::report(
+                        endpoints, &ticket, account, reporter, target, reason, log,
+                    )
+                    .await
+                }
+            })
+            .await?
+            .log_id
+            .0;
+
+        // This is the shape the official client uses, and what the moderators expect to see.
+        session
+            .send(ClientCommand::Report {
+                action: ReportAction::Report,
+                report: format!(
+                    "Current Tab/Channel: {tab} | Reporting User: {reported} | {reason}"
+                ),
+                character: reporter,
+                logid: Some(logid),
+            })
+            .await?;
+        Ok(logid)
+    }
+
+    /// Send a message as `character` (or as the session selector sees fit),
+    /// split into several if it's too long and splitting is on.
+    /// Once it's gone out, it's put in the cache and looped back
+    /// through EventListener::own_message, since the server never echoes it.
+    pub async fn send_message(
+        &self,
+        character: Option<&Character>,
+        target: Target,
+        message: String,
+    ) -> ClientResult<()> {
+        let purpose = match &target {
+            Target::Channel { channel } => SessionPurpose::ChannelMessage(channel.clone()),
+            Target::Character { recipient } => SessionPurpose::PrivateMessage(*recipient),
+        };
+        let session = self.choose_session(character, purpose)?;
+        let character = &session.character;
+        let source = match &target {
+            Target::Channel { channel } => MessageChannel::Channel(channel.clone()),
+            Target::Character { recipient } => {
+                MessageChannel::private(session.character, *recipient)
+            }
+        };
+        let max = match &target {
+            Target::Channel { .. } => session.variables.chat_max,
+            Target::Character { .. } => session.variables.priv_max,
+        } as usize;
+        let parts = if self.split_messages && max > 0 && message.len() > max {
+            split_message(&message, max)
+        } else {
+            vec![message.as_str()]
+        };
+        for (i, part) in parts.into_iter().enumerate() {
+            if i > 0 {
+                // Channels have a flood limit, and going over it gets the message dropped.
+                if let Target::Channel { .. } = target {
+                    tokio::time::sleep(Duration::from_secs_f32(session.variables.chat_cooldown))
+                        .await;
+                }
+            }
+            let command = match &target {
+                Target::Channel { channel } => ClientCommand::Message {
+                    channel: channel.clone(),
+                    message: part.to_owned(),
+                },
+                Target::Character { recipient } => ClientCommand::PrivateMessage {
+                    recipient: *recipient,
+                    message: part.to_owned(),
+                },
+            };
+            if !self.send_user_command(character, command).await? {
+                // Queued; it'll be looped back when it actually goes out.
+                continue;
+            }
+            self.loop_back(&session, source.clone(), part.to_owned())
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Capture the connected characters, their channels and the desired status,
+    /// to hand to ClientBuilder::resume later.
+    pub fn snapshot(&self) -> ClientSnapshot {
+        let sessions = self
+            .get_sessions()
+            .iter()
+            .map(|session| {
+                let mut channels = session
+                    .channels
+                    .iter()
+                    .map(|c| c.clone())
+                    .collect::<Vec<_>>();
+                channels.sort();
+                SessionSnapshot {
+                    character: session.character,
+                    channels,
+                }
+            })
+            .collect();
+        ClientSnapshot {
+            sessions,
+            status: self.status.desired(),
+        }
+    }
+
+    // Connect everything in the snapshot given to the builder. One character failing shouldn't
+    // stop the rest, so errors go to the listener rather than back out of init.
+    async fn resume_sessions(&self) {
+        let characters = self
+            .resuming
+            .iter()
+            .map(|entry| *entry.key())
+            .collect::<Vec<_>>();
+        for character in characters {
+            if let Err(err) = self.connect(character).await {
+                self.resuming.remove(&character);
+                self.event_listener.client_error(err).await
+            }
+        }
+    }
+
+    async fn rejoin_snapshot(&self, session: &Session) -> ClientResult<()> {
+        if let Some((_, channels)) = self.resuming.remove(&session.character) {
+            for channel in channels {
+                session
+                    .send_unattended(ClientCommand::JoinChannel { channel })
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn initial_sync(&self, session: &Session) -> ClientResult<()> {
+        let sync = self.initial_sync;
+        if sync.global_channels {
+            session
+                .send_unattended(ClientCommand::GlobalChannels)
+                .await?;
+        }
+        if sync.private_rooms {
+            session.send_unattended(ClientCommand::Channels).await?;
+        }
+        if sync.ignore_list {
+            session
+                .send_unattended(ClientCommand::IgnoreList {
+                    action: IgnoreAction::List,
+                    character: Default::default(), // Ignored for list
+                })
+                .await?;
+        }
+        Ok(())
+    }
+
+    // Put a message we sent into the cache, and let the listener know about it.
+    async fn loop_back(
+        &self,
+        session: &Arc<Session>,
+        source: MessageChannel,
+        message: String,
+    ) -> ClientResult<()> {
+        if let MessageChannel::Channel(channel) = &source {
+            // So our other characters in there don't log it again when it reaches them.
+            self.dedup.first_sighting(
+                session.character,
+                channel.clone(),
+                session.character,
+                &message,
+            );
+        }
+        let content = MessageContent::Message(message.into());
+        if self
+            .cache
+            .insert_message(
+                source.clone(),
+                Message {
+                    timestamp: Utc::now(),
+                    character: session.character,
+                    content: content.clone(),
+                },
+            )
+            .map_err(ClientError::cache)?
+        {
+            emit!(
+                self,
+                MESSAGES,
+                own_message(session.clone(), source, content)
+            )
+        }
+        Ok(())
+    }
+
+    // Send something on the user's behalf. If the outbox is on and the session is mid-reconnect
+    // (or its socket has just died), it's queued instead. Yields whether it went out now.
+    async fn send_user_command(
+        &self,
+        character: &Character,
+        command: ClientCommand,
+    ) -> ClientResult<bool> {
+        if let Some(outbox) = &self.outbox {
+            if outbox.lock().reconnecting.contains(character) {
+                self.queue(outbox, character, command);
+                return Ok(false);
+            }
+        }
+        let session = self
+            .get_session(character)
+            .ok_or(ClientError::NoSuchSession)?;
+        match (session.send(command.clone()).await, &self.outbox) {
+            (Ok(()), _) => Ok(true),
+            // The connection's gone; a reconnect should be on its way.
+            (Err(SessionError::TransportError(_)), Some(outbox)) => {
+                self.queue(outbox, character, command);
+                Ok(false)
+            }
+            (Err(err), _) => Err(err.into()),
+        }
+    }
+
+    fn queue(&self, outbox: &Mutex<Outbox>, character: &Character, command: ClientCommand) {
+        if let ClientCommand::Status { .. } = command {
+            return;
+        }
+        outbox
+            .lock()
+            .queued
+            .entry(*character)
+            .or_default()
+            .push(command);
+    }
+
+    // The session isn't coming back, so neither are its queued messages.
+    fn discard_outbox(&self, character: &Character) {
+        if let Some(outbox) = &self.outbox {
+            let mut outbox = outbox.lock();
+            outbox.reconnecting.remove(character);
+            outbox.queued.remove(character);
+        }
+    }
+
+    // Send everything that piled up while the session was away, in order.
+    async fn replay_outbox(&self, session: &Arc<Session>) -> ClientResult<()> {
+        let Some(outbox) = &self.outbox else {
+            return Ok(());
+        };
+        let queued = {
+            let mut outbox = outbox.lock();
+            outbox.reconnecting.remove(&session.character);
+            outbox.queued.remove(&session.character).unwrap_or_default()
+        };
+        for command in queued {
+            let source = match &command {
+                ClientCommand::Message { channel, message } => {
+                    Some((MessageChannel::Channel(channel.clone()), message.clone()))
+                }
+                ClientCommand::PrivateMessage { recipient, message } => Some((
+                    MessageChannel::private(session.character, *recipient),
+                    message.clone(),
+                )),
+                _ => None,
+            };
+            session.send(command).await?;
+            if let Some((source, message)) = source {
+                self.loop_back(session, source, message).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The kink mapping list. Taken from memory or the cache while it's younger than the mapping TTL,
+    /// and fetched from the site otherwise.
+    pub async fn kinks(&self) -> ClientResult<Arc<KinkRegistry>> {
+        if let Some(kinks) = self.fresh_kinks() {
+            return Ok(kinks);
+        }
+        let _loading = self.kinks_loading.lock().await;
+        // Someone else might've loaded it while this was waiting.
+        if let Some(kinks) = self.fresh_kinks() {
+            return Ok(kinks);
+        }
+        if let Some(cached) = self.cache.get_kink_mapping().map_err(ClientError::cache)? {
+            if cached.age(Utc::now()) < self.mapping_ttl {
+                return Ok(self.load_kinks(cached));
+            }
+        }
+        self.fetch_kinks().await
+    }
+
+    /// Fetch the mapping list from the site now, however old the one there is.
+    pub async fn refresh_kinks(&self) -> ClientResult<Arc<KinkRegistry>> {
+        let _loading = self.kinks_loading.lock().await;
+        self.fetch_kinks().await
+    }
+
+    async fn fetch_kinks(&self) -> ClientResult<Arc<KinkRegistry>> {
+        let mapping = retry(&self.retry, || {
+            http_endpoints::get_mapping_list(self.tickets.endpoints())
+        })
+        .await?;
+        let cached = CachedKinkMapping {
+            mapping: Arc::new(mapping),
+            fetched_at: Utc::now(),
+        };
+        self.cache
+            .set_kink_mapping(cached.clone())
+            .map_err(ClientError::cache)?;
+        Ok(self.load_kinks(cached))
+    }
+
+    fn load_kinks(&self, cached: CachedKinkMapping) -> Arc<KinkRegistry> {
+        let kinks = Arc::new(KinkRegistry::from(&*cached.mapping));
+        *self.kinks.write() = Some((kinks.clone(), cached.fetched_at));
+        kinks
+    }
+
+    fn fresh_kinks(&self) -> Option<Arc<KinkRegistry>> {
+        let kinks = self.kinks.read();
+        let (kinks, fetched_at) = kinks.as_ref()?;
+        let age = (Utc::now() - *fetched_at).to_std().unwrap_or_default();
+        (age < self.mapping_ttl).then(|| kinks.clone())
+    }
+
+    // These only see the mappings once kinks() has loaded them.
+    pub fn kink(&self, id: KinkId) -> Option<Kink> {
+        self.kinks.read().as_ref()?.0.kink(id).cloned()
+    }
+
+    pub fn kink_by_name(&self, name: &str) -> Option<Kink> {
+        self.kinks.read().as_ref()?.0.kink_by_name(name).cloned()
+    }
+
+    /// A character's kinks with their names and groups, loading the mapping list first if need be.
+    pub async fn get_profile_kinks(&self, character: Character) -> ClientResult<Vec<ResolvedKink>> {
+        let profile = self.get_profile(character).await?;
+        Ok(self.kinks().await?.resolve_kinks(&profile.kinks))
+    }
+
+    /// Whether a character is a global (chat) op. For our own characters, the VAR permissions are checked too,
+    /// since the op list only turns up once the session is up.
+    pub fn is_global_op(&self, character: &Character) -> ClientResult<bool> {
+        // As far as I can tell, bit 0 is admin and bit 1 is chat op.
+        const GLOBAL_OP_PERMISSIONS: u64 = 0b11;
+        if self
+            .get_session(character)
+            .is_some_and(|session| session.variables.permissions & GLOBAL_OP_PERMISSIONS != 0)
+        {
+            return Ok(true);
+        }
+        Ok(self
+            .cache
+            .get_global_ops()
+            .map_err(ClientError::cache)?
+            .contains(character))
+    }
+
+    /// Whether a character can moderate a channel: channel ops, the owner, and global ops.
+    pub fn is_op(&self, character: &Character, channel: &Channel) -> ClientResult<bool> {
+        if self
+            .cache
+            .get_channel_ops(channel)
+            .map_err(ClientError::cache)?
+            .contains(character)
+        {
+            return Ok(true);
+        }
+        if self
+            .cache
+            .get_channel(channel)
+            .map_err(ClientError::cache)?
+            .is_some_and(|data| data.owner == Some(*character))
+        {
+            return Ok(true);
+        }
+        self.is_global_op(character)
+    }
+
+    /// Friends of one of the account's characters. The site lists them for the whole account at once.
+    pub fn friends_of(&self, own: &Character) -> ClientResult<Vec<Character>> {
+        Ok(self
+            .cache
+            .get_friend_relations()
+            .map_err(ClientError::cache)?
+            .iter()
+            .filter(|relation| relation.own_character == *own)
+            .map(|relation| relation.other_character)
+            .collect())
+    }
+
+    /// Every friend, under whichever of the account's characters they're friends with.
+    pub fn friends_by_character(&self) -> ClientResult<HashMap<Character, Vec<Character>>> {
+        let mut friends = HashMap::<_, Vec<_>>::new();
+        for relation in self
+            .cache
+            .get_friend_relations()
+            .map_err(ClientError::cache)?
+            .iter()
+        {
+            friends
+                .entry(relation.own_character)
+                .or_default()
+                .push(relation.other_character);
+        }
+        Ok(friends)
+    }
+
+    pub fn is_ignored(&self, character: &Character) -> ClientResult<bool> {
+        Ok(self
+            .cache
+            .get_ignored()
+            .map_err(ClientError::cache)?
+            .contains(character))
+    }
+
+    pub async fn ignore(&self, character: Character) -> ClientResult<()> {
+        self.send_ignore(IgnoreAction::Add, character).await
+    }
+
+    pub async fn unignore(&self, character: Character) -> ClientResult<()> {
+        self.send_ignore(IgnoreAction::Delete, character).await
+    }
+
+    async fn send_ignore(&self, action: IgnoreAction, character: Character) -> ClientResult<()> {
+        // Any session will do; the server replies with IGN which updates the cache.
+        let session = self.any_session().ok_or(ClientError::NoSession)?;
+        Ok(session
+            .send(ClientCommand::IgnoreList { action, character })
+            .await?)
+    }
+
+    pub fn event_listener(&self) -> &T {
+        &self.event_listener
+    }
+
+    fn any_session(&self) -> Option<Arc<Session>> {
         self.sessions
-            .read()
             .iter()
-            .find(|this_session| this_session.character == *session)
-            .cloned()
+            .min_by_key(|entry| entry.0)
+            .map(|entry| entry.1.clone())
     }
 
+    pub fn get_session(&self, session: &Character) -> Option<Arc<Session>> {
+        self.sessions.get(session).map(|entry| entry.1.clone())
+    }
+
+    /// Every connected session, in the order they connected.
     pub fn get_sessions(&self) -> Vec<Arc<Session>> {
-        self.sessions.read().clone()
+        let mut sessions = self
+            .sessions
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect::<Vec<_>>();
+        sessions.sort_unstable_by_key(|(added, _)| *added);
+        sessions.into_iter().map(|(_, session)| session).collect()
     }
 
-    fn drop_session(&self, session: &Character) {
-        self.sessions.write().retain(|v| v.character != *session)
+    pub(crate) fn add_session(&self, session: Arc<Session>) {
+        let added = self.sessions_added.fetch_add(1, Ordering::Relaxed);
+        self.sessions.insert(session.character, (added, session));
+        telemetry::sessions(self.sessions.len());
+    }
+
+    pub(crate) fn drop_session(&self, session: &Character) {
+        self.sessions.remove(session);
+        telemetry::sessions(self.sessions.len());
     }
 
-    #[allow(unused_variables)]
     pub(crate) async fn dispatch(&self, event: Event) {
+        let character = event.session.character;
+        in_session_span(&character, self.dispatch_event(event)).await
+    }
+
+    async fn dispatch_event(&self, event: Event) {
         match event.event {
             crate::session::SessionEvent::Reconnect => {
+                telemetry::reconnect();
+                if let Some(outbox) = &self.outbox {
+                    outbox.lock().reconnecting.insert(event.session.character);
+                }
                 // Reconnect the session; treat it as having disconnected
-                self.refresh_fast().await.unwrap();
-                let ticket = self.token.read().ticket.clone();
+                if let Err(err) = self.refresh_fast().await {
+                    // Carry on with the old ticket; it might still be good.
+                    self.event_listener.client_error(err).await
+                }
                 let new_session = event
                     .session
                     .reconnect(
-                        self.username.clone(),
-                        ticket,
+                        self.tickets.account().to_owned(),
+                        self.tickets.current().to_string(),
                         self.client_name.clone(),
                         self.client_version.clone(),
                     )
                     .await;
                 self.drop_session(&event.session.character);
                 match new_session {
-                    Ok(session) => self.sessions.write().push(session),
-                    Err(err) => self.event_listener.session_error(event.session, err).await,
+                    Ok(session) => self.add_session(session),
+                    Err(err) => {
+                        self.discard_outbox(&event.session.character);
+                        self.event_listener.session_error(event.session, err).await
+                    }
                 }
                 self.event_listener.sessions_updated().await
             }
             crate::session::SessionEvent::Disconnected(err) => {
+                self.discard_outbox(&event.session.character);
                 self.drop_session(&event.session.character);
                 self.event_listener
                     .session_disconnected(event.session, err)
@@ -326,492 +2214,1068 @@ impl<T: EventListener, C: Cache> Client<T, C> {
                 self.event_listener
                     .raw_command(event.session.clone(), &command)
                     .await;
-                match command {
-                    ServerCommand::GlobalOps { ops } => {
-                        if self.cache.set_global_ops(ops.into()).unwrap() {
-                            self.event_listener.updated_global_ops().await
-                        }
-                    }
-                    ServerCommand::GlobalOpped { character } => {
-                        if self.cache.add_global_op(Cow::Owned(character)).unwrap() {
-                            self.event_listener.updated_global_ops().await
-                        }
-                    }
-                    ServerCommand::GlobalDeopped { character } => {
-                        if self.cache.remove_global_op(Cow::Owned(character)).unwrap() {
-                            self.event_listener.updated_global_ops().await
+                if let Err(err) = self.dispatch_command(event.session, command).await {
+                    self.event_listener.client_error(err).await
+                }
+            }
+            crate::session::SessionEvent::Error(err) => {
+                self.event_listener.session_error(event.session, err).await;
+            }
+            crate::session::SessionEvent::Sent(command) => {
+                // The server only confirms an unban in free text, so take our own word for it.
+                if let ClientCommand::Pardon { channel, character } = &command {
+                    match self
+                        .cache
+                        .remove_channel_ban(Cow::Borrowed(channel), Cow::Borrowed(character))
+                    {
+                        Ok(true) => emit!(self, CHANNELS, updated_channel(channel.clone())),
+                        Ok(false) => {}
+                        Err(err) => {
+                            self.event_listener
+                                .client_error(ClientError::cache(err))
+                                .await
                         }
                     }
+                }
+                self.event_listener
+                    .raw_outgoing(event.session, &command)
+                    .await
+            }
+        }
+    }
 
-                    ServerCommand::Banned {
-                        operator,
-                        channel,
-                        character,
-                    } => todo!("(Banned event)"), // Need a moderation abstraction
-                    ServerCommand::Kicked {
-                        operator,
-                        channel,
-                        character,
-                    } => todo!("(Kick event)"), // Each event has channel, character, operator
-                    ServerCommand::Timeout {
-                        channel,
-                        character,
-                        length,
-                        operator,
-                    } => todo!("(Timeout event)"), // No examples of use though.
+    #[allow(unused_variables)]
+    async fn dispatch_command(
+        &self,
+        session: Arc<Session>,
+        command: ServerCommand,
+    ) -> ClientResult<()> {
+        match command {
+            ServerCommand::GlobalOps { ops } => {
+                if self
+                    .cache
+                    .set_global_ops(ops.into())
+                    .map_err(ClientError::cache)?
+                {
+                    emit!(self, MODERATION, updated_global_ops())
+                }
+            }
+            ServerCommand::GlobalOpped { character } => {
+                if self
+                    .cache
+                    .add_global_op(Cow::Owned(character))
+                    .map_err(ClientError::cache)?
+                {
+                    emit!(self, MODERATION, updated_global_ops())
+                }
+            }
+            ServerCommand::GlobalDeopped { character } => {
+                if self
+                    .cache
+                    .remove_global_op(Cow::Owned(character))
+                    .map_err(ClientError::cache)?
+                {
+                    emit!(self, MODERATION, updated_global_ops())
+                }
+            }
 
-                    ServerCommand::Broadcast { message, character } => {
-                        self.event_listener.broadcast(character, message).await
-                    }
-                    ServerCommand::ChannelDescription {
-                        channel,
-                        description,
-                    } => {
-                        if self
-                            .cache
-                            .update_channel(
-                                Cow::Borrowed(&channel),
-                                PartialChannelData {
-                                    description: Some(description.into()),
-                                    ..Default::default()
-                                },
-                            )
-                            .unwrap()
-                        {
-                            self.event_listener.updated_channel(channel).await
-                        }
-                    }
-                    ServerCommand::GlobalChannels { mut channels } => {
-                        for channel in channels.iter() {
-                            if self
-                                .cache
-                                .update_channel(
-                                    Cow::Borrowed(&channel.name),
-                                    PartialChannelData {
-                                        title: Some(Cow::from(channel.name.0.as_ref())),
-                                        mode: Some(channel.mode),
-                                        ..Default::default()
-                                    },
-                                )
-                                .unwrap()
-                            {
-                                self.event_listener.updated_channel(channel.name).await
-                            }
-                        }
-                        if self
-                            .cache
-                            .set_global_channels(Cow::Owned(
-                                channels.drain(..).map(|v| (v.name, v.characters)).collect(),
-                            ))
-                            .unwrap()
-                        {
-                            self.event_listener.updated_channel_lists().await
-                        }
-                    }
-                    ServerCommand::Invited {
-                        sender,
-                        title,
-                        name,
-                    } => {
-                        if self
-                            .cache
-                            .update_channel(
-                                Cow::Borrowed(&name),
-                                PartialChannelData {
-                                    title: Some(title.into()),
-                                    ..Default::default()
-                                },
-                            )
-                            .unwrap()
-                        {
-                            self.event_listener.updated_channel(name).await
-                        }
-                        self.event_listener
-                            .invited(event.session, name, sender)
-                            .await
+            // Each of these removes the character from the channel, same as LCH.
+            ServerCommand::Banned {
+                operator,
+                channel,
+                character,
+            } => {
+                self.left_channel(&session, channel.clone(), character)
+                    .await?;
+                if self
+                    .cache
+                    .add_channel_ban(Cow::Borrowed(&channel), Cow::Borrowed(&character))
+                    .map_err(ClientError::cache)?
+                {
+                    emit!(self, CHANNELS, updated_channel(channel.clone()))
+                }
+                emit!(
+                    self,
+                    MODERATION,
+                    banned(session, channel, character, operator)
+                )
+            }
+            ServerCommand::Kicked {
+                operator,
+                channel,
+                character,
+            } => {
+                self.left_channel(&session, channel.clone(), character)
+                    .await?;
+                if session.character == character {
+                    if let Some(delay) = self.auto_rejoin.delay(&channel) {
+                        self.pending_rejoins.lock().push((
+                            character,
+                            channel.clone(),
+                            Instant::now() + delay,
+                        ));
                     }
+                }
+                emit!(
+                    self,
+                    MODERATION,
+                    kicked(session, channel, character, operator)
+                )
+            }
+            ServerCommand::Timeout {
+                channel,
+                character,
+                length,
+                operator,
+            } => {
+                // No examples of use though.
+                self.left_channel(&session, channel.clone(), character)
+                    .await?;
+                // The length is in minutes.
+                let expires = Utc::now() + chrono::Duration::minutes(length.into());
+                if self
+                    .cache
+                    .add_channel_timeout(
+                        Cow::Borrowed(&channel),
+                        Cow::Borrowed(&character),
+                        expires,
+                    )
+                    .map_err(ClientError::cache)?
+                {
+                    emit!(self, CHANNELS, updated_channel(channel.clone()))
+                }
+                emit!(
+                    self,
+                    MODERATION,
+                    timed_out(session, channel, character, operator, length)
+                )
+            }
 
-                    ServerCommand::Opped { character, channel } => {
-                        if self
-                            .cache
-                            .add_channel_op(Cow::Borrowed(&channel), Cow::Owned(character))
-                            .unwrap()
-                        {
-                            self.event_listener.updated_channel(channel).await
-                        }
-                    }
-                    ServerCommand::Ops { channel, oplist } => {
-                        if self
-                            .cache
-                            .set_channel_ops(Cow::Borrowed(&channel), Cow::Owned(oplist))
-                            .unwrap()
-                        {
-                            self.event_listener.updated_channel(channel).await
-                        }
-                    }
-                    ServerCommand::Connected { .. } => {
-                        self.event_listener.ready(event.session).await
-                    }
-                    ServerCommand::Deopped { character, channel } => {
-                        if self
-                            .cache
-                            .remove_channel_op(Cow::Borrowed(&channel), Cow::Owned(character))
-                            .unwrap()
-                        {
-                            self.event_listener.updated_channel(channel).await
-                        }
-                    }
-                    ServerCommand::SetOwner { character, channel } => todo!(), // It is unclear how this information is conveyed otherwise.
-                    ServerCommand::Error { number, message } => {
-                        self.event_listener
-                            .error(event.session, number.into(), message)
-                            .await
-                    }
-                    ServerCommand::Search { characters, kinks } => {
-                        todo!("FKS -- Should never reach client")
-                    } // Wrap up into session
-                    ServerCommand::Offline { character } => {
-                        if self
-                            .cache
-                            .update_character(
-                                Cow::Borrowed(&character),
-                                PartialUserData {
-                                    status: Some(Status::Offline),
-                                    ..Default::default()
-                                },
-                            )
-                            .unwrap()
-                        {
-                            self.event_listener.updated_character(character).await
-                        }
-                    }
-                    ServerCommand::Hello { .. } => panic!("HLO -- Should never reach client"), // Sunk by session impl
-                    ServerCommand::ChannelData {
-                        users,
-                        channel,
-                        mode,
-                    } => {
-                        if self
-                            .cache
-                            .insert_channel(
-                                Cow::Borrowed(&channel),
-                                PartialChannelData {
-                                    mode: Some(mode),
-                                    ..Default::default()
-                                },
-                                Cow::Owned(users),
-                            )
-                            .unwrap()
-                        {
-                            self.event_listener.updated_channel(channel).await
-                        }
-                    }
-                    ServerCommand::IdentifySuccess { .. } => {
-                        panic!("IDN -- Should never reach client")
-                    } // Sunk by session impl
-                    ServerCommand::JoinedChannel {
-                        channel,
-                        character,
-                        title,
-                    } => {
-                        if self
-                            .cache
-                            .update_channel(
-                                Cow::Borrowed(&channel),
-                                PartialChannelData {
-                                    title: Some(title.into()),
-                                    ..Default::default()
-                                },
-                            )
-                            .unwrap()
-                            || self
-                                .cache
-                                .add_channel_member(Cow::Borrowed(&channel), character)
-                                .unwrap()
-                        {
-                            self.event_listener.updated_channel(channel).await
-                        }
-                        if event.session.character == character {
-                            self.event_listener
-                                .updated_session_channels(event.session)
-                                .await
-                        }
-                    }
-                    ServerCommand::Kinks { .. } => {
-                        eprintln!("Received KID from server -- Use HTTP/JSON endpoint instead")
-                    }
-                    ServerCommand::LeftChannel { channel, character } => {
-                        if self
-                            .cache
-                            .remove_channel_member(Cow::Borrowed(&channel), character)
-                            .unwrap()
-                        {
-                            self.event_listener.updated_channel(channel).await
-                        }
-                        if event.session.character == character {
-                            self.event_listener
-                                .updated_session_channels(event.session)
-                                .await
-                        }
+            ServerCommand::Broadcast { message, character } => {
+                emit!(self, MESSAGES, broadcast(character, message))
+            }
+            ServerCommand::ChannelDescription {
+                channel,
+                description,
+            } => {
+                if self
+                    .cache
+                    .update_channel(
+                        Cow::Borrowed(&channel),
+                        PartialChannelData {
+                            description: Some(description.into()),
+                            ..Default::default()
+                        },
+                    )
+                    .map_err(ClientError::cache)?
+                {
+                    emit!(self, CHANNELS, updated_channel(channel))
+                }
+            }
+            ServerCommand::GlobalChannels { mut channels } => {
+                for channel in channels.iter() {
+                    if self
+                        .cache
+                        .update_channel(
+                            Cow::Borrowed(&channel.name),
+                            PartialChannelData {
+                                title: Some(Cow::from(channel.name.0.as_ref())),
+                                mode: Some(channel.mode),
+                                member_count: Some(channel.characters),
+                                ..Default::default()
+                            },
+                        )
+                        .map_err(ClientError::cache)?
+                    {
+                        emit!(self, CHANNELS, updated_channel(channel.name.clone()))
                     }
-                    ServerCommand::ListOnline { mut characters } => {
-                        for character in characters.drain(..) {
-                            if self
-                                .cache
-                                .update_character(
-                                    Cow::Borrowed(&character.0),
-                                    PartialUserData {
-                                        gender: Some(character.1),
-                                        status: Some(character.2),
-                                        status_message: Some(character.3.into()),
-                                    },
-                                )
-                                .unwrap()
-                            {
-                                self.event_listener.updated_character(character.0).await
-                            }
-                        }
+                }
+                if self
+                    .cache
+                    .set_global_channels(
+                        Cow::Owned(channels.drain(..).map(|v| (v.name, v.characters)).collect()),
+                        Utc::now(),
+                    )
+                    .map_err(ClientError::cache)?
+                {
+                    emit!(self, CHANNELS, updated_channel_lists())
+                }
+            }
+            ServerCommand::Invited {
+                sender,
+                title,
+                name,
+            } => {
+                if self
+                    .cache
+                    .update_channel(
+                        Cow::Borrowed(&name),
+                        PartialChannelData {
+                            title: Some(title.into()),
+                            ..Default::default()
+                        },
+                    )
+                    .map_err(ClientError::cache)?
+                {
+                    emit!(self, CHANNELS, updated_channel(name.clone()))
+                }
+                emit!(self, CHANNELS, invited(session, name, sender))
+            }
+
+            ServerCommand::Opped { character, channel } => {
+                if self
+                    .cache
+                    .add_channel_op(Cow::Borrowed(&channel), Cow::Owned(character))
+                    .map_err(ClientError::cache)?
+                {
+                    emit!(self, CHANNELS, updated_channel(channel))
+                }
+            }
+            ServerCommand::Ops {
+                channel,
+                mut oplist,
+            } => {
+                // The first entry is the owner, or an empty name for channels nobody owns.
+                let owner = oplist.first().copied().filter(|owner| !owner.0.is_empty());
+                oplist.retain(|op| !op.0.is_empty());
+                let mut changed = self
+                    .cache
+                    .set_channel_ops(Cow::Borrowed(&channel), Cow::Owned(oplist))
+                    .map_err(ClientError::cache)?;
+                if let Some(owner) = owner {
+                    changed |= self
+                        .cache
+                        .update_channel(
+                            Cow::Borrowed(&channel),
+                            PartialChannelData {
+                                owner: Some(owner),
+                                ..Default::default()
+                            },
+                        )
+                        .map_err(ClientError::cache)?;
+                }
+                if changed {
+                    emit!(self, CHANNELS, updated_channel(channel))
+                }
+            }
+            ServerCommand::Connected { .. } => {
+                if let Some((status, statusmsg)) = self.status.effective() {
+                    session
+                        .send(ClientCommand::Status { status, statusmsg })
+                        .await?;
+                }
+                if let Some(batch) = &self.character_batch {
+                    batch.lock().begin(session.character);
+                }
+                self.initial_sync(&session).await?;
+                self.rejoin_snapshot(&session).await?;
+                self.replay_outbox(&session).await?;
+                self.event_listener.ready(session).await
+            }
+            ServerCommand::Deopped { character, channel } => {
+                if self
+                    .cache
+                    .remove_channel_op(Cow::Borrowed(&channel), Cow::Owned(character))
+                    .map_err(ClientError::cache)?
+                {
+                    emit!(self, CHANNELS, updated_channel(channel))
+                }
+            }
+            ServerCommand::SetOwner { character, channel } => {
+                // Grab the old owner before it's overwritten, so that listeners can see the handover.
+                let old_owner = self
+                    .cache
+                    .get_channel(&channel)
+                    .map_err(ClientError::cache)?
+                    .and_then(|data| data.owner);
+                if self
+                    .cache
+                    .update_channel(
+                        Cow::Borrowed(&channel),
+                        PartialChannelData {
+                            owner: Some(character),
+                            ..Default::default()
+                        },
+                    )
+                    .map_err(ClientError::cache)?
+                {
+                    emit!(self, CHANNELS, updated_channel(channel.clone()))
+                }
+                if old_owner != Some(character) {
+                    emit!(
+                        self,
+                        CHANNELS,
+                        ownership_changed(channel, old_owner, character)
+                    )
+                }
+            }
+            ServerCommand::Error { number, message } => {
+                let error = ProtocolError::from(number);
+                if matches!(
+                    error,
+                    ProtocolError::NoResults
+                        | ProtocolError::SearchCooldown
+                        | ProtocolError::TooManySearchTerms
+                        | ProtocolError::TooManySearchResults
+                ) {
+                    if let Some((_, pending)) = self.pending_searches.remove(&session.character) {
+                        // The caller gets this one; no need to tell the listener too.
+                        let _ = pending.send(Err(error));
+                        return Ok(());
                     }
-                    ServerCommand::NewConnection {
-                        status,
-                        gender,
-                        identity,
-                    } => {
-                        if self
-                            .cache
+                }
+                if number == ProtocolError::AdCooldown as i32 {
+                    self.ads.cooled_down(&session.character, Instant::now());
+                }
+                self.event_listener
+                    .error(session, number.into(), message)
+                    .await
+            }
+            ServerCommand::Offline { character } => {
+                if self
+                    .cache
+                    .update_character(
+                        Cow::Borrowed(&character),
+                        PartialUserData {
+                            status: Some(Status::Offline),
+                            typing: Some(TypingStatus::Clear),
+                            ..Default::default()
+                        },
+                    )
+                    .map_err(ClientError::cache)?
+                {
+                    self.character_updated(&session, character).await
+                }
+                self.relation_presence(&session, character, false).await?;
+            }
+            ServerCommand::ChannelData {
+                users,
+                channel,
+                mode,
+            } => {
+                if self
+                    .cache
+                    .insert_channel(
+                        Cow::Borrowed(&channel),
+                        PartialChannelData {
+                            mode: Some(mode),
+                            ..Default::default()
+                        },
+                        Cow::Owned(users),
+                    )
+                    .map_err(ClientError::cache)?
+                {
+                    emit!(self, CHANNELS, updated_channel(channel))
+                }
+            }
+            ServerCommand::JoinedChannel {
+                channel,
+                character,
+                title,
+            } => {
+                if self
+                    .cache
+                    .update_channel(
+                        Cow::Borrowed(&channel),
+                        PartialChannelData {
+                            title: Some(title.into()),
+                            ..Default::default()
+                        },
+                    )
+                    .map_err(ClientError::cache)?
+                    // Not ||; the member goes in whether or not the title changed.
+                    | self
+                        .cache
+                        .add_channel_member(Cow::Borrowed(&channel), character)
+                        .map_err(ClientError::cache)?
+                {
+                    emit!(self, CHANNELS, updated_channel(channel))
+                }
+                if session.character == character {
+                    emit!(self, CHANNELS, updated_session_channels(session))
+                }
+            }
+            ServerCommand::Kinks { .. } => {
+                diagnostic!(
+                    warn,
+                    "Received KID from server -- Use HTTP/JSON endpoint instead"
+                )
+            }
+            ServerCommand::LeftChannel { channel, character } => {
+                self.left_channel(&session, channel, character).await?
+            }
+            ServerCommand::ListOnline { mut characters } => {
+                // Thousands of these on a busy server, so they go in one batch,
+                // and the events wait until it's committed.
+                self.cache.begin_batch().map_err(ClientError::cache)?;
+                let updated = characters
+                    .drain(..)
+                    .filter_map(|character| {
+                        self.cache
                             .update_character(
-                                Cow::Borrowed(&identity),
+                                Cow::Borrowed(&character.0),
                                 PartialUserData {
-                                    status: Some(status),
-                                    gender: Some(gender),
+                                    gender: Some(character.1),
+                                    status: Some(character.2),
+                                    status_message: Some(character.3.into()),
                                     ..Default::default()
                                 },
                             )
-                            .unwrap()
-                        {
-                            self.event_listener.updated_character(identity).await
-                        }
+                            .map(|changed| changed.then_some(character.0))
+                            .transpose()
+                    })
+                    .collect::<Result<Vec<_>, _>>();
+                self.cache.commit_batch().map_err(ClientError::cache)?;
+                for character in updated.map_err(ClientError::cache)? {
+                    self.character_updated(&session, character).await
+                }
+            }
+            ServerCommand::NewConnection {
+                status,
+                gender,
+                identity,
+            } => {
+                if self
+                    .cache
+                    .update_character(
+                        Cow::Borrowed(&identity),
+                        PartialUserData {
+                            status: Some(status),
+                            gender: Some(gender),
+                            ..Default::default()
+                        },
+                    )
+                    .map_err(ClientError::cache)?
+                {
+                    self.character_updated(&session, identity).await
+                }
+                if identity == session.character {
+                    // Our own NLN comes right after the LIS flood.
+                    self.end_character_batch(&session).await;
+                }
+                self.relation_presence(&session, identity, true).await?;
+            }
+            ServerCommand::Ignore {
+                action,
+                characters,
+                character,
+            } => {
+                // The ignore list is account-wide, so it doesn't matter which session sent it.
+                let updated = match action {
+                    IgnoreAction::Init | IgnoreAction::List => self
+                        .cache
+                        .set_ignored(Cow::Owned(characters))
+                        .map_err(ClientError::cache)?,
+                    IgnoreAction::Add => self
+                        .cache
+                        .add_ignored(Cow::Owned(character))
+                        .map_err(ClientError::cache)?,
+                    IgnoreAction::Delete => self
+                        .cache
+                        .remove_ignored(Cow::Owned(character))
+                        .map_err(ClientError::cache)?,
+                    IgnoreAction::Notify => false, // Someone on the list tried to message us.
+                };
+                if updated {
+                    emit!(self, RELATIONS, updated_ignore_list())
+                }
+            }
+            ServerCommand::Friends { .. } => {} // We ignore this because it's bad data.
+            ServerCommand::Channels { mut channels } => {
+                for channel in channels.iter() {
+                    if self
+                        .cache
+                        .update_channel(
+                            Cow::Borrowed(&channel.name),
+                            PartialChannelData {
+                                title: Some(Cow::Borrowed(&channel.title)),
+                                member_count: Some(channel.characters),
+                                ..Default::default()
+                            },
+                        )
+                        .map_err(ClientError::cache)?
+                    {
+                        emit!(self, CHANNELS, updated_channel(channel.name.clone()))
                     }
-                    ServerCommand::Ignore {
-                        action,
-                        characters,
-                        character,
-                    } => eprintln!("Missing handler for IGN"), // Should track
-                    ServerCommand::Friends { .. } => {} // We ignore this because it's bad data.
-                    ServerCommand::Channels { mut channels } => {
-                        for channel in channels.iter() {
-                            if self
-                                .cache
-                                .update_channel(
-                                    Cow::Borrowed(&channel.name),
-                                    PartialChannelData {
-                                        title: Some(Cow::Borrowed(&channel.title)),
-                                        ..Default::default()
-                                    },
-                                )
-                                .unwrap()
-                            {
-                                self.event_listener.updated_channel(channel.name).await
-                            }
-                        }
-                        if self
-                            .cache
-                            .set_unofficial_channels(Cow::Owned(
-                                channels.drain(..).map(|v| (v.name, v.characters)).collect(),
-                            ))
-                            .unwrap()
-                        {
-                            self.event_listener.updated_channel_lists().await
-                        }
+                }
+                if self
+                    .cache
+                    .set_unofficial_channels(
+                        Cow::Owned(channels.drain(..).map(|v| (v.name, v.characters)).collect()),
+                        Utc::now(),
+                    )
+                    .map_err(ClientError::cache)?
+                {
+                    emit!(self, CHANNELS, updated_channel_lists())
+                }
+            }
+            ServerCommand::ProfileData { .. } => {
+                diagnostic!(
+                    warn,
+                    "Received PRD from server -- Use HTTP/JSON endpoint instead"
+                )
+            }
+            ServerCommand::PrivateMessage { character, message } => {
+                let source = MessageChannel::private(session.character, character);
+                let content = MessageContent::Message(message.into());
+                if self
+                    .cache
+                    .insert_message(
+                        source.clone(),
+                        Message {
+                            timestamp: Utc::now(),
+                            character,
+                            content: content.clone(),
+                        },
+                    )
+                    .map_err(ClientError::cache)?
+                {
+                    emit!(self, MESSAGES, message(session, source, character, content))
+                }
+            }
+            ServerCommand::Message {
+                character,
+                message,
+                channel,
+            } => {
+                let source = MessageChannel::Channel(channel.clone());
+                let content = MessageContent::Message(message.as_str().into());
+                let highlight = self
+                    .highlighter
+                    .as_ref()
+                    .filter(|_| character != session.character)
+                    .and_then(|h| h.check(&session.character, &message));
+                // Mentions are per session, so they're checked even for a copy we've already seen.
+                if self.dedup.first_sighting(
+                    session.character,
+                    channel.clone(),
+                    character,
+                    &message,
+                ) && self
+                    .cache
+                    .insert_message(
+                        source.clone(),
+                        Message {
+                            timestamp: Utc::now(),
+                            character,
+                            content: content.clone(),
+                        },
+                    )
+                    .map_err(ClientError::cache)?
+                {
+                    emit!(
+                        self,
+                        MESSAGES,
+                        message(session.clone(), source, character, content)
+                    )
+                }
+                if let Some(highlight) = highlight {
+                    emit!(
+                        self,
+                        MESSAGES,
+                        mentioned(session, channel, character, message, highlight)
+                    )
+                }
+            }
+            ServerCommand::Ad {
+                character,
+                message,
+                channel,
+            } => {
+                if self.dedup.first_sighting(
+                    session.character,
+                    channel.clone(),
+                    character,
+                    &message,
+                ) {
+                    // It goes in the history with everything else, and stands as their latest ad.
+                    let timestamp = Utc::now();
+                    let logged = self
+                        .cache
+                        .insert_message(
+                            MessageChannel::Channel(channel.clone()),
+                            Message {
+                                timestamp,
+                                character,
+                                content: MessageContent::Ad(message.as_str().into()),
+                            },
+                        )
+                        .map_err(ClientError::cache)?;
+                    let latest = self
+                        .cache
+                        .insert_ad(
+                            Cow::Borrowed(&channel),
+                            Cow::Borrowed(&character),
+                            Cow::Borrowed(&message),
+                            timestamp,
+                        )
+                        .map_err(ClientError::cache)?;
+                    if logged || latest {
+                        emit!(self, MESSAGES, ad(channel, character, message));
                     }
-                    ServerCommand::Ping => panic!("PIN -- Should never reach client"), // Sunk by session impl
-                    ServerCommand::ProfileData { .. } => {
-                        eprintln!("Received PRD from server -- Use HTTP/JSON endpoint instead")
+                }
+            }
+            ServerCommand::Roll {
+                target,
+                results,
+                response_type,
+                rolls,
+                character,
+                endresult,
+                message,
+            } => {
+                // I hate this command signature with a passion fruit.
+                // Our own rolls come back with the other side as the recipient.
+                let source = match &target {
+                    Target::Channel { channel } => MessageChannel::Channel(channel.clone()),
+                    Target::Character { recipient } => {
+                        MessageChannel::private_between(session.character, *recipient, character)
                     }
-                    ServerCommand::PrivateMessage { character, message } => {
-                        let source =
-                            MessageChannel::PrivateMessage(event.session.character, character);
-                        let content = MessageContent::Message(message.clone());
-                        if self
-                            .cache
-                            .insert_message(
-                                source,
-                                Message {
-                                    timestamp: Utc::now(),
-                                    character,
-                                    content: content.clone(), // TODO: Ouch...
-                                },
-                            )
-                            .unwrap()
-                        {
-                            self.event_listener
-                                .message(event.session, source, character, content)
-                                .await
-                        }
+                };
+                let content = MessageContent::Roll(rolls.into(), results.into(), endresult);
+                let new = match target {
+                    Target::Channel { channel } => {
+                        self.dedup
+                            .first_sighting(session.character, channel, character, &message)
                     }
-                    ServerCommand::Message {
-                        character,
-                        message,
-                        channel,
-                    } => {
-                        let source = MessageChannel::Channel(channel);
-                        let content = MessageContent::Message(message.clone());
+                    Target::Character { .. } => true,
+                };
+                if new
+                    && self
+                        .cache
+                        .insert_message(
+                            source.clone(),
+                            Message {
+                                timestamp: Utc::now(),
+                                character,
+                                content: content.clone(),
+                            },
+                        )
+                        .map_err(ClientError::cache)?
+                {
+                    emit!(self, MESSAGES, message(session, source, character, content))
+                }
+            }
+            ServerCommand::ChannelMode { mode, channel } => {
+                if self
+                    .cache
+                    .update_channel(
+                        Cow::Borrowed(&channel),
+                        PartialChannelData {
+                            mode: Some(mode),
+                            ..Default::default()
+                        },
+                    )
+                    .map_err(ClientError::cache)?
+                {
+                    emit!(self, CHANNELS, updated_channel(channel))
+                }
+            }
+            ServerCommand::BridgeEvent {
+                response_type,
+                name,
+            } => {
+                match response_type {
+                    BridgeEvent::BookmarkAdd => {
                         if self
                             .cache
-                            .insert_message(
-                                source,
-                                Message {
-                                    timestamp: Utc::now(),
-                                    character,
-                                    content: content.clone(),
-                                },
-                            )
-                            .unwrap()
+                            .add_bookmark(Cow::Owned(name))
+                            .map_err(ClientError::cache)?
                         {
-                            self.event_listener
-                                .message(event.session, source, character, content)
-                                .await
+                            emit!(self, RELATIONS, updated_bookmarks())
                         }
                     }
-                    ServerCommand::Ad {
-                        character,
-                        message,
-                        channel,
-                    } => {
+                    BridgeEvent::BookmarkRemove => {
                         if self
                             .cache
-                            .insert_ad(
-                                Cow::Borrowed(&channel),
-                                Cow::Borrowed(&character),
-                                Cow::Borrowed(&message),
-                            )
-                            .unwrap()
+                            .remove_bookmark(Cow::Owned(name))
+                            .map_err(ClientError::cache)?
                         {
-                            self.event_listener.ad(channel, character, message).await;
+                            emit!(self, RELATIONS, updated_bookmarks())
                         }
                     }
-                    ServerCommand::Roll {
-                        target,
-                        results,
-                        response_type,
-                        rolls,
-                        character,
-                        endresult,
-                        message,
-                    } => {
-                        // I hate this command signature with a passion fruit.
-                        let source = match target {
-                            Target::Channel { channel } => MessageChannel::Channel(channel),
-                            Target::Character { recipient } => {
-                                MessageChannel::PrivateMessage(recipient, character)
-                            }
-                        };
-                        let content = MessageContent::Roll(rolls, results, endresult);
-                        if self
-                            .cache
-                            .insert_message(
-                                source,
-                                Message {
-                                    timestamp: Utc::now(),
-                                    character,
-                                    content: content.clone(),
-                                },
-                            )
-                            .unwrap()
-                        {
-                            self.event_listener
-                                .message(event.session, source, character, content)
-                                .await
-                        }
+                    BridgeEvent::FriendAdd | BridgeEvent::FriendRemove => {
+                        // Both FriendAdd and FriendRemove don't include the full relation data,
+                        // So we sync the friend list via the HTTP/JSON endpoint.
+                        self.sync_friends_bookmarks().await?;
                     }
-                    ServerCommand::ChannelMode { mode, channel } => {
-                        if self
-                            .cache
-                            .update_channel(
-                                Cow::Borrowed(&channel),
-                                PartialChannelData {
-                                    mode: Some(mode),
-                                    ..Default::default()
-                                },
-                            )
-                            .unwrap()
-                        {
-                            self.event_listener.updated_channel(channel).await
+                    BridgeEvent::FriendRequest => {
+                        // RTB only carries the name, so fetch the request list for the ID.
+                        let incoming = self.sync_friend_requests().await?;
+                        for request in incoming.into_iter().filter(|v| v.source == name) {
+                            emit!(self, RELATIONS, friend_request(request))
                         }
                     }
-                    ServerCommand::BridgeEvent {
-                        response_type,
-                        name,
+                }
+            }
+            ServerCommand::Report(alert) => {
+                // Only open reports are worth holding on to; confirmed ones are being handled.
+                match &alert {
+                    StaffAlert::Report(report) => {
+                        self.cache
+                            .insert_report(Cow::Borrowed(report))
+                            .map_err(ClientError::cache)?;
+                    }
+                    StaffAlert::Confirm {
+                        callid: Some(callid),
+                        ..
                     } => {
-                        match response_type {
-                            BridgeEvent::BookmarkAdd => {
-                                if self.cache.add_bookmark(Cow::Owned(name)).unwrap() {
-                                    self.event_listener.updated_bookmarks().await
-                                }
-                            }
-                            BridgeEvent::BookmarkRemove => {
-                                if self.cache.remove_bookmark(Cow::Owned(name)).unwrap() {
-                                    self.event_listener.updated_bookmarks().await
-                                }
-                            }
-                            BridgeEvent::FriendAdd | BridgeEvent::FriendRemove => {
-                                // Both FriendAdd and FriendRemove don't include the full relation data,
-                                // So we sync the friend list via the HTTP/JSON endpoint.
-                                self.sync_friends_bookmarks().await.unwrap();
-                            }
-                            BridgeEvent::FriendRequest => {
-                                eprintln!("Not handling RTB FriendRequest");
-                            }
-                        }
+                        self.cache
+                            .remove_report(*callid)
+                            .map_err(ClientError::cache)?;
                     }
-                    ServerCommand::Report {
-                        action,
-                        moderator,
+                    // The server doesn't send a callid with these, only who made the report.
+                    StaffAlert::Confirm {
+                        callid: None,
                         character,
-                        timestamp,
-                        callid,
-                        report,
-                        logid,
-                    } => todo!(),
-                    ServerCommand::Status {
-                        status,
-                        character,
-                        statusmsg,
+                        ..
                     } => {
-                        if self
+                        let callids = self
                             .cache
-                            .update_character(
-                                Cow::Borrowed(&character),
-                                PartialUserData {
-                                    status: Some(status),
-                                    status_message: Some(statusmsg.into()),
-                                    ..Default::default()
-                                },
-                            )
-                            .unwrap()
-                        {
-                            self.event_listener.updated_character(character).await
+                            .get_reports()
+                            .map_err(ClientError::cache)?
+                            .iter()
+                            .filter(|report| report.character == *character)
+                            .map(|report| report.callid)
+                            .collect::<Vec<_>>();
+                        for callid in callids {
+                            self.cache
+                                .remove_report(callid)
+                                .map_err(ClientError::cache)?;
                         }
                     }
-                    ServerCommand::SystemMessage { message, channel } => {
-                        // May need to look into parsing system messages.
-                        self.event_listener
-                            .system_message(event.session, channel, message)
-                            .await
+                }
+                emit!(self, MODERATION, staff_alert(session, alert))
+            }
+            ServerCommand::Status {
+                status,
+                character,
+                statusmsg,
+            } => {
+                let updated = self
+                    .cache
+                    .update_character_diff(
+                        Cow::Borrowed(&character),
+                        PartialUserData {
+                            status: Some(status),
+                            status_message: Some(Cow::Borrowed(&statusmsg)),
+                            ..Default::default()
+                        },
+                    )
+                    .map_err(ClientError::cache)?;
+                if let Updated::Changed(previous) = updated {
+                    self.character_updated(&session, character).await;
+                    let previous = previous.map(|data| data.status);
+                    emit!(
+                        self,
+                        PRESENCE,
+                        status_changed(character, previous, status, statusmsg)
+                    )
+                }
+            }
+            ServerCommand::SystemMessage { message, channel } => {
+                // May need to look into parsing system messages.
+                if let Some(bans) = parse_banlist(&message) {
+                    if self
+                        .cache
+                        .set_channel_bans(Cow::Borrowed(&channel), Cow::Owned(bans))
+                        .map_err(ClientError::cache)?
+                    {
+                        emit!(self, CHANNELS, updated_channel(channel.clone()))
+                    }
+                }
+                // Only the ones about a channel have a history to go in.
+                if !channel.0.is_empty()
+                    && self.dedup.first_sighting(
+                        session.character,
+                        channel.clone(),
+                        Character::default(),
+                        &message,
+                    )
+                {
+                    self.cache
+                        .insert_message(
+                            MessageChannel::Channel(channel.clone()),
+                            Message {
+                                timestamp: Utc::now(),
+                                character: Character::default(),
+                                content: MessageContent::System(message.as_str().into()),
+                            },
+                        )
+                        .map_err(ClientError::cache)?;
+                }
+                emit!(self, MESSAGES, system_message(session, channel, message))
+            }
+            ServerCommand::Typing { character, status } => {
+                if self
+                    .cache
+                    .update_character(
+                        Cow::Borrowed(&character),
+                        PartialUserData {
+                            typing: Some(status),
+                            ..Default::default()
+                        },
+                    )
+                    .map_err(ClientError::cache)?
+                {
+                    self.character_updated(&session, character).await
+                }
+                emit!(self, PRESENCE, typing(session, character, status))
+            }
+            ServerCommand::Uptime { .. } => diagnostic!(debug, "Not handling UPT"),
+            ServerCommand::Search { characters, kinks } => {
+                match self.pending_searches.remove(&session.character) {
+                    Some((_, pending)) => {
+                        let _ = pending.send(Ok(SearchResults {
+                            kinks,
+                            characters: characters
+                                .into_iter()
+                                .map(|character| SearchResult {
+                                    character,
+                                    data: None,
+                                })
+                                .collect(),
+                        }));
                     }
-                    ServerCommand::Typing { character, status } => {
+                    // Someone sent FKS by hand
+                    None => {
                         self.event_listener
-                            .typing(event.session, character, status)
+                            .unhandled_command(session, ServerCommand::Search { characters, kinks })
                             .await
                     }
-                    ServerCommand::Uptime { .. } => eprintln!("Not handling UPT"),
-                    ServerCommand::Variable(_) => panic!("VAR -- Should never reach client"), // Sunk by Session impl
                 }
             }
-            crate::session::SessionEvent::Error(err) => {
-                self.event_listener.session_error(event.session, err).await;
+            // HLO, IDN, PIN and VAR are sunk by the session impl.
+            // If they turn up anyway, hand them over rather than taking the whole loop down.
+            command @ (ServerCommand::Hello { .. }
+            | ServerCommand::IdentifySuccess { .. }
+            | ServerCommand::Ping
+            | ServerCommand::Variable(_)) => {
+                self.event_listener
+                    .unhandled_command(session, command)
+                    .await
+            }
+        }
+        Ok(())
+    }
+
+    async fn character_updated(&self, session: &Session, character: Character) {
+        if let Some(batch) = &self.character_batch {
+            let mut batch = batch.lock();
+            if batch.syncing.contains(&session.character) {
+                batch.pending.insert(character);
+                return;
+            }
+        }
+        emit!(self, PRESENCE, updated_character(character))
+    }
+
+    async fn end_character_batch(&self, session: &Session) {
+        let Some(batch) = &self.character_batch else {
+            return;
+        };
+        let characters = batch.lock().end(&session.character);
+        if let Some(characters) = characters {
+            emit!(self, PRESENCE, updated_characters(characters))
+        }
+    }
+
+    // One at a time, since each waits on the profile rate limit.
+    async fn poll_profiles(&self) {
+        let next = {
+            let mut revalidate = self.profile_revalidate.lock();
+            let next = revalidate.iter().next().copied();
+            if let Some(character) = &next {
+                revalidate.remove(character);
+            }
+            next
+        };
+        if let Some(character) = next {
+            if let Err(err) = self.fetch_profile(character).await {
+                self.event_listener.client_error(err).await
+            }
+        }
+    }
+
+    async fn poll_compaction(&self) {
+        let Some(interval) = self.cache_compaction else {
+            return;
+        };
+        {
+            let mut compacted = self.cache_compacted.lock();
+            if compacted.elapsed() < interval {
+                return;
+            }
+            *compacted = Instant::now();
+        }
+        if let Err(err) = self.cache.compact() {
+            self.event_listener
+                .client_error(ClientError::cache(err))
+                .await
+        }
+    }
+
+    async fn poll_cache(&self) {
+        if let Err(err) = self.cache.tick() {
+            self.event_listener
+                .client_error(ClientError::cache(err))
+                .await
+        }
+    }
+
+    async fn poll_rejoins(&self) {
+        let due = {
+            let mut pending = self.pending_rejoins.lock();
+            if pending.is_empty() {
+                return;
+            }
+            let now = Instant::now();
+            let (due, waiting) = pending.drain(..).partition(|(_, _, at)| *at <= now);
+            *pending = waiting;
+            due
+        };
+        for (character, channel, _) in due {
+            // Gone or already back in (the user got there first); nothing to do.
+            let Some(session) = self.get_session(&character) else {
+                continue;
+            };
+            if session.channels.contains(&channel) {
+                continue;
+            }
+            if let Err(err) = session
+                .send_unattended(ClientCommand::JoinChannel { channel })
+                .await
+            {
+                self.event_listener.client_error(err.into()).await
+            }
+        }
+    }
+
+    async fn poll_character_batch(&self) {
+        let Some(batch) = &self.character_batch else {
+            return;
+        };
+        let characters = {
+            let mut batch = batch.lock();
+            match batch.started {
+                Some(started) if started.elapsed() > CharacterBatch::MAX_WAIT => batch.take(),
+                _ => None,
+            }
+        };
+        if let Some(characters) = characters {
+            emit!(self, PRESENCE, updated_characters(characters))
+        }
+    }
+
+    // NLN/FLN for friends and bookmarks get their own callbacks.
+    async fn relation_presence(
+        &self,
+        session: &Arc<Session>,
+        character: Character,
+        online: bool,
+    ) -> ClientResult<()> {
+        // Every session hears about every login; only pass it on once.
+        if !self
+            .any_session()
+            .is_some_and(|first| Arc::ptr_eq(&first, session))
+        {
+            return Ok(());
+        }
+        let friends = self.cache.get_friends().map_err(ClientError::cache)?;
+        if friends.contains(&character) {
+            if online {
+                emit!(self, PRESENCE, friend_online(character))
+            } else {
+                emit!(self, PRESENCE, friend_offline(character))
+            }
+        }
+        let bookmarks = self.cache.get_bookmarks().map_err(ClientError::cache)?;
+        if bookmarks.contains(&character) {
+            if online {
+                emit!(self, PRESENCE, bookmark_online(character))
+            } else {
+                emit!(self, PRESENCE, bookmark_offline(character))
+            }
+        }
+        Ok(())
+    }
+
+    async fn left_channel(
+        &self,
+        session: &Arc<Session>,
+        channel: Channel,
+        character: Character,
+    ) -> ClientResult<()> {
+        if self
+            .cache
+            .remove_channel_member(Cow::Borrowed(&channel), character)
+            .map_err(ClientError::cache)?
+        {
+            emit!(self, CHANNELS, updated_channel(channel))
+        }
+        if session.character == character {
+            emit!(self, CHANNELS, updated_session_channels(session.clone()))
+        }
+        Ok(())
+    }
+}
+
+// Infotags are matched by name, with list items read as the data enums' serde names.
+pub(crate) fn profile_update(
+    kinks: &KinkRegistry,
+    infotags: &HashMap<StringInteger, String>,
+    updated_at: u64,
+) -> PartialUserData<'static> {
+    let infotags = kinks.resolve_infotags(infotags);
+    fn item<T: serde::de::DeserializeOwned>(
+        infotags: &[(&str, InfotagValue)],
+        name: &str,
+    ) -> Option<T> {
+        infotags.iter().find_map(|(infotag, value)| match value {
+            InfotagValue::List(item) if infotag.eq_ignore_ascii_case(name) => {
+                serde_json::from_value(serde_json::Value::String(item.clone())).ok()
             }
+            _ => None,
+        })
+    }
+    PartialUserData {
+        orientation: Some(item(&infotags, "Orientation")),
+        languages: Some(item(&infotags, "Language preference").into_iter().collect()),
+        furry_preference: Some(item(&infotags, "Furry preference")),
+        role: Some(item(&infotags, "Dom/Sub Role")),
+        last_profile_update: chrono::DateTime::from_timestamp(updated_at as i64, 0),
+        ..Default::default()
+    }
+}
+
+// The reply to CBL comes back as a SYS: "Channel bans for <title>: Someone, Someone Else"
+pub(crate) fn parse_banlist(message: &str) -> Option<Vec<Character>> {
+    let (_, names) = message.strip_prefix("Channel bans for ")?.split_once(':')?;
+    Some(
+        names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| Character(StackString::new(name)))
+            .collect(),
+    )
+}
+
+// Plain-text log lines for report uploads.
+fn format_log_line(message: &Message) -> String {
+    let time = message.timestamp.format("%Y-%m-%d %H:%M:%S");
+    let name = message.character.0;
+    match &message.content {
+        MessageContent::Message(text) => format!("[{time}] {name}: {text}"),
+        MessageContent::Emote(text) => format!("[{time}] *{name} {text}"),
+        MessageContent::Roll(dice, results, total) => {
+            format!("[{time}] {name} rolls {dice:?}: {results:?} = {total}")
         }
+        MessageContent::Bottle(target) => {
+            format!("[{time}] {name} spins the bottle: {}", target.0)
+        }
+        MessageContent::Ad(text) => format!("[{time}] {name} (ad): {text}"),
+        MessageContent::System(text) => format!("[{time}] {text}"),
     }
 }
 
@@ -819,13 +3283,18 @@ impl<T: EventListener, C: Cache> Client<T, C> {
 #[allow(unused_variables)]
 pub trait EventListener: std::marker::Sync + Sized + std::marker::Send {
     async fn raw_command(&self, ctx: Arc<Session>, command: &ServerCommand) {}
+    // Everything sent over any session, after it's gone out. Not filtered.
+    async fn raw_outgoing(&self, ctx: Arc<Session>, command: &ClientCommand) {}
+    async fn unhandled_command(&self, ctx: Arc<Session>, command: ServerCommand) {}
 
     async fn session_error(&self, ctx: Arc<Session>, error: SessionError) {}
+    async fn client_error(&self, error: ClientError) {} // Cache and HTTP failures while handling events
     async fn sessions_updated(&self) {}
     async fn session_disconnected(&self, ctx: Arc<Session>, error: ProtocolError) {}
     async fn ready(&self, ctx: Arc<Session>) {}
 
     async fn broadcast(&self, character: Character, message: String) {}
+    async fn staff_alert(&self, ctx: Arc<Session>, alert: StaffAlert) {}
     async fn invited(&self, ctx: Arc<Session>, channel: Channel, sender: Character) {}
     async fn ad(&self, channel: Channel, character: Character, ad: String) {}
     async fn system_message(&self, ctx: Arc<Session>, channel: Channel, message: String) {}
@@ -837,25 +3306,97 @@ pub trait EventListener: std::marker::Sync + Sized + std::marker::Send {
         message: MessageContent,
     ) {
     }
+    // Messages sent through Client::send_message. Hands over to message() unless overridden.
+    async fn own_message(
+        &self,
+        ctx: Arc<Session>,
+        channel: MessageChannel,
+        message: MessageContent,
+    ) {
+        let character = ctx.character;
+        self.message(ctx, channel, character, message).await
+    }
+    // A channel message matched the highlighter. This comes after message(), not instead of it.
+    async fn mentioned(
+        &self,
+        ctx: Arc<Session>,
+        channel: Channel,
+        character: Character,
+        message: String,
+        highlight: HighlightMatch,
+    ) {
+    }
     async fn typing(&self, ctx: Arc<Session>, character: Character, status: TypingStatus) {}
 
+    async fn kicked(
+        &self,
+        ctx: Arc<Session>,
+        channel: Channel,
+        character: Character,
+        operator: Character,
+    ) {
+    }
+    async fn banned(
+        &self,
+        ctx: Arc<Session>,
+        channel: Channel,
+        character: Character,
+        operator: Character,
+    ) {
+    }
+    async fn timed_out(
+        &self,
+        ctx: Arc<Session>,
+        channel: Channel,
+        character: Character,
+        operator: Character,
+        length: u32,
+    ) {
+    }
+
+    // Friends and bookmarks coming and going. These need the friend and bookmark lists in the cache.
+    async fn friend_online(&self, character: Character) {}
+    async fn friend_offline(&self, character: Character) {}
+    async fn bookmark_online(&self, character: Character) {}
+    async fn bookmark_offline(&self, character: Character) {}
     async fn updated_friends(&self) {} // No need to send anything optimistically; end user can read off client
     async fn updated_bookmarks(&self) {} // Ditto for bookmarks, although I'm unsure how it behaves...
+    async fn updated_friend_requests(&self) {}
+    async fn friend_request(&self, request: PendingFriendRequest) {}
     async fn updated_channel(&self, channel: Channel) {} // Don't send the new data, because we don't track old data.
     async fn updated_character(&self, user: Character) {}
+    // From STA only. `previous` is None if the cache hadn't seen them (or doesn't keep anything).
+    async fn status_changed(
+        &self,
+        character: Character,
+        previous: Option<Status>,
+        status: Status,
+        message: String,
+    ) {
+    }
+    // Only with ClientBuilder::with_batched_character_updates, in place of many updated_character calls.
+    async fn updated_characters(&self, users: Vec<Character>) {}
     async fn updated_global_ops(&self) {}
+    async fn updated_ignore_list(&self) {}
     async fn updated_channel_lists(&self) {}
     async fn updated_session_channels(&self, session: Arc<Session>) {}
+    async fn ownership_changed(
+        &self,
+        channel: Channel,
+        old_owner: Option<Character>,
+        new_owner: Character,
+    ) {
+    }
 
     async fn error(&self, ctx: Arc<Session>, err: ProtocolError, message: String) {
         // Map the ID to an appropriate known error type. Use enums.
         if err.is_fatal() {
-            panic!("Fatal error: {err:?}")
-        }
-        if err.has_message() {
-            eprintln!("Error {err:?} -- {message}")
+            // The session will be dropped by the disconnect that follows; no need to panic.
+            diagnostic!(error, "Fatal error {err:?} -- {message}")
+        } else if err.has_message() {
+            diagnostic!(warn, "Error {err:?} -- {message}")
         } else {
-            eprintln!("Error {err:?}")
+            diagnostic!(warn, "Error {err:?}")
         }
     }
 }
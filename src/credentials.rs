@@ -0,0 +1,56 @@
+// Where the client gets its credentials from when it needs a new API ticket.
+// Implement CredentialProvider to pull them from a keyring, a vault, or the user,
+// rather than keeping a plaintext password around for the lifetime of the client.
+
+use std::{future::Future, pin::Pin};
+
+use async_trait::async_trait;
+
+pub type CredentialError = Box<dyn std::error::Error + Send + Sync>;
+
+pub type TicketFuture = Pin<Box<dyn Future<Output = Result<String, CredentialError>> + Send>>;
+
+/// Called whenever the client needs a fresh API ticket, for clients initialized without a password.
+pub type TicketRefresher = Box<dyn Fn() -> TicketFuture + Send + Sync>;
+
+/// Consulted every time the client needs a new ticket.
+/// If a ticket is supplied it is used as-is; otherwise the password is used to log in.
+#[async_trait]
+#[allow(unused_variables)]
+pub trait CredentialProvider: Send + Sync {
+    async fn get_ticket(&self, account: &str) -> Result<Option<String>, CredentialError> {
+        Ok(None)
+    }
+    async fn get_password(&self, account: &str) -> Result<Option<String>, CredentialError> {
+        Ok(None)
+    }
+}
+
+impl std::fmt::Debug for dyn CredentialProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CredentialProvider(..)")
+    }
+}
+
+/// Keeps the password in memory, same as it ever was.
+pub struct PlainPassword(pub String);
+
+impl std::fmt::Debug for PlainPassword {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PlainPassword(..)") // Never print the password.
+    }
+}
+
+#[async_trait]
+impl CredentialProvider for PlainPassword {
+    async fn get_password(&self, _account: &str) -> Result<Option<String>, CredentialError> {
+        Ok(Some(self.0.clone()))
+    }
+}
+
+#[async_trait]
+impl CredentialProvider for TicketRefresher {
+    async fn get_ticket(&self, _account: &str) -> Result<Option<String>, CredentialError> {
+        self().await.map(Some)
+    }
+}
@@ -1,12 +1,16 @@
-#![forbid(private_in_public)]
+#![forbid(private_interfaces, private_bounds)]
 
 use crate::{
     stringable,
-    util::{timestamp::Timestamp, StackString},
+    util::{timestamp::Timestamp, CompactString, StackString},
 };
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use thiserror::Error;
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Hash)]
+pub use crate::kinks::KinkRegistry;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Hash, Default)]
 #[serde(rename_all = "PascalCase")]
 pub enum Gender {
     Male,
@@ -18,21 +22,48 @@ pub enum Gender {
     MaleHerm,
     #[serde(rename = "Cunt-boy")]
     CBoy, // Look, I don't make the rules.
+    #[default]
     None,
 }
-impl Default for Gender {
-    fn default() -> Self {
-        Self::None
+
+impl Gender {
+    /// The name color the site and the official client use, as "#rrggbb".
+    pub fn color(&self) -> &'static str {
+        match self {
+            Gender::Male => "#6699ff",
+            Gender::Female => "#ff6699",
+            Gender::Transgender => "#ee8822",
+            Gender::Herm => "#9b30ff",
+            Gender::Shemale => "#cc66ff",
+            Gender::MaleHerm => "#007fff",
+            Gender::CBoy => "#00cc66",
+            Gender::None => "#b8b8b8",
+        }
+    }
+
+    /// Lower sorts first. Same order as the site's gender list, None last.
+    pub fn sort_priority(&self) -> u8 {
+        match self {
+            Gender::Male => 0,
+            Gender::Female => 1,
+            Gender::Transgender => 2,
+            Gender::Herm => 3,
+            Gender::Shemale => 4,
+            Gender::MaleHerm => 5,
+            Gender::CBoy => 6,
+            Gender::None => 7,
+        }
     }
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Hash)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Hash, Default)]
 #[serde(rename_all = "PascalCase")]
 pub enum Orientation {
     Straight,
     Gay,
     Bisexual,
     Asexual,
+    #[default]
     Unsure,
     #[serde(rename = "Bi - male preference")]
     BiMalePref,
@@ -42,17 +73,13 @@ pub enum Orientation {
     #[serde(rename = "Bi-curious")]
     Bicurious,
 }
-impl Default for Orientation {
-    fn default() -> Self {
-        Self::Unsure
-    }
-}
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Hash)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Hash, Default)]
 #[serde(rename_all = "PascalCase")]
 pub enum Language {
     Dutch,
-    English,
+    #[default]
+    English, // Other? No. English.
     French,
     Spanish,
     German,
@@ -66,32 +93,23 @@ pub enum Language {
     Swedish,
     Other,
 }
-impl Default for Language {
-    fn default() -> Self {
-        Self::English // Other? No. English.
-    }
-}
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Hash)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Hash, Default)]
 pub enum FurryPreference {
     #[serde(rename = "No furry characters, just humans")]
     HumanOnly,
     #[serde(rename = "Furries ok, Humans Preferred")]
     HumanPref,
     #[serde(rename = "Furs and / or humans")]
+    #[default]
     Both,
     #[serde(rename = "Humans ok, Furries Preferred")]
     FurryPref,
     #[serde(rename = "No humans, just furry characters")]
     FurryOnly,
 }
-impl Default for FurryPreference {
-    fn default() -> Self {
-        Self::Both
-    }
-}
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Hash)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Hash, Default)]
 #[serde(rename_all = "PascalCase")]
 pub enum Role {
     #[serde(rename = "Always dominant")]
@@ -103,45 +121,40 @@ pub enum Role {
     UsuallySub,
     #[serde(rename = "Always submissive")]
     AlwaysSub,
+    #[default]
     None,
 }
-impl Default for Role {
-    fn default() -> Self {
-        Self::None
-    }
-}
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Hash)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Hash, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum ChannelMode {
     #[serde(rename = "chat")]
     ChatOnly,
     #[serde(rename = "ads")]
     AdsOnly,
+    #[default]
     Both,
 }
-impl Default for ChannelMode {
-    fn default() -> Self {
-        Self::Both
+
+impl ChannelMode {
+    pub fn allows_ads(&self) -> bool {
+        matches!(self, ChannelMode::AdsOnly | ChannelMode::Both)
     }
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Hash)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Hash, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum ChannelStatus {
+    #[default]
     Public,
     Private,
 }
-impl Default for ChannelStatus {
-    fn default() -> Self {
-        Self::Public
-    }
-}
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Hash)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Hash, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum Status {
-    Online,
+    #[default]
+    Online, // Assume online unless *explicitly* Offline.
     Looking,
     Busy,
     Dnd,
@@ -150,29 +163,63 @@ pub enum Status {
     Crown,   // If you try to set crown, you will die.
     Offline, // Also this isn't transmitted by the server but it's sane and internal.
 }
-impl Default for Status {
-    fn default() -> Self {
-        Self::Online // Assume online unless *explicitly* Offline.
+
+impl Status {
+    /// Color for the status indicator, as "#rrggbb".
+    pub fn color(&self) -> &'static str {
+        match self {
+            Status::Online => "#dddddd",
+            Status::Looking => "#40c040",
+            Status::Busy => "#e0a000",
+            Status::Dnd => "#e04040",
+            Status::Idle | Status::Away => "#909090",
+            Status::Crown => "#ffcc00",
+            Status::Offline => "#606060",
+        }
+    }
+
+    /// Lower sorts first: people who want to be talked to at the top, offline at the bottom.
+    pub fn sort_priority(&self) -> u8 {
+        match self {
+            Status::Looking => 0,
+            Status::Crown | Status::Online => 1,
+            Status::Busy => 2,
+            Status::Idle => 3,
+            Status::Away => 4,
+            Status::Dnd => 5,
+            Status::Offline => 6,
+        }
     }
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Hash)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Hash, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum TypingStatus {
+    #[default]
     Clear,
     Paused,
     Typing,
 }
-impl Default for TypingStatus {
-    fn default() -> Self {
-        Self::Clear
-    }
-}
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Hash)]
 pub struct FriendRelation {
     pub own_character: Character,
     pub other_character: Character,
+    // As of the last friend list fetch. None if the site didn't say.
+    #[serde(
+        default,
+        serialize_with = "crate::util::timestamp::serialize_option",
+        deserialize_with = "crate::util::timestamp::deserialize_option"
+    )]
+    pub last_online: Option<Timestamp>,
+}
+
+// Friend requests are only ever sent between two characters; the ID is needed to accept/deny/cancel.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Hash)]
+pub struct PendingFriendRequest {
+    pub id: u64,
+    pub source: Character,
+    pub dest: Character,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Hash)]
@@ -198,18 +245,19 @@ pub enum KinkInterest {
 // Don't @ me, I don't make the titles.
 
 // Channel -names- are limited to 64 characters ("64.4999" per error message)
-// Accordingly, this may later just become String again.
-#[derive(Serialize, Deserialize, Default, Copy, Clone, PartialOrd, Ord, Debug)]
-pub struct Channel(pub StackString<32>);
+// and private channel titles turn up where IDs should be often enough,
+// so anything past 32 bytes goes on the heap rather than being refused.
+#[derive(Serialize, Deserialize, Default, Clone, PartialOrd, Ord, Debug)]
+pub struct Channel(pub CompactString<32>);
 impl PartialEq for Channel {
     fn eq(&self, other: &Self) -> bool {
-        self.0.eq_ignore_ascii_case(&*other.0)
+        self.0.eq_ignore_ascii_case(&other.0)
     }
 }
 impl Eq for Channel {}
 impl std::hash::Hash for Channel {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.0.to_ascii_lowercase().hash(state);
+        self.0.hash_ignore_ascii_case(state);
     }
 }
 
@@ -221,30 +269,136 @@ impl std::hash::Hash for Channel {
 pub struct Character(pub StackString<32>);
 impl PartialEq for Character {
     fn eq(&self, other: &Self) -> bool {
-        self.0.eq_ignore_ascii_case(&*other.0)
+        self.0.eq_ignore_ascii_case(&other.0)
     }
 }
 impl Eq for Character {}
 impl std::hash::Hash for Character {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        // Optimize later with extra StackString? Probably not.
-        // Would avoid intermediate heap allocation for String.
-        self.0.to_ascii_lowercase().hash(state);
+        self.0.hash_ignore_ascii_case(state);
+    }
+}
+
+impl Character {
+    // Not the site's limit (20, see above) but what a StackString<32> holds, which leaves room
+    // for the legacy names that are longer than that.
+    pub const MAX_LEN: usize = 32;
+
+    /// For names typed in by a user. Names from the server go through serde, which doesn't check.
+    pub fn try_new(name: &str) -> Result<Self, NameError> {
+        check_name(name, Self::MAX_LEN)?;
+        if let Some(c) = name
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, ' ' | '-' | '_')))
+        {
+            return Err(NameError::InvalidCharacter(c));
+        }
+        Ok(Character(StackString::new(name)))
+    }
+}
+
+impl Channel {
+    // Titles are limited to 64 characters, and IDs are shorter than that.
+    pub const MAX_LEN: usize = 64;
+
+    pub fn try_new(name: &str) -> Result<Self, NameError> {
+        check_name(name, Self::MAX_LEN)?;
+        if let Some(c) = name.chars().find(|c| c.is_control()) {
+            return Err(NameError::InvalidCharacter(c));
+        }
+        Ok(Channel(name.into()))
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum NameError {
+    #[error("Name is empty")]
+    Empty,
+    #[error("Name is longer than {0}")]
+    TooLong(usize),
+    #[error("Names can't start or end with a space")]
+    Padded,
+    #[error("Names can't contain {0:?}")]
+    InvalidCharacter(char),
+}
+
+fn check_name(name: &str, max: usize) -> Result<(), NameError> {
+    if name.is_empty() {
+        Err(NameError::Empty)
+    } else if name.chars().count() > max {
+        Err(NameError::TooLong(max))
+    } else if name.trim() != name {
+        Err(NameError::Padded)
+    } else {
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Character {
+    type Err = NameError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Character::try_new(s)
+    }
+}
+
+impl std::str::FromStr for Channel {
+    type Err = NameError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Channel::try_new(s)
+    }
+}
+
+impl std::fmt::Display for Character {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::fmt::Display for Channel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
     }
 }
 
 stringable!(CharacterId: u64, CharacterIdProxy, "CharacterIdProxy");
 
 // Abstraction for unifying message streams
-#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 #[serde(tag = "type", content = "channel")]
 #[serde(rename_all = "lowercase")]
 pub enum MessageChannel {
     Channel(Channel),
+    // Always (own character, other character); see MessageChannel::private.
     #[serde(rename = "pm")]
     PrivateMessage(Character, Character),
 }
 
+impl MessageChannel {
+    /// A PM conversation, keyed the way the caches and dispatch expect: one of ours first,
+    /// then whoever they're talking to. Whoever sent a given message, it's the same key.
+    pub fn private(own: Character, other: Character) -> Self {
+        MessageChannel::PrivateMessage(own, other)
+    }
+
+    /// For PM traffic where either side could be ours, e.g. a roll that's echoed back to us.
+    pub fn private_between(own: Character, a: Character, b: Character) -> Self {
+        if a == own {
+            MessageChannel::private(own, b)
+        } else {
+            MessageChannel::private(own, a)
+        }
+    }
+
+    /// For PMs, whoever's on the other end from `own`.
+    pub fn peer(&self, own: &Character) -> Option<Character> {
+        match self {
+            MessageChannel::PrivateMessage(a, b) if a == own => Some(*b),
+            MessageChannel::PrivateMessage(a, b) if b == own => Some(*a),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub struct Message {
     #[serde(with = "crate::util::timestamp")]
@@ -253,14 +407,17 @@ pub struct Message {
     pub content: MessageContent,
 }
 
+// Shared rather than owned, since every message goes to the cache and the listener both.
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 #[serde(tag = "type", content = "content")]
 #[serde(rename_all = "lowercase")]
 pub enum MessageContent {
-    Message(String),
-    Emote(String),
-    Roll(Vec<String>, Vec<i32>, i32),
+    Message(Arc<str>),
+    Emote(Arc<str>),
+    Roll(Arc<[String]>, Arc<[i32]>, i32),
     Bottle(Character),
+    Ad(Arc<str>),     // LRP
+    System(Arc<str>), // SYS, from nobody in particular
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize, Hash, PartialEq, Eq)]
@@ -270,6 +427,23 @@ pub struct ChannelData {
     pub members: Vec<Character>,
     pub description: String,
     pub title: String,
+    pub owner: Option<Character>,
+    // From the channel's op list, in COL order.
+    #[serde(default)]
+    pub ops: Vec<Character>,
+    // The member list's length for channels we're in, otherwise what the last channel list said.
+    #[serde(default)]
+    pub member_count: u32,
+    // Follows channel_mode.
+    #[serde(default)]
+    pub ads_allowed: bool,
+    // When the last message or ad went by.
+    #[serde(
+        default,
+        serialize_with = "crate::util::timestamp::serialize_option",
+        deserialize_with = "crate::util::timestamp::deserialize_option"
+    )]
+    pub last_activity: Option<Timestamp>,
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize, Hash, PartialEq, Eq)]
@@ -278,4 +452,49 @@ pub struct CharacterData {
     pub gender: Gender,
     pub status: Status,
     pub status_message: String,
+    // Whether they're typing a PM to one of our characters.
+    #[serde(default)]
+    pub typing: TypingStatus,
+    // The rest comes from their profile, once it's been fetched.
+    #[serde(default)]
+    pub orientation: Option<Orientation>,
+    #[serde(default)]
+    pub languages: Vec<Language>,
+    #[serde(default)]
+    pub furry_preference: Option<FurryPreference>,
+    #[serde(default)]
+    pub role: Option<Role>,
+    #[serde(
+        default,
+        serialize_with = "crate::util::timestamp::serialize_option",
+        deserialize_with = "crate::util::timestamp::deserialize_option"
+    )]
+    pub last_profile_update: Option<Timestamp>,
+}
+
+impl CharacterData {
+    /// Member list order: by status priority, then name ignoring case.
+    /// Names that only differ by case fall back to a plain comparison, so the order is total.
+    pub fn member_cmp(&self, other: &Self) -> std::cmp::Ordering {
+        let (a, b) = (&self.character.0, &other.character.0);
+        self.status
+            .sort_priority()
+            .cmp(&other.status.sort_priority())
+            .then_with(|| {
+                let fold = |b: u8| b.to_ascii_lowercase();
+                a.bytes().map(fold).cmp(b.bytes().map(fold))
+            })
+            .then_with(|| a.cmp(b))
+    }
+}
+
+// Open staff reports (SFC), only ever sent to chat-ops.
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, PartialEq, Eq)]
+pub struct StaffReport {
+    pub callid: u32,
+    pub character: Character,
+    pub timestamp: String, // Poorly documented...
+    pub report: String,
+    #[serde(default)]
+    pub logid: Option<u32>,
 }
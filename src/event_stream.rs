@@ -0,0 +1,410 @@
+// Stream-based alternative to implementing EventListener.
+// EventStream is itself a listener which forwards every callback into a broadcast channel,
+// so any number of consumers can select!/combine the events however they like.
+
+use std::sync::Arc;
+
+use futures_util::{Stream, stream};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    cache::Cache,
+    client::{Client, ClientBuilder, ClientError, EventListener, async_trait},
+    data::{
+        Channel, Character, MessageChannel, MessageContent, PendingFriendRequest, Status,
+        TypingStatus,
+    },
+    highlight::HighlightMatch,
+    protocol::{ProtocolError, ServerCommand, StaffAlert},
+    session::{Session, SessionError},
+};
+
+/// Typed mirror of the EventListener callbacks.
+/// Raw commands are not forwarded; they're far too noisy for a shared channel.
+#[derive(Debug, Clone)]
+pub enum ClientEvent {
+    UnhandledCommand(Arc<Session>, ServerCommand),
+
+    SessionError(Arc<Session>, Arc<SessionError>),
+    ClientError(Arc<ClientError>),
+    SessionsUpdated,
+    SessionDisconnected(Arc<Session>, ProtocolError),
+    Ready(Arc<Session>),
+
+    Broadcast {
+        character: Character,
+        message: String,
+    },
+    StaffAlert(Arc<Session>, StaffAlert),
+    Invited {
+        ctx: Arc<Session>,
+        channel: Channel,
+        sender: Character,
+    },
+    Ad {
+        channel: Channel,
+        character: Character,
+        ad: String,
+    },
+    SystemMessage {
+        ctx: Arc<Session>,
+        channel: Channel,
+        message: String,
+    },
+    Message {
+        ctx: Arc<Session>,
+        channel: MessageChannel,
+        character: Character,
+        message: MessageContent,
+    },
+    // Something this client sent, looped back. The author is ctx.character.
+    OwnMessage {
+        ctx: Arc<Session>,
+        channel: MessageChannel,
+        message: MessageContent,
+    },
+    Mentioned {
+        ctx: Arc<Session>,
+        channel: Channel,
+        character: Character,
+        message: String,
+        highlight: HighlightMatch,
+    },
+    Typing {
+        ctx: Arc<Session>,
+        character: Character,
+        status: TypingStatus,
+    },
+
+    Kicked {
+        ctx: Arc<Session>,
+        channel: Channel,
+        character: Character,
+        operator: Character,
+    },
+    Banned {
+        ctx: Arc<Session>,
+        channel: Channel,
+        character: Character,
+        operator: Character,
+    },
+    TimedOut {
+        ctx: Arc<Session>,
+        channel: Channel,
+        character: Character,
+        operator: Character,
+        length: u32,
+    },
+
+    FriendOnline(Character),
+    FriendOffline(Character),
+    BookmarkOnline(Character),
+    BookmarkOffline(Character),
+    UpdatedFriends,
+    UpdatedBookmarks,
+    UpdatedFriendRequests,
+    FriendRequest(PendingFriendRequest),
+    UpdatedChannel(Channel),
+    UpdatedCharacter(Character),
+    StatusChanged {
+        character: Character,
+        previous: Option<Status>,
+        status: Status,
+        message: String,
+    },
+    UpdatedCharacters(Vec<Character>),
+    UpdatedGlobalOps,
+    UpdatedIgnoreList,
+    UpdatedChannelLists,
+    UpdatedSessionChannels(Arc<Session>),
+    OwnershipChanged {
+        channel: Channel,
+        old_owner: Option<Character>,
+        new_owner: Character,
+    },
+
+    Error {
+        ctx: Arc<Session>,
+        error: ProtocolError,
+        message: String,
+    },
+
+    /// The consumer fell behind and this many events were dropped.
+    Lagged(u64),
+}
+
+#[derive(Debug)]
+pub struct EventStream {
+    sender: broadcast::Sender<ClientEvent>,
+}
+
+impl EventStream {
+    pub fn new() -> Self {
+        Self::with_capacity(256)
+    }
+
+    /// Capacity is how many events a slow consumer can fall behind by before it starts lagging.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        EventStream { sender }
+    }
+
+    pub fn subscribe(&self) -> impl Stream<Item = ClientEvent> {
+        stream::unfold(self.sender.subscribe(), |mut receiver| async move {
+            match receiver.recv().await {
+                Ok(event) => Some((event, receiver)),
+                Err(RecvError::Lagged(count)) => Some((ClientEvent::Lagged(count), receiver)),
+                Err(RecvError::Closed) => None,
+            }
+        })
+    }
+
+    fn send(&self, event: ClientEvent) {
+        // Nobody listening is not an error.
+        let _ = self.sender.send(event);
+    }
+}
+
+impl Default for EventStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClientBuilder<EventStream, crate::cache::NoCache> {
+    pub fn streaming() -> Self {
+        ClientBuilder::new(EventStream::new())
+    }
+}
+
+impl<C: Cache> Client<EventStream, C> {
+    /// Every event from this point onwards. Can be called as many times as needed.
+    pub fn events(&self) -> impl Stream<Item = ClientEvent> {
+        self.event_listener().subscribe()
+    }
+}
+
+#[async_trait]
+impl EventListener for EventStream {
+    async fn unhandled_command(&self, ctx: Arc<Session>, command: ServerCommand) {
+        self.send(ClientEvent::UnhandledCommand(ctx, command))
+    }
+
+    async fn session_error(&self, ctx: Arc<Session>, error: SessionError) {
+        self.send(ClientEvent::SessionError(ctx, Arc::new(error)))
+    }
+    async fn client_error(&self, error: ClientError) {
+        self.send(ClientEvent::ClientError(Arc::new(error)))
+    }
+    async fn sessions_updated(&self) {
+        self.send(ClientEvent::SessionsUpdated)
+    }
+    async fn session_disconnected(&self, ctx: Arc<Session>, error: ProtocolError) {
+        self.send(ClientEvent::SessionDisconnected(ctx, error))
+    }
+    async fn ready(&self, ctx: Arc<Session>) {
+        self.send(ClientEvent::Ready(ctx))
+    }
+
+    async fn broadcast(&self, character: Character, message: String) {
+        self.send(ClientEvent::Broadcast { character, message })
+    }
+    async fn staff_alert(&self, ctx: Arc<Session>, alert: StaffAlert) {
+        self.send(ClientEvent::StaffAlert(ctx, alert))
+    }
+    async fn invited(&self, ctx: Arc<Session>, channel: Channel, sender: Character) {
+        self.send(ClientEvent::Invited {
+            ctx,
+            channel,
+            sender,
+        })
+    }
+    async fn ad(&self, channel: Channel, character: Character, ad: String) {
+        self.send(ClientEvent::Ad {
+            channel,
+            character,
+            ad,
+        })
+    }
+    async fn system_message(&self, ctx: Arc<Session>, channel: Channel, message: String) {
+        self.send(ClientEvent::SystemMessage {
+            ctx,
+            channel,
+            message,
+        })
+    }
+    async fn message(
+        &self,
+        ctx: Arc<Session>,
+        channel: MessageChannel,
+        character: Character,
+        message: MessageContent,
+    ) {
+        self.send(ClientEvent::Message {
+            ctx,
+            channel,
+            character,
+            message,
+        })
+    }
+    async fn own_message(
+        &self,
+        ctx: Arc<Session>,
+        channel: MessageChannel,
+        message: MessageContent,
+    ) {
+        self.send(ClientEvent::OwnMessage {
+            ctx,
+            channel,
+            message,
+        })
+    }
+    async fn mentioned(
+        &self,
+        ctx: Arc<Session>,
+        channel: Channel,
+        character: Character,
+        message: String,
+        highlight: HighlightMatch,
+    ) {
+        self.send(ClientEvent::Mentioned {
+            ctx,
+            channel,
+            character,
+            message,
+            highlight,
+        })
+    }
+    async fn typing(&self, ctx: Arc<Session>, character: Character, status: TypingStatus) {
+        self.send(ClientEvent::Typing {
+            ctx,
+            character,
+            status,
+        })
+    }
+
+    async fn kicked(
+        &self,
+        ctx: Arc<Session>,
+        channel: Channel,
+        character: Character,
+        operator: Character,
+    ) {
+        self.send(ClientEvent::Kicked {
+            ctx,
+            channel,
+            character,
+            operator,
+        })
+    }
+    async fn banned(
+        &self,
+        ctx: Arc<Session>,
+        channel: Channel,
+        character: Character,
+        operator: Character,
+    ) {
+        self.send(ClientEvent::Banned {
+            ctx,
+            channel,
+            character,
+            operator,
+        })
+    }
+    async fn timed_out(
+        &self,
+        ctx: Arc<Session>,
+        channel: Channel,
+        character: Character,
+        operator: Character,
+        length: u32,
+    ) {
+        self.send(ClientEvent::TimedOut {
+            ctx,
+            channel,
+            character,
+            operator,
+            length,
+        })
+    }
+
+    async fn friend_online(&self, character: Character) {
+        self.send(ClientEvent::FriendOnline(character))
+    }
+    async fn friend_offline(&self, character: Character) {
+        self.send(ClientEvent::FriendOffline(character))
+    }
+    async fn bookmark_online(&self, character: Character) {
+        self.send(ClientEvent::BookmarkOnline(character))
+    }
+    async fn bookmark_offline(&self, character: Character) {
+        self.send(ClientEvent::BookmarkOffline(character))
+    }
+    async fn updated_friends(&self) {
+        self.send(ClientEvent::UpdatedFriends)
+    }
+    async fn updated_bookmarks(&self) {
+        self.send(ClientEvent::UpdatedBookmarks)
+    }
+    async fn updated_friend_requests(&self) {
+        self.send(ClientEvent::UpdatedFriendRequests)
+    }
+    async fn friend_request(&self, request: PendingFriendRequest) {
+        self.send(ClientEvent::FriendRequest(request))
+    }
+    async fn updated_channel(&self, channel: Channel) {
+        self.send(ClientEvent::UpdatedChannel(channel))
+    }
+    async fn updated_character(&self, user: Character) {
+        self.send(ClientEvent::UpdatedCharacter(user))
+    }
+    async fn status_changed(
+        &self,
+        character: Character,
+        previous: Option<Status>,
+        status: Status,
+        message: String,
+    ) {
+        self.send(ClientEvent::StatusChanged {
+            character,
+            previous,
+            status,
+            message,
+        })
+    }
+    async fn updated_characters(&self, users: Vec<Character>) {
+        self.send(ClientEvent::UpdatedCharacters(users))
+    }
+    async fn updated_global_ops(&self) {
+        self.send(ClientEvent::UpdatedGlobalOps)
+    }
+    async fn updated_ignore_list(&self) {
+        self.send(ClientEvent::UpdatedIgnoreList)
+    }
+    async fn updated_channel_lists(&self) {
+        self.send(ClientEvent::UpdatedChannelLists)
+    }
+    async fn updated_session_channels(&self, session: Arc<Session>) {
+        self.send(ClientEvent::UpdatedSessionChannels(session))
+    }
+    async fn ownership_changed(
+        &self,
+        channel: Channel,
+        old_owner: Option<Character>,
+        new_owner: Character,
+    ) {
+        self.send(ClientEvent::OwnershipChanged {
+            channel,
+            old_owner,
+            new_owner,
+        })
+    }
+
+    async fn error(&self, ctx: Arc<Session>, error: ProtocolError, message: String) {
+        self.send(ClientEvent::Error {
+            ctx,
+            error,
+            message,
+        })
+    }
+}
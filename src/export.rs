@@ -0,0 +1,174 @@
+// Writes cached history out the way F-Chat 3.0 (and Rising, which kept the format) stores its logs,
+// so it can be dropped into the official client's data directory:
+//
+// <data dir>/<own character>/logs/<key>      the messages, back to back
+// <data dir>/<own character>/logs/<key>.idx  the conversation name, then an offset for each day
+//
+// The key is the other character's name for PMs, or #channel, both lowercased.
+//
+// A message record is:
+// u32 LE time (unix seconds), u8 type, u8 sender length, sender, u16 LE text length, text,
+// u16 LE offset of this trailer within the record (so the file can be read backwards).
+//
+// An index entry is a u16 LE day number (days since the epoch) and the 40-bit LE offset of the
+// first message on that day. The official client uses local days; there's no timezone to go on
+// here, so these are UTC days.
+
+use std::{
+    borrow::Cow,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::{
+    cache::Cache,
+    data::{Character, Message, MessageChannel, MessageContent},
+};
+
+#[derive(Error, Debug)]
+pub enum ExportError {
+    #[error("Failed to write log files")]
+    Io(#[from] io::Error),
+    #[error("Error from Cache implementation")]
+    Cache(#[source] Box<dyn std::error::Error + Send + Sync>),
+}
+
+// Message types, as numbered by the official client.
+const TYPE_MESSAGE: u8 = 0;
+const TYPE_ACTION: u8 = 1;
+const TYPE_AD: u8 = 2;
+const TYPE_ROLL: u8 = 3;
+const TYPE_EVENT: u8 = 5;
+
+const SECONDS_PER_DAY: i64 = 86400;
+
+/// Export the history for each source, as seen by `own_character`.
+/// PM sources belonging to other characters are skipped. Existing logs for the same conversations
+/// are overwritten rather than appended to.
+pub fn export_rising_logs<C: Cache>(
+    cache: &C,
+    data_dir: &Path,
+    own_character: Character,
+    sources: &[MessageChannel],
+) -> Result<(), ExportError> {
+    let logs = data_dir.join(&*own_character.0).join("logs");
+    fs::create_dir_all(&logs)?;
+    for source in sources {
+        let (key, name) = match source {
+            MessageChannel::Channel(channel) => {
+                // The index holds the channel's display name, if we know it.
+                let title = cache
+                    .get_channel(channel)
+                    .map_err(|err| ExportError::Cache(Box::new(err)))?
+                    .map(|data| data.title)
+                    .filter(|title| !title.is_empty());
+                (
+                    format!("#{}", channel.0).to_lowercase(),
+                    title.unwrap_or_else(|| channel.0.to_string()),
+                )
+            }
+            MessageChannel::PrivateMessage(own, other) if *own == own_character => {
+                (other.0.to_lowercase(), other.0.to_string())
+            }
+            MessageChannel::PrivateMessage(..) => continue,
+        };
+        let messages = cache
+            .get_messages(source, None, None)
+            .map_err(|err| ExportError::Cache(Box::new(err)))?;
+        write_log(&logs, &key, &name, &messages)?;
+    }
+    Ok(())
+}
+
+fn write_log(logs: &Path, key: &str, name: &str, messages: &[Message]) -> io::Result<()> {
+    let mut log = BufWriter::new(File::create(logs.join(key))?);
+    let mut index = BufWriter::new(File::create(index_path(logs, key))?);
+
+    let name = truncate(name, u8::MAX as usize);
+    index.write_all(&[name.len() as u8])?;
+    index.write_all(name.as_bytes())?;
+
+    let mut position: u64 = 0;
+    let mut last_day = None;
+    for message in messages {
+        let day = message.timestamp.timestamp().div_euclid(SECONDS_PER_DAY) as u16;
+        if last_day != Some(day) {
+            index.write_all(&day.to_le_bytes())?;
+            index.write_all(&position.to_le_bytes()[..5])?;
+            last_day = Some(day);
+        }
+        let record = encode_message(message);
+        log.write_all(&record)?;
+        position += record.len() as u64;
+    }
+    log.flush()?;
+    index.flush()
+}
+
+fn index_path(logs: &Path, key: &str) -> PathBuf {
+    logs.join(format!("{key}.idx"))
+}
+
+pub(crate) fn encode_message(message: &Message) -> Vec<u8> {
+    let sender = truncate(&message.character.0, u8::MAX as usize);
+    let (kind, text) = message_text(message);
+    let text = truncate(&text, u16::MAX as usize);
+
+    let mut record = Vec::with_capacity(10 + sender.len() + text.len());
+    record.extend_from_slice(&(message.timestamp.timestamp() as u32).to_le_bytes());
+    record.push(kind);
+    record.push(sender.len() as u8);
+    record.extend_from_slice(sender.as_bytes());
+    record.extend_from_slice(&(text.len() as u16).to_le_bytes());
+    record.extend_from_slice(text.as_bytes());
+    let trailer = record.len() as u16;
+    record.extend_from_slice(&trailer.to_le_bytes());
+    record
+}
+
+// Rolls are kept as the server's BBCode line, more or less.
+fn message_text(message: &Message) -> (u8, Cow<'_, str>) {
+    let name = message.character.0;
+    match &message.content {
+        MessageContent::Message(text) => (TYPE_MESSAGE, Cow::Borrowed(&**text)),
+        MessageContent::Emote(text) => (TYPE_ACTION, Cow::Borrowed(&**text)),
+        MessageContent::Roll(dice, results, total) => {
+            let results = results
+                .iter()
+                .map(|result| format!("[b]{result}[/b]"))
+                .collect::<Vec<_>>()
+                .join(" + ");
+            (
+                TYPE_ROLL,
+                Cow::Owned(format!(
+                    "[user]{name}[/user] rolls {}: {results} = [b]{total}[/b]",
+                    dice.join(" + ")
+                )),
+            )
+        }
+        MessageContent::Bottle(target) => (
+            TYPE_ROLL,
+            Cow::Owned(format!(
+                "[user]{name}[/user] spins the bottle: [user]{}[/user]",
+                target.0
+            )),
+        ),
+        MessageContent::Ad(text) => (TYPE_AD, Cow::Borrowed(&**text)),
+        MessageContent::System(text) => (TYPE_EVENT, Cow::Borrowed(&**text)),
+    }
+}
+
+// Lengths are fixed-width in the format, so anything longer is cut at a character boundary.
+fn truncate(text: &str, max: usize) -> &str {
+    if text.len() <= max {
+        return text;
+    }
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
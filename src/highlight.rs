@@ -0,0 +1,81 @@
+// Decides whether an incoming channel message should ping the user.
+// Names and keywords match case-insensitively on word boundaries; patterns are used as given.
+
+use regex::Regex;
+
+use crate::data::Character;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HighlightMatch {
+    Name(Character),
+    Keyword(String),
+    Pattern(String), // The pattern's source
+}
+
+#[derive(Debug, Clone)]
+pub struct Highlighter {
+    own_name: bool,
+    keywords: Vec<String>, // Lowercased
+    patterns: Vec<Regex>,
+}
+
+impl Default for Highlighter {
+    fn default() -> Self {
+        Highlighter {
+            own_name: true,
+            keywords: Vec::new(),
+            patterns: Vec::new(),
+        }
+    }
+}
+
+impl Highlighter {
+    /// Highlights the receiving character's name, and nothing else until told otherwise.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_keyword(mut self, keyword: &str) -> Self {
+        self.keywords.push(keyword.to_lowercase());
+        self
+    }
+
+    pub fn with_pattern(mut self, pattern: Regex) -> Self {
+        self.patterns.push(pattern);
+        self
+    }
+
+    pub fn without_own_name(self) -> Self {
+        Highlighter {
+            own_name: false,
+            ..self
+        }
+    }
+
+    /// Check a message received by `own`. Yields the first thing that matched.
+    pub fn check(&self, own: &Character, message: &str) -> Option<HighlightMatch> {
+        let lower = message.to_lowercase();
+        if self.own_name && contains_word(&lower, &own.0.to_lowercase()) {
+            return Some(HighlightMatch::Name(*own));
+        }
+        if let Some(keyword) = self.keywords.iter().find(|k| contains_word(&lower, k)) {
+            return Some(HighlightMatch::Keyword(keyword.clone()));
+        }
+        self.patterns
+            .iter()
+            .find(|p| p.is_match(message))
+            .map(|p| HighlightMatch::Pattern(p.as_str().to_owned()))
+    }
+}
+
+// So that "Ann" doesn't go off for "announcement".
+fn contains_word(haystack: &str, needle: &str) -> bool {
+    if needle.is_empty() {
+        return false;
+    }
+    haystack.match_indices(needle).any(|(start, _)| {
+        let before = haystack[..start].chars().next_back();
+        let after = haystack[start + needle.len()..].chars().next();
+        !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+    })
+}
@@ -1,11 +1,99 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
 use crate::{
-    data::{Channel, Character, CharacterId, KinkInterest},
-    util::{StringBool, StringInteger},
+    data::{Channel, Character, CharacterId, FriendRelation, KinkInterest, PendingFriendRequest},
+    ratelimit::HttpLimiter,
+    transport::{HttpResult, HttpTransport, ReqwestTransport},
+    util::{timestamp::Timestamp, StringBool, StringInteger},
 };
+use chrono::DurationRound;
+use bytes::Bytes;
+use futures_util::Stream;
 use reqwest::Client;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
+
+pub const DEFAULT_BASE_URL: &str = "https://www.f-list.net";
+// Where avatars and profile images are served from. It isn't behind the JSON API, so it doesn't
+// follow Endpoints' base URL.
+pub const STATIC_URL: &str = "https://static.f-list.net";
+
+/// Whatever a response had that the struct doesn't have a field for (yet).
+#[cfg(feature = "extra-fields")]
+pub type ExtraFields = HashMap<String, serde_json::Value>;
+
+/// The site the JSON endpoints are on, and the HTTP client used to reach it.
+/// Point it elsewhere to go through a mirror or proxy, or at a mock server in tests.
+#[derive(Debug, Clone)]
+pub struct Endpoints {
+    client: Client,                    // For downloads
+    transport: Arc<dyn HttpTransport>, // For the JSON endpoints
+    base_url: String,
+    limiter: Option<Arc<HttpLimiter>>, // Shared by every clone
+}
+
+impl Endpoints {
+    pub fn new(client: Client) -> Self {
+        Endpoints {
+            transport: Arc::new(ReqwestTransport(client.clone())),
+            client,
+            base_url: DEFAULT_BASE_URL.to_owned(),
+            limiter: None,
+        }
+    }
+
+    /// Everything before the /json/... path, e.g. `https://www.f-list.net`.
+    pub fn with_base_url<S: Into<String>>(mut self, base_url: S) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_owned();
+        self
+    }
+
+    /// Make requests to the JSON endpoints through `transport` instead of the client.
+    /// Downloads still go through the client.
+    pub fn with_transport<T: HttpTransport + 'static>(mut self, transport: T) -> Self {
+        self.transport = Arc::new(transport);
+        self
+    }
+
+    /// Hold every request made through these endpoints to `limiter`'s caps.
+    pub fn with_limiter(mut self, limiter: HttpLimiter) -> Self {
+        self.limiter = Some(Arc::new(limiter));
+        self
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub(crate) fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url)
+    }
+
+    // Posts the form and reads back the JSON, once the limiter (if any) allows.
+    async fn post<T: Serialize + ?Sized, R: DeserializeOwned>(
+        &self,
+        path: &str,
+        data: &T,
+    ) -> HttpResult<R> {
+        let form = serde_urlencoded::to_string(data)?;
+        let _permit = match &self.limiter {
+            Some(limiter) => Some(limiter.acquire().await),
+            None => None,
+        };
+        let body = self.transport.post_form(&self.url(path), form).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Endpoints::new(Client::new())
+    }
+}
 
 #[derive(Serialize)]
 struct ApiTicketRequest<'a, 'b> {
@@ -22,6 +110,10 @@ pub struct ApiTicketResponse {
     #[serde(flatten)]
     pub extra: Option<TicketExtra>,
     pub ticket: String,
+    // Not `extra` like everywhere else, that one's taken.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra_fields: ExtraFields,
 }
 
 #[derive(Deserialize, Debug)]
@@ -37,13 +129,40 @@ pub struct Bookmark {
     pub name: Character,
 }
 
+/// Why the site wouldn't hand out a ticket.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum AuthenticationError {
+    #[error("Wrong account name or password")]
+    InvalidCredentials,
+    #[error("F-List refused the login: {0}")]
+    Rejected(String),
+}
+
+impl AuthenticationError {
+    pub(crate) fn from_message(message: String) -> Self {
+        match message.as_str() {
+            "Login failed." => AuthenticationError::InvalidCredentials,
+            _ => AuthenticationError::Rejected(message),
+        }
+    }
+}
+
+// A failed login has an error and no ticket, which ApiTicketResponse can't be read from.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(crate) enum ApiTicketResult {
+    Ticket(ApiTicketResponse),
+    Failed { error: String },
+}
+
 // Only new-format char list. Includes friends & bookmarks response if extra.
+// A refused login is the inner error; the outer one is for the request itself.
 pub async fn get_api_ticket(
-    client: &Client,
+    endpoints: &Endpoints,
     username: &str,
     password: &str,
     extra: bool,
-) -> reqwest::Result<ApiTicketResponse> {
+) -> HttpResult<Result<ApiTicketResponse, AuthenticationError>> {
     let body = ApiTicketRequest {
         account: username,
         password,
@@ -52,31 +171,33 @@ pub async fn get_api_ticket(
         no_bookmarks: StringBool(!extra),
         new_character_list: StringBool(extra),
     };
-    client
-        .post("https://www.f-list.net/json/getApiTicket.php")
-        .form(&body)
-        .send()
-        .await?
-        .json()
-        .await
+    let response = endpoints.post("/json/getApiTicket.php", &body).await?;
+    Ok(match response {
+        ApiTicketResult::Ticket(ticket) => Ok(ticket),
+        ApiTicketResult::Failed { error } => Err(AuthenticationError::from_message(error)),
+    })
 }
 
-#[derive(Deserialize, Debug)]
+// Derives Serialize so a Cache can hold on to it between runs.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct MappingListResponse {
     pub kinks: Vec<Kink>,
     pub kink_groups: Vec<KinkGroup>,
     pub infotags: Vec<InfoTag>,
     pub infotags_groups: Vec<InfoTagGroup>,
     pub listitems: Vec<ListItem>,
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ExtraFields,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct IdItem {
     pub name: String,
     pub id: StringInteger,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Kink {
     #[serde(flatten)]
     pub id: IdItem,
@@ -84,10 +205,10 @@ pub struct Kink {
     pub group_id: StringInteger,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct KinkGroup(pub IdItem);
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct InfoTag {
     pub group_id: StringInteger,
     pub id: StringInteger,
@@ -97,7 +218,7 @@ pub struct InfoTag {
     pub tag_type: InfoTagType,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InfoTagType {
     #[serde(rename = "text")]
     Text,
@@ -107,32 +228,107 @@ pub enum InfoTagType {
     List,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct InfoTagGroup(pub IdItem);
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ListItem {
+    #[serde(flatten)]
     pub id: IdItem,
     pub value: String,
 }
 
-pub async fn get_mapping_list(client: &Client) -> reqwest::Result<MappingListResponse> {
+pub async fn get_mapping_list(endpoints: &Endpoints) -> HttpResult<MappingListResponse> {
     let empty_data: HashMap<String, String> = HashMap::new(); // Forgive me, for I am sin.
-    client
-        .post("https://www.f-list.net/json/api/mapping-list.php")
-        .form(&empty_data)
-        .send()
-        .await?
-        .json()
+    endpoints
+        .post("/json/api/mapping-list.php", &empty_data)
         .await
 }
 
-#[derive(Deserialize, Debug)]
-pub struct HasError<T> {
-    #[serde(default)]
-    pub error: String,
-    #[serde(flatten)]
-    pub inner: T,
+/// A response that might be an error instead. The site leaves the rest of the body out when it
+/// sends one, so `error` is checked before the response is read.
+#[derive(Debug)]
+pub enum HasError<T> {
+    Ok(T),
+    Failed(ApiError),
+}
+
+impl<T> HasError<T> {
+    /// The error the site sent back, if there was one.
+    pub fn api_error(&self) -> Option<ApiError> {
+        match self {
+            HasError::Ok(_) => None,
+            HasError::Failed(err) => Some(err.clone()),
+        }
+    }
+
+    /// The response, unless the site sent an error instead.
+    pub fn into_result(self) -> Result<T, ApiError> {
+        match self {
+            HasError::Ok(response) => Ok(response),
+            HasError::Failed(err) => Err(err),
+        }
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for HasError<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::Error;
+        let mut body = serde_json::Map::deserialize(deserializer)?;
+        // The error field belongs here, not to the response.
+        let error = match body.remove("error") {
+            Some(serde_json::Value::String(error)) => error,
+            Some(serde_json::Value::Null) | None => String::new(),
+            Some(other) => return Err(D::Error::custom(format!("error wasn't a string: {other}"))),
+        };
+        if let Some(err) = ApiError::parse(&error) {
+            return Ok(HasError::Failed(err));
+        }
+        T::deserialize(serde_json::Value::Object(body))
+            .map(HasError::Ok)
+            .map_err(D::Error::custom)
+    }
+}
+
+/// Errors the JSON endpoints report in their `error` field. The site only sends messages,
+/// so the ones worth acting on are picked out and anything else is passed along as is.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ApiError {
+    #[error("Invalid ticket")]
+    InvalidTicket,
+    #[error("Ticket expired")]
+    TicketExpired,
+    #[error("Too many requests")]
+    RateLimited,
+    #[error("{0}")]
+    Unknown(String),
+}
+
+impl ApiError {
+    /// None for an empty message, which is what the site sends when all's well.
+    pub fn parse(message: &str) -> Option<Self> {
+        if message.is_empty() {
+            return None;
+        }
+        let lower = message.to_lowercase();
+        Some(if lower.contains("ticket") && lower.contains("expired") {
+            ApiError::TicketExpired
+        } else if lower.contains("invalid ticket") {
+            ApiError::InvalidTicket
+        } else if lower.contains("too many") || lower.contains("rate limit") {
+            ApiError::RateLimited
+        } else {
+            ApiError::Unknown(message.to_owned())
+        })
+    }
+
+    /// Whether a new ticket should fix it.
+    pub fn is_ticket_error(&self) -> bool {
+        matches!(self, ApiError::InvalidTicket | ApiError::TicketExpired)
+    }
 }
 
 #[derive(Serialize)]
@@ -162,7 +358,7 @@ impl From<CharacterId> for CharacterRequest {
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct CharacterProfileResponse {
     pub badges: Vec<String>,
     pub character_list: Vec<FullCharacter>, // I hate you.
@@ -173,7 +369,8 @@ pub struct CharacterProfileResponse {
     pub description: String,
     pub id: u64,
     pub images: Vec<Image>,
-    pub infotags: Vec<String>, // Dead. Dead to me. Killed.
+    #[serde(deserialize_with = "infotag_map")]
+    pub infotags: HashMap<StringInteger, String>, // Infotag ID to value; see KinkRegistry::infotags
     pub inlines: HashMap<StringInteger, Inline>,
     pub is_self: bool,
     pub kinks: HashMap<StringInteger, KinkInterest>,
@@ -182,12 +379,48 @@ pub struct CharacterProfileResponse {
     pub settings: Settings,
     pub updated_at: u64,
     pub views: u64,
-}
-
-#[derive(Deserialize, Debug)]
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
+// Newer responses give a map of infotag ID to value. Old ones (and empty ones, thanks PHP) give a
+// list with nothing worth keeping in it.
+pub(crate) fn infotag_map<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<StringInteger, String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        String(String),
+        Number(serde_json::Number),
+    }
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Infotags {
+        New(HashMap<StringInteger, Value>),
+        #[allow(dead_code)] // Only there so it parses
+        Old(Vec<serde::de::IgnoredAny>),
+    }
+    Ok(match Infotags::deserialize(deserializer)? {
+        Infotags::New(infotags) => infotags
+            .into_iter()
+            .map(|(id, value)| {
+                let value = match value {
+                    Value::String(value) => value,
+                    Value::Number(value) => value.to_string(),
+                };
+                (id, value)
+            })
+            .collect(),
+        Infotags::Old(_) => HashMap::new(),
+    })
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FullCharacter(pub IdItem);
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct CustomKink {
     pub name: String,
     pub description: String,
@@ -195,7 +428,7 @@ pub struct CustomKink {
     pub children: Vec<u64>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Image {
     pub description: String,
     pub extension: String,
@@ -207,20 +440,124 @@ pub struct Image {
     pub url: Option<String>, // Included in full response but not profile? Supposedly can be constructed manually.
 }
 
-#[derive(Deserialize, Debug)]
+impl Image {
+    /// The full-size image, as the API gave it or built from the ID if it didn't.
+    pub fn full_url(&self) -> String {
+        match &self.url {
+            Some(url) => url.clone(),
+            None => format!(
+                "{STATIC_URL}/images/charimage/{}.{}",
+                self.image_id, self.extension
+            ),
+        }
+    }
+
+    pub fn thumbnail_url(&self) -> String {
+        format!(
+            "{STATIC_URL}/images/charthumb/{}.{}",
+            self.image_id, self.extension
+        )
+    }
+}
+
+/// A character's avatar. Every character has one; the site serves a default if they never set it.
+pub fn avatar_url(character: Character) -> String {
+    format!("{STATIC_URL}/images/avatar/{}.png", url_name(&character.0))
+}
+
+/// The image for an eicon, as in `[eicon]name[/eicon]`. Names aren't checked; a missing eicon is a 404.
+pub fn eicon_url(name: &str) -> String {
+    format!("{STATIC_URL}/images/eicon/{}.gif", url_name(name.trim()))
+}
+
+/// Fetch an eicon's image, for frontends that cache or re-encode them rather than link to them.
+pub async fn download_eicon(endpoints: &Endpoints, name: &str) -> reqwest::Result<Vec<u8>> {
+    let bytes = download(endpoints, &eicon_url(name)).await?.bytes().await?;
+    Ok(bytes.to_vec())
+}
+
+/// A file on its way down, e.g. from one of the URLs above.
+/// The headers are in; the body comes through bytes or into_stream.
+#[derive(Debug)]
+pub struct Download {
+    pub content_type: Option<String>,
+    pub size: Option<u64>, // If the server said
+    response: reqwest::Response,
+}
+
+impl Download {
+    pub async fn bytes(self) -> reqwest::Result<Bytes> {
+        self.response.bytes().await
+    }
+
+    pub fn into_stream(self) -> impl Stream<Item = reqwest::Result<Bytes>> {
+        self.response.bytes_stream()
+    }
+}
+
+/// Start downloading an image (or anything else), through the endpoints' HTTP client but not their
+/// limiter; that's for the JSON API. Error statuses, like a 404 for a missing eicon, are errors.
+pub async fn download(endpoints: &Endpoints, url: &str) -> reqwest::Result<Download> {
+    let response = endpoints
+        .client()
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?;
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+    Ok(Download {
+        content_type,
+        size: response.content_length(),
+        response,
+    })
+}
+
+// Static files are named after the lowercased name, spaces and all.
+// Eicon names can have just about anything in them, so everything else gets escaped too.
+fn url_name(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for byte in name.to_lowercase().bytes() {
+        match byte {
+            b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => out.push(byte as char),
+            _ => out.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    out
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Inline {
     pub extension: String,
     pub hash: String,
     pub nsfw: bool,
 }
 
-#[derive(Deserialize, Debug)]
+impl Inline {
+    /// Inline images are filed under the first two pairs of characters of their hash.
+    pub fn url(&self) -> String {
+        let hash = &self.hash;
+        match (hash.get(..2), hash.get(2..4)) {
+            (Some(first), Some(second)) => format!(
+                "{STATIC_URL}/images/charinline/{first}/{second}/{hash}.{}",
+                self.extension
+            ),
+            // Never seen one this short; there's nowhere sensible to look for it.
+            _ => format!("{STATIC_URL}/images/charinline/{hash}.{}", self.extension),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Memo {
     pub id: u64,
     pub memo: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Settings {
     pub customs_first: bool,
     pub show_friends: bool,
@@ -229,19 +566,19 @@ pub struct Settings {
     pub public: bool,
 }
 
-type HasResult<T> = reqwest::Result<HasError<T>>;
+type HasResult<T> = HttpResult<HasError<T>>;
 
 pub async fn req_base<T: Serialize, R: DeserializeOwned>(
-    url: &str,
-    client: &Client,
+    path: &str,
+    endpoints: &Endpoints,
     data: T,
 ) -> HasResult<R> {
-    client.post(url).form(&data).send().await?.json().await
+    endpoints.post(path, &data).await
 }
 
 pub async fn get_character_base<T: Into<CharacterRequest>, R: DeserializeOwned>(
-    url: &str,
-    client: &Client,
+    path: &str,
+    endpoints: &Endpoints,
     ticket: &str,
     account: &str,
     character: T,
@@ -251,25 +588,18 @@ pub async fn get_character_base<T: Into<CharacterRequest>, R: DeserializeOwned>(
         ticket,
         inner: character.into(),
     };
-    req_base(url, client, data).await
+    req_base(path, endpoints, data).await
 }
 
 macro_rules! character_fn {
-    ($url:literal, $i:ident : $t:ty) => {
+    ($path:literal, $i:ident : $t:ty) => {
         pub async fn $i<T: Into<CharacterRequest>>(
-            client: &Client,
+            endpoints: &Endpoints,
             ticket: &str,
             account: &str,
             character: T,
         ) -> HasResult<$t> {
-            get_character_base(
-                concat!("https://www.f-list.net", $url),
-                client,
-                ticket,
-                account,
-                character,
-            )
-            .await
+            get_character_base($path, endpoints, ticket, account, character).await
         }
     };
 }
@@ -282,6 +612,9 @@ character_fn!(
 #[derive(Deserialize, Debug)]
 pub struct CharacterFriendsResponse {
     pub friends: Vec<FullCharacter>,
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ExtraFields,
 }
 
 character_fn!(
@@ -292,6 +625,9 @@ character_fn!(
 #[derive(Deserialize, Debug)]
 pub struct CharacterImagesResponse {
     pub images: Vec<Image>,
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ExtraFields,
 }
 
 character_fn!(
@@ -320,10 +656,13 @@ impl From<CharacterRequest> for MemoCharacterRequest {
 pub struct CharacterMemoResponse {
     pub id: u64,
     pub note: String,
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ExtraFields,
 }
 
 pub async fn get_character_memo<T: Into<CharacterRequest>>(
-    client: &Client,
+    endpoints: &Endpoints,
     ticket: &str,
     account: &str,
     character: T,
@@ -333,15 +672,7 @@ pub async fn get_character_memo<T: Into<CharacterRequest>>(
         ticket,
         inner: character.into().into(),
     };
-    req_base(
-        concat!(
-            "https://www.f-list.net",
-            "/json/api/character-memo-get2.php"
-        ),
-        client,
-        data,
-    )
-    .await
+    req_base("/json/api/character-memo-get2.php", endpoints, data).await
 }
 
 #[derive(Serialize)]
@@ -354,10 +685,13 @@ struct SaveCharacterMemoRequest<'a> {
 #[derive(Deserialize, Debug)]
 pub struct SaveCharacterMemoResponse {
     pub note: String,
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ExtraFields,
 }
 
 pub async fn set_character_memo<T: Into<CharacterRequest>>(
-    client: &Client,
+    endpoints: &Endpoints,
     ticket: &str,
     account: &str,
     character: T,
@@ -371,15 +705,7 @@ pub async fn set_character_memo<T: Into<CharacterRequest>>(
             note: memo,
         },
     };
-    req_base(
-        concat!(
-            "https://www.f-list.net",
-            "/json/api/character-memo-save.php"
-        ),
-        client,
-        data,
-    )
-    .await
+    req_base("/json/api/character-memo-save.php", endpoints, data).await
 }
 
 #[derive(Serialize)]
@@ -389,7 +715,7 @@ struct CharacterGuestbookRequest {
     page: u64,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct CharacterGuestbookResponse {
     pub page: u64,
     #[serde(rename = "canEdit")]
@@ -397,9 +723,12 @@ pub struct CharacterGuestbookResponse {
     #[serde(rename = "nextPage")]
     pub next_page: bool,
     pub posts: Vec<GuestbookPost>,
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ExtraFields,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct GuestbookPost {
     pub approved: bool,
     #[serde(rename = "canEdit")]
@@ -414,7 +743,7 @@ pub struct GuestbookPost {
 }
 
 pub async fn get_character_guestbook<T: Into<CharacterRequest>>(
-    client: &Client,
+    endpoints: &Endpoints,
     ticket: &str,
     account: &str,
     character: T,
@@ -428,15 +757,108 @@ pub async fn get_character_guestbook<T: Into<CharacterRequest>>(
             character: character.into(),
         },
     };
-    req_base(
-        concat!(
-            "https://www.f-list.net",
-            "/json/api/character-guestbook.php"
-        ),
-        client,
-        data,
-    )
-    .await
+    req_base("/json/api/character-guestbook.php", endpoints, data).await
+}
+
+#[derive(Serialize)]
+struct GuestbookPostRequest<'a> {
+    #[serde(flatten)]
+    source: SourceRequest,
+    #[serde(flatten)]
+    target: TargetRequest,
+    message: &'a str,
+    private: StringBool,
+}
+
+/// Sign `target`'s guestbook as `source`. Private posts are only shown to the guestbook's owner.
+/// Depending on the owner's settings, the post may wait for their approval.
+pub async fn post_guestbook<T1: Into<CharacterRequest>, T2: Into<CharacterRequest>>(
+    endpoints: &Endpoints,
+    ticket: &str,
+    account: &str,
+    source: T1,
+    target: T2,
+    message: &str,
+    private: bool,
+) -> HasResult<EmptyResponse> {
+    let data = Authenticated {
+        account,
+        ticket,
+        inner: GuestbookPostRequest {
+            source: source.into().into(),
+            target: target.into().into(),
+            message,
+            private: StringBool(private),
+        },
+    };
+    req_base("/json/api/guestbook-post.php", endpoints, data).await
+}
+
+#[derive(Serialize)]
+struct GuestbookReplyRequest<'a> {
+    id: u64,
+    reply: &'a str,
+}
+
+/// Reply to a post on one of the account's guestbooks. An empty reply takes the old one down.
+pub async fn reply_guestbook(
+    endpoints: &Endpoints,
+    ticket: &str,
+    account: &str,
+    post: u64,
+    reply: &str,
+) -> HasResult<EmptyResponse> {
+    let data = Authenticated {
+        account,
+        ticket,
+        inner: GuestbookReplyRequest { id: post, reply },
+    };
+    req_base("/json/api/guestbook-reply.php", endpoints, data).await
+}
+
+#[derive(Serialize)]
+struct GuestbookApproveRequest {
+    id: u64,
+    approve: StringBool,
+}
+
+/// Approve a post waiting on one of the account's guestbooks, or hide an approved one again.
+pub async fn approve_guestbook(
+    endpoints: &Endpoints,
+    ticket: &str,
+    account: &str,
+    post: u64,
+    approve: bool,
+) -> HasResult<EmptyResponse> {
+    let data = Authenticated {
+        account,
+        ticket,
+        inner: GuestbookApproveRequest {
+            id: post,
+            approve: StringBool(approve),
+        },
+    };
+    req_base("/json/api/guestbook-approve.php", endpoints, data).await
+}
+
+#[derive(Serialize)]
+struct GuestbookPostId {
+    id: u64,
+}
+
+/// Delete a post, either from one of the account's guestbooks or one the account wrote.
+pub async fn delete_guestbook(
+    endpoints: &Endpoints,
+    ticket: &str,
+    account: &str,
+    post: u64,
+) -> HasResult<EmptyResponse> {
+    let data = Authenticated {
+        account,
+        ticket,
+        inner: GuestbookPostId { id: post },
+    };
+    req_base("/json/api/guestbook-delete.php", endpoints, data).await
 }
 
 #[derive(Serialize)]
@@ -451,16 +873,50 @@ struct FriendListRequest {
     pending_outgoing: StringBool,
 }
 
+// Lists that weren't asked for come back empty, or not at all.
 #[derive(Deserialize, Debug)]
 pub struct FriendListResponse {
-    #[serde(rename = "bookmarklist")]
+    #[serde(rename = "bookmarklist", default)]
     pub bookmarks: Vec<Character>,
-    #[serde(rename = "friendlist")]
+    #[serde(rename = "friendlist", default)]
     pub friends: Vec<Friend>,
-    #[serde(rename = "requestlist")]
+    #[serde(rename = "requestlist", default)]
     pub pending_incoming: Vec<FriendRequest>,
-    #[serde(rename = "requestpending")]
+    #[serde(rename = "requestpending", default)]
     pub pending_outgoing: Vec<FriendRequest>,
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
+/// Which of the lists get_friends_list asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FriendLists {
+    pub bookmarks: bool,
+    pub friends: bool,
+    pub pending_incoming: bool,
+    pub pending_outgoing: bool,
+}
+
+impl FriendLists {
+    pub const ALL: FriendLists = FriendLists {
+        bookmarks: true,
+        friends: true,
+        pending_incoming: true,
+        pending_outgoing: true,
+    };
+    pub const RELATIONS: FriendLists = FriendLists {
+        bookmarks: true,
+        friends: true,
+        pending_incoming: false,
+        pending_outgoing: false,
+    };
+    pub const REQUESTS: FriendLists = FriendLists {
+        bookmarks: false,
+        friends: false,
+        pending_incoming: true,
+        pending_outgoing: true,
+    };
 }
 
 #[derive(Deserialize, Debug)]
@@ -474,6 +930,26 @@ pub struct Friend {
     pub source: Character,
 }
 
+impl Friend {
+    /// As a relation from our character (dest) to theirs. `received` is when the list arrived,
+    /// which last_online counts back from. Rounded to the minute, so fetching the list again
+    /// doesn't look like a change. A 0 is taken as the site not saying, and so is anything
+    /// too far back to be a date.
+    pub fn relation(&self, received: Timestamp) -> FriendRelation {
+        let last_online = (self.last_online != 0)
+            .then_some(self.last_online)
+            .and_then(|seconds| i64::try_from(seconds).ok())
+            .and_then(chrono::TimeDelta::try_seconds)
+            .and_then(|ago| received.checked_sub_signed(ago))
+            .and_then(|at| at.duration_trunc(chrono::Duration::minutes(1)).ok());
+        FriendRelation {
+            own_character: self.dest,
+            other_character: self.source,
+            last_online,
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct FriendRequest {
     pub dest: Character,
@@ -481,30 +957,93 @@ pub struct FriendRequest {
     pub source: Character,
 }
 
+impl From<FriendRequest> for PendingFriendRequest {
+    fn from(req: FriendRequest) -> Self {
+        PendingFriendRequest {
+            id: req.id,
+            source: req.source,
+            dest: req.dest,
+        }
+    }
+}
+
 pub async fn get_friends_list(
-    client: &Client,
+    endpoints: &Endpoints,
     ticket: &str,
     account: &str,
+    lists: FriendLists,
 ) -> HasResult<FriendListResponse> {
     let data = Authenticated {
         account,
         ticket,
         inner: FriendListRequest {
-            bookmarks: StringBool(true),
-            friends: StringBool(true),
-            pending_incoming: StringBool(true),
-            pending_outgoing: StringBool(true),
+            bookmarks: StringBool(lists.bookmarks),
+            friends: StringBool(lists.friends),
+            pending_incoming: StringBool(lists.pending_incoming),
+            pending_outgoing: StringBool(lists.pending_outgoing),
         },
     };
-    req_base(
-        concat!(
-            "https://www.f-list.net",
-            "/json/api/friend-bookmark-lists.php"
-        ),
-        client,
-        data,
-    )
-    .await
+    req_base("/json/api/friend-bookmark-lists.php", endpoints, data).await
+}
+
+#[derive(Serialize)]
+struct CharacterListRequest {
+    new_character_list: StringBool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CharacterListResponse {
+    #[serde(deserialize_with = "character_list")]
+    pub characters: Vec<OwnCharacter>,
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
+// One of the account's characters. The old-format list only has names.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct OwnCharacter {
+    pub name: Character,
+    pub id: Option<CharacterId>,
+}
+
+// Names mapped to IDs when the new list is asked for, like getApiTicket's; a bare list of names otherwise.
+fn character_list<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<OwnCharacter>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum List {
+        New(HashMap<Character, CharacterId>),
+        Old(Vec<Character>),
+    }
+    let mut characters = match List::deserialize(deserializer)? {
+        List::New(characters) => characters
+            .into_iter()
+            .map(|(name, id)| OwnCharacter { name, id: Some(id) })
+            .collect::<Vec<_>>(),
+        List::Old(characters) => characters
+            .into_iter()
+            .map(|name| OwnCharacter { name, id: None })
+            .collect(),
+    };
+    characters.sort_by_key(|character| character.name);
+    Ok(characters)
+}
+
+pub async fn get_character_list(
+    endpoints: &Endpoints,
+    ticket: &str,
+    account: &str,
+) -> HasResult<CharacterListResponse> {
+    let data = Authenticated {
+        account,
+        ticket,
+        inner: CharacterListRequest {
+            new_character_list: StringBool(true),
+        },
+    };
+    req_base("/json/api/character-list.php", endpoints, data).await
 }
 
 #[derive(Deserialize, Debug)]
@@ -558,7 +1097,7 @@ impl From<CharacterRequest> for DestRequest {
 }
 
 pub async fn remove_friend<T1: Into<CharacterRequest>, T2: Into<CharacterRequest>>(
-    client: &Client,
+    endpoints: &Endpoints,
     ticket: &str,
     account: &str,
     source: T1,
@@ -572,12 +1111,7 @@ pub async fn remove_friend<T1: Into<CharacterRequest>, T2: Into<CharacterRequest
             dest: dest.into().into(),
         },
     };
-    req_base(
-        concat!("https://www.f-list.net", "/json/api/friend-remove.php"),
-        client,
-        data,
-    )
-    .await
+    req_base("/json/api/friend-remove.php", endpoints, data).await
 }
 
 #[derive(Serialize)]
@@ -586,7 +1120,7 @@ struct FriendRequestId {
 }
 
 pub async fn accept_friend_request(
-    client: &Client,
+    endpoints: &Endpoints,
     ticket: &str,
     account: &str,
     request: u64,
@@ -598,16 +1132,11 @@ pub async fn accept_friend_request(
             request_id: request,
         },
     };
-    req_base(
-        concat!("https://www.f-list.net", "/json/api/request-accept.php"),
-        client,
-        data,
-    )
-    .await
+    req_base("/json/api/request-accept.php", endpoints, data).await
 }
 
 pub async fn deny_friend_request(
-    client: &Client,
+    endpoints: &Endpoints,
     ticket: &str,
     account: &str,
     request: u64,
@@ -619,16 +1148,11 @@ pub async fn deny_friend_request(
             request_id: request,
         },
     };
-    req_base(
-        concat!("https://www.f-list.net", "/json/api/request-deny.php"),
-        client,
-        data,
-    )
-    .await
+    req_base("/json/api/request-deny.php", endpoints, data).await
 }
 
 pub async fn cancel_friend_request(
-    client: &Client,
+    endpoints: &Endpoints,
     ticket: &str,
     account: &str,
     request: u64,
@@ -640,12 +1164,7 @@ pub async fn cancel_friend_request(
             request_id: request,
         },
     };
-    req_base(
-        concat!("https://www.f-list.net", "/json/api/request-cancel.php"),
-        client,
-        data,
-    )
-    .await
+    req_base("/json/api/request-cancel.php", endpoints, data).await
 }
 
 #[derive(Serialize)]
@@ -665,6 +1184,9 @@ enum TargetRequest {
 #[derive(Deserialize, Debug)]
 pub struct FriendRequestResponse {
     pub request: FriendRequestPartial,
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ExtraFields,
 }
 
 #[derive(Deserialize, Debug)]
@@ -686,7 +1208,7 @@ impl From<CharacterRequest> for TargetRequest {
 }
 
 pub async fn send_friend_request<T1: Into<CharacterRequest>, T2: Into<CharacterRequest>>(
-    client: &Client,
+    endpoints: &Endpoints,
     ticket: &str,
     account: &str,
     source: T1,
@@ -700,12 +1222,7 @@ pub async fn send_friend_request<T1: Into<CharacterRequest>, T2: Into<CharacterR
             target: target.into().into(),
         },
     };
-    req_base(
-        concat!("https://www.f-list.net", "/json/api/request-send2.php"),
-        client,
-        data,
-    )
-    .await
+    req_base("/json/api/request-send2.php", endpoints, data).await
 }
 
 #[derive(Serialize)]
@@ -719,7 +1236,7 @@ struct ReportRequest<'a, 'b> {
     text: StringBool, // Must be "true". Always.
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum ReportTarget {
     Character {
@@ -749,7 +1266,7 @@ pub struct ReportResponse {
 }
 
 pub async fn report<T: Into<ReportTarget>>(
-    client: &Client,
+    endpoints: &Endpoints,
     ticket: &str,
     account: &str,
     from: Character,
@@ -768,10 +1285,5 @@ pub async fn report<T: Into<ReportTarget>>(
             text: StringBool(true),
         },
     };
-    req_base(
-        concat!("https://www.f-list.net", "/json/api/report-submit.php"),
-        client,
-        data,
-    )
-    .await
+    req_base("/json/api/report-submit.php", endpoints, data).await
 }
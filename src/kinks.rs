@@ -0,0 +1,268 @@
+// The mapping list is the only place kink IDs get names, so everything kink-related leans on this:
+// looking kinks up for searches, and putting names to the IDs on profiles.
+// Profile infotags are in the same boat, so they're in here too.
+// It's big and rarely changes, so it's kept (in memory and in the cache) and only fetched again
+// once it's older than the client's mapping TTL, or when Client::refresh_kinks asks for it.
+
+use std::collections::HashMap;
+
+use crate::{
+    data::KinkInterest,
+    http_endpoints::{InfoTag, InfoTagType, Kink, MappingListResponse},
+    protocol::KinkId,
+    util::StringInteger,
+};
+
+#[derive(Debug, Default)]
+pub struct KinkRegistry {
+    kinks: HashMap<KinkId, Kink>,
+    by_name: HashMap<String, KinkId>, // Lowercased
+    groups: HashMap<u64, String>,
+    by_group: HashMap<u64, Vec<KinkId>>, // Sorted by name
+    infotags: HashMap<u64, InfoTag>,
+    infotag_groups: HashMap<u64, String>,
+    list_items: HashMap<u64, String>, // The values list infotags can take
+}
+
+/// A kink from a profile, with everything the mapping list knows about it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedKink {
+    pub id: KinkId,
+    pub name: String,
+    pub description: String,
+    pub group: Option<String>,
+    pub interest: KinkInterest,
+}
+
+/// An infotag's value, read according to its type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InfotagValue {
+    Text(String),
+    Number(f64),
+    /// The chosen list item's text, e.g. "Straight" for Orientation.
+    List(String),
+}
+
+impl KinkRegistry {
+    pub fn kink(&self, id: KinkId) -> Option<&Kink> {
+        self.kinks.get(&id)
+    }
+
+    /// Case-insensitive, because the site isn't consistent about it either.
+    pub fn kink_by_name(&self, name: &str) -> Option<&Kink> {
+        self.by_name
+            .get(&name.to_lowercase())
+            .and_then(|id| self.kinks.get(id))
+    }
+
+    pub fn kink_id(&self, name: &str) -> Option<KinkId> {
+        self.by_name.get(&name.to_lowercase()).copied()
+    }
+
+    pub fn group_name(&self, kink: &Kink) -> Option<&str> {
+        self.groups.get(&kink.group_id.0).map(String::as_str)
+    }
+
+    pub fn kinks(&self) -> impl Iterator<Item = (KinkId, &Kink)> {
+        self.kinks.iter().map(|(id, kink)| (*id, kink))
+    }
+
+    /// Kinks whose names match `query`, best first: the name itself, then names starting with it,
+    /// then names whose words start with its words, in order ("fem dom" for "Female domination"),
+    /// then names containing it, then names with all its letters in order.
+    /// Case and punctuation are ignored.
+    pub fn search(&self, query: &str) -> Vec<(KinkId, &Kink)> {
+        let query = fold(query);
+        if query.is_empty() {
+            return Vec::new();
+        }
+        let mut matches = self
+            .kinks
+            .iter()
+            .filter_map(|(id, kink)| Some((match_rank(&fold(&kink.id.name), &query)?, *id, kink)))
+            .collect::<Vec<_>>();
+        matches.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.2.id.name.cmp(&b.2.id.name)));
+        matches
+            .into_iter()
+            .map(|(_, id, kink)| (id, kink))
+            .collect()
+    }
+
+    /// Kink groups as ID and name, sorted by name.
+    pub fn groups(&self) -> Vec<(u64, &str)> {
+        let mut groups = self
+            .groups
+            .iter()
+            .map(|(id, name)| (*id, name.as_str()))
+            .collect::<Vec<_>>();
+        groups.sort_by(|a, b| a.1.cmp(b.1));
+        groups
+    }
+
+    /// The kinks in a group, sorted by name.
+    pub fn group_kinks(&self, group: u64) -> impl Iterator<Item = (KinkId, &Kink)> {
+        self.by_group
+            .get(&group)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| Some((*id, self.kinks.get(id)?)))
+    }
+
+    /// A profile's kinks (CharacterProfileResponse::kinks) with their names, sorted by name.
+    /// Kinks the mapping list doesn't know are left out; custom kinks come with their own names.
+    pub fn resolve_kinks(&self, kinks: &HashMap<StringInteger, KinkInterest>) -> Vec<ResolvedKink> {
+        let mut resolved = kinks
+            .iter()
+            .filter_map(|(id, interest)| {
+                let id = KinkId(u32::try_from(id.0).ok()?);
+                let kink = self.kinks.get(&id)?;
+                Some(ResolvedKink {
+                    id,
+                    name: kink.id.name.clone(),
+                    description: kink.description.clone(),
+                    group: self.group_name(kink).map(str::to_owned),
+                    interest: *interest,
+                })
+            })
+            .collect::<Vec<_>>();
+        resolved.sort_by(|a, b| a.name.cmp(&b.name));
+        resolved
+    }
+
+    pub fn infotag(&self, id: u64) -> Option<&InfoTag> {
+        self.infotags.get(&id)
+    }
+
+    pub fn infotag_group_name(&self, infotag: &InfoTag) -> Option<&str> {
+        self.infotag_groups
+            .get(&infotag.group_id.0)
+            .map(String::as_str)
+    }
+
+    /// A profile's infotags (CharacterProfileResponse::infotags) as name and value, in ID order.
+    /// Infotags the mapping list doesn't know are left out.
+    pub fn resolve_infotags<'a>(
+        &'a self,
+        infotags: &HashMap<StringInteger, String>,
+    ) -> Vec<(&'a str, InfotagValue)> {
+        let mut ids = infotags.keys().copied().collect::<Vec<_>>();
+        ids.sort();
+        ids.into_iter()
+            .filter_map(|id| {
+                let infotag = self.infotags.get(&id.0)?;
+                let raw = &infotags[&id];
+                Some((infotag.name.as_str(), self.infotag_value(infotag, raw)))
+            })
+            .collect()
+    }
+
+    // Anything that doesn't fit the infotag's type is passed through as text.
+    fn infotag_value(&self, infotag: &InfoTag, raw: &str) -> InfotagValue {
+        let value = match infotag.tag_type {
+            InfoTagType::Text => None,
+            InfoTagType::Number => raw.trim().parse().ok().map(InfotagValue::Number),
+            InfoTagType::List => raw
+                .parse()
+                .ok()
+                .and_then(|id: u64| self.list_items.get(&id))
+                .map(|item| InfotagValue::List(item.clone())),
+        };
+        value.unwrap_or_else(|| InfotagValue::Text(raw.to_owned()))
+    }
+}
+
+impl From<MappingListResponse> for KinkRegistry {
+    fn from(mappings: MappingListResponse) -> Self {
+        KinkRegistry::from(&mappings)
+    }
+}
+
+// Borrowed, so a mapping list that's also in the cache isn't copied whole to build this.
+impl From<&MappingListResponse> for KinkRegistry {
+    fn from(mappings: &MappingListResponse) -> Self {
+        let mut by_name = HashMap::with_capacity(mappings.kinks.len());
+        let mut kinks = HashMap::with_capacity(mappings.kinks.len());
+        for kink in &mappings.kinks {
+            // IDs are well within u32; anything else is junk from the API.
+            let Ok(id) = u32::try_from(kink.id.id.0) else {
+                continue;
+            };
+            by_name.insert(kink.id.name.to_lowercase(), KinkId(id));
+            kinks.insert(KinkId(id), kink.clone());
+        }
+        let mut by_group = HashMap::<u64, Vec<KinkId>>::new();
+        for (id, kink) in &kinks {
+            by_group.entry(kink.group_id.0).or_default().push(*id);
+        }
+        for ids in by_group.values_mut() {
+            ids.sort_by(|a, b| kinks[a].id.name.cmp(&kinks[b].id.name));
+        }
+        let groups = mappings
+            .kink_groups
+            .iter()
+            .map(|group| (group.0.id.0, group.0.name.clone()))
+            .collect();
+        let infotags = mappings
+            .infotags
+            .iter()
+            .map(|infotag| (infotag.id.0, infotag.clone()))
+            .collect();
+        let infotag_groups = mappings
+            .infotags_groups
+            .iter()
+            .map(|group| (group.0.id.0, group.0.name.clone()))
+            .collect();
+        let list_items = mappings
+            .listitems
+            .iter()
+            .map(|item| (item.id.id.0, item.value.clone()))
+            .collect();
+        KinkRegistry {
+            kinks,
+            by_name,
+            groups,
+            by_group,
+            infotags,
+            infotag_groups,
+            list_items,
+        }
+    }
+}
+
+// Lowercase words, with anything that isn't a letter or digit as the space between them.
+fn fold(text: &str) -> String {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Lower is better; None for no match. Both are folded.
+fn match_rank(name: &str, query: &str) -> Option<u8> {
+    if name == query {
+        return Some(0);
+    }
+    if name.starts_with(query) {
+        return Some(1);
+    }
+    let mut words = name.split(' ');
+    if query
+        .split(' ')
+        .all(|part| words.any(|word| word.starts_with(part)))
+    {
+        return Some(2);
+    }
+    if name.contains(query) {
+        return Some(3);
+    }
+    let mut letters = name.chars();
+    if query
+        .chars()
+        .filter(|c| *c != ' ')
+        .all(|c| letters.any(|letter| letter == c))
+    {
+        return Some(4);
+    }
+    None
+}
@@ -1,13 +1,32 @@
 #![warn(missing_debug_implementations)]
+#![allow(clippy::result_large_err)] // Error enums carry tungstenite errors as-is
 
 pub mod util; // Import first because it has macros
 
+pub mod ads;
 pub mod cache;
 pub mod client;
+pub mod credentials;
 pub mod data;
+pub mod event_stream;
+pub mod export;
+pub mod highlight;
 pub mod http_endpoints;
+pub mod kinks;
+#[cfg(feature = "mock-server")]
+pub mod mock;
 pub mod protocol;
+pub mod ratelimit;
+pub mod replay;
+pub mod retry;
+pub mod search;
+pub mod selector;
 pub mod session;
+mod single_flight;
+pub mod status;
+mod telemetry;
+pub mod tickets;
+pub mod transport;
 
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,204 @@
+// A chat server on localhost that speaks just enough of the protocol for sessions to connect to,
+// so bots can be tested without going anywhere near the live one.
+//
+// let server = MockServer::bind().await?;
+// let builder = ClientBuilder::new(events).with_chat_url(server.url());
+// ... client.connect(character) on one task, and on the other:
+// let mut connection = server.accept().await?;
+// connection.send(ServerCommand::PrivateMessage { character, message }).await?;
+// connection.expect(ClientCommand::PrivateMessage { recipient, message }).await;
+
+use std::{net::SocketAddr, time::Duration};
+
+use futures_util::{SinkExt, StreamExt};
+use thiserror::Error;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+use tokio_tungstenite::{accept_async, tungstenite::Message, WebSocketStream};
+
+use crate::{
+    data::Character,
+    protocol::{read_frame, write_frame, ClientCommand, ServerCommand, Variable},
+    util::StringInteger,
+};
+
+#[derive(Error, Debug)]
+pub enum MockError {
+    #[error("Error from the listening socket")]
+    Io(#[from] std::io::Error),
+    #[error("Error from Websocket (Tungstenite)")]
+    WebsocketError(#[from] tokio_tungstenite::tungstenite::Error),
+    #[error("Couldn't read or write a command")]
+    Json(#[from] serde_json::Error),
+    #[error("Expected IDN to open the connection, got {0:?}")]
+    NotIdentified(ClientCommand),
+    #[error("Nothing arrived in time")]
+    Timeout,
+    #[error("The client hung up")]
+    Closed,
+}
+
+pub type MockResult<T> = Result<T, MockError>;
+
+#[derive(Debug)]
+pub struct MockServer {
+    listener: TcpListener,
+    variables: Vec<Variable>,
+    timeout: Duration,
+}
+
+impl MockServer {
+    /// Listen on a free port on localhost.
+    pub async fn bind() -> MockResult<Self> {
+        Ok(MockServer {
+            listener: TcpListener::bind("127.0.0.1:0").await?,
+            // What the live server sends, give or take.
+            variables: vec![
+                Variable::ChatMax(4096),
+                Variable::PrivMax(50000),
+                Variable::AdMax(50000),
+                Variable::AdCooldown(600.0),
+                Variable::ChatCooldown(0.5),
+                Variable::StatusCooldown(5.0),
+                Variable::Permissions(StringInteger(0)),
+                Variable::IconBlacklist(Vec::new()),
+            ],
+            timeout: Duration::from_secs(5),
+        })
+    }
+
+    /// The VARs sent between IDN and HLO, in place of the live server's.
+    pub fn with_variables(self, variables: Vec<Variable>) -> Self {
+        MockServer { variables, ..self }
+    }
+
+    /// How long accept and recv wait before giving up. 5 seconds by default.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        MockServer { timeout, ..self }
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.listener
+            .local_addr()
+            .expect("Listener has no local address")
+    }
+
+    /// For ClientBuilder::with_chat_url (or Session::connect).
+    pub fn url(&self) -> String {
+        format!("ws://{}", self.local_addr())
+    }
+
+    /// Wait for a session to connect, and take it through the handshake:
+    /// IDN from the client, then IDN, the VARs, HLO and CON back.
+    pub async fn accept(&self) -> MockResult<MockConnection> {
+        let (stream, _) = timeout(self.timeout, self.listener.accept())
+            .await
+            .map_err(|_| MockError::Timeout)??;
+        let socket = accept_async(stream).await?;
+        let mut connection = MockConnection {
+            socket,
+            identify: ClientCommand::Pong, // Replaced just below
+            timeout: self.timeout,
+        };
+        connection.identify = connection.recv().await?;
+        let ClientCommand::Identify { character, .. } = connection.identify else {
+            return Err(MockError::NotIdentified(connection.identify));
+        };
+        connection
+            .send(ServerCommand::IdentifySuccess { character })
+            .await?;
+        for variable in &self.variables {
+            connection
+                .send(ServerCommand::Variable(variable.clone()))
+                .await?;
+        }
+        connection
+            .send(ServerCommand::Hello {
+                message: "Welcome. Running F-Chat (mock).".to_owned(),
+            })
+            .await?;
+        connection
+            .send(ServerCommand::Connected { count: 1 })
+            .await?;
+        Ok(connection)
+    }
+}
+
+/// One session's connection, from the server's end.
+#[derive(Debug)]
+pub struct MockConnection {
+    socket: WebSocketStream<TcpStream>,
+    identify: ClientCommand,
+    timeout: Duration,
+}
+
+impl MockConnection {
+    /// Who connected.
+    pub fn character(&self) -> Character {
+        match self.identify {
+            ClientCommand::Identify { character, .. } => character,
+            _ => unreachable!("Connections are only handed out after IDN"),
+        }
+    }
+
+    /// The IDN the client opened with, ticket and all.
+    pub fn identify(&self) -> &ClientCommand {
+        &self.identify
+    }
+
+    pub async fn send(&mut self, command: ServerCommand) -> MockResult<()> {
+        self.send_raw(write_frame(&command)?).await
+    }
+
+    /// For frames ServerCommand can't express, like ones the client shouldn't be able to parse.
+    pub async fn send_raw(&mut self, frame: String) -> MockResult<()> {
+        self.socket.send(Message::Text(frame)).await?;
+        Ok(())
+    }
+
+    /// The next command from the client.
+    pub async fn recv(&mut self) -> MockResult<ClientCommand> {
+        loop {
+            let frame = timeout(self.timeout, self.socket.next())
+                .await
+                .map_err(|_| MockError::Timeout)?;
+            match frame {
+                Some(Ok(Message::Text(frame))) => return Ok(read_frame(&frame)?),
+                Some(Ok(Message::Close(_))) | None => return Err(MockError::Closed),
+                Some(Ok(_)) => continue, // Websocket pings and the like
+                Some(Err(err)) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Panics unless the next command from the client is `expected`.
+    pub async fn expect(&mut self, expected: ClientCommand) {
+        match self.recv().await {
+            Ok(command) => assert_eq!(command, expected),
+            Err(err) => panic!("Expected {expected:?}, but: {err}"),
+        }
+    }
+
+    /// Skips commands until one matches, and hands it back. Panics if none does in time.
+    /// For when the client sends things of its own accord (status, rejoins) in between.
+    pub async fn expect_matching<F>(&mut self, mut predicate: F) -> ClientCommand
+    where
+        F: FnMut(&ClientCommand) -> bool,
+    {
+        loop {
+            match self.recv().await {
+                Ok(command) if predicate(&command) => return command,
+                Ok(_) => continue,
+                Err(err) => panic!("Nothing matching came through: {err}"),
+            }
+        }
+    }
+
+    /// Drop the connection without a close frame, the way the live server does.
+    /// Send an ERR first to make it a fatal disconnect rather than a reconnect.
+    pub fn hang_up(self) {
+        drop(self.socket)
+    }
+}
@@ -1,54 +1,213 @@
 use crate::{
     data::*,
-    util::{timestamp::Timestamp, StackString},
+    util::{timestamp::Timestamp, StringInteger},
 };
-use serde::{Deserialize, Serialize};
-use serde_json::{from_str, from_value, to_value, to_writer, Value};
-use std::{io::Write as _, str::FromStr};
+use serde::{
+    de::{value::BorrowedStrDeserializer, DeserializeSeed, MapAccess},
+    ser::{self, Impossible, SerializeStruct},
+    Deserialize, Serialize, Serializer,
+};
+use std::str::FromStr;
 
 // For full ser/de of commands
 // ClientCommand can be serialized,
 // ServerCommand can be deserialized
 // But mutually they can suck one.
 
-#[derive(Serialize, Deserialize, Debug)]
-struct CommandDummy {
-    command: StackString<3>,
-    #[serde(default)]
-    data: Value,
+// Both are adjacently tagged, {"command": "XXX", "data": {...}}, and the frames are "XXX {...}".
+// These go between the two without building the JSON object in between.
+
+// Hands serde the frame as if it were the object, with the body parsed straight from the frame.
+struct CommandAccess<'de> {
+    head: &'de str,
+    body: Option<&'de str>,
+    read: u8, // How many of command/data have been read
+}
+
+impl<'de> MapAccess<'de> for CommandAccess<'de> {
+    type Error = serde_json::Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        let key = match (self.read, self.body) {
+            (0, _) => "command",
+            (1, Some(_)) => "data",
+            _ => return Ok(None),
+        };
+        seed.deserialize(BorrowedStrDeserializer::new(key))
+            .map(Some)
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        self.read += 1;
+        match (self.read, self.body) {
+            (1, _) => seed.deserialize(BorrowedStrDeserializer::new(self.head)),
+            (_, Some(body)) => {
+                let mut json = serde_json::Deserializer::from_str(body);
+                let value = seed.deserialize(&mut json)?;
+                json.end()?;
+                Ok(value)
+            }
+            (_, None) => Err(serde::de::Error::missing_field("data")),
+        }
+    }
+}
+
+// Either way round; the mock server reads ClientCommands and writes ServerCommands.
+pub(crate) fn read_frame<'de, T: Deserialize<'de>>(frame: &'de str) -> serde_json::Result<T> {
+    // Split the command into the JSON data body and the command head
+    let (head, body) = match frame.split_once(' ') {
+        Some((head, body)) => (head, Some(body).filter(|body| !body.trim().is_empty())),
+        None => (frame, None), // If the command has no body.
+    };
+    T::deserialize(serde::de::value::MapAccessDeserializer::new(
+        CommandAccess {
+            head,
+            body,
+            read: 0,
+        },
+    ))
+}
+
+pub(crate) fn write_frame<T: Serialize>(command: &T) -> serde_json::Result<String> {
+    let mut command_buffer = Vec::with_capacity(256);
+    command.serialize(CommandWriter {
+        out: &mut command_buffer,
+    })?;
+    // serde_json only writes UTF-8, so this hands the buffer over as it is.
+    Ok(String::from_utf8(command_buffer).expect("Command wasn't UTF-8"))
 }
 
-// Use Serde to convert them to/from adjacent format
 // These should later return a specific Result instead.
 pub fn parse_command(command: &str) -> ServerCommand {
-    from_value(
-        to_value(if command.len() < 4 {
-            // If the command has no body.
-            CommandDummy {
-                command: StackString::new(command),
-                data: Value::Null,
-            }
-        } else {
-            // Split the command into the JSON data body and the command head
-            let (head, data) = command.split_at(4);
-            CommandDummy {
-                command: StackString::new(head.trim()),
-                data: from_str(data).expect("Unable to parse data to Value"),
-            }
-        })
-        .expect("Unable to convert CommandDummy to Value"),
-    )
-    .expect("Unable to convert Value to ServerCommand") // Forgive me, for I have sinned.
+    read_frame(command).expect("Unable to parse ServerCommand") // Forgive me, for I have sinned.
 }
 
 pub fn prepare_command(command: &ClientCommand) -> String {
-    let dummy_value: CommandDummy =
-        from_value(to_value(command).expect("Unable to convert command to Value"))
-            .expect("Unable to convert Value to CommandDummy");
-    let mut command_buffer = Vec::with_capacity(256);
-    write!(&mut command_buffer, "{} ", dummy_value.command).expect("Failed to write command head");
-    to_writer(&mut command_buffer, &dummy_value.data).expect("Failed to write command data-body");
-    String::from_utf8_lossy(&command_buffer).to_string()
+    write_frame(command).expect("Failed to write command")
+}
+
+// Writes the command head, then the body (if there is one) as JSON after a space.
+// Anything that isn't a command is turned down.
+struct CommandWriter<'a> {
+    out: &'a mut Vec<u8>,
+}
+
+macro_rules! not_a_command {
+    ($($method:ident($($arg:ty),*) -> $ok:ty;)*) => {
+        $(
+            fn $method(self, $(_: $arg),*) -> Result<$ok, Self::Error> {
+                Err(ser::Error::custom("only commands can be written as commands"))
+            }
+        )*
+    };
+}
+
+impl<'a> Serializer for CommandWriter<'a> {
+    type Ok = ();
+    type Error = serde_json::Error;
+    type SerializeSeq = Impossible<(), Self::Error>;
+    type SerializeTuple = Impossible<(), Self::Error>;
+    type SerializeTupleStruct = Impossible<(), Self::Error>;
+    type SerializeTupleVariant = Impossible<(), Self::Error>;
+    type SerializeMap = Impossible<(), Self::Error>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Impossible<(), Self::Error>;
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<Self, Self::Error> {
+        Ok(self)
+    }
+
+    not_a_command! {
+        serialize_bool(bool) -> ();
+        serialize_i8(i8) -> ();
+        serialize_i16(i16) -> ();
+        serialize_i32(i32) -> ();
+        serialize_i64(i64) -> ();
+        serialize_u8(u8) -> ();
+        serialize_u16(u16) -> ();
+        serialize_u32(u32) -> ();
+        serialize_u64(u64) -> ();
+        serialize_f32(f32) -> ();
+        serialize_f64(f64) -> ();
+        serialize_char(char) -> ();
+        serialize_str(&str) -> ();
+        serialize_bytes(&[u8]) -> ();
+        serialize_none() -> ();
+        serialize_unit() -> ();
+        serialize_unit_struct(&'static str) -> ();
+        serialize_unit_variant(&'static str, u32, &'static str) -> ();
+        serialize_seq(Option<usize>) -> Self::SerializeSeq;
+        serialize_tuple(usize) -> Self::SerializeTuple;
+        serialize_tuple_struct(&'static str, usize) -> Self::SerializeTupleStruct;
+        serialize_tuple_variant(&'static str, u32, &'static str, usize) -> Self::SerializeTupleVariant;
+        serialize_map(Option<usize>) -> Self::SerializeMap;
+        serialize_struct_variant(&'static str, u32, &'static str, usize) -> Self::SerializeStructVariant;
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, _: &T) -> Result<(), Self::Error> {
+        Err(ser::Error::custom(
+            "only commands can be written as commands",
+        ))
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _: &'static str,
+        _: &T,
+    ) -> Result<(), Self::Error> {
+        Err(ser::Error::custom(
+            "only commands can be written as commands",
+        ))
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> Result<(), Self::Error> {
+        Err(ser::Error::custom(
+            "only commands can be written as commands",
+        ))
+    }
+}
+
+impl<'a> SerializeStruct for CommandWriter<'a> {
+    type Ok = ();
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        match key {
+            "command" => {
+                // It comes out as a JSON string; the quotes come back off.
+                // The codes are three capital letters, so there's nothing escaped in there.
+                let start = self.out.len();
+                serde_json::to_writer(&mut *self.out, value)?;
+                self.out.pop();
+                self.out.remove(start);
+            }
+            _ => {
+                self.out.push(b' ');
+                serde_json::to_writer(&mut *self.out, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
 }
 
 // F-Chat commands follow a specific format:
@@ -56,7 +215,8 @@ pub fn prepare_command(command: &ClientCommand) -> String {
 // Being a 3 character command code, followed by JSON data
 // This is fine.
 
-#[derive(Serialize, PartialEq, Eq, Debug)]
+#[derive(Serialize, PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "mock-server", derive(Deserialize))] // For the server's end
 #[serde(tag = "command", content = "data")]
 pub enum ClientCommand {
     #[serde(rename = "ACB")]
@@ -196,6 +356,8 @@ pub enum ClientCommand {
         action: ReportAction,
         report: String,
         character: Character,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        logid: Option<u64>, // From report-submit.php, if a log was uploaded
     }, // action is always 'report'
     #[serde(rename = "STA")]
     Status { status: Status, statusmsg: String },
@@ -216,7 +378,8 @@ pub enum ClientCommand {
     Uptime,
 }
 
-#[derive(Deserialize, PartialEq, Debug)]
+#[derive(Deserialize, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "mock-server", derive(Serialize))]
 #[serde(tag = "command", content = "data")]
 pub enum ServerCommand {
     #[serde(rename = "ADL")]
@@ -395,15 +558,7 @@ pub enum ServerCommand {
         name: Character,
     },
     #[serde(rename = "SFC")]
-    Report {
-        action: String,
-        moderator: Character,
-        character: Character,
-        timestamp: String, // Poorly documented...
-        callid: u32,
-        report: String,
-        logid: u32,
-    }, // May need to flatten an enum in here instead.
+    Report(StaffAlert),
     #[serde(rename = "STA")]
     Status {
         status: Status,
@@ -433,7 +588,7 @@ pub enum ServerCommand {
     Variable(Variable), // Could be int, float, [string]; I hate it. Use an adjacently tagged enum.
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 #[serde(tag = "variable", content = "value")]
 #[serde(rename_all = "snake_case")]
 pub enum Variable {
@@ -449,7 +604,7 @@ pub enum Variable {
     ChatCooldown(f32),
     #[serde(rename = "sta_flood")]
     StatusCooldown(f32),
-    Permissions(String),
+    Permissions(StringInteger), // Bitmask; sent as a number, despite the docs
     IconBlacklist(Vec<Channel>),
 }
 
@@ -478,6 +633,25 @@ mod character_identity {
         DCharacterIdentity::deserialize(deserializer).map(|v| v.identity)
     }
 
+    #[cfg(feature = "mock-server")]
+    #[derive(serde::Serialize)]
+    pub(super) struct SCharacterIdentity<'a> {
+        identity: &'a Character,
+    }
+
+    #[cfg(feature = "mock-server")]
+    pub(super) fn serialize<S>(character: &Character, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serde::Serialize::serialize(
+            &SCharacterIdentity {
+                identity: character,
+            },
+            serializer,
+        )
+    }
+
     pub(crate) mod vec {
         use super::super::Character;
         use super::DCharacterIdentity;
@@ -490,10 +664,25 @@ mod character_identity {
             <Vec<DCharacterIdentity>>::deserialize(deserializer)
                 .map(|vec| unsafe { std::mem::transmute(vec) })
         }
+
+        #[cfg(feature = "mock-server")]
+        pub(crate) fn serialize<S>(
+            characters: &[Character],
+            serializer: S,
+        ) -> Result<S::Ok, S::Error>
+        where
+            S: serde::Serializer,
+        {
+            serializer.collect_seq(
+                characters
+                    .iter()
+                    .map(|identity| super::SCharacterIdentity { identity }),
+            )
+        }
     }
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 #[serde(rename_all = "lowercase")]
 pub enum KinkResponsePart {
     Start,
@@ -501,24 +690,24 @@ pub enum KinkResponsePart {
     End,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub struct FlatCharacterData(pub Character, pub Gender, pub Status, pub String); // Last part is status message
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub struct ChannelInfo {
     pub name: Channel,
     pub characters: u32,
     pub title: String,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub struct GlobalChannelInfo {
     pub name: Channel,
     pub mode: ChannelMode,
     pub characters: u32,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 #[serde(rename_all = "lowercase")]
 pub enum ProfileDataPart {
     Start,
@@ -527,19 +716,33 @@ pub enum ProfileDataPart {
     Select,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
 #[serde(rename_all = "lowercase")]
 pub enum ReportAction {
     Report,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+// SFC from the server is either a new report, or a moderator picking one up.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum StaffAlert {
+    Report(StaffReport),
+    Confirm {
+        moderator: Character,
+        character: Character,
+        timestamp: String, // Poorly documented...
+        #[serde(default)]
+        callid: Option<u32>,
+    },
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
 #[serde(rename_all = "lowercase")]
 pub enum IdentifyMethod {
     Ticket,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 #[serde(rename_all = "lowercase")]
 pub enum IgnoreAction {
     Add,
@@ -564,7 +767,7 @@ pub enum BridgeEvent {
     FriendRequest,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, Hash, PartialOrd, Ord)]
 #[serde(into = "KinkIdExpanded")]
 #[serde(try_from = "KinkIdExpanded")]
 pub struct KinkId(pub u32);
@@ -590,7 +793,7 @@ enum KinkIdExpanded {
     Number(u32),
 }
 
-#[derive(Debug, num_enum::FromPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, num_enum::FromPrimitive)]
 #[repr(i32)]
 pub enum ProtocolError {
     Success = 0, // Not an error.
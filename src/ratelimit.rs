@@ -0,0 +1,126 @@
+// Some limits are per account rather than per connection, so five sessions searching at once
+// will trip the cooldown for all of them. Everything that hits one of those goes through here first.
+//
+// The JSON API is throttled as a whole too, which is what HttpLimiter is for.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LimitedAction {
+    Search,  // FKS
+    Profile, // character-data.php
+    Report,  // report-submit.php + SFC
+}
+
+impl LimitedAction {
+    // Roughly what the server enforces. Being a little slow beats eating an ERR.
+    fn default_cooldown(self) -> Duration {
+        match self {
+            LimitedAction::Search => Duration::from_secs(5),
+            LimitedAction::Profile => Duration::from_secs(1),
+            LimitedAction::Report => Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    cooldowns: HashMap<LimitedAction, Duration>, // Overrides
+    next_allowed: Mutex<HashMap<LimitedAction, Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_cooldown(mut self, action: LimitedAction, cooldown: Duration) -> Self {
+        self.cooldowns.insert(action, cooldown);
+        self
+    }
+
+    pub fn cooldown(&self, action: LimitedAction) -> Duration {
+        self.cooldowns
+            .get(&action)
+            .copied()
+            .unwrap_or_else(|| action.default_cooldown())
+    }
+
+    /// Wait for a turn at `action`. Turns are handed out in the order they're asked for.
+    pub async fn acquire(&self, action: LimitedAction) {
+        let at = {
+            let mut next_allowed = self.next_allowed.lock();
+            let now = Instant::now();
+            let at = next_allowed
+                .get(&action)
+                .map_or(now, |next| (*next).max(now));
+            next_allowed.insert(action, at + self.cooldown(action));
+            at
+        };
+        tokio::time::sleep_until(at.into()).await
+    }
+}
+
+/// Caps on every request to the JSON API: a steady rate, and how many can be out at once.
+/// Requests over either wait their turn rather than failing. No caps by default.
+#[derive(Debug)]
+pub struct HttpLimiter {
+    interval: Duration, // Between one request starting and the next
+    next_allowed: Mutex<Instant>,
+    in_flight: Semaphore,
+}
+
+impl Default for HttpLimiter {
+    fn default() -> Self {
+        HttpLimiter {
+            interval: Duration::ZERO,
+            next_allowed: Mutex::new(Instant::now()),
+            in_flight: Semaphore::new(Semaphore::MAX_PERMITS),
+        }
+    }
+}
+
+impl HttpLimiter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Spread requests out so no more than `per_minute` start in any minute.
+    pub fn with_requests_per_minute(self, per_minute: u32) -> Self {
+        HttpLimiter {
+            interval: Duration::from_secs(60) / per_minute.max(1),
+            ..self
+        }
+    }
+
+    /// Allow at most `concurrency` requests in flight at once.
+    pub fn with_concurrency(self, concurrency: usize) -> Self {
+        HttpLimiter {
+            in_flight: Semaphore::new(concurrency.clamp(1, Semaphore::MAX_PERMITS)),
+            ..self
+        }
+    }
+
+    /// Wait for a turn to send a request. Hold on to the permit until the response has been read.
+    pub async fn acquire(&self) -> SemaphorePermit<'_> {
+        let permit = self
+            .in_flight
+            .acquire()
+            .await
+            .expect("HttpLimiter semaphore is never closed");
+        let at = {
+            let mut next_allowed = self.next_allowed.lock();
+            let at = (*next_allowed).max(Instant::now());
+            *next_allowed = at + self.interval;
+            at
+        };
+        tokio::time::sleep_until(at.into()).await;
+        permit
+    }
+}
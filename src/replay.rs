@@ -0,0 +1,262 @@
+// Recording what goes over the wire, and playing it back.
+//
+// ClientBuilder::with_frame_recorder has every session write its frames to a FrameRecorder, one
+// JSON object per line, with how long after the recorder was made each one went by.
+// The handshake isn't recorded, so tickets don't end up in there.
+//
+// With the mock-server feature, Replay::run feeds the received frames back through a Client as if
+// they'd just arrived, so whatever a live session did to the cache or the listener can be
+// reproduced in a test.
+
+use std::{
+    fmt::Debug,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    time::Instant,
+};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{data::Character, util::diagnostic};
+
+#[cfg(feature = "mock-server")]
+use crate::{
+    cache::Cache,
+    client::{Client, EventListener},
+    mock::{MockError, MockServer},
+    protocol::{read_frame, ServerCommand},
+    session::{Event, Session, SessionError, SessionEvent},
+    transport::WebsocketTransport,
+};
+#[cfg(feature = "mock-server")]
+use std::sync::Arc;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FrameDirection {
+    Received,
+    Sent,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RecordedFrame {
+    pub character: Character, // Whose session it went over
+    pub offset_ms: u64,       // Since the recorder was made
+    pub direction: FrameDirection,
+    pub frame: String,
+}
+
+// RecordedFrame, without copying the frame to write it out.
+#[derive(Serialize)]
+struct FrameRef<'a> {
+    character: &'a Character,
+    offset_ms: u64,
+    direction: FrameDirection,
+    frame: &'a str,
+}
+
+pub struct FrameRecorder {
+    started: Instant,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Debug for FrameRecorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FrameRecorder")
+            .field("started", &self.started)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FrameRecorder {
+    pub fn new<W: Write + Send + 'static>(out: W) -> Self {
+        FrameRecorder {
+            started: Instant::now(),
+            out: Mutex::new(Box::new(out)),
+        }
+    }
+
+    /// Record to a file, replacing whatever was in it.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(FrameRecorder::new(BufWriter::new(File::create(path)?)))
+    }
+
+    // A frame that can't be written is only worth a warning; the session carries on regardless.
+    pub(crate) fn record(&self, character: Character, direction: FrameDirection, frame: &str) {
+        // Timed once the lock's held, so sessions recording at once still write in offset order.
+        let mut out = self.out.lock();
+        let line = FrameRef {
+            character: &character,
+            offset_ms: self.started.elapsed().as_millis() as u64,
+            direction,
+            frame,
+        };
+        if let Err(err) = serde_json::to_writer(&mut *out, &line)
+            .map_err(io::Error::from)
+            .and_then(|_| out.write_all(b"\n"))
+        {
+            diagnostic!(warn, "Couldn't record a frame: {err}")
+        }
+    }
+
+    /// Frames are buffered (if the writer buffers); this pushes them out.
+    pub fn flush(&self) -> io::Result<()> {
+        self.out.lock().flush()
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ReplayError {
+    #[error("Couldn't read the recording")]
+    Io(#[from] io::Error),
+    #[error("Couldn't parse a recorded frame")]
+    Json(#[from] serde_json::Error),
+    #[cfg(feature = "mock-server")]
+    #[error("Error from the mock server")]
+    Mock(#[from] MockError),
+    #[cfg(feature = "mock-server")]
+    #[error("Error from a replaying session")]
+    SessionError(#[from] SessionError),
+}
+
+/// How quickly Replay::run goes through the frames.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplayPace {
+    Immediate,   // One after the other, as soon as the last one's been handled
+    Recorded,    // With the gaps there were between them
+    Scaled(f64), // Recorded, but this many times faster
+}
+
+/// A recording, loaded back in.
+#[derive(Debug, Clone, Default)]
+pub struct Replay {
+    frames: Vec<RecordedFrame>,
+}
+
+impl Replay {
+    pub fn new(frames: Vec<RecordedFrame>) -> Self {
+        Replay { frames }
+    }
+
+    pub fn read<R: BufRead>(reader: R) -> Result<Self, ReplayError> {
+        let mut frames = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                frames.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(Replay { frames })
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ReplayError> {
+        Replay::read(BufReader::new(File::open(path)?))
+    }
+
+    pub fn frames(&self) -> &[RecordedFrame] {
+        &self.frames
+    }
+
+    /// Feed every received frame to `client`, in order, as if it had just come in over that
+    /// character's session. Sent frames are left out; whatever the client sends in response goes
+    /// to a MockServer and is thrown away.
+    ///
+    /// Each recorded character gets a session connected to the mock server for the duration,
+    /// in place of any the client already had.
+    #[cfg(feature = "mock-server")]
+    pub async fn run<T: EventListener, C: Cache>(
+        &self,
+        client: &Client<T, C>,
+        pace: ReplayPace,
+    ) -> Result<(), ReplayError> {
+        let received = self
+            .frames
+            .iter()
+            .filter(|frame| frame.direction == FrameDirection::Received)
+            .collect::<Vec<_>>();
+        let mut characters = Vec::new();
+        for frame in &received {
+            if !characters.contains(&frame.character) {
+                characters.push(frame.character);
+            }
+        }
+
+        // The sessions' own events (CON from the handshake, mostly) aren't part of the recording.
+        let (events, mut discarded) = tokio::sync::mpsc::channel(8);
+        let (outgoing, _) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move { while discarded.recv().await.is_some() {} });
+
+        let server = MockServer::bind().await?;
+        let mut sessions = Vec::new();
+        let mut drains = Vec::new();
+        for character in characters {
+            let (session, connection) = tokio::join!(
+                Session::connect(
+                    Arc::new(WebsocketTransport),
+                    server.url(),
+                    "replay".to_owned(),
+                    "replay".to_owned(),
+                    "f-chat-rs replay".to_owned(),
+                    "0".to_owned(),
+                    character,
+                    events.clone(),
+                    outgoing.clone(),
+                    None,
+                ),
+                server.accept()
+            );
+            let (session, mut connection) = (session?, connection?);
+            drains.push(tokio::spawn(async move {
+                while let Ok(_) | Err(MockError::Timeout) = connection.recv().await {}
+            }));
+            client.add_session(session.clone());
+            sessions.push(session);
+        }
+
+        let started = tokio::time::Instant::now();
+        let first = received.first().map_or(0, |frame| frame.offset_ms);
+        for frame in received {
+            // Logs can be edited by hand; one out of order just goes straight away.
+            let offset = std::time::Duration::from_millis(frame.offset_ms.saturating_sub(first));
+            let wait = match pace {
+                ReplayPace::Immediate => None,
+                ReplayPace::Recorded => Some(offset),
+                ReplayPace::Scaled(speed) => {
+                    std::time::Duration::try_from_secs_f64(offset.as_secs_f64() / speed).ok()
+                }
+            };
+            // Same for an offset too far off to wait for, or a speed that makes no sense.
+            if let Some(at) = wait.and_then(|wait| started.checked_add(wait)) {
+                tokio::time::sleep_until(at).await
+            }
+            let session = sessions
+                .iter()
+                .find(|session| session.character == frame.character)
+                .expect("Every recorded character has a session");
+            // Same as a session's own event loop does with a frame.
+            let command: ServerCommand = read_frame(&frame.frame)?;
+            let event = match Session::handle_command(session, &command).await {
+                Ok(true) => SessionEvent::Command(command),
+                Ok(false) => continue,
+                Err(err) => SessionEvent::Error(err),
+            };
+            client
+                .dispatch(Event {
+                    session: session.clone(),
+                    event,
+                })
+                .await;
+        }
+
+        for session in &sessions {
+            client.drop_session(&session.character);
+        }
+        for drain in drains {
+            drain.abort();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+// F-List's endpoints drop the odd request; a timeout or a 502 shouldn't sink a whole sync.
+// Only used for requests that are safe to repeat.
+
+use std::{future::Future, time::Duration};
+
+use reqwest::StatusCode;
+
+use crate::transport::{HttpError, HttpResult};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub attempts: u32, // Including the first try
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration, // The backoff doubles each attempt, up to this
+}
+
+impl RetryPolicy {
+    pub const NONE: RetryPolicy = RetryPolicy {
+        attempts: 1,
+        initial_backoff: Duration::ZERO,
+        max_backoff: Duration::ZERO,
+    };
+
+    pub fn new(attempts: u32, initial_backoff: Duration, max_backoff: Duration) -> Self {
+        RetryPolicy {
+            attempts,
+            initial_backoff,
+            max_backoff,
+        }
+    }
+
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+pub(crate) fn is_transient(err: &HttpError) -> bool {
+    if let HttpError::RequestError(err) = err {
+        if err.is_timeout() || err.is_connect() {
+            return true;
+        }
+    }
+    err.status()
+        .is_some_and(|status| status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS)
+}
+
+pub(crate) async fn retry<T, F, Fut>(policy: &RetryPolicy, mut request: F) -> HttpResult<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = HttpResult<T>>,
+{
+    let mut attempt = 0;
+    loop {
+        match request().await {
+            Err(err) if attempt + 1 < policy.attempts && is_transient(&err) => {
+                tokio::time::sleep(policy.backoff(attempt)).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
@@ -0,0 +1,90 @@
+// FKS is an awkward shape: a flat bag of kink IDs and enums going out,
+// a list of bare names (and the kinks again) coming back.
+
+use crate::{
+    data::{Character, CharacterData, FurryPreference, Gender, Language, Orientation, Role},
+    protocol::{ClientCommand, KinkId},
+};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SearchQuery {
+    pub kinks: Vec<KinkId>,
+    pub genders: Vec<Gender>,
+    pub orientations: Vec<Orientation>,
+    pub languages: Vec<Language>,
+    pub furryprefs: Vec<FurryPreference>,
+    pub roles: Vec<Role>,
+}
+
+impl SearchQuery {
+    // The server refuses searches without a kink, and with more than this many.
+    pub const MAX_KINKS: usize = 5;
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_kink(mut self, kink: KinkId) -> Self {
+        self.kinks.push(kink);
+        self
+    }
+
+    pub fn with_gender(mut self, gender: Gender) -> Self {
+        self.genders.push(gender);
+        self
+    }
+
+    pub fn with_orientation(mut self, orientation: Orientation) -> Self {
+        self.orientations.push(orientation);
+        self
+    }
+
+    pub fn with_language(mut self, language: Language) -> Self {
+        self.languages.push(language);
+        self
+    }
+
+    pub fn with_furrypref(mut self, furrypref: FurryPreference) -> Self {
+        self.furryprefs.push(furrypref);
+        self
+    }
+
+    pub fn with_role(mut self, role: Role) -> Self {
+        self.roles.push(role);
+        self
+    }
+
+    /// Check the query against the server's limits, so it doesn't cost a search cooldown to find out.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.kinks.is_empty() {
+            Err("A search needs at least one kink")
+        } else if self.kinks.len() > Self::MAX_KINKS {
+            Err("A search can have at most 5 kinks")
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn into_command(self) -> ClientCommand {
+        ClientCommand::Search {
+            kinks: self.kinks,
+            genders: self.genders,
+            orientations: self.orientations,
+            languages: self.languages,
+            furryprefs: self.furryprefs,
+            roles: self.roles,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    pub character: Character,
+    pub data: Option<CharacterData>, // From the cache, if it knows them
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct SearchResults {
+    pub kinks: Vec<KinkId>,
+    pub characters: Vec<SearchResult>,
+}
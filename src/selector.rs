@@ -0,0 +1,82 @@
+// Picks which of our own connected characters does something, when the caller didn't say.
+
+use std::{
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use crate::{
+    data::{Channel, Character},
+    session::Session,
+};
+
+/// What a session is being picked for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionPurpose {
+    ChannelMessage(Channel), // Needs to be in the channel
+    PrivateMessage(Character),
+    Join(Channel),
+    Leave(Channel), // Needs to be in the channel
+}
+
+impl SessionPurpose {
+    fn eligible<'a>(&self, sessions: &'a [Arc<Session>]) -> Vec<&'a Arc<Session>> {
+        sessions
+            .iter()
+            .filter(|session| match self {
+                SessionPurpose::ChannelMessage(channel) | SessionPurpose::Leave(channel) => {
+                    session.channels.contains(channel)
+                }
+                // Joining with a character that's already in there gets nothing done.
+                SessionPurpose::Join(channel) => !session.channels.contains(channel),
+                SessionPurpose::PrivateMessage(_) => true,
+            })
+            .collect()
+    }
+}
+
+/// Policy for picking a session when a Client method is called without a character.
+/// An explicitly named character always wins over the selector.
+pub trait SessionSelector: Debug + Send + Sync {
+    fn select(&self, sessions: &[Arc<Session>], purpose: &SessionPurpose) -> Option<Arc<Session>>;
+}
+
+/// Never picks anything; every call has to name its character. The default.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Explicit;
+
+impl SessionSelector for Explicit {
+    fn select(&self, _: &[Arc<Session>], _: &SessionPurpose) -> Option<Arc<Session>> {
+        None
+    }
+}
+
+/// The first connected session that can do it, in connection order.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FirstInChannel;
+
+impl SessionSelector for FirstInChannel {
+    fn select(&self, sessions: &[Arc<Session>], purpose: &SessionPurpose) -> Option<Arc<Session>> {
+        purpose.eligible(sessions).first().map(|s| (*s).clone())
+    }
+}
+
+/// Takes turns between the sessions that can do it, to spread out the flood limits.
+#[derive(Debug, Default)]
+pub struct RoundRobin {
+    next: AtomicUsize,
+}
+
+impl SessionSelector for RoundRobin {
+    fn select(&self, sessions: &[Arc<Session>], purpose: &SessionPurpose) -> Option<Arc<Session>> {
+        let eligible = purpose.eligible(sessions);
+        if eligible.is_empty() {
+            return None;
+        }
+        let turn = self.next.fetch_add(1, Ordering::Relaxed);
+        Some(eligible[turn % eligible.len()].clone())
+    }
+}
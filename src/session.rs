@@ -1,35 +1,51 @@
-use std::sync::{atomic::AtomicI32, Arc};
+use std::{
+    sync::{Arc, Weak, atomic::AtomicI32},
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
 
 use dashmap::{DashMap, DashSet};
 use thiserror::Error;
 // Optionally switch to BTree and manually manage R/W sync
-use futures_util::{
-    join,
-    stream::{SplitSink, SplitStream},
-    SinkExt, StreamExt, TryStreamExt,
-};
-use tokio::net::TcpStream;
+use futures_util::join;
 use tokio::{
     sync::{
-        mpsc::{error::SendError, Sender},
-        Mutex as AsyncMutex,
+        mpsc::{error::SendError, Sender, UnboundedSender},
+        Mutex as AsyncMutex, Notify,
     },
     task::JoinHandle,
 };
-use tokio_tungstenite::{
-    connect_async_tls_with_config,
-    tungstenite::{error::ProtocolError as WebsocketError, Message},
-    MaybeTlsStream, WebSocketStream,
-};
 
 use crate::{
     data::{Channel, Character, TypingStatus},
     protocol::{
-        parse_command, prepare_command, ClientCommand, IdentifyMethod, ProtocolError,
-        ServerCommand, Target, Variable,
+        prepare_command, read_frame, ClientCommand, IdentifyMethod, ProtocolError, ServerCommand,
+        Target, Variable,
     },
+    replay::{FrameDirection, FrameRecorder},
+    telemetry,
+    transport::{FrameReceiver, FrameSender, Transport, TransportError},
+    util::diagnostic,
 };
 
+// Everything done on behalf of a session happens in a span named after its character.
+#[cfg(feature = "tracing")]
+pub(crate) fn in_session_span<F: std::future::Future>(
+    character: &Character,
+    future: F,
+) -> tracing::instrument::Instrumented<F> {
+    tracing::Instrument::instrument(
+        future,
+        tracing::info_span!("session", character = %character.0),
+    )
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn in_session_span<F: std::future::Future>(_character: &Character, future: F) -> F {
+    future
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct Variables {
     pub chat_max: u32,
@@ -38,6 +54,7 @@ pub struct Variables {
     pub chat_cooldown: f32,
     pub ad_cooldown: f32,
     pub status_cooldown: f32,
+    pub permissions: u64,
     pub icon_blacklist: Vec<Channel>, // I'm not sure that this is actually session-bound
                                       // Actually I'm not sure that any of these are session-bound.
 }
@@ -48,6 +65,7 @@ pub enum SessionEvent {
     Disconnected(ProtocolError), // Fatal* disconnects caused by server
     Command(ServerCommand),
     Error(SessionError),
+    Sent(ClientCommand), // Something went out over this session (see Session::connect)
 }
 
 #[derive(Debug)]
@@ -56,8 +74,10 @@ pub struct Event {
     pub event: SessionEvent,
 }
 
-type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
-type StreamWriter = AsyncMutex<SplitSink<Socket, Message>>;
+/// Where the live chat server is. See ClientBuilder::with_chat_url to go elsewhere.
+pub const DEFAULT_CHAT_URL: &str = "wss://chat.f-list.net/chat2";
+
+type StreamWriter = AsyncMutex<Box<dyn FrameSender>>;
 
 #[derive(Debug)]
 pub struct Session {
@@ -66,15 +86,101 @@ pub struct Session {
     pub private_messages: DashMap<Character, TypingStatus>,
     pub variables: Variables, // I'm not sure that these are actually session-bound
     pub last_err: AtomicI32,
+    channels_changed: Notify,
+    last_activity: Mutex<Instant>,
 
+    transport: Arc<dyn Transport>, // Reconnects go back the same way,
+    url: String,                   // and to the same place
+    recorder: Option<Arc<FrameRecorder>>, // Everything after the handshake, if anyone's asked
     write: StreamWriter,
     event_channel: Sender<Event>,
+    outgoing: UnboundedSender<Event>,
+    me: Weak<Session>, // For tagging outgoing events, since send only gets &self
+}
+
+/// Keeps a typing indicator up in a PM for as long as it's held.
+/// Dropping it clears the indicator; pause() and resume() switch between paused and typing.
+#[derive(Debug)]
+pub struct ComposingGuard {
+    session: Arc<Session>,
+    recipient: Character,
+    refresh: Option<JoinHandle<()>>,
+}
+
+impl ComposingGuard {
+    // The indicator is re-sent this often in case the other side missed it (e.g. they reconnected).
+    const REFRESH: Duration = Duration::from_secs(10);
+
+    fn typing(&mut self) {
+        let session = self.session.clone();
+        let recipient = self.recipient;
+        self.refresh = Some(tokio::spawn(async move {
+            loop {
+                if session
+                    .send_unattended(ClientCommand::Typing {
+                        character: recipient,
+                        status: TypingStatus::Typing,
+                    })
+                    .await
+                    .is_err()
+                {
+                    break; // The session's gone; nothing to keep up.
+                }
+                tokio::time::sleep(Self::REFRESH).await;
+            }
+        }));
+    }
+
+    fn stop(&mut self, status: TypingStatus) {
+        if let Some(refresh) = self.refresh.take() {
+            refresh.abort();
+        }
+        // Drop can't be async, so the last word gets its own task.
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let session = self.session.clone();
+            let recipient = self.recipient;
+            runtime.spawn(async move {
+                let _ = session
+                    .send_unattended(ClientCommand::Typing {
+                        character: recipient,
+                        status,
+                    })
+                    .await;
+            });
+        }
+    }
+
+    pub fn recipient(&self) -> Character {
+        self.recipient
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.refresh.is_none()
+    }
+
+    pub fn pause(&mut self) {
+        if !self.is_paused() {
+            self.stop(TypingStatus::Paused);
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if self.is_paused() {
+            self.typing();
+        }
+    }
+}
+
+impl Drop for ComposingGuard {
+    fn drop(&mut self) {
+        self.stop(TypingStatus::Clear);
+    }
 }
 
 #[derive(Error, Debug)]
 pub enum SessionError {
-    #[error("Error from Websocket (Tungstenite)")]
-    WebsocketError(#[from] tokio_tungstenite::tungstenite::Error),
+    #[error("Error from Transport")]
+    TransportError(#[from] TransportError),
     #[error("Unknown failure in connection stage -- Typically protocol-related")]
     MiscConnectionFailure,
     #[error("Unknown or unexpected protocol message: {0}")]
@@ -88,33 +194,49 @@ pub enum SessionError {
 pub type SessionResult<T> = Result<T, SessionError>;
 
 impl Session {
-    const WS_URL: &'static str = "wss://chat.f-list.net/chat2";
-
+    #[allow(clippy::too_many_arguments)] // How and where to, everything IDN needs, and where things go
     pub async fn connect(
+        transport: Arc<dyn Transport>,
+        url: String,
         account: String,
         ticket: String,
         client_name: String,
         client_version: String,
         character: Character,
         event_channel: Sender<Event>,
+        outgoing: UnboundedSender<Event>,
+        recorder: Option<Arc<FrameRecorder>>,
     ) -> SessionResult<Arc<Self>> {
-        let mut socket =
-            Session::connect_internal(account, ticket, client_name, client_version, character)
-                .await?;
-        let (variables, next) = Session::read_variables(&mut socket).await?;
-        let (write, read) = socket.split();
+        let (write, mut read) = Session::connect_internal(
+            &*transport,
+            &url,
+            account,
+            ticket,
+            client_name,
+            client_version,
+            character,
+        )
+        .await?;
+        let (variables, next) = Session::read_variables(&mut *read).await?;
 
-        let session = Arc::new(Session {
+        let session = Arc::new_cyclic(|me| Session {
             character,
             channels: DashSet::new(),
             private_messages: DashMap::new(),
             variables,
             last_err: AtomicI32::new(ProtocolError::Other as i32),
+            channels_changed: Notify::new(),
+            last_activity: Mutex::new(Instant::now()),
 
+            transport,
+            url,
+            recorder,
             write: AsyncMutex::new(write),
             event_channel,
+            outgoing,
+            me: me.clone(),
         });
-        Session::start_event_loop(session.clone(), read)?;
+        Session::start_event_loop(session.clone(), read);
         if Session::handle_command(&session, &next).await? {
             Session::emit_event(&session, SessionEvent::Command(next))
                 .await
@@ -132,23 +254,36 @@ impl Session {
         client_name: String,
         client_version: String,
     ) -> SessionResult<Arc<Self>> {
-        let mut socket =
-            Session::connect_internal(account, ticket, client_name, client_version, self.character)
-                .await?;
-        let (variables, next) = Session::read_variables(&mut socket).await?;
-        let (write, read) = socket.split();
-
-        let session = Arc::new(Session {
+        let (write, mut read) = Session::connect_internal(
+            &*self.transport,
+            &self.url,
+            account,
+            ticket,
+            client_name,
+            client_version,
+            self.character,
+        )
+        .await?;
+        let (variables, next) = Session::read_variables(&mut *read).await?;
+
+        let session = Arc::new_cyclic(|me| Session {
             character: self.character,
             channels: DashSet::new(),
             private_messages: DashMap::new(),
             variables,
             last_err: AtomicI32::new(ProtocolError::Other as i32),
+            channels_changed: Notify::new(),
+            last_activity: Mutex::new(self.last_activity()), // Reconnecting isn't the user doing anything
 
+            transport: self.transport.clone(),
+            url: self.url.clone(),
+            recorder: self.recorder.clone(),
             write: AsyncMutex::new(write),
             event_channel: self.event_channel.clone(),
+            outgoing: self.outgoing.clone(),
+            me: me.clone(),
         });
-        Session::start_event_loop(session.clone(), read)?;
+        Session::start_event_loop(session.clone(), read);
         if Session::handle_command(&session, &next).await? {
             Session::emit_event(&session, SessionEvent::Command(next))
                 .await
@@ -158,13 +293,14 @@ impl Session {
         // Now try to re-join all of the old channels.
         let mut write = session.write.lock().await;
         for channel in self.channels.iter() {
-            write
-                .feed(Message::Text(prepare_command(
-                    &ClientCommand::JoinChannel {
-                        channel: channel.to_owned(),
-                    },
-                )))
-                .await?
+            let command = ClientCommand::JoinChannel {
+                channel: channel.to_owned(),
+            };
+            let text = prepare_command(&command);
+            telemetry::command_sent(&text);
+            session.record(FrameDirection::Sent, &text);
+            write.feed(text).await?;
+            session.sent(command);
         }
         write.flush().await?;
         drop(write); // If I don't drop here, it complains that the guard still exists when I return session.
@@ -175,37 +311,38 @@ impl Session {
     // Sometimes, the existing session needs to be reconnected.
     // Because this uses the same logic as connect, this is abstracted.
     async fn connect_internal(
+        transport: &dyn Transport,
+        url: &str,
         account: String,
         ticket: String,
         client_name: String,
         client_version: String,
         character: Character,
-    ) -> SessionResult<Socket> {
+    ) -> SessionResult<(Box<dyn FrameSender>, Box<dyn FrameReceiver>)> {
         // Establish the connection
-        let (mut socket, _) = connect_async_tls_with_config(Self::WS_URL, None, None).await?;
+        let (mut write, mut read) = transport.connect(url).await?;
 
         // Identify (IDN)
-        socket
-            .send(Message::Text(prepare_command(&ClientCommand::Identify {
+        write
+            .send(prepare_command(&ClientCommand::Identify {
                 method: IdentifyMethod::Ticket,
                 account,
                 ticket,
                 character,
                 client_name,
                 client_version,
-            })))
+            }))
             .await?;
 
         // Wait for IDN response or blow up (protocol error)
-        // Messages sent are -always- Text
-        // Never Ping, Close, etc. Server does not follow recommendations for closing connections.
-        if let Some(Message::Text(message)) = socket.try_next().await? {
-            if let ServerCommand::IdentifySuccess {
+        // Server does not follow recommendations for closing connections.
+        if let Some(message) = read.recv().await? {
+            if let Ok(ServerCommand::IdentifySuccess {
                 character: character_id,
-            } = parse_command(&message)
+            }) = read_frame(&message)
             {
                 assert_eq!(character, character_id);
-                Ok(socket)
+                Ok((write, read))
             } else {
                 Err(SessionError::UnexpectedProtocolMessage(message))
             }
@@ -214,6 +351,12 @@ impl Session {
         }
     }
 
+    fn record(&self, direction: FrameDirection, frame: &str) {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(self.character, direction, frame);
+        }
+    }
+
     async fn emit_event(
         session: &Arc<Session>,
         event: SessionEvent,
@@ -227,12 +370,17 @@ impl Session {
             .await
     }
 
-    // Reads VAR from a socket until there's no more VAR, and yields the next command (should be HLO)
-    async fn read_variables(socket: &mut Socket) -> SessionResult<(Variables, ServerCommand)> {
+    // Reads VAR from a connection until there's no more VAR, and yields the next command (should be HLO)
+    async fn read_variables(
+        read: &mut dyn FrameReceiver,
+    ) -> SessionResult<(Variables, ServerCommand)> {
         let mut vars: Variables = Default::default();
         loop {
-            if let Some(Message::Text(message)) = socket.try_next().await? {
-                match parse_command(&message) {
+            if let Some(message) = read.recv().await? {
+                let Ok(command) = read_frame(&message) else {
+                    return Err(SessionError::UnexpectedProtocolMessage(message));
+                };
+                match command {
                     ServerCommand::Variable(var) => match var {
                         Variable::ChatMax(v) => vars.chat_max = v,
                         Variable::PrivMax(v) => vars.priv_max = v,
@@ -241,7 +389,8 @@ impl Session {
                         Variable::ChatCooldown(v) => vars.chat_cooldown = v,
                         Variable::StatusCooldown(v) => vars.status_cooldown = v,
                         Variable::IconBlacklist(v) => vars.icon_blacklist = v,
-                        other => eprintln!("Unhandled var {other:?}"),
+                        Variable::Permissions(v) => vars.permissions = v.0,
+                        other => diagnostic!(warn, "Unhandled var {other:?}"),
                     },
                     other => return Ok((vars, other)),
                 }
@@ -251,49 +400,74 @@ impl Session {
         }
     }
 
-    fn start_event_loop(
-        session: Arc<Session>,
-        read: SplitStream<Socket>,
-    ) -> SessionResult<JoinHandle<()>> {
-        Ok(tokio::spawn(read.for_each(move |res| {let session = session.clone(); async move {
+    fn start_event_loop(session: Arc<Session>, mut read: Box<dyn FrameReceiver>) -> JoinHandle<()> {
+        let character = session.character;
+        tokio::spawn(in_session_span(&character, async move {
             // We don't want this to happen concurrently, because the events need to arrive in order
             // But they only need to arrive in order for any given connection.
             // Connections will end up interleaved in the channel consumer.
-            match res {
-                Err(err) => match err {
-                    tokio_tungstenite::tungstenite::Error::Protocol(err) => match err {
-                        WebsocketError::ResetWithoutClosingHandshake => {
-                            // The server has closed the connection. It never sends close frames.
-                            // Check for the most recent ERR type, and if it's fatal.
-                            let last_err = ProtocolError::from(session.last_err.load(std::sync::atomic::Ordering::SeqCst));
-                            if last_err.is_fatal() {
-                                Session::emit_event(&session, SessionEvent::Disconnected(last_err)).await.expect("Failed to send event through event channel (disconnect)")
-                            } else {
-                                Session::emit_event(&session, SessionEvent::Reconnect).await.expect("Failed to send event through event channel (reconnect)")
+            loop {
+                match read.recv().await {
+                    Ok(Some(command)) => {
+                        #[cfg(feature = "tracing")]
+                        tracing::trace!(command = command.get(..3).unwrap_or(&command), "received");
+                        telemetry::command_received(&command);
+                        session.record(FrameDirection::Received, &command);
+                        // Something the server sent that we can't read isn't worth the session.
+                        let command = match read_frame(&command) {
+                            Ok(parsed) => parsed,
+                            Err(err) => {
+                                diagnostic!(warn, "Unreadable frame from F-Chat: {err}");
+                                let err = SessionError::UnexpectedProtocolMessage(command);
+                                Session::emit_event(&session, SessionEvent::Error(err))
+                                    .await
+                                    .expect("Failed to send event through event channel (error)");
+                                continue;
                             }
-                        },
-                        WebsocketError::ReceivedAfterClosing => eprintln!("Close frames are not respected by F-Chat"),
-                        other => panic!("Unexpected (protocol) error from Tungstenite: {other:?}")
-                    },
-                    other => panic!("Unexpected error from Tungstenite: {other:?}")
-                },
-                Ok(Message::Text(command)) => {
-                    let command = parse_command(&command);
-                    // Handle the command and decide if we should forward it to the event channel
-                    match Session::handle_command(&session, &command).await {
-                        Ok(true) => Session::emit_event(&session, SessionEvent::Command(command)).await.expect("Failed to send event through event channel (command)"),
-                        Err(err) => Session::emit_event(&session, SessionEvent::Error(err)).await.expect("Failed to send event through event channel (error)"),
-                        Ok(false) => {} // Do nothing; there was no error, and we're not fowarding the command.
+                        };
+                        // Handle the command and decide if we should forward it to the event channel
+                        match Session::handle_command(&session, &command).await {
+                            Ok(true) => {
+                                Session::emit_event(&session, SessionEvent::Command(command))
+                                    .await
+                                    .expect("Failed to send event through event channel (command)")
+                            }
+                            Err(err) => Session::emit_event(&session, SessionEvent::Error(err))
+                                .await
+                                .expect("Failed to send event through event channel (error)"),
+                            Ok(false) => {} // Do nothing; there was no error, and we're not fowarding the command.
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(TransportError::Dropped) => {
+                        // The server has closed the connection. It never sends close frames.
+                        // Check for the most recent ERR type, and if it's fatal.
+                        let last_err = ProtocolError::from(
+                            session.last_err.load(std::sync::atomic::Ordering::SeqCst),
+                        );
+                        if last_err.is_fatal() {
+                            Session::emit_event(&session, SessionEvent::Disconnected(last_err))
+                                .await
+                                .expect("Failed to send event through event channel (disconnect)")
+                        } else {
+                            Session::emit_event(&session, SessionEvent::Reconnect).await.expect("Failed to send event through event channel (reconnect)")
+                        }
+                        break;
+                    }
+                    Err(err) => {
+                        // Whatever it was, the connection is no good any more.
+                        diagnostic!(error, "Unexpected error from the transport: {err:?}");
+                        Session::emit_event(&session, SessionEvent::Reconnect)
+                            .await
+                            .expect("Failed to send event through event channel (reconnect)");
+                        break;
                     }
-                },
-                Ok(other) => {
-                    eprintln!("Unexpected frame from F-Chat: {other:?}")
                 }
             }
-        }})))
+        }))
     }
 
-    async fn handle_command(
+    pub(crate) async fn handle_command(
         session: &Arc<Session>,
         command: &ServerCommand,
     ) -> SessionResult<bool> {
@@ -314,14 +488,25 @@ impl Session {
             } => {
                 if *character == session.character {
                     // If it was this session, update the joined-channels list.
-                    session.channels.insert(*channel);
+                    session.channels.insert(channel.clone());
+                    session.channels_changed.notify_waiters();
                 }
                 Ok(true)
             }
-            ServerCommand::LeftChannel { channel, character } => {
+            ServerCommand::LeftChannel { channel, character }
+            | ServerCommand::Kicked {
+                channel, character, ..
+            }
+            | ServerCommand::Banned {
+                channel, character, ..
+            }
+            | ServerCommand::Timeout {
+                channel, character, ..
+            } => {
                 if *character == session.character {
                     // As above, so below.
                     session.channels.remove(channel);
+                    session.channels_changed.notify_waiters();
                 }
                 Ok(true)
             }
@@ -343,13 +528,39 @@ impl Session {
         }
     }
 
+    /// When this session last sent something on the user's behalf (anything but pings and status updates).
+    pub fn last_activity(&self) -> Instant {
+        *self.last_activity.lock()
+    }
+
     pub async fn send(&self, command: ClientCommand) -> SessionResult<()> {
-        Ok(self
-            .write
-            .lock()
-            .await
-            .send(Message::Text(prepare_command(&command)))
-            .await?)
+        if !matches!(command, ClientCommand::Pong | ClientCommand::Status { .. }) {
+            *self.last_activity.lock() = Instant::now();
+        }
+        self.send_unattended(command).await
+    }
+
+    // For things the client does by itself (scheduled ads and the like), which shouldn't keep it from going idle.
+    pub(crate) async fn send_unattended(&self, command: ClientCommand) -> SessionResult<()> {
+        let text = prepare_command(&command);
+        #[cfg(feature = "tracing")]
+        tracing::trace!(character = %self.character.0, command = &text[..3], "sent");
+        telemetry::command_sent(&text);
+        self.record(FrameDirection::Sent, &text);
+        self.write.lock().await.send(text).await?;
+        self.sent(command);
+        Ok(())
+    }
+
+    // Report outgoing traffic. It's unbounded, so it can't hold up sends made from inside the event loop.
+    fn sent(&self, command: ClientCommand) {
+        if let Some(session) = self.me.upgrade() {
+            // Nobody listening is fine.
+            let _ = self.outgoing.send(Event {
+                session,
+                event: SessionEvent::Sent(command),
+            });
+        }
     }
 
     pub async fn send_message(&self, target: Target, message: String) -> SessionResult<()> {
@@ -388,7 +599,45 @@ impl Session {
         .await
     }
 
+    /// Show `recipient` that we're typing, until the guard is dropped.
+    pub fn start_typing(self: &Arc<Self>, recipient: Character) -> ComposingGuard {
+        let mut guard = ComposingGuard {
+            session: self.clone(),
+            recipient,
+            refresh: None,
+        };
+        guard.typing();
+        guard
+    }
+
     pub async fn join_channel(&self, channel: Channel) -> SessionResult<()> {
         self.send(ClientCommand::JoinChannel { channel }).await
     }
+
+    pub async fn leave_channel(&self, channel: Channel) -> SessionResult<()> {
+        self.send(ClientCommand::LeaveChannel { channel }).await
+    }
+
+    /// Wait until this session has joined (or left) the channel, as reported by the server.
+    /// Returns false if it didn't happen within the timeout, e.g. because the server sent an ERR instead.
+    pub async fn wait_for_channel(
+        &self,
+        channel: &Channel,
+        joined: bool,
+        timeout: Duration,
+    ) -> bool {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            // Register for the notification before checking, or a change could slip between the two.
+            let notified = self.channels_changed.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if self.channels.contains(channel) == joined {
+                return true;
+            }
+            if tokio::time::timeout_at(deadline, notified).await.is_err() {
+                return false;
+            }
+        }
+    }
 }
@@ -0,0 +1,73 @@
+// Coalesces identical requests that are in flight at the same time: the first caller makes the
+// request, and anyone asking for the same thing meanwhile waits for its answer instead of making
+// their own. Errors aren't shared; if the first caller fails (or is dropped), the others go ahead
+// and make the request themselves.
+
+use std::{collections::HashMap, future::Future, hash::Hash};
+
+use parking_lot::Mutex;
+use tokio::sync::watch;
+
+#[derive(Debug)]
+pub(crate) struct SingleFlight<K, V> {
+    in_flight: Mutex<HashMap<K, watch::Receiver<Option<V>>>>,
+}
+
+impl<K, V> Default for SingleFlight<K, V> {
+    fn default() -> Self {
+        SingleFlight {
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> SingleFlight<K, V> {
+    pub(crate) async fn run<E, F, Fut>(&self, key: K, request: F) -> Result<V, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, E>>,
+    {
+        let sender = {
+            let mut in_flight = self.in_flight.lock();
+            match in_flight.get(&key) {
+                Some(receiver) => Err(receiver.clone()),
+                None => {
+                    let (sender, receiver) = watch::channel(None);
+                    in_flight.insert(key.clone(), receiver);
+                    Ok(sender)
+                }
+            }
+        };
+        let sender = match sender {
+            Ok(sender) => sender,
+            Err(mut receiver) => {
+                // Only ever set once, so the first change is the answer.
+                // If the sender's gone without one, that request didn't work out.
+                if receiver.changed().await.is_ok() {
+                    if let Some(value) = receiver.borrow().clone() {
+                        return Ok(value);
+                    }
+                }
+                return request().await;
+            }
+        };
+        let _flight = Flight { flights: self, key };
+        let result = request().await;
+        if let Ok(value) = &result {
+            sender.send_replace(Some(value.clone()));
+        }
+        result
+    }
+}
+
+// Takes the request out of the map however run ends, even if it's dropped halfway.
+struct Flight<'a, K: Eq + Hash, V> {
+    flights: &'a SingleFlight<K, V>,
+    key: K,
+}
+
+impl<K: Eq + Hash, V> Drop for Flight<'_, K, V> {
+    fn drop(&mut self) {
+        self.flights.in_flight.lock().remove(&self.key);
+    }
+}
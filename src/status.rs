@@ -0,0 +1,87 @@
+// Keeps track of the status the user actually wants, as opposed to whatever the sessions last sent.
+// The client re-applies it whenever a session (re)connects, and swaps in an idle status
+// if nothing has been sent for a while.
+
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use parking_lot::RwLock;
+
+use crate::data::Status;
+
+#[derive(Debug, Clone, Copy)]
+pub struct AutoIdle {
+    pub after: Duration,
+    pub status: Status, // Usually Idle or Away
+}
+
+#[derive(Debug)]
+pub struct StatusManager {
+    desired: RwLock<Option<(Status, String)>>,
+    last_set: RwLock<Instant>,
+    auto_idle: Option<AutoIdle>,
+    idle: AtomicBool,
+}
+
+impl StatusManager {
+    pub fn new(auto_idle: Option<AutoIdle>) -> Self {
+        StatusManager {
+            desired: RwLock::new(None),
+            last_set: RwLock::new(Instant::now()),
+            auto_idle,
+            idle: AtomicBool::new(false),
+        }
+    }
+
+    pub fn auto_idle(&self) -> Option<AutoIdle> {
+        self.auto_idle
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.idle.load(Ordering::Relaxed)
+    }
+
+    // Setting a status explicitly counts as coming back from idle.
+    pub fn set_desired(&self, status: Status, message: String) {
+        *self.desired.write() = Some((status, message));
+        *self.last_set.write() = Instant::now();
+        self.idle.store(false, Ordering::Relaxed);
+    }
+
+    pub fn desired(&self) -> Option<(Status, String)> {
+        self.desired.read().clone()
+    }
+
+    // What should be sent to a freshly connected session, if anything.
+    pub fn effective(&self) -> Option<(Status, String)> {
+        match (self.auto_idle, self.is_idle()) {
+            (Some(auto_idle), true) => Some((
+                auto_idle.status,
+                self.desired()
+                    .map(|(_, message)| message)
+                    .unwrap_or_default(),
+            )),
+            _ => self.desired(),
+        }
+    }
+
+    /// Given when the last outgoing activity happened, work out whether the status needs to change.
+    /// Returns the status to broadcast to every session, or None if nothing changed.
+    pub fn poll(&self, last_activity: Instant) -> Option<(Status, String)> {
+        let auto_idle = self.auto_idle?;
+        let last_activity = last_activity.max(*self.last_set.read());
+        let inactive = last_activity.elapsed() >= auto_idle.after;
+        if inactive == self.is_idle() {
+            return None;
+        }
+        self.idle.store(inactive, Ordering::Relaxed);
+        if inactive {
+            self.effective()
+        } else {
+            // Back from idle; restore whatever was set before, or plain online if nothing was.
+            Some(self.desired().unwrap_or_default())
+        }
+    }
+}
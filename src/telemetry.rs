@@ -0,0 +1,75 @@
+// Counters and gauges for the "metrics" feature, reported through the metrics facade.
+// Install whatever recorder/exporter you like (e.g. metrics-exporter-prometheus) to scrape them.
+// Without the feature these are all no-ops.
+//
+// fchat_sessions                      gauge, sessions currently connected
+// fchat_commands_received_total       counter, labelled by command (MSG, PRI, LIS, ...)
+// fchat_commands_sent_total           counter, labelled by command
+// fchat_reconnects_total              counter
+// fchat_cache_errors_total            counter
+// fchat_ticket_refreshes_total        counter
+// fchat_cache_operations_total        counter, labelled by operation and whether it failed
+// fchat_cache_operation_seconds       histogram, labelled by operation
+//
+// The last two only come from caches wrapped in an ObservedCache with the MetricsObserver.
+
+#[cfg(feature = "metrics")]
+mod imp {
+    // Commands are always 3 characters, but don't trust that for a label.
+    fn command_label(command: &str) -> String {
+        command.get(..3).unwrap_or(command).to_owned()
+    }
+
+    pub(crate) fn sessions(connected: usize) {
+        metrics::gauge!("fchat_sessions").set(connected as f64);
+    }
+
+    pub(crate) fn command_received(command: &str) {
+        metrics::counter!("fchat_commands_received_total", "command" => command_label(command))
+            .increment(1);
+    }
+
+    pub(crate) fn command_sent(command: &str) {
+        metrics::counter!("fchat_commands_sent_total", "command" => command_label(command))
+            .increment(1);
+    }
+
+    pub(crate) fn reconnect() {
+        metrics::counter!("fchat_reconnects_total").increment(1);
+    }
+
+    pub(crate) fn cache_error() {
+        metrics::counter!("fchat_cache_errors_total").increment(1);
+    }
+
+    pub(crate) fn ticket_refresh() {
+        metrics::counter!("fchat_ticket_refreshes_total").increment(1);
+    }
+
+    pub(crate) fn cache_operation(
+        operation: &'static str,
+        elapsed: std::time::Duration,
+        failed: bool,
+    ) {
+        metrics::counter!(
+            "fchat_cache_operations_total",
+            "operation" => operation,
+            "failed" => if failed { "true" } else { "false" }
+        )
+        .increment(1);
+        metrics::histogram!("fchat_cache_operation_seconds", "operation" => operation)
+            .record(elapsed.as_secs_f64());
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+mod imp {
+    pub(crate) fn sessions(_connected: usize) {}
+    pub(crate) fn command_received(_command: &str) {}
+    pub(crate) fn command_sent(_command: &str) {}
+    pub(crate) fn reconnect() {}
+    pub(crate) fn cache_error() {}
+    pub(crate) fn ticket_refresh() {}
+}
+
+pub(crate) use imp::*;
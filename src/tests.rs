@@ -10,43 +10,43 @@ fn server_command_deserialize() {
         ServerCommand::ListOnline {
             characters: vec![
                 FlatCharacterData(
-                    Character("Alexandrea".into()),
+                    Character("Alexandrea".try_into().unwrap()),
                     Gender::Female,
                     Status::Online,
                     "".to_owned()
                 ),
                 FlatCharacterData(
-                    Character("Fa Mulan".into()),
+                    Character("Fa Mulan".try_into().unwrap()),
                     Gender::Female,
                     Status::Busy,
                     "Away, check out my new alt Aya Kinjou!".to_owned()
                 ),
                 FlatCharacterData(
-                    Character("Adorkable Lexi".into()),
+                    Character("Adorkable Lexi".try_into().unwrap()),
                     Gender::Female,
                     Status::Online,
                     "".to_owned()
                 ),
                 FlatCharacterData(
-                    Character("Melfice Cyrum".into()),
+                    Character("Melfice Cyrum".try_into().unwrap()),
                     Gender::Male,
                     Status::Online,
                     "".to_owned()
                 ),
                 FlatCharacterData(
-                    Character("Jenasys Stryphe".into()),
+                    Character("Jenasys Stryphe".try_into().unwrap()),
                     Gender::Female,
                     Status::Online,
                     "".to_owned()
                 ),
                 FlatCharacterData(
-                    Character("Cassie Hazel".into()),
+                    Character("Cassie Hazel".try_into().unwrap()),
                     Gender::Herm,
                     Status::Looking,
                     "".to_owned()
                 ),
                 FlatCharacterData(
-                    Character("Viol".into()),
+                    Character("Viol".try_into().unwrap()),
                     Gender::CBoy,
                     Status::Looking,
                     "".to_owned()
@@ -61,7 +61,7 @@ fn client_command_serialize() {
     use crate::data::{FurryPreference, Gender, Language, Orientation, Role};
     use crate::protocol::{prepare_command, ClientCommand, KinkId};
     assert_eq!(
-        r#"FKS {"furryprefs":["Furs and / or humans","Humans ok, Furries Preferred","No humans, just furry characters"],"genders":["Male","Male-Herm"],"kinks":["523","66"],"languages":["Dutch"],"orientations":["Gay","Bi - male preference","Bisexual"],"roles":["Always dominant","Usually dominant"]}"#,
+        r#"FKS {"kinks":["523","66"],"genders":["Male","Male-Herm"],"orientations":["Gay","Bi - male preference","Bisexual"],"languages":["Dutch"],"furryprefs":["Furs and / or humans","Humans ok, Furries Preferred","No humans, just furry characters"],"roles":["Always dominant","Usually dominant"]}"#,
         prepare_command(&ClientCommand::Search {
             kinks: vec![KinkId(523), KinkId(66)],
             genders: vec![Gender::Male, Gender::MaleHerm],
@@ -137,4 +137,2095 @@ fn test_string_integer() {
         StringInteger(621),
         from_str::<StringInteger>(r#""621""#).expect("Failed to deserialize from string")
     );
+    assert_eq!(StringInteger::from(621).to_string(), "621");
+    assert_eq!("621".parse::<StringInteger>(), Ok(StringInteger(621)));
+    assert!("six".parse::<StringInteger>().is_err());
+    assert_eq!(
+        "true"
+            .parse::<crate::util::StringBool>()
+            .unwrap()
+            .to_string(),
+        "true"
+    );
+}
+
+#[test]
+fn staff_alert_deserialize() {
+    use crate::data::{Character, StaffReport};
+    use crate::protocol::{parse_command, ServerCommand, StaffAlert};
+    assert_eq!(
+        parse_command(
+            r#"SFC {"action":"report","callid":1234,"character":"Someone","timestamp":"1662422400","report":"Current Tab/Channel: Frontpage | Reporting User: Someone | Help please","logid":4567}"#
+        ),
+        ServerCommand::Report(StaffAlert::Report(StaffReport {
+            callid: 1234,
+            character: Character("Someone".try_into().unwrap()),
+            timestamp: "1662422400".to_owned(),
+            report: "Current Tab/Channel: Frontpage | Reporting User: Someone | Help please"
+                .to_owned(),
+            logid: Some(4567),
+        }))
+    );
+    assert_eq!(
+        parse_command(
+            r#"SFC {"action":"confirm","moderator":"Kira","character":"Someone","timestamp":"1662422400"}"#
+        ),
+        ServerCommand::Report(StaffAlert::Confirm {
+            moderator: Character("Kira".try_into().unwrap()),
+            character: Character("Someone".try_into().unwrap()),
+            timestamp: "1662422400".to_owned(),
+            callid: None,
+        })
+    );
+}
+
+#[test]
+fn kink_mappings_resolve() {
+    use crate::http_endpoints::MappingListResponse;
+    use crate::kinks::KinkRegistry;
+    use crate::protocol::KinkId;
+    // Trimmed down from mapping-list.php
+    let mappings: MappingListResponse = serde_json::from_str(
+        r#"{"kinks":[{"description":"Cuddling and hugging.","group_id":"1","name":"Cuddling","id":"139"}],"kink_groups":[{"name":"General","id":"1"}],"infotags":[],"infotags_groups":[],"listitems":[],"error":""}"#,
+    )
+    .unwrap();
+    let mappings = KinkRegistry::from(mappings);
+    let kink = mappings.kink(KinkId(139)).unwrap();
+    assert_eq!(kink.id.name, "Cuddling");
+    assert_eq!(mappings.kink_id("cuddling"), Some(KinkId(139)));
+    assert_eq!(mappings.group_name(kink), Some("General"));
+    assert!(mappings.kink_by_name("Hugging").is_none());
+
+    let profile = std::collections::HashMap::from([
+        (
+            crate::util::StringInteger(139),
+            crate::data::KinkInterest::Fave,
+        ),
+        (
+            crate::util::StringInteger(4000),
+            crate::data::KinkInterest::No,
+        ),
+    ]);
+    let resolved = mappings.resolve_kinks(&profile);
+    assert_eq!(resolved.len(), 1);
+    assert_eq!(resolved[0].name, "Cuddling");
+    assert_eq!(resolved[0].group.as_deref(), Some("General"));
+    assert_eq!(resolved[0].interest, crate::data::KinkInterest::Fave);
+}
+
+#[test]
+fn kink_registry_search() {
+    use crate::data::KinkRegistry;
+    use crate::http_endpoints::MappingListResponse;
+    use crate::protocol::KinkId;
+    let mappings: MappingListResponse = serde_json::from_str(
+        r#"{"kinks":[{"description":"","group_id":"1","name":"Cuddling","id":"1"},
+            {"description":"","group_id":"2","name":"Female domination","id":"2"},
+            {"description":"","group_id":"2","name":"Domination","id":"3"},
+            {"description":"","group_id":"1","name":"Feet","id":"4"}],
+            "kink_groups":[{"name":"General","id":"1"},{"name":"Dominance","id":"2"}],
+            "infotags":[],"infotags_groups":[],"listitems":[],"error":""}"#,
+    )
+    .unwrap();
+    let registry = KinkRegistry::from(mappings);
+    let found = |query| {
+        registry
+            .search(query)
+            .into_iter()
+            .map(|(id, _)| id.0)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(found("domination"), vec![3, 2]);
+    assert_eq!(found("fem-dom"), vec![2]);
+    assert_eq!(found("cdlg"), vec![1]);
+    assert!(found("  ").is_empty());
+
+    assert_eq!(registry.groups(), vec![(2, "Dominance"), (1, "General")]);
+    assert_eq!(
+        registry
+            .group_kinks(1)
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>(),
+        vec![KinkId(1), KinkId(4)]
+    );
+    assert_eq!(registry.group_kinks(99).count(), 0);
+}
+
+#[test]
+fn profile_infotags_resolve() {
+    use crate::http_endpoints::MappingListResponse;
+    use crate::kinks::{InfotagValue, KinkRegistry};
+    let mappings: MappingListResponse = serde_json::from_str(
+        r#"{"kinks":[],"kink_groups":[],
+            "infotags":[{"group_id":"1","id":"1","list":"","name":"Age","type":"text"},
+                {"group_id":"1","id":"2","list":"orientation","name":"Orientation","type":"list"},
+                {"group_id":"1","id":"3","list":"","name":"Height","type":"number"}],
+            "infotags_groups":[{"name":"General details","id":"1"}],
+            "listitems":[{"id":"7","name":"orientation","value":"Straight"}],"error":""}"#,
+    )
+    .unwrap();
+    let mappings = KinkRegistry::from(mappings);
+    assert_eq!(
+        mappings.infotag_group_name(mappings.infotag(2).unwrap()),
+        Some("General details")
+    );
+    #[derive(serde::Deserialize)]
+    struct Infotags {
+        #[serde(deserialize_with = "crate::http_endpoints::infotag_map")]
+        infotags: std::collections::HashMap<crate::util::StringInteger, String>,
+    }
+    let profile: Infotags =
+        serde_json::from_str(r#"{"infotags": {"3": 180, "1": "Ancient", "2": "7", "99": "?"}}"#)
+            .unwrap();
+    assert_eq!(
+        mappings.resolve_infotags(&profile.infotags),
+        vec![
+            ("Age", InfotagValue::Text("Ancient".to_owned())),
+            ("Orientation", InfotagValue::List("Straight".to_owned())),
+            ("Height", InfotagValue::Number(180.0)),
+        ]
+    );
+    let empty: Infotags = serde_json::from_str(r#"{"infotags": []}"#).unwrap();
+    assert!(empty.infotags.is_empty());
+
+    let update = crate::client::profile_update(&mappings, &profile.infotags, 1_700_000_000);
+    assert_eq!(
+        update.orientation,
+        Some(Some(crate::data::Orientation::Straight))
+    );
+    assert_eq!(update.role, Some(None));
+    assert_eq!(update.languages, Some(vec![]));
+    assert_eq!(
+        update.last_profile_update.map(|at| at.timestamp()),
+        Some(1_700_000_000)
+    );
+}
+
+#[test]
+fn highlighter_matches() {
+    use crate::data::Character;
+    use crate::highlight::{HighlightMatch, Highlighter};
+    let own = Character("Ann".try_into().unwrap());
+    let highlighter = Highlighter::new()
+        .with_keyword("Dragons")
+        .with_pattern(regex::Regex::new(r"\bscal(e|y)\b").unwrap());
+    assert_eq!(
+        highlighter.check(&own, "hey ann, you around?"),
+        Some(HighlightMatch::Name(own))
+    );
+    assert_eq!(highlighter.check(&own, "Big announcement!"), None);
+    assert_eq!(
+        highlighter.check(&own, "Looking for dragons."),
+        Some(HighlightMatch::Keyword("dragons".to_owned()))
+    );
+    assert_eq!(
+        highlighter.check(&own, "very scaly"),
+        Some(HighlightMatch::Pattern(r"\bscal(e|y)\b".to_owned()))
+    );
+}
+
+#[test]
+fn split_long_messages() {
+    use crate::util::split_message;
+    assert_eq!(
+        split_message("the quick brown fox jumps", 10),
+        vec!["the quick", "brown fox", "jumps"]
+    );
+    assert_eq!(split_message("short", 10), vec!["short"]);
+    // No whitespace to break on, and multi-byte characters can't be cut in half.
+    assert_eq!(split_message("ééééé", 4), vec!["éé", "éé", "é"]);
+}
+
+#[test]
+fn permissions_variable_deserialize() {
+    use crate::protocol::{parse_command, ServerCommand, Variable};
+    use crate::util::StringInteger;
+    assert_eq!(
+        parse_command(r#"VAR {"value":2,"variable":"permissions"}"#),
+        ServerCommand::Variable(Variable::Permissions(StringInteger(2)))
+    );
+}
+
+#[test]
+fn retry_backoff_is_capped() {
+    use crate::retry::RetryPolicy;
+    use std::time::Duration;
+    let policy = RetryPolicy::new(5, Duration::from_millis(500), Duration::from_secs(3));
+    assert_eq!(policy.backoff(0), Duration::from_millis(500));
+    assert_eq!(policy.backoff(2), Duration::from_secs(2));
+    assert_eq!(policy.backoff(3), Duration::from_secs(3));
+    assert_eq!(policy.backoff(40), Duration::from_secs(3));
+}
+
+#[test]
+fn snapshot_round_trip() {
+    use crate::client::{ClientSnapshot, SessionSnapshot};
+    use crate::data::{Channel, Character, Status};
+    let snapshot = ClientSnapshot {
+        sessions: vec![SessionSnapshot {
+            character: Character("Some Bot".try_into().unwrap()),
+            channels: vec![Channel("Frontpage".into())],
+        }],
+        status: Some((Status::Busy, "Restarting".to_owned())),
+    };
+    let json = serde_json::to_string(&snapshot).unwrap();
+    assert_eq!(serde_json::from_str::<ClientSnapshot>(&json).unwrap(), snapshot);
+}
+
+#[test]
+fn memory_cache_change_detection() {
+    use crate::cache::{Cache, MemoryCache, PartialUserData};
+    use crate::data::{Channel, Character, Status};
+    use std::borrow::Cow;
+    let cache = MemoryCache::new();
+    let channel = Channel("Frontpage".into());
+    let someone = Character("Someone".try_into().unwrap());
+
+    assert!(cache.add_channel_member(Cow::Borrowed(&channel), someone).unwrap());
+    assert!(!cache.add_channel_member(Cow::Borrowed(&channel), someone).unwrap());
+    assert_eq!(cache.get_channel(&channel).unwrap().unwrap().members, vec![someone]);
+
+    let update = || PartialUserData {
+        status: Some(Status::Busy),
+        ..Default::default()
+    };
+    assert!(cache.update_character(Cow::Borrowed(&someone), update()).unwrap());
+    assert!(!cache.update_character(Cow::Borrowed(&someone), update()).unwrap());
+
+    assert!(cache.set_bookmarks(Cow::Owned(vec![someone])).unwrap());
+    assert!(!cache.set_bookmarks(Cow::Owned(vec![someone])).unwrap());
+    assert!(cache.remove_bookmark(Cow::Borrowed(&someone)).unwrap());
+}
+
+#[test]
+fn channel_data_extras() {
+    use crate::cache::{Cache, MemoryCache, PartialChannelData};
+    use crate::data::{Channel, ChannelMode, Character, Message, MessageChannel, MessageContent};
+    use chrono::{TimeZone, Utc};
+    use std::borrow::Cow;
+    let cache = MemoryCache::new();
+    let channel = Channel("Frontpage".into());
+    let someone = Character("Someone".try_into().unwrap());
+    let listed = |count| PartialChannelData {
+        mode: Some(ChannelMode::ChatOnly),
+        member_count: Some(count),
+        ..Default::default()
+    };
+
+    // Not in it; the count comes from the channel list.
+    assert!(
+        cache
+            .update_channel(Cow::Borrowed(&channel), listed(40))
+            .unwrap()
+    );
+    assert!(
+        !cache
+            .update_channel(Cow::Borrowed(&channel), listed(40))
+            .unwrap()
+    );
+    let data = cache.get_channel(&channel).unwrap().unwrap();
+    assert_eq!(data.member_count, 40);
+    assert!(!data.ads_allowed);
+    assert_eq!(data.last_activity, None);
+
+    cache
+        .add_channel_member(Cow::Borrowed(&channel), someone)
+        .unwrap();
+    cache
+        .set_channel_ops(Cow::Borrowed(&channel), Cow::Owned(vec![someone]))
+        .unwrap();
+    let then = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+    let earlier = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+    for timestamp in [then, earlier] {
+        let message = Message {
+            timestamp,
+            character: someone,
+            content: MessageContent::Message("Hi".into()),
+        };
+        cache
+            .insert_message(MessageChannel::Channel(channel.clone()), message)
+            .unwrap();
+    }
+    let data = cache.get_channel(&channel).unwrap().unwrap();
+    assert_eq!(data.member_count, 1);
+    assert_eq!(data.ops, vec![someone]);
+    assert_eq!(data.last_activity, Some(then));
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn sqlite_cache_round_trip() {
+    use crate::cache::{Cache, PartialChannelData, SqliteCache};
+    use crate::data::{Channel, ChannelMode, Character, Message, MessageChannel, MessageContent};
+    use std::borrow::Cow;
+    let cache = SqliteCache::open_in_memory().unwrap();
+    let channel = Channel("Frontpage".into());
+    let someone = Character("Someone".try_into().unwrap());
+
+    assert!(cache
+        .insert_channel(
+            Cow::Borrowed(&channel),
+            PartialChannelData {
+                mode: Some(ChannelMode::ChatOnly),
+                ..Default::default()
+            },
+            Cow::Owned(vec![someone]),
+        )
+        .unwrap());
+    assert!(!cache.add_channel_member(Cow::Borrowed(&channel), someone).unwrap());
+    let data = cache.get_channel(&channel).unwrap().unwrap();
+    assert_eq!(data.channel_mode, ChannelMode::ChatOnly);
+    assert_eq!(data.members, vec![someone]);
+    assert_eq!(data.member_count, 1);
+    assert!(!data.ads_allowed);
+
+    let typing = crate::cache::PartialUserData {
+        typing: Some(crate::data::TypingStatus::Typing),
+        ..Default::default()
+    };
+    assert!(
+        cache
+            .update_character(Cow::Borrowed(&someone), typing.clone())
+            .unwrap()
+    );
+    assert!(
+        !cache
+            .update_character(Cow::Borrowed(&someone), typing)
+            .unwrap()
+    );
+    assert_eq!(
+        cache.get_character(&someone).unwrap().unwrap().typing,
+        crate::data::TypingStatus::Typing
+    );
+    let profile = crate::cache::PartialUserData {
+        orientation: Some(Some(crate::data::Orientation::Pansexual)),
+        languages: Some(vec![crate::data::Language::German]),
+        ..Default::default()
+    };
+    assert!(
+        cache
+            .update_character(Cow::Borrowed(&someone), profile)
+            .unwrap()
+    );
+    let data = cache.get_characters().unwrap()[0].clone();
+    assert_eq!(data.orientation, Some(crate::data::Orientation::Pansexual));
+    assert_eq!(data.languages, vec![crate::data::Language::German]);
+    assert_eq!(data.role, None);
+
+    let friend = crate::data::FriendRelation {
+        own_character: someone,
+        other_character: Character("Friend".try_into().unwrap()),
+        last_online: Some(chrono::TimeZone::timestamp_opt(&chrono::Utc, 1_700_000_000, 0).unwrap()),
+    };
+    assert!(cache.set_friends(Cow::Owned(vec![friend])).unwrap());
+    assert!(!cache.set_friends(Cow::Owned(vec![friend])).unwrap());
+    assert_eq!(cache.get_friend_relations().unwrap()[..], [friend]);
+
+    let source = MessageChannel::Channel(channel.clone());
+    for text in ["one", "two", "three"] {
+        cache
+            .insert_message(
+                source.clone(),
+                Message {
+                    timestamp: chrono::Utc::now(),
+                    character: someone,
+                    content: MessageContent::Message(text.into()),
+                },
+            )
+            .unwrap();
+    }
+    let recent = cache.get_messages(&source, None, Some(2)).unwrap();
+    assert_eq!(
+        recent.iter().map(|m| m.content.clone()).collect::<Vec<_>>(),
+        vec![
+            MessageContent::Message("two".into()),
+            MessageContent::Message("three".into())
+        ]
+    );
+
+    // The mapping list goes in as JSON, with the API's string-or-number IDs.
+    let mapping: crate::http_endpoints::MappingListResponse = serde_json::from_str(
+        r#"{"kinks": [{"name": "Cuddling", "id": "1", "description": "", "group_id": 2}],
+            "kink_groups": [{"name": "General", "id": 2}],
+            "infotags": [], "infotags_groups": [], "listitems": []}"#,
+    )
+    .unwrap();
+    let fetched_at = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+    cache
+        .set_kink_mapping(crate::cache::CachedKinkMapping {
+            mapping: mapping.into(),
+            fetched_at,
+        })
+        .unwrap();
+    let cached = cache.get_kink_mapping().unwrap().unwrap();
+    assert_eq!(cached.fetched_at, fetched_at);
+    assert_eq!(cached.mapping.kinks[0].id.name, "Cuddling");
+    assert_eq!(cached.mapping.kinks[0].group_id.0, 2);
+
+    let cache = SqliteCache::open_in_memory()
+        .unwrap()
+        .with_retention(crate::cache::Retention::max_messages(1));
+    for text in ["one", "two"] {
+        cache
+            .insert_message(
+                source.clone(),
+                Message {
+                    timestamp: chrono::Utc::now(),
+                    character: someone,
+                    content: MessageContent::Message(text.into()),
+                },
+            )
+            .unwrap();
+    }
+    let kept = cache.get_messages(&source, None, None).unwrap();
+    assert_eq!(kept.len(), 1);
+    assert_eq!(kept[0].content, MessageContent::Message("two".into()));
+
+    // Batches nest, and writes inside one (which use savepoints of their own) still land.
+    cache.begin_batch().unwrap();
+    cache.begin_batch().unwrap();
+    assert!(cache
+        .add_channel_member(Cow::Borrowed(&channel), someone)
+        .unwrap());
+    cache.commit_batch().unwrap();
+    cache.commit_batch().unwrap();
+    assert_eq!(
+        cache.get_channel_members(&channel).unwrap().into_owned(),
+        vec![someone]
+    );
+
+    let me = Character("Me".try_into().unwrap());
+    let pm = Message {
+        timestamp: chrono::Utc::now(),
+        character: someone,
+        content: MessageContent::Message("hi".into()),
+    };
+    cache
+        .insert_message(MessageChannel::PrivateMessage(me, someone), pm.clone())
+        .unwrap();
+    cache
+        .insert_message(MessageChannel::PrivateMessage(someone, me), pm)
+        .unwrap();
+    assert_eq!(
+        cache.get_pm_partners(&me).unwrap().into_owned(),
+        vec![someone]
+    );
+    assert_eq!(
+        cache
+            .get_private_messages(&me, &someone, None, None)
+            .unwrap()
+            .len(),
+        1
+    );
+
+    // Already trimmed on the way in.
+    assert!(!cache.compact().unwrap());
+    assert!(cache.purge_character(Cow::Borrowed(&someone)).unwrap());
+    assert!(cache.get_pm_partners(&me).unwrap().is_empty());
+    assert!(cache.get_messages(&source, None, None).unwrap().is_empty());
+    assert!(!cache.purge_character(Cow::Borrowed(&someone)).unwrap());
+}
+
+// Needs a server to talk to, so it only runs with FCHAT_TEST_POSTGRES set to the URL of a
+// throwaway database. Whatever's cached there is wiped.
+#[cfg(feature = "postgres")]
+#[tokio::test(flavor = "multi_thread")]
+async fn postgres_cache_round_trip() {
+    use crate::cache::{Cache, PartialChannelData, PostgresCache};
+    use crate::data::{Channel, ChannelMode, Character, Message, MessageChannel, MessageContent};
+    use std::borrow::Cow;
+    let Ok(url) = std::env::var("FCHAT_TEST_POSTGRES") else {
+        return;
+    };
+    let cache = PostgresCache::connect(&url)
+        .await
+        .unwrap()
+        .with_retention(crate::cache::Retention::max_messages(2));
+    sqlx::query("TRUNCATE channels, channel_members, messages, channel_bans")
+        .execute(cache.pool())
+        .await
+        .unwrap();
+    let channel = Channel("Frontpage".into());
+    let someone = Character("Someone".try_into().unwrap());
+
+    assert!(
+        cache
+            .insert_channel(
+                Cow::Borrowed(&channel),
+                PartialChannelData {
+                    mode: Some(ChannelMode::ChatOnly),
+                    ..Default::default()
+                },
+                Cow::Owned(vec![someone]),
+            )
+            .unwrap()
+    );
+    assert!(
+        !cache
+            .add_channel_member(Cow::Borrowed(&channel), someone)
+            .unwrap()
+    );
+    let data = cache.get_channel(&channel).unwrap().unwrap();
+    assert_eq!(data.channel_mode, ChannelMode::ChatOnly);
+    assert_eq!(data.members, vec![someone]);
+
+    let source = MessageChannel::Channel(channel.clone());
+    for text in ["one", "two", "three"] {
+        cache
+            .insert_message(
+                source.clone(),
+                Message {
+                    timestamp: chrono::Utc::now(),
+                    character: someone,
+                    content: MessageContent::Message(text.into()),
+                },
+            )
+            .unwrap();
+    }
+    let kept = cache.get_messages(&source, None, None).unwrap();
+    assert_eq!(
+        kept.iter().map(|m| m.content.clone()).collect::<Vec<_>>(),
+        vec![
+            MessageContent::Message("two".into()),
+            MessageContent::Message("three".into())
+        ]
+    );
+
+    // Timeouts survive the trip through TIMESTAMPTZ without looking changed.
+    let expires = chrono::Utc::now() + chrono::Duration::minutes(5);
+    assert!(
+        cache
+            .add_channel_timeout(Cow::Borrowed(&channel), Cow::Borrowed(&someone), expires)
+            .unwrap()
+    );
+    assert!(
+        !cache
+            .add_channel_timeout(Cow::Borrowed(&channel), Cow::Borrowed(&someone), expires)
+            .unwrap()
+    );
+    assert!(
+        !cache
+            .set_channel_bans(Cow::Borrowed(&channel), Cow::Owned(vec![someone]))
+            .unwrap()
+    );
+    assert!(!cache.compact().unwrap());
+    assert!(cache.purge_character(Cow::Borrowed(&someone)).unwrap());
+    assert!(cache.get_messages(&source, None, None).unwrap().is_empty());
+
+    // Two processes' worth of writers at once: every member gets a position of their own,
+    // and the same ban only counts as new the once.
+    let other = PostgresCache::connect(&url).await.unwrap();
+    let crowd = Channel("Crowd".into());
+    let new_bans = std::thread::scope(|scope| {
+        let writers = (0..16)
+            .map(|i| {
+                let cache = if i % 2 == 0 { &cache } else { &other };
+                let crowd = &crowd;
+                scope.spawn(move || {
+                    let member = Character(format!("Member {i}").as_str().try_into().unwrap());
+                    cache
+                        .add_channel_member(Cow::Borrowed(crowd), member)
+                        .unwrap();
+                    cache
+                        .add_channel_ban(Cow::Borrowed(crowd), Cow::Borrowed(&someone))
+                        .unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+        writers
+            .into_iter()
+            .map(|writer| writer.join().unwrap())
+            .filter(|added| *added)
+            .count()
+    });
+    assert_eq!(new_bans, 1);
+    let positions: i64 = sqlx::query_scalar(
+        "SELECT COUNT(DISTINCT position) FROM channel_members WHERE channel = 'Crowd'",
+    )
+    .fetch_one(cache.pool())
+    .await
+    .unwrap();
+    assert_eq!(positions, 16);
+}
+
+#[test]
+fn channel_bans_from_banlist() {
+    use crate::cache::{Cache, MemoryCache};
+    use crate::client::parse_banlist;
+    use crate::data::{Channel, Character};
+    use std::borrow::Cow;
+
+    assert_eq!(parse_banlist("Someone has been banned."), None);
+    let bans = parse_banlist("Channel bans for Frontpage: Someone, Someone Else").unwrap();
+    assert_eq!(
+        bans,
+        vec![
+            Character("Someone".try_into().unwrap()),
+            Character("Someone Else".try_into().unwrap())
+        ]
+    );
+
+    let cache = MemoryCache::new();
+    let channel = Channel("Frontpage".into());
+    assert!(cache
+        .set_channel_bans(Cow::Borrowed(&channel), Cow::Owned(bans))
+        .unwrap());
+    let expires = chrono::Utc::now() + chrono::Duration::minutes(5);
+    assert!(
+        cache
+            .add_channel_timeout(
+                Cow::Borrowed(&channel),
+                Cow::Owned(Character("Third".try_into().unwrap())),
+                expires
+            )
+            .unwrap()
+    );
+    assert!(
+        cache
+            .remove_channel_ban(
+                Cow::Borrowed(&channel),
+                Cow::Owned(Character("Someone".try_into().unwrap()))
+            )
+            .unwrap()
+    );
+    let bans = cache.get_channel_bans(&channel).unwrap();
+    assert_eq!(bans.len(), 2);
+    assert!(bans.iter().all(|ban| ban.in_effect(chrono::Utc::now())));
+}
+
+#[test]
+fn memory_cache_retention() {
+    use crate::cache::{Cache, MemoryCache, Retention};
+    use crate::data::{Channel, Character, Message, MessageChannel, MessageContent};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    let evicted = Arc::new(Mutex::new(Vec::new()));
+    let hook = evicted.clone();
+    let pms = MessageChannel::PrivateMessage(
+        Character("Me".try_into().unwrap()),
+        Character("You".try_into().unwrap()),
+    );
+    let cache = MemoryCache::new()
+        .with_retention(Retention::max_messages(2).with_max_age(Duration::from_secs(3600)))
+        .with_source_retention(pms.clone(), Retention::FOREVER)
+        .with_eviction_hook(move |_, messages: Vec<Message>| hook.lock().unwrap().extend(messages));
+
+    let channel = MessageChannel::Channel(Channel("Frontpage".into()));
+    let message = |text: &str, hours_ago| Message {
+        timestamp: chrono::Utc::now() - chrono::Duration::hours(hours_ago),
+        character: Character("Someone".try_into().unwrap()),
+        content: MessageContent::Message(text.into()),
+    };
+    for source in [channel.clone(), pms.clone()] {
+        cache
+            .insert_message(source.clone(), message("ancient", 2))
+            .unwrap();
+        for text in ["one", "two", "three"] {
+            cache
+                .insert_message(source.clone(), message(text, 0))
+                .unwrap();
+        }
+    }
+
+    assert_eq!(cache.get_messages(&channel, None, None).unwrap().len(), 2);
+    assert_eq!(cache.get_messages(&pms, None, None).unwrap().len(), 4);
+    let evicted = evicted.lock().unwrap();
+    assert_eq!(
+        evicted
+            .iter()
+            .map(|m| m.content.clone())
+            .collect::<Vec<_>>(),
+        vec![
+            MessageContent::Message("ancient".into()),
+            MessageContent::Message("one".into())
+        ]
+    );
+}
+
+#[test]
+fn rising_log_record() {
+    use crate::data::{Character, Message, MessageContent};
+    use crate::export::encode_message;
+    use chrono::TimeZone;
+
+    let record = encode_message(&Message {
+        timestamp: chrono::Utc.timestamp_opt(1_600_000_000, 0).unwrap(),
+        character: Character("Ann".try_into().unwrap()),
+        content: MessageContent::Emote("waves".into()),
+    });
+    let mut expected = 1_600_000_000u32.to_le_bytes().to_vec();
+    expected.extend_from_slice(&[1, 3]);
+    expected.extend_from_slice(b"Ann");
+    expected.extend_from_slice(&5u16.to_le_bytes());
+    expected.extend_from_slice(b"waves");
+    expected.extend_from_slice(&16u16.to_le_bytes()); // Where the trailer starts
+    assert_eq!(record, expected);
+
+    let ad = encode_message(&Message {
+        timestamp: chrono::Utc.timestamp_opt(1_600_000_000, 0).unwrap(),
+        character: Character("Ann".try_into().unwrap()),
+        content: MessageContent::Ad("LF RP".into()),
+    });
+    assert_eq!(ad[4], 2);
+    assert_eq!(
+        serde_json::to_string(&MessageContent::System("Hi".into())).unwrap(),
+        r#"{"type":"system","content":"Hi"}"#
+    );
+}
+
+#[test]
+fn friend_last_online() {
+    use crate::cache::{Cache, MemoryCache};
+    use crate::http_endpoints::Friend;
+    use chrono::{TimeZone, Utc};
+    use std::borrow::Cow;
+    let friends: Vec<Friend> = serde_json::from_str(
+        r#"[{"dest":"Me","source":"Them","last_online":90000},
+            {"dest":"Me","source":"Other"},
+            {"dest":"Me","source":"Ancient","last_online":18446744073709551615},
+            {"dest":"Me","source":"Elder","last_online":9000000000000000}]"#,
+    )
+    .unwrap();
+    let received = Utc.timestamp_opt(1_700_000_030, 0).unwrap();
+    let relations = friends
+        .iter()
+        .map(|friend| friend.relation(received))
+        .collect::<Vec<_>>();
+    // A day and an hour back, to the minute.
+    assert_eq!(
+        relations[0].last_online,
+        Some(Utc.timestamp_opt(1_699_909_980, 0).unwrap())
+    );
+    assert_eq!(relations[1].last_online, None);
+    // Too big for an i64, and too far back for a date: nonsense, not a crash.
+    assert_eq!(relations[2].last_online, None);
+    assert_eq!(relations[3].last_online, None);
+
+    let cache = MemoryCache::new();
+    assert!(cache.set_friends(Cow::Borrowed(&relations)).unwrap());
+    assert!(!cache.set_friends(Cow::Borrowed(&relations)).unwrap());
+}
+
+#[test]
+fn update_diff_returns_previous() {
+    use crate::cache::{Cache, MemoryCache, NoCache, PartialUserData, Updated};
+    use crate::data::{Character, Status};
+    use std::borrow::Cow;
+
+    let someone = Character("Someone".try_into().unwrap());
+    let status = |status| PartialUserData {
+        status: Some(status),
+        ..Default::default()
+    };
+    let cache = MemoryCache::new();
+    assert_eq!(
+        cache
+            .update_character_diff(Cow::Owned(someone), status(Status::Looking))
+            .unwrap(),
+        Updated::Changed(None)
+    );
+    let Updated::Changed(Some(previous)) = cache
+        .update_character_diff(Cow::Owned(someone), status(Status::Busy))
+        .unwrap()
+    else {
+        panic!("Expected the previous data");
+    };
+    assert_eq!(previous.status, Status::Looking);
+    assert!(
+        !cache
+            .update_character_diff(Cow::Owned(someone), status(Status::Busy))
+            .unwrap()
+            .is_changed()
+    );
+
+    // The default implementation, through NoCache, knows nothing but still reports the change.
+    assert_eq!(
+        NoCache
+            .update_character_diff(Cow::Owned(someone), status(Status::Busy))
+            .unwrap(),
+        Updated::Changed(None)
+    );
+}
+
+#[test]
+fn pm_history_is_per_own_character() {
+    use crate::cache::{Cache, MemoryCache};
+    use crate::data::{Character, Message, MessageChannel, MessageContent};
+
+    let cache = MemoryCache::new();
+    let (alt, main, friend) = (
+        Character("Alt".try_into().unwrap()),
+        Character("Main".try_into().unwrap()),
+        Character("Friend".try_into().unwrap()),
+    );
+    let message = |text: &str| Message {
+        timestamp: chrono::Utc::now(),
+        character: friend,
+        content: MessageContent::Message(text.into()),
+    };
+    cache
+        .insert_message(
+            MessageChannel::PrivateMessage(main, friend),
+            message("to main"),
+        )
+        .unwrap();
+    cache
+        .insert_message(
+            MessageChannel::PrivateMessage(alt, friend),
+            message("to alt"),
+        )
+        .unwrap();
+
+    assert_eq!(
+        cache.get_pm_partners(&main).unwrap().into_owned(),
+        vec![friend]
+    );
+    let history = cache
+        .get_private_messages(&alt, &friend, None, None)
+        .unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].content, MessageContent::Message("to alt".into()));
+}
+
+#[test]
+fn memory_cache_character_limit() {
+    use crate::cache::{Cache, MemoryCache, PartialUserData};
+    use crate::data::Character;
+    use std::borrow::Cow;
+
+    let cache = MemoryCache::new().with_character_limit(10);
+    let name = |i: usize| Character(format!("Someone {i}").try_into().unwrap());
+    let bookmark = name(0);
+    cache.add_bookmark(Cow::Owned(bookmark)).unwrap();
+    for i in 0..10 {
+        cache
+            .update_character(Cow::Owned(name(i)), PartialUserData::default())
+            .unwrap();
+    }
+    // Used recently, so it outlives the others.
+    cache.get_character(&name(1)).unwrap().unwrap();
+    cache
+        .update_character(Cow::Owned(name(10)), PartialUserData::default())
+        .unwrap();
+
+    assert_eq!(cache.get_characters().unwrap().len(), 9);
+    assert!(cache.get_character(&bookmark).unwrap().is_some());
+    assert!(cache.get_character(&name(1)).unwrap().is_some());
+    assert!(cache.get_character(&name(10)).unwrap().is_some());
+    assert!(cache.get_character(&name(2)).unwrap().is_none());
+}
+
+#[test]
+fn channel_message_dedup() {
+    use crate::client::MessageDedup;
+    use crate::data::{Channel, Character};
+
+    let dedup = MessageDedup::default();
+    let (first, second) = (
+        Character("First".try_into().unwrap()),
+        Character("Second".try_into().unwrap()),
+    );
+    let channel = Channel("Frontpage".into());
+    let someone = Character("Someone".try_into().unwrap());
+
+    // Both our characters see the line; only the first copy counts.
+    assert!(dedup.first_sighting(first, channel.clone(), someone, "hello"));
+    assert!(!dedup.first_sighting(second, channel.clone(), someone, "hello"));
+    // Saying it again is a new message, whichever session gets there first.
+    assert!(dedup.first_sighting(second, channel.clone(), someone, "hello"));
+    assert!(!dedup.first_sighting(first, channel.clone(), someone, "hello"));
+    assert!(dedup.first_sighting(first, channel, someone, "goodbye"));
+}
+
+#[test]
+fn layered_cache_falls_through() {
+    use crate::cache::{Cache, LayeredCache, MemoryCache, PartialUserData};
+    use crate::data::{Channel, Character, Message, MessageChannel, MessageContent, Status};
+    use std::borrow::Cow;
+
+    let someone = Character("Someone".try_into().unwrap());
+    let source = MessageChannel::Channel(Channel("Frontpage".into()));
+    let cache = LayeredCache::new(MemoryCache::new(), MemoryCache::new());
+    cache
+        .insert_message(
+            source.clone(),
+            Message {
+                timestamp: chrono::Utc::now(),
+                character: someone,
+                content: MessageContent::Message("hello".into()),
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        cache
+            .front()
+            .get_messages(&source, None, None)
+            .unwrap()
+            .len(),
+        1
+    );
+
+    // Like a restart: a fresh front over the same back still finds the history.
+    let (_, back) = cache.into_inner();
+    let cache = LayeredCache::new(MemoryCache::new(), back);
+    assert_eq!(cache.get_messages(&source, None, None).unwrap().len(), 1);
+
+    // Change detection is the front's, so the first sighting after a restart counts.
+    let busy = || PartialUserData {
+        status: Some(Status::Busy),
+        ..Default::default()
+    };
+    assert!(cache.update_character(Cow::Owned(someone), busy()).unwrap());
+    assert!(!cache.update_character(Cow::Owned(someone), busy()).unwrap());
+}
+
+#[test]
+fn json_file_cache_survives_reopen() {
+    use crate::cache::{Cache, JsonFileCache};
+    use crate::data::{Channel, Character, Message, MessageChannel, MessageContent};
+    use std::borrow::Cow;
+    let path = std::env::temp_dir().join(format!("f-chat-rs-cache-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let channel = Channel("Frontpage".into());
+    let someone = Character("Someone".try_into().unwrap());
+    let source = MessageChannel::Channel(channel.clone());
+    let expires = chrono::Utc::now() + chrono::Duration::minutes(5);
+
+    let cache = JsonFileCache::open(&path).unwrap();
+    cache
+        .insert_message(
+            source.clone(),
+            Message {
+                timestamp: chrono::Utc::now(),
+                character: someone,
+                content: MessageContent::Message("hello".into()),
+            },
+        )
+        .unwrap();
+    cache.add_bookmark(Cow::Borrowed(&someone)).unwrap();
+    cache
+        .add_channel_timeout(Cow::Borrowed(&channel), Cow::Borrowed(&someone), expires)
+        .unwrap();
+    // Well inside the default interval, so nothing's on disk until it's flushed.
+    assert!(!path.exists());
+    drop(cache);
+
+    let cache = JsonFileCache::open(&path).unwrap();
+    let messages = cache.get_messages(&source, None, None).unwrap();
+    assert_eq!(messages[0].content, MessageContent::Message("hello".into()));
+    assert_eq!(cache.get_bookmarks().unwrap().into_owned(), vec![someone]);
+    let bans = cache.get_channel_bans(&channel).unwrap();
+    assert_eq!(
+        bans[0].expires.map(|at| at.timestamp()),
+        Some(expires.timestamp())
+    );
+    drop(cache);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn json_file_cache_flushes_on_tick() {
+    use crate::cache::{Cache, JsonFileCache};
+    use crate::data::Character;
+    use std::borrow::Cow;
+    use std::time::Duration;
+    let path = std::env::temp_dir().join(format!("f-chat-rs-tick-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let cache = JsonFileCache::open(&path)
+        .unwrap()
+        .with_flush_interval(Duration::from_millis(200));
+    cache
+        .add_bookmark(Cow::Owned(Character("Someone".try_into().unwrap())))
+        .unwrap();
+    // The change itself doesn't write anything, and neither does a tick before the interval's up.
+    cache.tick().unwrap();
+    assert!(!path.exists());
+    // Once it is, the next tick writes it out, with no more changes needed to get it there.
+    std::thread::sleep(Duration::from_millis(250));
+    cache.tick().unwrap();
+    assert!(path.exists());
+    drop(cache);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn purge_character_forgets_them() {
+    use crate::cache::{Cache, MemoryCache};
+    use crate::data::{Channel, Character, Message, MessageChannel, MessageContent};
+    use std::borrow::Cow;
+    let cache = MemoryCache::new();
+    let me = Character("Me".try_into().unwrap());
+    let someone = Character("Someone".try_into().unwrap());
+    let bystander = Character("Bystander".try_into().unwrap());
+    let channel = Channel("Frontpage".into());
+    let said = |character, text: &str| Message {
+        timestamp: chrono::Utc::now(),
+        character,
+        content: MessageContent::Message(text.into()),
+    };
+    let source = MessageChannel::Channel(channel.clone());
+    cache
+        .insert_message(source.clone(), said(someone, "hi"))
+        .unwrap();
+    cache
+        .insert_message(source.clone(), said(bystander, "hey"))
+        .unwrap();
+    cache
+        .insert_message(
+            MessageChannel::PrivateMessage(me, someone),
+            said(me, "psst"),
+        )
+        .unwrap();
+    cache
+        .insert_ad(
+            Cow::Borrowed(&channel),
+            Cow::Borrowed(&someone),
+            Cow::Borrowed("LF roleplay"),
+            chrono::Utc::now(),
+        )
+        .unwrap();
+    cache
+        .set_memo(Cow::Borrowed(&someone), Cow::Borrowed("Met at the fair"))
+        .unwrap();
+
+    assert!(cache.purge_character(Cow::Borrowed(&someone)).unwrap());
+    let left = cache.get_messages(&source, None, None).unwrap();
+    assert_eq!(left.len(), 1);
+    assert_eq!(left[0].character, bystander);
+    assert!(cache.get_pm_partners(&me).unwrap().is_empty());
+    assert!(cache.get_ads(&channel, None).unwrap().is_empty());
+    assert_eq!(cache.get_memo(&someone).unwrap(), None);
+    assert!(!cache.purge_character(Cow::Borrowed(&someone)).unwrap());
+}
+
+#[test]
+fn class_retention_and_compaction() {
+    use crate::cache::{Cache, MemoryCache, Retention, RetentionClass};
+    use crate::data::{Channel, Character, Message, MessageChannel, MessageContent};
+    use std::{borrow::Cow, time::Duration};
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+    let me = Character("Me".try_into().unwrap());
+    let someone = Character("Someone".try_into().unwrap());
+    let channel = Channel("Frontpage".into());
+    let pm = MessageChannel::PrivateMessage(me, someone);
+    let public = MessageChannel::Channel(channel.clone());
+    let days_ago = |days| Message {
+        timestamp: chrono::Utc::now() - chrono::Duration::days(days),
+        character: someone,
+        content: MessageContent::Message(format!("{days} days ago").into()),
+    };
+
+    // Everything from before the limits were set.
+    let old = MemoryCache::new();
+    for days in [30, 0] {
+        old.insert_message(pm.clone(), days_ago(days)).unwrap();
+        old.insert_message(public.clone(), days_ago(days)).unwrap();
+    }
+    old.insert_ad(
+        Cow::Borrowed(&channel),
+        Cow::Borrowed(&someone),
+        Cow::Borrowed("LF roleplay"),
+        chrono::Utc::now() - chrono::Duration::days(2),
+    )
+    .unwrap();
+
+    let cache = MemoryCache::new()
+        .with_retention(Retention::max_messages(1))
+        .with_class_retention(RetentionClass::PrivateMessage, Retention::FOREVER)
+        .with_class_retention(RetentionClass::Channel, Retention::max_age(7 * DAY))
+        .with_ad_retention(DAY);
+    cache.restore(old.state());
+    // Expired ads aren't handed out, even before they're compacted away.
+    assert!(cache.get_ads(&channel, None).unwrap().is_empty());
+    assert!(cache.compact().unwrap());
+    assert_eq!(cache.get_messages(&pm, None, None).unwrap().len(), 2);
+    let kept = cache.get_messages(&public, None, None).unwrap();
+    assert_eq!(kept.len(), 1);
+    assert_eq!(
+        kept[0].content,
+        MessageContent::Message("0 days ago".into())
+    );
+    assert!(cache.get_ads(&channel, None).unwrap().is_empty());
+    assert!(!cache.compact().unwrap());
+}
+
+#[test]
+fn observed_cache_reports_operations() {
+    use crate::cache::{Cache, MemoryCache, ObservedCache};
+    use crate::data::Character;
+    use std::borrow::Cow;
+    use std::sync::{Arc, Mutex};
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let cache = ObservedCache::new(MemoryCache::new(), {
+        let seen = seen.clone();
+        move |name, _elapsed, failed| seen.lock().unwrap().push((name, failed))
+    });
+    let someone = Character("Someone".try_into().unwrap());
+    cache.add_bookmark(Cow::Borrowed(&someone)).unwrap();
+    assert_eq!(cache.get_bookmarks().unwrap().into_owned(), vec![someone]);
+    assert_eq!(
+        *seen.lock().unwrap(),
+        vec![("add_bookmark", false), ("get_bookmarks", false)]
+    );
+}
+
+#[test]
+fn character_list_deserialize() {
+    use crate::data::{Character, CharacterId};
+    use crate::http_endpoints::{CharacterListResponse, OwnCharacter};
+    let new: CharacterListResponse =
+        serde_json::from_str(r#"{"characters": {"Zed": 2, "Alpha": 1}, "error": ""}"#).unwrap();
+    assert_eq!(
+        new.characters,
+        vec![
+            OwnCharacter {
+                name: Character("Alpha".try_into().unwrap()),
+                id: Some(CharacterId(1)),
+            },
+            OwnCharacter {
+                name: Character("Zed".try_into().unwrap()),
+                id: Some(CharacterId(2)),
+            },
+        ]
+    );
+    let old: CharacterListResponse =
+        serde_json::from_str(r#"{"characters": ["Alpha"], "error": ""}"#).unwrap();
+    assert_eq!(old.characters[0].id, None);
+}
+
+#[test]
+fn api_ticket_login_failure() {
+    use crate::http_endpoints::{ApiTicketResult, AuthenticationError};
+    let ok = serde_json::from_str(
+        r#"{"ticket": "abc", "error": "", "bookmarks": [], "friends": [],
+            "characters": {"Someone": 1}, "default_character": 1}"#,
+    )
+    .unwrap();
+    assert!(matches!(ok, ApiTicketResult::Ticket(response)
+        if response.ticket == "abc" && response.extra.is_some()));
+    let ApiTicketResult::Failed { error } =
+        serde_json::from_str(r#"{"error": "Login failed."}"#).unwrap()
+    else {
+        panic!("A failed login was read as a ticket");
+    };
+    assert_eq!(
+        AuthenticationError::from_message(error),
+        AuthenticationError::InvalidCredentials
+    );
+}
+
+#[test]
+fn api_error_parse() {
+    use crate::http_endpoints::ApiError;
+    assert_eq!(ApiError::parse(""), None);
+    assert_eq!(
+        ApiError::parse("Ticket expired."),
+        Some(ApiError::TicketExpired)
+    );
+    assert_eq!(
+        ApiError::parse("Invalid ticket."),
+        Some(ApiError::InvalidTicket)
+    );
+    assert!(
+        ApiError::parse("Invalid ticket.")
+            .unwrap()
+            .is_ticket_error()
+    );
+    assert_eq!(
+        ApiError::parse("Character not found."),
+        Some(ApiError::Unknown("Character not found.".to_owned()))
+    );
+    let response: crate::http_endpoints::HasError<crate::http_endpoints::EmptyResponse> =
+        serde_json::from_str(r#"{"error": "Ticket expired."}"#).unwrap();
+    assert!(matches!(
+        response.into_result(),
+        Err(ApiError::TicketExpired)
+    ));
+    let response: crate::http_endpoints::HasError<crate::http_endpoints::EmptyResponse> =
+        serde_json::from_str(r#"{"error": ""}"#).unwrap();
+    assert!(response.into_result().is_ok());
+    // Error bodies leave the response's own fields out, so the error has to be read first.
+    let response: crate::http_endpoints::HasError<crate::http_endpoints::CharacterMemoResponse> =
+        serde_json::from_str(r#"{"error": "Ticket expired."}"#).unwrap();
+    assert!(matches!(
+        response.into_result(),
+        Err(ApiError::TicketExpired)
+    ));
+}
+
+#[tokio::test]
+async fn expired_ticket_is_refreshed_and_retried() {
+    use crate::client::{async_trait, PlainPassword};
+    use crate::http_endpoints::{get_character_memo, Endpoints};
+    use crate::retry::RetryPolicy;
+    use crate::tickets::TicketProvider;
+    use crate::transport::{HttpResult, HttpTransport};
+
+    // Hands out "fresh" tickets, and turns down anything sent with the "stale" one.
+    type Requests = std::sync::Arc<parking_lot::Mutex<Vec<(String, String)>>>;
+    #[derive(Debug)]
+    struct Site(Requests);
+    #[async_trait]
+    impl HttpTransport for Site {
+        async fn post_form(&self, url: &str, form: String) -> HttpResult<bytes::Bytes> {
+            self.0.lock().push((url.to_owned(), form.clone()));
+            Ok(if url.ends_with("/json/getApiTicket.php") {
+                r#"{"ticket": "fresh", "error": ""}"#.into()
+            } else if form.contains("ticket=stale") {
+                r#"{"error": "Ticket expired."}"#.into()
+            } else {
+                r#"{"id": 1, "note": "Remember them", "error": ""}"#.into()
+            })
+        }
+    }
+
+    let requests = Requests::default();
+    let tickets = TicketProvider::new(
+        "account".to_owned(),
+        Box::new(PlainPassword("password".to_owned())),
+        "stale".to_owned(),
+        Endpoints::default()
+            .with_base_url("http://stub")
+            .with_transport(Site(requests.clone())),
+        RetryPolicy::NONE,
+    );
+    let character: crate::data::Character = "Someone".parse().unwrap();
+    let memo = tickets
+        .request(|endpoints, ticket, account| async move {
+            get_character_memo(endpoints, &ticket, account, character).await
+        })
+        .await
+        .unwrap();
+    assert_eq!(memo.note, "Remember them");
+    assert_eq!(&*tickets.current(), "fresh");
+
+    let requests = requests.lock();
+    let urls = requests
+        .iter()
+        .map(|(url, _)| url.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        urls,
+        [
+            "http://stub/json/api/character-memo-get2.php",
+            "http://stub/json/getApiTicket.php",
+            "http://stub/json/api/character-memo-get2.php",
+        ]
+    );
+    assert!(requests[0].1.contains("ticket=stale"));
+    assert!(requests[2].1.contains("ticket=fresh"));
+}
+
+#[test]
+fn endpoints_base_url() {
+    use crate::http_endpoints::Endpoints;
+    let endpoints = Endpoints::default();
+    assert_eq!(
+        endpoints.url("/json/api/character-data.php"),
+        "https://www.f-list.net/json/api/character-data.php"
+    );
+    let endpoints = endpoints.with_base_url("http://localhost:8080/");
+    assert_eq!(
+        endpoints.url("/json/getApiTicket.php"),
+        "http://localhost:8080/json/getApiTicket.php"
+    );
+}
+
+#[tokio::test]
+async fn http_limiter_queues_requests() {
+    use crate::ratelimit::HttpLimiter;
+    use std::time::{Duration, Instant};
+    let limiter = HttpLimiter::new()
+        .with_requests_per_minute(1200) // One every 50ms
+        .with_concurrency(1);
+    let start = Instant::now();
+    let permit = limiter.acquire().await;
+    // The one slot's taken, so the next has to wait for it.
+    assert!(
+        tokio::time::timeout(Duration::from_millis(100), limiter.acquire())
+            .await
+            .is_err()
+    );
+    drop(permit);
+    drop(limiter.acquire().await);
+    assert!(start.elapsed() >= Duration::from_millis(100));
+}
+
+#[test]
+fn image_urls() {
+    use crate::data::Character;
+    use crate::http_endpoints::{avatar_url, Image};
+    assert_eq!(
+        avatar_url(Character("Some Body".try_into().unwrap())),
+        "https://static.f-list.net/images/avatar/some%20body.png"
+    );
+    let mut image: Image = serde_json::from_str(
+        r#"{"description": "", "extension": "jpg", "height": "600", "image_id": "12345",
+            "sort_order": "0", "width": "800"}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        image.full_url(),
+        "https://static.f-list.net/images/charimage/12345.jpg"
+    );
+    assert_eq!(
+        image.thumbnail_url(),
+        "https://static.f-list.net/images/charthumb/12345.jpg"
+    );
+    image.url = Some("https://example.com/12345.jpg".to_owned());
+    assert_eq!(image.full_url(), "https://example.com/12345.jpg");
+}
+
+#[test]
+fn eicon_urls() {
+    use crate::http_endpoints::eicon_url;
+    assert_eq!(
+        eicon_url("HeartBeat"),
+        "https://static.f-list.net/images/eicon/heartbeat.gif"
+    );
+    assert_eq!(
+        eicon_url(" big hug?"),
+        "https://static.f-list.net/images/eicon/big%20hug%3F.gif"
+    );
+}
+
+#[test]
+fn inline_url() {
+    use crate::http_endpoints::Inline;
+    let inline = Inline {
+        extension: "png".to_owned(),
+        hash: "0a1b2c3d4e5f".to_owned(),
+        nsfw: false,
+    };
+    assert_eq!(
+        inline.url(),
+        "https://static.f-list.net/images/charinline/0a/1b/0a1b2c3d4e5f.png"
+    );
+}
+
+#[tokio::test]
+async fn single_flight_coalesces() {
+    use crate::single_flight::SingleFlight;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+    let flights = SingleFlight::<u32, u32>::default();
+    let calls = AtomicU32::new(0);
+    let request = |result: Result<u32, ()>| {
+        let calls = &calls;
+        move || async move {
+            calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            result
+        }
+    };
+    let (a, b) = tokio::join!(
+        flights.run(1, request(Ok(5))),
+        flights.run(1, request(Ok(6)))
+    );
+    assert_eq!((a, b), (Ok(5), Ok(5)));
+    assert_eq!(calls.swap(0, Ordering::SeqCst), 1);
+    // A failure isn't handed on; the other caller tries for itself.
+    let (a, b) = tokio::join!(
+        flights.run(1, request(Err(()))),
+        flights.run(1, request(Ok(6)))
+    );
+    assert_eq!((a, b), (Err(()), Ok(6)));
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn client_coalesces_requests() {
+    use crate::cache::NoCache;
+    use crate::client::{async_trait, ClientBuilder, EventListener, PlainPassword};
+    use crate::data::Character;
+    use crate::transport::{HttpResult, HttpTransport};
+    use std::time::Duration;
+
+    // Slow enough that requests made together overlap.
+    type Requests = std::sync::Arc<parking_lot::Mutex<Vec<String>>>;
+    #[derive(Debug)]
+    struct Site(Requests);
+    #[async_trait]
+    impl HttpTransport for Site {
+        async fn post_form(&self, url: &str, form: String) -> HttpResult<bytes::Bytes> {
+            self.0.lock().push(url.to_owned());
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Ok(if url.ends_with("/json/api/character-images.php") {
+                r#"{"images": [], "error": ""}"#.into()
+            } else {
+                let page = if form.contains("page=2") { 2 } else { 1 };
+                format!(r#"{{"page": {page}, "canEdit": false, "nextPage": false, "posts": []}}"#)
+                    .into()
+            })
+        }
+    }
+
+    struct Listener;
+    impl EventListener for Listener {}
+    let requests = Requests::default();
+    let someone: Character = "Someone".parse().unwrap();
+    let (client, _events) = ClientBuilder::new(Listener)
+        .with_cache(NoCache)
+        .with_base_url("http://stub")
+        .with_http_transport(Site(requests.clone()))
+        .build(
+            "account".to_owned(),
+            Box::new(PlainPassword("password".to_owned())),
+            "ticket".to_owned(),
+            vec![],
+        );
+    let (a, b, first, again, second) = tokio::join!(
+        client.get_character_images(someone),
+        client.get_character_images(someone),
+        client.get_guestbook(someone, 1),
+        client.get_guestbook(someone, 1),
+        client.get_guestbook(someone, 2),
+    );
+    assert!(a.unwrap().is_empty() && b.unwrap().is_empty());
+    assert_eq!((first.unwrap().page, again.unwrap().page), (1, 1));
+    // A different page is a different request.
+    assert_eq!(second.unwrap().page, 2);
+
+    let mut requests = requests.lock().clone();
+    requests.sort();
+    assert_eq!(
+        requests,
+        [
+            "http://stub/json/api/character-guestbook.php",
+            "http://stub/json/api/character-guestbook.php",
+            "http://stub/json/api/character-images.php",
+        ]
+    );
+}
+
+#[test]
+fn long_channel_names() {
+    use crate::data::Channel;
+    use crate::util::CompactString;
+    let title = "A private room with a title that goes on well past thirty-two bytes";
+    let channel: Channel = serde_json::from_str(&format!("\"{title}\"")).unwrap();
+    assert!(matches!(channel.0, CompactString::Heap(_)));
+    assert_eq!(channel.0.to_string(), title);
+    assert_eq!(channel, Channel(title.to_uppercase().as_str().into()));
+    assert_eq!(
+        serde_json::to_string(&channel).unwrap(),
+        format!("\"{title}\"")
+    );
+    assert_eq!(Channel("Frontpage".into()), Channel("frontpage".into()));
+}
+
+#[test]
+fn validated_names() {
+    use crate::data::{Channel, Character, NameError};
+    let character: Character = "Some-Body_2".parse().unwrap();
+    assert_eq!(character.to_string(), "Some-Body_2");
+    assert_eq!(Character::try_new(""), Err(NameError::Empty));
+    assert_eq!(Character::try_new(" Padded"), Err(NameError::Padded));
+    assert_eq!(
+        Character::try_new("Zoë"),
+        Err(NameError::InvalidCharacter('ë'))
+    );
+    assert_eq!(
+        Character::try_new(&"a".repeat(33)),
+        Err(NameError::TooLong(Character::MAX_LEN))
+    );
+    let channel: Channel = "Sci-fi & Fantasy Ünd Mehr".parse().unwrap();
+    assert_eq!(channel.to_string(), "Sci-fi & Fantasy Ünd Mehr");
+    assert_eq!(
+        Channel::try_new("Line\nbreak"),
+        Err(NameError::InvalidCharacter('\n'))
+    );
+}
+
+#[test]
+fn private_message_keys() {
+    use crate::data::{Character, MessageChannel};
+    let me = Character("Me".try_into().unwrap());
+    let you = Character("You".try_into().unwrap());
+    // A roll of ours comes back addressed to them; one of theirs is addressed to us.
+    assert_eq!(
+        MessageChannel::private_between(me, you, me),
+        MessageChannel::private(me, you)
+    );
+    assert_eq!(
+        MessageChannel::private_between(me, me, you),
+        MessageChannel::private(me, you)
+    );
+    assert_eq!(MessageChannel::private(me, you).peer(&me), Some(you));
+    assert_eq!(MessageChannel::private(me, you).peer(&you), Some(me));
+}
+
+#[test]
+fn stack_string_construction() {
+    use crate::util::{StackString, StackStringError};
+    // Cut short rather than split the ë.
+    assert_eq!(&*StackString::<3>::new("Zoë"), "Zo");
+    assert_eq!(&*StackString::<3>::new("NLN"), "NLN");
+    assert_eq!(
+        StackString::<3>::try_from("LONG"),
+        Err(StackStringError::TooLong { len: 4, max: 3 })
+    );
+    assert_eq!(
+        StackString::<8>::try_from("Fits".to_owned()).map(|s| s.to_string()),
+        Ok("Fits".to_owned())
+    );
+    assert!(serde_json::from_str::<StackString<3>>(r#""LONG""#).is_err());
+}
+
+#[test]
+fn case_insensitive_hash() {
+    use crate::data::{Channel, Character};
+    use std::hash::BuildHasher;
+    let hasher = std::collections::hash_map::RandomState::new();
+    let a: Character = "Some Body".parse().unwrap();
+    let b: Character = "sOME bODY".parse().unwrap();
+    assert_eq!(hasher.hash_one(a), hasher.hash_one(b));
+    assert_ne!(hasher.hash_one(a), hasher.hash_one(Character::default()));
+    // Long enough to be chunked, and on the heap rather than inline.
+    let title = "A Channel Title That Runs Well Past The Inline Limit";
+    let upper = title.to_uppercase();
+    let (a, b) = (Channel(title.into()), Channel(upper.as_str().into()));
+    assert_eq!(a, b);
+    assert_eq!(hasher.hash_one(a), hasher.hash_one(b));
+}
+
+#[test]
+fn lenient_timestamps() {
+    use crate::util::timestamp::{self, Timestamp};
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Stamps {
+        #[serde(with = "timestamp")]
+        plain: Timestamp,
+        #[serde(with = "timestamp::millis")]
+        millis: Timestamp,
+        #[serde(with = "timestamp::string")]
+        string: Timestamp,
+        #[serde(
+            default,
+            serialize_with = "timestamp::string::serialize_option",
+            deserialize_with = "timestamp::string::deserialize_option"
+        )]
+        optional: Option<Timestamp>,
+    }
+    let expected = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+    for raw in [
+        r#"{"plain":1700000000,"millis":1700000000000,"string":"1700000000","optional":"1700000000000"}"#,
+        r#"{"plain":"1700000000000","millis":1700000000.0,"string":" 1700000000 ","optional":1700000000}"#,
+    ] {
+        let stamps: Stamps = serde_json::from_str(raw).unwrap();
+        assert_eq!(stamps.plain, expected);
+        assert_eq!(stamps.millis, expected);
+        assert_eq!(stamps.string, expected);
+        assert_eq!(stamps.optional, Some(expected));
+    }
+    let stamps: Stamps =
+        serde_json::from_str(r#"{"plain":0,"millis":0,"string":"0","optional":null}"#).unwrap();
+    assert_eq!(stamps.optional, None);
+    assert!(serde_json::from_str::<Stamps>(r#"{"plain":"soon","millis":0,"string":"0"}"#).is_err());
+
+    let stamps = Stamps {
+        plain: expected,
+        millis: expected,
+        string: expected,
+        optional: Some(expected),
+    };
+    assert_eq!(
+        serde_json::to_string(&stamps).unwrap(),
+        r#"{"plain":1700000000,"millis":1700000000000,"string":"1700000000","optional":"1700000000"}"#
+    );
+}
+
+#[test]
+fn member_list_order() {
+    use crate::data::{CharacterData, Gender, Status};
+    let member = |name: &str, status| CharacterData {
+        character: name.parse().unwrap(),
+        status,
+        ..Default::default()
+    };
+    let mut members = [
+        member("zed", Status::Online),
+        member("Away Person", Status::Away),
+        member("Alpha", Status::Online),
+        member("alpha", Status::Online),
+        member("Lonely", Status::Looking),
+        member("Gone", Status::Offline),
+        member("Royal", Status::Crown),
+    ];
+    members.sort_by(CharacterData::member_cmp);
+    let names: Vec<_> = members.iter().map(|m| m.character.0.to_string()).collect();
+    assert_eq!(
+        names,
+        [
+            "Lonely",
+            "Alpha",
+            "alpha",
+            "Royal",
+            "zed",
+            "Away Person",
+            "Gone"
+        ]
+    );
+
+    assert_eq!(Gender::Female.color(), "#ff6699");
+    assert!(Gender::Male.sort_priority() < Gender::None.sort_priority());
+    assert!(Status::Looking.sort_priority() < Status::Dnd.sort_priority());
+}
+
+#[cfg(feature = "extra-fields")]
+#[test]
+fn response_extra_fields() {
+    use crate::http_endpoints::{ApiTicketResult, CharacterListResponse, HasError};
+    let list: HasError<CharacterListResponse> = serde_json::from_str(
+        r#"{"characters": ["Alpha"], "error": "", "premium": true, "slots": 30}"#,
+    )
+    .unwrap();
+    let list = list.into_result().unwrap();
+    assert_eq!(list.characters.len(), 1);
+    // The error field belongs to HasError, not the response.
+    assert_eq!(list.extra.len(), 2);
+    assert_eq!(list.extra["slots"], serde_json::json!(30));
+
+    let ApiTicketResult::Ticket(ticket) = serde_json::from_str(
+        r#"{"ticket": "abc", "error": "", "bookmarks": [], "friends": [],
+            "characters": {"Someone": 1}, "default_character": 1, "new_field": "yes"}"#,
+    )
+    .unwrap() else {
+        panic!("A ticket was read as a failed login");
+    };
+    assert!(ticket.extra.is_some());
+    // No HasError around this one, so error is just another field.
+    let mut keys: Vec<_> = ticket.extra_fields.keys().collect();
+    keys.sort();
+    assert_eq!(keys, ["error", "new_field"]);
+}
+
+#[test]
+fn message_content_shared() {
+    use crate::data::MessageContent;
+    let content = MessageContent::Message("hello".into());
+    let (MessageContent::Message(a), MessageContent::Message(b)) = (&content, &content.clone())
+    else {
+        unreachable!()
+    };
+    assert!(std::sync::Arc::ptr_eq(a, b));
+    // Same JSON as when these were Strings and Vecs, so cached history still reads back.
+    let roll = MessageContent::Roll(vec!["1d6".to_owned()].into(), vec![4].into(), 4);
+    let json = serde_json::to_string(&roll).unwrap();
+    assert_eq!(json, r#"{"type":"roll","content":[["1d6"],[4],4]}"#);
+    assert_eq!(serde_json::from_str::<MessageContent>(&json).unwrap(), roll);
+}
+
+#[test]
+fn bodyless_commands() {
+    use crate::protocol::{parse_command, prepare_command, ClientCommand, ServerCommand};
+    assert_eq!(parse_command("PIN"), ServerCommand::Ping);
+    assert_eq!(parse_command("PIN "), ServerCommand::Ping);
+    assert_eq!(prepare_command(&ClientCommand::Pong), "PIN");
+    assert_eq!(prepare_command(&ClientCommand::Channels), "ORS");
+    assert_eq!(
+        prepare_command(&ClientCommand::LeaveChannel {
+            channel: "Frontpage".parse().unwrap()
+        }),
+        r#"LCH {"channel":"Frontpage"}"#
+    );
+}
+
+#[cfg(feature = "mock-server")]
+#[tokio::test]
+async fn mock_server_session() {
+    use crate::mock::MockServer;
+    use crate::protocol::{ClientCommand, ServerCommand};
+    use crate::session::{Session, SessionEvent};
+    let server = MockServer::bind().await.unwrap();
+    let (events, mut received) = tokio::sync::mpsc::channel(8);
+    let (outgoing, _sent) = tokio::sync::mpsc::unbounded_channel();
+    let character: crate::data::Character = "Tester".parse().unwrap();
+    let (session, connection) = tokio::join!(
+        Session::connect(
+            std::sync::Arc::new(crate::transport::WebsocketTransport),
+            server.url(),
+            "account".to_owned(),
+            "ticket".to_owned(),
+            "tests".to_owned(),
+            "0.0".to_owned(),
+            character,
+            events,
+            outgoing,
+            None,
+        ),
+        server.accept()
+    );
+    let (session, mut connection) = (session.unwrap(), connection.unwrap());
+    assert_eq!(connection.character(), character);
+    assert!(matches!(
+        connection.identify(),
+        ClientCommand::Identify { ticket, .. } if ticket == "ticket"
+    ));
+    assert_eq!(session.variables.chat_max, 4096);
+    // CON is what tells the client it's ready.
+    let event = received.recv().await.unwrap();
+    assert!(matches!(
+        event.event,
+        SessionEvent::Command(ServerCommand::Connected { count: 1 })
+    ));
+
+    connection.send(ServerCommand::Ping).await.unwrap();
+    connection.expect(ClientCommand::Pong).await;
+    let channel: crate::data::Channel = "Frontpage".parse().unwrap();
+    session
+        .send(ClientCommand::JoinChannel {
+            channel: channel.clone(),
+        })
+        .await
+        .unwrap();
+    connection
+        .expect(ClientCommand::JoinChannel { channel })
+        .await;
+}
+
+#[cfg(feature = "mock-server")]
+#[tokio::test]
+async fn replay_recorded_frames() {
+    use crate::cache::{Cache, MemoryCache};
+    use crate::client::{async_trait, ClientBuilder, EventListener, PlainPassword};
+    use crate::data::{Channel, Character, MessageChannel};
+    use crate::replay::{FrameDirection, FrameRecorder, Replay, ReplayPace};
+    use crate::session::Session;
+    use std::sync::Arc;
+
+    type Heard = Arc<parking_lot::Mutex<Vec<(Character, String)>>>;
+    struct Listener(Heard);
+    #[async_trait]
+    impl EventListener for Listener {
+        async fn message(
+            &self,
+            _ctx: Arc<Session>,
+            _channel: MessageChannel,
+            character: Character,
+            message: crate::data::MessageContent,
+        ) {
+            if let crate::data::MessageContent::Message(text) = message {
+                self.0.lock().push((character, text.to_string()));
+            }
+        }
+    }
+
+    let me: Character = "Tester".parse().unwrap();
+    let other: Character = "Someone".parse().unwrap();
+    let channel: Channel = "Frontpage".parse().unwrap();
+    let path = std::env::temp_dir().join(format!("f-chat-rs-replay-{}.jsonl", std::process::id()));
+    let recorder = FrameRecorder::create(&path).unwrap();
+    recorder.record(
+        me,
+        FrameDirection::Received,
+        r#"JCH {"channel":"Frontpage","character":{"identity":"Tester"},"title":"Frontpage"}"#,
+    );
+    recorder.record(me, FrameDirection::Sent, "PIN");
+    recorder.record(
+        me,
+        FrameDirection::Received,
+        r#"MSG {"character":"Someone","message":"Hello","channel":"Frontpage"}"#,
+    );
+    recorder.flush().unwrap();
+    let replay = Replay::open(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(replay.frames().len(), 3);
+    assert_eq!(replay.frames()[1].direction, FrameDirection::Sent);
+
+    let heard = Heard::default();
+    let (client, _events) = ClientBuilder::new(Listener(heard.clone()))
+        .with_cache(MemoryCache::new())
+        .build(
+            "account".to_owned(),
+            Box::new(PlainPassword("password".to_owned())),
+            "ticket".to_owned(),
+            vec![me],
+        );
+    replay.run(&client, ReplayPace::Immediate).await.unwrap();
+
+    let data = client.cache.get_channel(&channel).unwrap().unwrap();
+    assert_eq!(data.title, "Frontpage");
+    assert_eq!(data.members, vec![me]);
+    let messages = client
+        .cache
+        .get_messages(&MessageChannel::Channel(channel), None, None)
+        .unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(heard.lock().as_slice(), &[(other, "Hello".to_owned())]);
+    assert!(client.get_sessions().is_empty());
+
+    // Out of order, as a hand-edited log might be: the late one goes straight away.
+    let mut frames = replay.frames().to_vec();
+    frames[0].offset_ms = 20;
+    frames[2].offset_ms = 10;
+    Replay::new(frames)
+        .run(&client, ReplayPace::Recorded)
+        .await
+        .unwrap();
+}
+
+#[cfg(feature = "mock-server")]
+#[tokio::test]
+async fn confirmed_reports_are_dropped() {
+    use crate::cache::{Cache, MemoryCache};
+    use crate::client::{ClientBuilder, EventListener, PlainPassword};
+    use crate::data::Character;
+    use crate::replay::{FrameDirection, RecordedFrame, Replay, ReplayPace};
+
+    let me: Character = "Tester".parse().unwrap();
+    let report = |callid: u32, character: &str| {
+        format!(
+            r#"SFC {{"action":"report","callid":{callid},"character":"{character}","timestamp":"1662422400","report":"Help please"}}"#
+        )
+    };
+    let frames = [
+        report(1, "Someone"),
+        report(2, "Someone Else"),
+        report(3, "Someone"),
+        // Picking one up doesn't say which; it's all of theirs that are being handled.
+        r#"SFC {"action":"confirm","moderator":"Kira","character":"Someone","timestamp":"1662422400"}"#
+            .to_owned(),
+    ];
+    struct Listener;
+    impl EventListener for Listener {}
+    let (client, _events) = ClientBuilder::new(Listener)
+        .with_cache(MemoryCache::new())
+        .build(
+            "account".to_owned(),
+            Box::new(PlainPassword("password".to_owned())),
+            "ticket".to_owned(),
+            vec![me],
+        );
+    Replay::new(
+        frames
+            .into_iter()
+            .map(|frame| RecordedFrame {
+                character: me,
+                offset_ms: 0,
+                direction: FrameDirection::Received,
+                frame,
+            })
+            .collect(),
+    )
+    .run(&client, ReplayPace::Immediate)
+    .await
+    .unwrap();
+
+    let reports = client.cache.get_reports().unwrap();
+    assert_eq!(
+        reports
+            .iter()
+            .map(|report| report.callid)
+            .collect::<Vec<_>>(),
+        [2]
+    );
+}
+
+#[cfg(feature = "mock-server")]
+#[tokio::test]
+async fn friend_request_sync_errors_reach_listener() {
+    use crate::cache::MemoryCache;
+    use crate::client::{async_trait, ClientBuilder, ClientError, EventListener, PlainPassword};
+    use crate::data::{Character, PendingFriendRequest};
+    use crate::replay::{FrameDirection, RecordedFrame, Replay, ReplayPace};
+    use crate::transport::{HttpError, HttpResult, HttpTransport};
+    use std::sync::Arc;
+
+    #[derive(Debug)]
+    struct Down;
+    #[async_trait]
+    impl HttpTransport for Down {
+        async fn post_form(&self, _url: &str, _form: String) -> HttpResult<bytes::Bytes> {
+            Err(HttpError::Status(reqwest::StatusCode::NOT_FOUND))
+        }
+    }
+
+    #[derive(Default)]
+    struct Heard {
+        errors: Vec<ClientError>,
+        requests: Vec<PendingFriendRequest>,
+    }
+    struct Listener(Arc<parking_lot::Mutex<Heard>>);
+    #[async_trait]
+    impl EventListener for Listener {
+        async fn client_error(&self, error: ClientError) {
+            self.0.lock().errors.push(error);
+        }
+        async fn friend_request(&self, request: PendingFriendRequest) {
+            self.0.lock().requests.push(request);
+        }
+    }
+
+    let me: Character = "Tester".parse().unwrap();
+    let heard = Arc::new(parking_lot::Mutex::new(Heard::default()));
+    let (client, _events) = ClientBuilder::new(Listener(heard.clone()))
+        .with_cache(MemoryCache::new())
+        .with_http_transport(Down)
+        .build(
+            "account".to_owned(),
+            Box::new(PlainPassword("password".to_owned())),
+            "ticket".to_owned(),
+            vec![me],
+        );
+    Replay::new(vec![RecordedFrame {
+        character: me,
+        offset_ms: 0,
+        direction: FrameDirection::Received,
+        frame: r#"RTB {"type":"friendrequest","name":"Someone"}"#.to_owned(),
+    }])
+    .run(&client, ReplayPace::Immediate)
+    .await
+    .unwrap();
+
+    let heard = heard.lock();
+    assert!(heard.requests.is_empty());
+    assert!(matches!(
+        heard.errors.as_slice(),
+        [ClientError::RequestError(err)] if err.status() == Some(reqwest::StatusCode::NOT_FOUND)
+    ));
+}
+
+#[tokio::test]
+async fn session_over_custom_transport() {
+    use crate::client::async_trait;
+    use crate::protocol::{prepare_command, ClientCommand, ServerCommand};
+    use crate::session::{Session, SessionError, SessionEvent};
+    use crate::transport::{
+        Connection, FrameReceiver, FrameSender, Transport, TransportError, TransportResult,
+    };
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+    // Frames go back and forth over channels, and the "server" hangs up when its end is dropped.
+    #[derive(Debug)]
+    struct Channels(parking_lot::Mutex<Option<Connection>>);
+    #[derive(Debug)]
+    struct Sender(UnboundedSender<String>);
+    #[derive(Debug)]
+    struct Receiver(UnboundedReceiver<String>);
+    #[async_trait]
+    impl Transport for Channels {
+        async fn connect(&self, url: &str) -> TransportResult<Connection> {
+            assert_eq!(url, "test://chat");
+            Ok(self.0.lock().take().expect("Only one connection"))
+        }
+    }
+    #[async_trait]
+    impl FrameSender for Sender {
+        async fn send(&mut self, frame: String) -> TransportResult<()> {
+            self.0
+                .send(frame)
+                .map_err(|err| TransportError::Other(err.into()))
+        }
+    }
+    #[async_trait]
+    impl FrameReceiver for Receiver {
+        async fn recv(&mut self) -> TransportResult<Option<String>> {
+            self.0.recv().await.map(Some).ok_or(TransportError::Dropped)
+        }
+    }
+
+    let character: crate::data::Character = "Tester".parse().unwrap();
+    let (to_server, mut server_in) = unbounded_channel();
+    let (server_out, from_server) = unbounded_channel();
+    for frame in [
+        r#"IDN {"character":"Tester"}"#,
+        r#"VAR {"variable":"chat_max","value":4096}"#,
+        r#"HLO {"message":"Welcome."}"#,
+        r#"CON {"count":1}"#,
+        "MSG {not json",
+        "PIN",
+    ] {
+        server_out.send(frame.to_owned()).unwrap();
+    }
+    let transport = Channels(parking_lot::Mutex::new(Some((
+        Box::new(Sender(to_server)),
+        Box::new(Receiver(from_server)),
+    ))));
+
+    let (events, mut received) = tokio::sync::mpsc::channel(8);
+    let (outgoing, _sent) = tokio::sync::mpsc::unbounded_channel();
+    let session = Session::connect(
+        std::sync::Arc::new(transport),
+        "test://chat".to_owned(),
+        "account".to_owned(),
+        "ticket".to_owned(),
+        "tests".to_owned(),
+        "0.0".to_owned(),
+        character,
+        events,
+        outgoing,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(session.variables.chat_max, 4096);
+    assert!(server_in.recv().await.unwrap().starts_with("IDN "));
+    assert!(matches!(
+        received.recv().await.unwrap().event,
+        SessionEvent::Command(ServerCommand::Connected { count: 1 })
+    ));
+    // A frame that doesn't parse is reported, and the session carries on.
+    assert!(matches!(
+        received.recv().await.unwrap().event,
+        SessionEvent::Error(SessionError::UnexpectedProtocolMessage(frame)) if frame == "MSG {not json"
+    ));
+    assert_eq!(
+        server_in.recv().await.unwrap(),
+        prepare_command(&ClientCommand::Pong)
+    );
+
+    // No ERR beforehand, so it's worth reconnecting.
+    drop(server_out);
+    assert!(matches!(
+        received.recv().await.unwrap().event,
+        SessionEvent::Reconnect
+    ));
+}
+
+#[tokio::test]
+async fn endpoints_over_custom_http_transport() {
+    use crate::client::async_trait;
+    use crate::http_endpoints::{get_api_ticket, get_mapping_list, AuthenticationError, Endpoints};
+    use crate::transport::{HttpError, HttpResult, HttpTransport};
+
+    // Turns down every login, and remembers what it was asked.
+    type Requests = std::sync::Arc<parking_lot::Mutex<Vec<(String, String)>>>;
+    #[derive(Debug)]
+    struct Stub(Requests);
+    #[async_trait]
+    impl HttpTransport for Stub {
+        async fn post_form(&self, url: &str, form: String) -> HttpResult<bytes::Bytes> {
+            self.0.lock().push((url.to_owned(), form));
+            match url {
+                "http://stub/json/getApiTicket.php" => Ok(r#"{"error": "Login failed."}"#.into()),
+                _ => Err(HttpError::Status(reqwest::StatusCode::NOT_FOUND)),
+            }
+        }
+    }
+
+    let requests = Requests::default();
+    let endpoints = Endpoints::default()
+        .with_base_url("http://stub")
+        .with_transport(Stub(requests.clone()));
+
+    let result = get_api_ticket(&endpoints, "account", "pass word", false)
+        .await
+        .unwrap();
+    assert!(matches!(
+        result,
+        Err(AuthenticationError::InvalidCredentials)
+    ));
+    let err = get_mapping_list(&endpoints).await.unwrap_err();
+    assert_eq!(err.status(), Some(reqwest::StatusCode::NOT_FOUND));
+
+    let requests = requests.lock();
+    assert_eq!(requests[0].0, "http://stub/json/getApiTicket.php");
+    assert!(
+        requests[0]
+            .1
+            .starts_with("account=account&password=pass+word&")
+    );
+    assert_eq!(requests[1].0, "http://stub/json/api/mapping-list.php");
 }
@@ -0,0 +1,197 @@
+// Everything an authenticated request to the JSON API needs: the account, where its tickets come
+// from, the current ticket, and the endpoints to send it to. The Client owns one, and hands it out
+// (Client::tickets) for anything it doesn't wrap itself.
+//
+// Tickets are meant to last 30 minutes, but they're refreshed after 25 to be safe, and once more
+// whenever the site turns one down anyway. However many requests notice at once, only one of them
+// goes and gets the new ticket.
+
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use arc_swap::ArcSwap;
+
+use crate::{
+    client::ClientError,
+    credentials::CredentialProvider,
+    http_endpoints::{get_api_ticket, Endpoints, HasError},
+    retry::{retry, RetryPolicy},
+    telemetry,
+    transport::HttpResult,
+};
+
+// Swapped out whole on refresh, so reading it never waits on anything.
+#[derive(Debug, Clone)]
+struct Token {
+    last_updated: Instant,
+    ticket: Arc<str>,
+}
+
+impl Token {
+    fn new(ticket: String) -> Token {
+        Token {
+            ticket: ticket.into(),
+            last_updated: Instant::now(),
+        }
+    }
+    fn expired(&self) -> bool {
+        self.last_updated.elapsed() > Duration::from_secs(25 * 60)
+    }
+}
+
+#[derive(Debug)]
+pub struct TicketProvider {
+    account: String,
+    credentials: Box<dyn CredentialProvider>,
+    token: ArcSwap<Token>,
+    refreshing: tokio::sync::Mutex<()>,
+    endpoints: Endpoints,
+    retry: RetryPolicy,
+}
+
+impl TicketProvider {
+    pub(crate) fn new(
+        account: String,
+        credentials: Box<dyn CredentialProvider>,
+        ticket: String,
+        endpoints: Endpoints,
+        retry: RetryPolicy,
+    ) -> Self {
+        TicketProvider {
+            account,
+            credentials,
+            token: ArcSwap::from_pointee(Token::new(ticket)),
+            refreshing: tokio::sync::Mutex::new(()),
+            endpoints,
+            retry,
+        }
+    }
+
+    pub fn account(&self) -> &str {
+        &self.account
+    }
+
+    pub fn endpoints(&self) -> &Endpoints {
+        &self.endpoints
+    }
+
+    /// The ticket as it is, however old.
+    pub fn current(&self) -> Arc<str> {
+        self.token.load().ticket.clone()
+    }
+
+    /// A ticket that should still be good, getting a new one first if it's due.
+    pub async fn ticket(&self) -> Result<Arc<str>, ClientError> {
+        self.refresh_fast().await?;
+        Ok(self.current())
+    }
+
+    /// Get a new ticket now, from the credential provider or by logging in again.
+    pub async fn refresh(&self) -> Result<(), ClientError> {
+        let _refreshing = self.refreshing.lock().await;
+        self.refresh_locked().await
+    }
+
+    async fn refresh_locked(&self) -> Result<(), ClientError> {
+        let ticket = match self
+            .credentials
+            .get_ticket(&self.account)
+            .await
+            .map_err(ClientError::TicketRefresh)?
+        {
+            Some(ticket) => ticket,
+            None => {
+                let password = self
+                    .credentials
+                    .get_password(&self.account)
+                    .await
+                    .map_err(ClientError::TicketRefresh)?
+                    .ok_or(ClientError::NoCredentials)?;
+                retry(&self.retry, || {
+                    get_api_ticket(&self.endpoints, &self.account, &password, false)
+                })
+                .await??
+                .ticket
+            }
+        };
+        self.token.store(Arc::new(Token::new(ticket)));
+        telemetry::ticket_refresh();
+        Ok(())
+    }
+
+    /// Refresh only if the ticket is getting old.
+    pub async fn refresh_fast(&self) -> Result<(), ClientError> {
+        // Optimistically refresh if the token is more than 20 minutes old
+        // Supposedly it lasts 30 minutes but I don't trust these devs and their crap API
+        if self.token.load().expired() {
+            let _refreshing = self.refreshing.lock().await;
+            // Whoever held the lock before may have just done it.
+            if self.token.load().expired() {
+                self.refresh_locked().await?;
+            }
+        }
+        Ok(())
+    }
+
+    // For a ticket the site turned down: only replaced if nobody has replaced it already.
+    async fn refresh_rejected(&self, rejected: &str) -> Result<(), ClientError> {
+        let _refreshing = self.refreshing.lock().await;
+        if *self.token.load().ticket == *rejected {
+            self.refresh_locked().await?;
+        }
+        Ok(())
+    }
+
+    /// Make an authenticated request, retrying it if it fails in a way that's worth retrying.
+    /// `request` is given the endpoints, a ticket and the account name, and is called again for
+    /// each attempt. Only for requests that are safe to repeat; see request_once for the rest.
+    pub async fn request<'s, R, F, Fut>(&'s self, request: F) -> Result<R, ClientError>
+    where
+        F: FnMut(&'s Endpoints, Arc<str>, &'s str) -> Fut,
+        Fut: Future<Output = HttpResult<HasError<R>>>,
+    {
+        self.send(request, &self.retry).await
+    }
+
+    /// Make an authenticated request, without retrying it if the request itself fails.
+    pub async fn request_once<'s, R, F, Fut>(&'s self, request: F) -> Result<R, ClientError>
+    where
+        F: FnMut(&'s Endpoints, Arc<str>, &'s str) -> Fut,
+        Fut: Future<Output = HttpResult<HasError<R>>>,
+    {
+        self.send(request, &RetryPolicy::NONE).await
+    }
+
+    // If the site turns the ticket down, this gets a new one and goes again, once;
+    // the request didn't happen the first time.
+    async fn send<'s, R, F, Fut>(
+        &'s self,
+        mut request: F,
+        policy: &RetryPolicy,
+    ) -> Result<R, ClientError>
+    where
+        F: FnMut(&'s Endpoints, Arc<str>, &'s str) -> Fut,
+        Fut: Future<Output = HttpResult<HasError<R>>>,
+    {
+        let mut ticket = self.ticket().await?;
+        let mut refreshed = false;
+        loop {
+            let response = retry(policy, || {
+                request(&self.endpoints, ticket.clone(), &self.account)
+            })
+            .await?;
+            match response.into_result() {
+                Ok(response) => return Ok(response),
+                Err(err) if err.is_ticket_error() && !refreshed => {
+                    self.refresh_rejected(&ticket).await?;
+                    ticket = self.current();
+                    refreshed = true;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
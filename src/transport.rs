@@ -0,0 +1,181 @@
+// What sessions talk to the chat server over, and what the JSON endpoints are reached through.
+//
+// A Transport opens a connection and hands back its two halves: a FrameSender for text going out,
+// and a FrameReceiver for text coming in. Sessions only ever deal in text frames; pings, close
+// frames and so on are the transport's business.
+//
+// WebsocketTransport (tokio-tungstenite) is the default. Anything else (another websocket stack,
+// something for WASM, a test double) goes in with ClientBuilder::with_transport.
+//
+// The JSON endpoints are all a form POSTed and JSON back, so that's all an HttpTransport does.
+// ReqwestTransport is the default; others go in with ClientBuilder::with_http_transport
+// (or Endpoints::with_transport).
+
+use std::fmt::Debug;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
+use reqwest::{header::CONTENT_TYPE, StatusCode};
+use thiserror::Error;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    connect_async_tls_with_config,
+    tungstenite::{self, error::ProtocolError, Message},
+    MaybeTlsStream, WebSocketStream,
+};
+
+use crate::util::diagnostic;
+
+#[derive(Error, Debug)]
+pub enum TransportError {
+    // F-Chat never sends close frames; it just drops the connection, usually right after an ERR.
+    #[error("The server dropped the connection")]
+    Dropped,
+    #[error("Error from Websocket (Tungstenite)")]
+    WebsocketError(#[from] tungstenite::Error),
+    #[error("Error from Transport implementation")]
+    Other(#[source] Box<dyn std::error::Error + Send + Sync>),
+}
+
+pub type TransportResult<T> = Result<T, TransportError>;
+
+pub type Connection = (Box<dyn FrameSender>, Box<dyn FrameReceiver>);
+
+#[async_trait]
+pub trait Transport: Debug + Send + Sync {
+    async fn connect(&self, url: &str) -> TransportResult<Connection>;
+}
+
+#[async_trait]
+pub trait FrameSender: Debug + Send {
+    async fn send(&mut self, frame: String) -> TransportResult<()>;
+    // For sending several at once; nothing has to go out until flush.
+    async fn feed(&mut self, frame: String) -> TransportResult<()> {
+        self.send(frame).await
+    }
+    async fn flush(&mut self) -> TransportResult<()> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+pub trait FrameReceiver: Debug + Send {
+    /// The next text frame, or None if the connection was closed properly.
+    /// TransportError::Dropped if it wasn't, which is what tells a session to check the last ERR.
+    async fn recv(&mut self) -> TransportResult<Option<String>>;
+}
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WebsocketTransport;
+
+#[async_trait]
+impl Transport for WebsocketTransport {
+    async fn connect(&self, url: &str) -> TransportResult<Connection> {
+        let (socket, _) = connect_async_tls_with_config(url, None, None).await?;
+        let (write, read) = socket.split();
+        Ok((
+            Box::new(WebsocketSender(write)),
+            Box::new(WebsocketReceiver(read)),
+        ))
+    }
+}
+
+#[derive(Debug)]
+pub struct WebsocketSender(SplitSink<Socket, Message>);
+
+#[async_trait]
+impl FrameSender for WebsocketSender {
+    async fn send(&mut self, frame: String) -> TransportResult<()> {
+        Ok(self.0.send(Message::Text(frame)).await?)
+    }
+
+    async fn feed(&mut self, frame: String) -> TransportResult<()> {
+        Ok(self.0.feed(Message::Text(frame)).await?)
+    }
+
+    async fn flush(&mut self) -> TransportResult<()> {
+        Ok(self.0.flush().await?)
+    }
+}
+
+#[derive(Debug)]
+pub struct WebsocketReceiver(SplitStream<Socket>);
+
+#[async_trait]
+impl FrameReceiver for WebsocketReceiver {
+    async fn recv(&mut self) -> TransportResult<Option<String>> {
+        loop {
+            match self.0.next().await {
+                // Messages sent are -always- Text
+                Some(Ok(Message::Text(frame))) => return Ok(Some(frame)),
+                Some(Ok(other)) => diagnostic!(warn, "Unexpected frame from F-Chat: {other:?}"),
+                Some(Err(tungstenite::Error::Protocol(
+                    ProtocolError::ResetWithoutClosingHandshake,
+                ))) => return Err(TransportError::Dropped),
+                Some(Err(tungstenite::Error::Protocol(ProtocolError::ReceivedAfterClosing))) => {
+                    diagnostic!(warn, "Close frames are not respected by F-Chat")
+                }
+                Some(Err(err)) => return Err(err.into()),
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum HttpError {
+    #[error("Error from HTTP Request")]
+    RequestError(#[from] reqwest::Error),
+    #[error("The site responded with {0}")]
+    Status(StatusCode),
+    #[error("Couldn't encode the form")]
+    Form(#[from] serde_urlencoded::ser::Error),
+    #[error("Couldn't parse the response")]
+    Json(#[from] serde_json::Error),
+    #[error("Error from HttpTransport implementation")]
+    Other(#[source] Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl HttpError {
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            HttpError::RequestError(err) => err.status(),
+            HttpError::Status(status) => Some(*status),
+            _ => None,
+        }
+    }
+}
+
+pub type HttpResult<T> = Result<T, HttpError>;
+
+#[async_trait]
+pub trait HttpTransport: Debug + Send + Sync {
+    /// POST `form` (already urlencoded) to `url`, and hand back the body.
+    /// Anything but a success status should be an error; HttpError::Status will do.
+    async fn post_form(&self, url: &str, form: String) -> HttpResult<Bytes>;
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ReqwestTransport(pub reqwest::Client);
+
+#[async_trait]
+impl HttpTransport for ReqwestTransport {
+    async fn post_form(&self, url: &str, form: String) -> HttpResult<Bytes> {
+        Ok(self
+            .0
+            .post(url)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(form)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?)
+    }
+}
@@ -1,6 +1,7 @@
 use std::{
     fmt::Display,
     ops::{Deref, DerefMut},
+    sync::Arc,
 };
 
 use serde::{de::Visitor, Deserialize, Serialize};
@@ -39,6 +40,24 @@ macro_rules! stringable {
             }
         }
 
+        // So they can be used like the value they wrap, outside of serde too.
+        impl From<$t> for $i {
+            fn from(v: $t) -> $i {
+                $i(v)
+            }
+        }
+        impl std::str::FromStr for $i {
+            type Err = <$t as std::str::FromStr>::Err;
+            fn from_str(s: &str) -> Result<$i, Self::Err> {
+                s.parse().map($i)
+            }
+        }
+        impl std::fmt::Display for $i {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
         #[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug, Clone)]
         #[serde(untagged)]
         enum $pi {
@@ -48,27 +67,69 @@ macro_rules! stringable {
     };
 }
 
+// Diagnostics go through tracing when the feature is on, and stderr otherwise.
+// Only takes a format string, so it works the same either way.
+macro_rules! diagnostic {
+    ($level:ident, $($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($arg)+);
+        #[cfg(not(feature = "tracing"))]
+        eprintln!($($arg)+);
+    }};
+}
+pub(crate) use diagnostic;
+
 stringable!(StringBool: bool, BoolProxy, "BoolProxy");
 stringable!(StringInteger: u64, IntegerProxy, "IntegerProxy");
 
 pub struct StackString<const N: usize>([u8; N], usize); // If you use it for N>48 I'll kill you.
 impl<const N: usize> StackString<N> {
     // Take &str to promise that it's valid utf8
+    /// Anything past N bytes is cut off, at a character boundary. try_new refuses it instead.
     pub fn new(from: &str) -> Self {
-        let len = from.len();
+        let mut len = from.len().min(N);
+        while !from.is_char_boundary(len) {
+            len -= 1;
+        }
         let mut data = [0u8; N];
-        let (left, _) = data.split_at_mut(len); // We already promise len <= N; use unchecked when stable
-        left.copy_from_slice(from.as_bytes());
+        data[..len].copy_from_slice(&from.as_bytes()[..len]);
         StackString(data, len)
     }
 
-    pub fn try_new(from: &str) -> Result<Self, ()> {
+    pub fn try_new(from: &str) -> Result<Self, StackStringError> {
         if from.len() > N {
-            Err(()) // It's too long.
+            Err(StackStringError::TooLong {
+                len: from.len(),
+                max: N,
+            })
         } else {
             Ok(Self::new(from))
         }
     }
+
+    /// Hashes the same for strings that are eq_ignore_ascii_case, without lowercasing a copy.
+    pub fn hash_ignore_ascii_case<H: std::hash::Hasher>(&self, state: &mut H) {
+        hash_ignore_ascii_case(self, state)
+    }
+}
+
+// Lowercased a chunk at a time, on the stack. Equal strings are the same length, so they're
+// chunked the same way and hash the same.
+fn hash_ignore_ascii_case<H: std::hash::Hasher>(text: &str, state: &mut H) {
+    let mut buffer = [0u8; 32];
+    for chunk in text.as_bytes().chunks(buffer.len()) {
+        let lowered = &mut buffer[..chunk.len()];
+        lowered.copy_from_slice(chunk);
+        lowered.make_ascii_lowercase();
+        state.write(lowered);
+    }
+    state.write_u8(0xff); // Same as str does, so "a", "b" and "ab" don't collide in a tuple
+}
+
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackStringError {
+    #[error("String is {len} bytes long, but only {max} fit")]
+    TooLong { len: usize, max: usize },
 }
 
 impl<const N: usize> Copy for StackString<N> {}
@@ -91,7 +152,7 @@ impl<const N: usize> DerefMut for StackString<N> {
 
 impl<const N: usize> AsRef<str> for StackString<N> {
     fn as_ref(&self) -> &str {
-        &**self
+        self
     }
 }
 
@@ -119,13 +180,13 @@ impl<const N: usize> Serialize for StackString<N> {
     where
         S: serde::Serializer,
     {
-        serializer.serialize_str(&**self)
+        serializer.serialize_str(self)
     }
 }
 
 impl<const N: usize> PartialOrd for StackString<N> {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        <Self as AsRef<str>>::as_ref(self).partial_cmp(other.as_ref())
+        Some(self.cmp(other))
     }
 }
 
@@ -147,9 +208,7 @@ impl<const N: usize> PartialEq for StackString<N> {
     }
 }
 
-impl<const N: usize> Eq for StackString<N> {
-    fn assert_receiver_is_total_eq(&self) {}
-}
+impl<const N: usize> Eq for StackString<N> {}
 
 impl<const N: usize> std::hash::Hash for StackString<N> {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
@@ -163,9 +222,17 @@ impl<const N: usize> Default for StackString<N> {
     }
 }
 
-impl<const N: usize> From<&str> for StackString<N> {
-    fn from(v: &str) -> Self {
-        StackString::new(v)
+impl<const N: usize> TryFrom<&str> for StackString<N> {
+    type Error = StackStringError;
+    fn try_from(v: &str) -> Result<Self, Self::Error> {
+        StackString::try_new(v)
+    }
+}
+
+impl<const N: usize> TryFrom<String> for StackString<N> {
+    type Error = StackStringError;
+    fn try_from(v: String) -> Result<Self, Self::Error> {
+        StackString::try_new(&v)
     }
 }
 
@@ -177,7 +244,15 @@ impl<'de, const N: usize> Visitor<'de> for StackStringVisitor<N> {
     where
         E: serde::de::Error,
     {
-        StackString::try_new(v).map_err(|_| E::custom("deserialized string is too long"))
+        StackString::try_new(v).map_err(E::custom)
+    }
+
+    // Owned strings are copied out of, not kept, so this just saves the default going via String.
+    fn visit_string<E>(self, v: String) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.visit_str(&v)
     }
 
     fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -194,9 +269,167 @@ impl<'de, const N: usize> Deserialize<'de> for StackString<N> {
     }
 }
 
-pub(crate) mod timestamp {
-    use chrono::{DateTime, NaiveDateTime, Utc};
-    use serde::{de::Visitor, Deserializer, Serializer};
+/// Like StackString, but anything longer than N bytes goes on the heap instead, so it's never
+/// too long. Cheap to clone either way; the heap variant is shared, not copied.
+#[derive(Clone)]
+pub enum CompactString<const N: usize> {
+    Inline(StackString<N>),
+    Heap(Arc<str>),
+}
+
+impl<const N: usize> CompactString<N> {
+    pub fn new(from: &str) -> Self {
+        match StackString::try_new(from) {
+            Ok(inline) => CompactString::Inline(inline),
+            Err(StackStringError::TooLong { .. }) => CompactString::Heap(from.into()),
+        }
+    }
+
+    /// See StackString::hash_ignore_ascii_case.
+    pub fn hash_ignore_ascii_case<H: std::hash::Hasher>(&self, state: &mut H) {
+        hash_ignore_ascii_case(self, state)
+    }
+}
+
+impl<const N: usize> Deref for CompactString<N> {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            CompactString::Inline(inline) => inline,
+            CompactString::Heap(heap) => heap,
+        }
+    }
+}
+
+impl<const N: usize> AsRef<str> for CompactString<N> {
+    fn as_ref(&self) -> &str {
+        self
+    }
+}
+
+impl<const N: usize> std::fmt::Debug for CompactString<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("CompactString")
+            .field(&self.as_ref())
+            .finish()
+    }
+}
+
+impl<const N: usize> Display for CompactString<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        <Self as AsRef<str>>::as_ref(self).fmt(f)
+    }
+}
+
+// Everything else goes by the contents, whichever way they're stored.
+impl<const N: usize> PartialEq for CompactString<N> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<const N: usize> Eq for CompactString<N> {}
+
+impl<const N: usize> PartialOrd for CompactString<N> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<const N: usize> Ord for CompactString<N> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (**self).cmp(&**other)
+    }
+}
+
+impl<const N: usize> std::hash::Hash for CompactString<N> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        (**self).hash(state);
+    }
+}
+
+impl<const N: usize> Default for CompactString<N> {
+    fn default() -> Self {
+        CompactString::Inline(StackString::default())
+    }
+}
+
+impl<const N: usize> From<&str> for CompactString<N> {
+    fn from(v: &str) -> Self {
+        CompactString::new(v)
+    }
+}
+
+impl<const N: usize> Serialize for CompactString<N> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self)
+    }
+}
+
+struct CompactStringVisitor<const N: usize>();
+impl<'de, const N: usize> Visitor<'de> for CompactStringVisitor<N> {
+    type Value = CompactString<N>;
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(CompactString::new(v))
+    }
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a string")
+    }
+}
+
+impl<'de, const N: usize> Deserialize<'de> for CompactString<N> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_str(CompactStringVisitor::<N>())
+    }
+}
+
+/// Split a message into parts of at most `max` bytes, preferring to break on whitespace.
+/// A single word longer than `max` gets broken wherever it has to be.
+/// BBCode tags aren't respected; a tag spanning a split will show up broken on both sides.
+pub fn split_message(message: &str, max: usize) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut rest = message.trim();
+    while rest.len() > max {
+        // Largest char boundary that fits
+        let mut end = max;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let split = match rest[..end].rfind(char::is_whitespace) {
+            Some(space) if space > 0 => space,
+            _ => end,
+        };
+        if split == 0 {
+            // max is smaller than the first character; nothing sensible to do.
+            break;
+        }
+        parts.push(rest[..split].trim_end());
+        rest = rest[split..].trim_start();
+    }
+    if !rest.is_empty() {
+        parts.push(rest);
+    }
+    parts
+}
+
+pub mod timestamp {
+    use chrono::{DateTime, Utc};
+    use serde::{
+        de::{Unexpected, Visitor},
+        Deserializer, Serializer,
+    };
 
     // Why not NaiveDateTime?
     // The server has a timezone in mind when it sends these timestamps.
@@ -210,19 +443,44 @@ pub(crate) mod timestamp {
         serializer.serialize_u64(timestamp.timestamp() as u64)
     }
 
+    pub fn serialize_option<S>(
+        timestamp: &Option<Timestamp>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match timestamp {
+            Some(timestamp) => serializer.serialize_some(&(timestamp.timestamp() as u64)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    // Anything past this is treated as milliseconds. In seconds it'd be the year 5138,
+    // in milliseconds it's early 1973, so there's no real overlap either way.
+    const MILLIS_THRESHOLD: i64 = 100_000_000_000;
+
+    fn from_number(v: i64) -> Timestamp {
+        if v.abs() >= MILLIS_THRESHOLD {
+            DateTime::from_timestamp_millis(v).unwrap_or_default()
+        } else {
+            DateTime::from_timestamp(v, 0).unwrap_or_default()
+        }
+    }
+
     struct TimestampVisitor;
     impl<'de> Visitor<'de> for TimestampVisitor {
         type Value = Timestamp;
 
         fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-            formatter.write_str("unix timestamp (UTC)")
+            formatter.write_str("unix timestamp (UTC), in seconds or milliseconds")
         }
 
         fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
         where
             E: serde::de::Error,
         {
-            Ok(DateTime::from_utc(NaiveDateTime::from_timestamp(v, 0), Utc))
+            Ok(from_number(v))
         }
 
         fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
@@ -230,10 +488,7 @@ pub(crate) mod timestamp {
             E: serde::de::Error,
         {
             // Okay, it's i64. Still...
-            Ok(DateTime::from_utc(
-                NaiveDateTime::from_timestamp(v as i64, 0),
-                Utc,
-            ))
+            Ok(from_number(v.min(i64::MAX as u64) as i64))
         }
 
         fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E>
@@ -241,10 +496,51 @@ pub(crate) mod timestamp {
             E: serde::de::Error,
         {
             // Floats lose their precision long before they represent values larger than i64 max value
-            Ok(DateTime::from_utc(
-                NaiveDateTime::from_timestamp(v as i64, 0),
-                Utc,
-            ))
+            Ok(from_number(v as i64))
+        }
+
+        fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            let v = v.trim();
+            if let Ok(v) = v.parse::<i64>() {
+                return Ok(from_number(v));
+            }
+            match v.parse::<f64>() {
+                Ok(v) if v.is_finite() => Ok(from_number(v as i64)),
+                _ => Err(E::invalid_value(Unexpected::Str(v), &self)),
+            }
+        }
+    }
+
+    struct OptionVisitor;
+    impl<'de> Visitor<'de> for OptionVisitor {
+        type Value = Option<Timestamp>;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("optional unix timestamp (UTC)")
+        }
+
+        fn visit_none<E>(self) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            Ok(None)
+        }
+
+        fn visit_unit<E>(self) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            Ok(None)
+        }
+
+        fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            deserialize(deserializer).map(Some)
         }
     }
 
@@ -252,7 +548,72 @@ pub(crate) mod timestamp {
     where
         D: Deserializer<'de>,
     {
-        deserializer.deserialize_i64(TimestampVisitor)
+        // Not deserialize_i64, the value might be a string
+        deserializer.deserialize_any(TimestampVisitor)
+    }
+
+    pub fn deserialize_option<'de, D>(deserializer: D) -> Result<Option<Timestamp>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_option(OptionVisitor)
+    }
+
+    /// Milliseconds since the epoch, for `#[serde(with = "f_chat_rs::util::timestamp::millis")]`.
+    /// Deserializing accepts everything the parent module does.
+    pub mod millis {
+        use super::Timestamp;
+        use serde::Serializer;
+
+        pub use super::{deserialize, deserialize_option};
+
+        pub fn serialize<S>(timestamp: &Timestamp, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            serializer.serialize_i64(timestamp.timestamp_millis())
+        }
+
+        pub fn serialize_option<S>(
+            timestamp: &Option<Timestamp>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            match timestamp {
+                Some(timestamp) => serializer.serialize_some(&timestamp.timestamp_millis()),
+                None => serializer.serialize_none(),
+            }
+        }
+    }
+
+    /// Seconds since the epoch as a string, the way some of the HTTP endpoints send them.
+    pub mod string {
+        use super::Timestamp;
+        use serde::Serializer;
+
+        pub use super::{deserialize, deserialize_option};
+
+        pub fn serialize<S>(timestamp: &Timestamp, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            serializer.collect_str(&timestamp.timestamp())
+        }
+
+        pub fn serialize_option<S>(
+            timestamp: &Option<Timestamp>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            match timestamp {
+                Some(timestamp) => serializer.serialize_some(&timestamp.timestamp().to_string()),
+                None => serializer.serialize_none(),
+            }
+        }
     }
 }
 